@@ -1,5 +1,6 @@
 use std::process::Command;
 use std::env;
+use std::fs;
 
 const RPI_RUST_TARGET : &'static str = "arm-unknown-linux-gnueabihf";
 
@@ -40,6 +41,14 @@ fn main() {
     .current_dir(root.as_str())
     .status().expect("ln libbearssl.a failed");
   
+  // OCSP stapling needs BearSSL to be patched with src/tls/bearssl/lib/status_request.patch
+  let ssl_header = fs::read_to_string(format!("{}/{}/inc/bearssl_ssl.h", &root, BEAR_SSL_ROOT))
+    .expect("reading bearssl_ssl.h failed");
+  println!("cargo:rustc-check-cfg=cfg(bearssl_status_request)");
+  if ssl_header.contains("br_ssl_server_set_ocsp_response") {
+    println!("cargo:rustc-cfg=bearssl_status_request");
+  }
+
   println!("cargo:rustc-link-search=native={}", out_dir);
   println!("cargo:rustc-link-lib=static=wwwee-aio");
   println!("cargo:rustc-link-lib=static=bearssl");
@@ -0,0 +1,362 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::{Duration, UNIX_EPOCH};
+use buffer::Buffer;
+use http;
+use http::body_stream::BodyStream;
+use io::{Event, Context};
+use io::sources::file::{DirectoryListing, EntryInfo, EntryKind};
+
+// how much of the listing is rendered before waiting for the socket to take it
+const BATCH_SIZE : usize = 16 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ListingFormat {
+  Html,
+  Json
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SortKey {
+  Name,
+  Size,
+  Modified
+}
+
+impl SortKey {
+  fn as_str(self) -> &'static str {
+    match self {
+      SortKey::Name => "name",
+      SortKey::Size => "size",
+      SortKey::Modified => "mtime"
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ListingOptions {
+  pub format: ListingFormat,
+  /// None keeps the order of the directory
+  pub sort: Option<SortKey>,
+  pub descending: bool
+}
+
+impl ListingOptions {
+  /// From the query string: `format=html|json`, `sort=name|size|mtime`
+  /// and `order=asc|desc`. None if any of them has an unknown value.
+  pub fn from_query_params(params: http::UrlEncodedParamsIterator) -> Option<ListingOptions> {
+    let mut options = ListingOptions {
+      format: ListingFormat::Html,
+      sort: None,
+      descending: false
+    };
+    for param in params {
+      match (param.name, param.value) {
+        ("format", "html") => options.format = ListingFormat::Html,
+        ("format", "json") => options.format = ListingFormat::Json,
+        ("sort", "name") => options.sort = Some(SortKey::Name),
+        ("sort", "size") => options.sort = Some(SortKey::Size),
+        ("sort", "mtime") => options.sort = Some(SortKey::Modified),
+        ("order", "asc") => options.descending = false,
+        ("order", "desc") => options.descending = true,
+        ("format", _) | ("sort", _) | ("order", _) => return None,
+        _ => {}
+      }
+    }
+    Some(options)
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self.format {
+      ListingFormat::Html => "text/html; charset=utf-8",
+      ListingFormat::Json => "application/json"
+    }
+  }
+}
+
+enum Source {
+  Directory(DirectoryListing),
+  // sorting needs all entries before the first one can be written,
+  // so their names are kept back to back in one string
+  Sorted {
+    names: String,
+    entries: Vec<(Range<usize>, EntryInfo)>,
+    next: usize
+  }
+}
+
+struct Renderer {
+  options: ListingOptions,
+  // the url of the directory, ends with a /
+  url: String,
+  entry_count: usize
+}
+
+/// Writes the entries of a directory as HTML or JSON while the socket takes them.
+/// Unsorted listings are read from the directory as they are written,
+/// sorted listings read all entries when created.
+pub struct ListingStream {
+  source: Source,
+  renderer: Renderer,
+  is_started: bool
+}
+
+impl ListingStream {
+  pub fn new(mut listing: DirectoryListing, url: &str, options: ListingOptions) -> io::Result<ListingStream> {
+    let source = match options.sort {
+      None => Source::Directory(listing),
+      Some(key) => {
+        let mut names = String::new();
+        let mut entries = Vec::new();
+        while let Some((name, info)) = listing.next_entry()? {
+          let start = names.len();
+          names.push_str(name);
+          entries.push((start .. names.len(), info));
+        }
+        {
+          let names = &names;
+          entries.sort_by(|a, b| {
+            let order = match key {
+              SortKey::Name => names[a.0.clone()].cmp(&names[b.0.clone()]),
+              SortKey::Size => a.1.size.cmp(&b.1.size),
+              SortKey::Modified => a.1.mtime.cmp(&b.1.mtime)
+            }.then_with(|| names[a.0.clone()].cmp(&names[b.0.clone()]));
+            if options.descending { order.reverse() } else { order }
+          });
+        }
+        Source::Sorted { names, entries, next: 0 }
+      }
+    };
+    Ok(ListingStream {
+      source,
+      renderer: Renderer {
+        options,
+        url: url.to_owned(),
+        entry_count: 0
+      },
+      is_started: false
+    })
+  }
+
+  // returns false once all entries were written
+  fn write_next_entry(&mut self, body: &mut Buffer) -> io::Result<bool> {
+    match self.source {
+      Source::Directory(ref mut listing) => {
+        match listing.next_entry()? {
+          Some((name, info)) => self.renderer.write_entry(body, name, &info).map(|_| true),
+          None => Ok(false)
+        }
+      },
+      Source::Sorted { ref names, ref entries, ref mut next } => {
+        match entries.get(*next) {
+          Some(&(ref range, ref info)) => {
+            *next += 1;
+            self.renderer.write_entry(body, &names[range.clone()], info).map(|_| true)
+          },
+          None => Ok(false)
+        }
+      }
+    }
+  }
+}
+
+impl BodyStream for ListingStream {
+  fn on_event(&mut self, _event: &Event, _ctx: &mut Context, body: &mut Buffer) -> io::Result<bool> {
+    if !self.is_started {
+      self.is_started = true;
+      self.renderer.write_start(body)?;
+    }
+    while body.len() < BATCH_SIZE {
+      if !self.write_next_entry(body)? {
+        self.renderer.write_end(body)?;
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+}
+
+impl Renderer {
+  fn write_start(&self, dst: &mut dyn Write) -> io::Result<()> {
+    match self.options.format {
+      ListingFormat::Json => write!(dst, "["),
+      ListingFormat::Html => {
+        write!(dst, "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of ")?;
+        write_html_escaped(dst, &self.url)?;
+        write!(dst, "</title></head>\n<body><h1>Index of ")?;
+        write_html_escaped(dst, &self.url)?;
+        write!(dst, "</h1>\n<table>\n<tr>")?;
+        self.write_sort_header(dst, SortKey::Name, "Name")?;
+        self.write_sort_header(dst, SortKey::Size, "Size")?;
+        self.write_sort_header(dst, SortKey::Modified, "Modified")?;
+        write!(dst, "<th>Type</th></tr>\n")?;
+        if self.url != "/" {
+          write!(dst, "<tr><td><a href=\"../\">../</a></td><td>-</td><td></td><td>directory</td></tr>\n")?;
+        }
+        Ok( () )
+      }
+    }
+  }
+
+  // clicking the column that is sorted on reverses the order
+  fn write_sort_header(&self, dst: &mut dyn Write, key: SortKey, title: &str) -> io::Result<()> {
+    let order = if self.options.sort == Some(key) && !self.options.descending {
+      "desc"
+    } else {
+      "asc"
+    };
+    write!(dst, "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>", key.as_str(), order, title)
+  }
+
+  fn write_entry(&mut self, dst: &mut dyn Write, name: &str, info: &EntryInfo) -> io::Result<()> {
+    self.entry_count += 1;
+    match self.options.format {
+      ListingFormat::Json => {
+        if self.entry_count != 1 {
+          write!(dst, ",")?;
+        }
+        write!(dst, "\n{{\"name\":")?;
+        write_json_string(dst, name)?;
+        write!(dst, ",\"type\":\"{}\",\"size\":{},\"mtime\":{}}}",
+          kind_name(info.kind), info.size, info.mtime)
+      },
+      ListingFormat::Html => {
+        let slash = if info.kind == EntryKind::Directory { "/" } else { "" };
+        write!(dst, "<tr><td><a href=\"")?;
+        write_url_encoded(dst, name)?;
+        write!(dst, "{}\">", slash)?;
+        write_html_escaped(dst, name)?;
+        write!(dst, "{}</a></td><td>", slash)?;
+        if info.kind == EntryKind::Directory {
+          write!(dst, "-")?;
+        } else {
+          write!(dst, "{}", info.size)?;
+        }
+        write!(dst, "</td><td>")?;
+        let mtime = UNIX_EPOCH + Duration::from_secs(info.mtime.max(0) as u64);
+        http::date::write_http_date(dst, mtime)?;
+        write!(dst, "</td><td>{}</td></tr>\n", kind_name(info.kind))
+      }
+    }
+  }
+
+  fn write_end(&self, dst: &mut dyn Write) -> io::Result<()> {
+    match self.options.format {
+      ListingFormat::Json => write!(dst, "\n]\n"),
+      ListingFormat::Html => write!(dst, "</table>\n</body></html>\n")
+    }
+  }
+}
+
+fn kind_name(kind: EntryKind) -> &'static str {
+  match kind {
+    EntryKind::File => "file",
+    EntryKind::Directory => "directory",
+    EntryKind::Symlink => "symlink",
+    EntryKind::Other => "other"
+  }
+}
+
+fn write_html_escaped(dst: &mut dyn Write, text: &str) -> io::Result<()> {
+  let mut start = 0;
+  for (idx, c) in text.char_indices() {
+    let escaped = match c {
+      '&' => "&amp;",
+      '<' => "&lt;",
+      '>' => "&gt;",
+      '"' => "&quot;",
+      '\'' => "&#39;",
+      _ => continue
+    };
+    dst.write_all(text[start .. idx].as_bytes())?;
+    dst.write_all(escaped.as_bytes())?;
+    start = idx + 1;
+  }
+  dst.write_all(text[start ..].as_bytes())
+}
+
+// everything but the unreserved characters, so a name is always a single path segment
+fn write_url_encoded(dst: &mut dyn Write, text: &str) -> io::Result<()> {
+  for b in text.bytes() {
+    match b {
+      b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'-' | b'.' | b'_' | b'~' => dst.write_all(&[b])?,
+      _ => write!(dst, "%{:02X}", b)?
+    }
+  }
+  Ok( () )
+}
+
+fn write_json_string(dst: &mut dyn Write, text: &str) -> io::Result<()> {
+  write!(dst, "\"")?;
+  let mut start = 0;
+  for (idx, b) in text.bytes().enumerate() {
+    if b != b'"' && b != b'\\' && b >= 0x20 {
+      continue;
+    }
+    dst.write_all(text[start .. idx].as_bytes())?;
+    match b {
+      b'"' => write!(dst, "\\\"")?,
+      b'\\' => write!(dst, "\\\\")?,
+      _ => write!(dst, "\\u{:04x}", b)?
+    }
+    start = idx + 1;
+  }
+  dst.write_all(text[start ..].as_bytes())?;
+  write!(dst, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+  use buffer::Buffer;
+  use io::sources::file::{Directory, DirectoryListing, EntryInfo, EntryKind};
+  use super::{ListingStream, ListingOptions, ListingFormat, SortKey, Renderer};
+  use super::{write_html_escaped, write_url_encoded, write_json_string};
+
+  fn escape(write: fn(&mut dyn Write, &str) -> ::std::io::Result<()>, text: &str) -> String {
+    let mut dst = Vec::new();
+    write(&mut dst, text).unwrap();
+    String::from_utf8(dst).unwrap()
+  }
+
+  #[test]
+  fn test_escaping() {
+    assert_eq!(escape(write_html_escaped, "<a href='x'>&\"</a>"),
+      "&lt;a href=&#39;x&#39;&gt;&amp;&quot;&lt;/a&gt;");
+    assert_eq!(escape(write_url_encoded, "my photo #1?.jpg"), "my%20photo%20%231%3F.jpg");
+    assert_eq!(escape(write_url_encoded, "é"), "%C3%A9");
+    assert_eq!(escape(write_json_string, "a\"b\\c\nd"), "\"a\\\"b\\\\c\\u000ad\"");
+  }
+
+  #[test]
+  fn test_render_json() {
+    let options = ListingOptions { format: ListingFormat::Json, sort: None, descending: false };
+    let mut renderer = Renderer { options, url: "/".to_owned(), entry_count: 0 };
+    let mut dst = Vec::new();
+    renderer.write_start(&mut dst).unwrap();
+    let info = EntryInfo { kind: EntryKind::File, size: 12, mtime: 1000 };
+    renderer.write_entry(&mut dst, "a.jpg", &info).unwrap();
+    let info = EntryInfo { kind: EntryKind::Directory, size: 4096, mtime: 2000 };
+    renderer.write_entry(&mut dst, "b", &info).unwrap();
+    renderer.write_end(&mut dst).unwrap();
+    assert_eq!(String::from_utf8(dst).unwrap(), concat!(
+      "[\n{\"name\":\"a.jpg\",\"type\":\"file\",\"size\":12,\"mtime\":1000},",
+      "\n{\"name\":\"b\",\"type\":\"directory\",\"size\":4096,\"mtime\":2000}\n]\n"));
+  }
+
+  #[test]
+  fn test_sorted_listing() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/")).unwrap();
+    let listing = DirectoryListing::open(&dir.sub_path("aio/").unwrap()).unwrap();
+    let options = ListingOptions { format: ListingFormat::Json, sort: Some(SortKey::Size), descending: true };
+    let mut stream = ListingStream::new(listing, "/aio/", options).unwrap();
+    let mut body = Buffer::new();
+    while stream.write_next_entry(&mut body).unwrap() {}
+    if let super::Source::Sorted { ref entries, .. } = stream.source {
+      assert!(!entries.is_empty());
+      assert!(entries.windows(2).all(|pair| pair[0].1.size >= pair[1].1.size));
+    } else {
+      panic!("listing should be sorted");
+    }
+  }
+}
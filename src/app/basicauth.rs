@@ -1,42 +1,69 @@
 use http;
 use http::headers::Authorization;
+use http::headers::authorization::BasicCredentials;
+use http::session::{Session, SessionRequestHandler};
 use std::io::Write;
 use std::io;
 
+/// Checks the credentials of a Basic auth request,
+/// implemented for closures and `&CredentialStore`.
+pub trait PasswordValidator {
+  fn validate_password(&self, credentials: &BasicCredentials) -> bool;
+}
+
+impl<F> PasswordValidator for F
+where
+  F: Fn(&BasicCredentials) -> bool
+{
+  fn validate_password(&self, credentials: &BasicCredentials) -> bool {
+    self(credentials)
+  }
+}
+
 pub struct BasicAuthHandler<'a, T, V> {
   child_handler: T,
-  validate_password: V,
+  validator: V,
   realm: &'a str
 }
 
 impl<'a, T, V> BasicAuthHandler<'a, T, V>
 where
   T: http::RequestHandler,
-  V: Fn(&http::headers::authorization::BasicCredentials) -> bool
+  V: PasswordValidator
 {
-  pub fn new(child_handler: T, realm: &'a str, validate_password: V) -> BasicAuthHandler<'a, T, V> {
-    BasicAuthHandler { child_handler, validate_password, realm }
+  pub fn new(child_handler: T, realm: &'a str, validator: V) -> BasicAuthHandler<'a, T, V> {
+    BasicAuthHandler { child_handler, validator, realm }
   }
-}
 
-impl<'a, T, V> http::RequestHandler for BasicAuthHandler<'a, T, V>
-where
-  T: http::RequestHandler,
-  V: Fn(&http::headers::authorization::BasicCredentials) -> bool
-{
-  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+  fn validated_user<'r>(&self, request: &'r http::Request<'r>) -> Option<&'r str> {
     if let Some(Authorization::Basic(ref credentials)) = request.headers().authorization {
-      if (self.validate_password)(&credentials) {
-        return self.child_handler.read_headers(request, responder);
+      if self.validator.validate_password(&credentials) {
+        return Some(credentials.user);
       }
     }
-    
+    None
+  }
+
+  fn respond_unauthorized(&self, responder: &http::Responder) -> io::Result<Option<http::Response>> {
     let mut response = responder.respond(http::status::UNAUTHORIZED)?;
     response.set_header_writer("WWW-Authenticate", |ref mut value| {
       write!(value, "Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
     })?;
     Ok(Some(response.into_body()?.finish()))
   }
+}
+
+impl<'a, T, V> http::RequestHandler for BasicAuthHandler<'a, T, V>
+where
+  T: http::RequestHandler,
+  V: PasswordValidator
+{
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    if self.validated_user(request).is_some() {
+      return self.child_handler.read_headers(request, responder);
+    }
+    self.respond_unauthorized(responder)
+  }
 
   fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
     -> std::io::Result<Option<http::Response>>
@@ -45,4 +72,43 @@ where
     // it's safe to assume we are authorized and can just forward read_body
     self.child_handler.read_body(body, responder)
   }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> std::io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
+
+/// Behind a `SessionHandler`, the credentials are only validated once,
+/// after which the session holds the user name.
+impl<'a, T, V> SessionRequestHandler for BasicAuthHandler<'a, T, V>
+where
+  T: http::RequestHandler,
+  V: PasswordValidator
+{
+  fn read_headers(&mut self, request: &http::Request, session: &mut Session, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    if session.data().is_empty() {
+      match self.validated_user(request) {
+        // if the name doesn't fit in the session, validate again next time
+        Some(user) => { let _ = session.set_data(user.as_bytes()); },
+        None => return self.respond_unauthorized(responder)
+      }
+    }
+    self.child_handler.read_headers(request, responder)
+  }
+
+  fn read_body(&mut self, body: &mut [u8], _session: &mut Session, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
 }
@@ -0,0 +1,111 @@
+use http;
+use http::headers::Authorization;
+use http::session::unix_time_now;
+use std::io::Write;
+use std::io;
+use super::Claims;
+
+/// tokens are copied to the stack to be decoded in place
+pub const MAX_TOKEN_LEN : usize = 2048;
+
+/// Checks a bearer token, like a JWT or an access token looked up in a database.
+pub trait TokenVerifier {
+  /// The claims the token makes, if it is valid at `now` (seconds since the unix epoch).
+  /// The token can be decoded in place.
+  fn verify<'t>(&self, token: &'t mut [u8], now: u64) -> Option<Claims<'t>>;
+}
+
+impl<'v, V: TokenVerifier> TokenVerifier for &'v V {
+  fn verify<'t>(&self, token: &'t mut [u8], now: u64) -> Option<Claims<'t>> {
+    (*self).verify(token, now)
+  }
+}
+
+/// Like `RequestHandler`, with the claims of the verified token.
+/// The claims only live as long as the `read_headers` call,
+/// copy what you need for `read_body`.
+pub trait BearerRequestHandler {
+
+  fn read_headers(&mut self, _request: &http::Request, _claims: &Claims, _responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    Ok(None)
+  }
+
+  fn read_body(&mut self, _body: &mut [u8], _responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    Ok(None)
+  }
+
+  fn render_error(&mut self, _status: http::status::Status, _message: &str, _responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    Ok(None)
+  }
+}
+
+/// Bearer token authentication (RFC 6750), as used by APIs
+/// like the Mastodon and Matrix client APIs.
+pub struct BearerAuthHandler<'a, T, V> {
+  child_handler: T,
+  verifier: V,
+  realm: &'a str
+}
+
+impl<'a, T, V> BearerAuthHandler<'a, T, V>
+where
+  T: BearerRequestHandler,
+  V: TokenVerifier
+{
+  pub fn new(child_handler: T, realm: &'a str, verifier: V) -> BearerAuthHandler<'a, T, V> {
+    BearerAuthHandler { child_handler, verifier, realm }
+  }
+
+  fn respond_unauthorized(&self, is_token_invalid: bool, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let mut response = responder.respond(http::status::UNAUTHORIZED)?;
+    response.set_header_writer("WWW-Authenticate", |ref mut value| {
+      write!(value, "Bearer realm=\"{}\"", self.realm)?;
+      if is_token_invalid {
+        write!(value, ", error=\"invalid_token\"")?;
+      }
+      Ok( () )
+    })?;
+    Ok(Some(response.into_body()?.finish()))
+  }
+}
+
+impl<'a, T, V> http::RequestHandler for BearerAuthHandler<'a, T, V>
+where
+  T: BearerRequestHandler,
+  V: TokenVerifier
+{
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let token = match request.headers().authorization {
+      Some(Authorization::Bearer(token)) => token,
+      _ => return self.respond_unauthorized(false, responder)
+    };
+    if token.len() > MAX_TOKEN_LEN {
+      return self.respond_unauthorized(true, responder);
+    }
+    let mut buffer = [0u8; MAX_TOKEN_LEN];
+    let buffer = &mut buffer[.. token.len()];
+    buffer.copy_from_slice(token.as_bytes());
+    match self.verifier.verify(buffer, unix_time_now()) {
+      Some(claims) => self.child_handler.read_headers(request, &claims, responder),
+      None => self.respond_unauthorized(true, responder)
+    }
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
@@ -0,0 +1,72 @@
+use http;
+use http::request_handler::respond_with_error;
+use std::io;
+
+/// Decides which clients are let through, by the common name
+/// of their certificate. Implemented for closures.
+pub trait ClientCertValidator {
+  fn allows(&self, common_name: &str) -> bool;
+}
+
+impl<F> ClientCertValidator for F
+where
+  F: Fn(&str) -> bool
+{
+  fn allows(&self, common_name: &str) -> bool {
+    self(common_name)
+  }
+}
+
+/// Only lets requests through over a connection where the client
+/// authenticated with a certificate issued by one of the CAs passed
+/// to `tls::HandlerFactory::with_client_auth`, and the validator allows it.
+/// Other requests get a `403`.
+pub struct ClientCertAuthHandler<T, V> {
+  child_handler: T,
+  validator: V
+}
+
+impl<T, V> ClientCertAuthHandler<T, V>
+where
+  T: http::RequestHandler,
+  V: ClientCertValidator
+{
+  pub fn new(child_handler: T, validator: V) -> ClientCertAuthHandler<T, V> {
+    ClientCertAuthHandler { child_handler, validator }
+  }
+
+  fn is_allowed(&self, request: &http::Request) -> bool {
+    request.connection()
+      .and_then(|connection| connection.tls.as_ref())
+      .and_then(|tls| tls.client_common_name())
+      .map(|common_name| self.validator.allows(common_name))
+      .unwrap_or(false)
+  }
+}
+
+impl<T, V> http::RequestHandler for ClientCertAuthHandler<T, V>
+where
+  T: http::RequestHandler,
+  V: ClientCertValidator
+{
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    if self.is_allowed(request) {
+      return self.child_handler.read_headers(request, responder);
+    }
+    let message = "A valid client certificate is required";
+    Ok(respond_with_error(&mut self.child_handler, http::status::FORBIDDEN, message, responder))
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    // read_headers already responded if the client isn't allowed
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
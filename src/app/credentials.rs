@@ -0,0 +1,215 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use encoding::base64;
+use http::headers::authorization::BasicCredentials;
+use tls::{Random, mac_eq, pbkdf2_hmac_sha256};
+use super::PasswordValidator;
+
+pub const PBKDF2_ITERATIONS : u32 = 100_000;
+const SCHEME : &'static str = "pbkdf2-sha256";
+const SALT_SIZE : usize = 16;
+const HASH_SIZE : usize = 32;
+
+struct Credential {
+  user: String,
+  iterations: u32,
+  salt: [u8; SALT_SIZE],
+  hash: [u8; HASH_SIZE]
+}
+
+/// Users and their salted password hashes, read from a file
+/// with one user per line, like htpasswd:
+///
+/// `user:pbkdf2-sha256:iterations:base64(salt):base64(hash)`
+///
+/// Lines that are empty or start with `#` are ignored.
+/// `wwwee adduser <user>` adds users to `./conf/passwd`, which the server reads.
+pub struct CredentialStore {
+  credentials: Vec<Credential>
+}
+
+impl CredentialStore {
+  pub fn open(path: &Path) -> io::Result<CredentialStore> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    CredentialStore::parse(&text)
+  }
+
+  pub fn parse(text: &str) -> io::Result<CredentialStore> {
+    let mut credentials = Vec::new();
+    for (line_idx, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let credential = parse_line(line).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData,
+          format!("invalid credentials on line {}", line_idx + 1))
+      })?;
+      credentials.push(credential);
+    }
+    Ok(CredentialStore { credentials })
+  }
+
+  pub fn validate(&self, user: &str, password: &str) -> bool {
+    let credential = self.credentials.iter().find(|c| c.user == user);
+    let mut hash = [0u8; HASH_SIZE];
+    match credential {
+      Some(credential) => {
+        pbkdf2_hmac_sha256(password.as_bytes(), &credential.salt, credential.iterations, &mut hash);
+        mac_eq(&hash, &credential.hash)
+      },
+      None => {
+        // take as long as for an existing user,
+        // so the response time doesn't tell which users exist
+        pbkdf2_hmac_sha256(password.as_bytes(), &[0u8; SALT_SIZE], PBKDF2_ITERATIONS, &mut hash);
+        false
+      }
+    }
+  }
+}
+
+impl<'a> PasswordValidator for &'a CredentialStore {
+  fn validate_password(&self, credentials: &BasicCredentials) -> bool {
+    self.validate(credentials.user, credentials.password)
+  }
+}
+
+/// Adds the user to the credentials file, or changes the password if it already exists.
+/// The file is created if it doesn't exist yet.
+pub fn add_user(path: &Path, user: &str, password: &str) -> io::Result<()> {
+  if !is_valid_user(user) {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+      "user name can't be empty or contain ':', whitespace or control characters"));
+  }
+  let mut text = String::new();
+  match File::open(path) {
+    Ok(mut file) => { file.read_to_string(&mut text)?; },
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+    Err(err) => return Err(err)
+  };
+  // don't rewrite a file we can't parse
+  CredentialStore::parse(&text)?;
+
+  let mut salt = [0u8; SALT_SIZE];
+  Random::from_system()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no system random source"))?
+    .fill(&mut salt);
+  let mut hash = [0u8; HASH_SIZE];
+  pbkdf2_hmac_sha256(password.as_bytes(), &salt, PBKDF2_ITERATIONS, &mut hash);
+
+  let mut lines = Vec::new();
+  for line in text.lines() {
+    if line.trim().splitn(2, ':').next() != Some(user) {
+      writeln!(lines, "{}", line)?;
+    }
+  }
+  write_line(&mut lines, user, PBKDF2_ITERATIONS, &salt, &hash)?;
+
+  let tmp_path = path.with_extension("tmp");
+  create_private(&tmp_path)?.write_all(&lines)?;
+  fs::rename(&tmp_path, path)
+}
+
+// only readable by the owner, the hashes can be attacked offline
+fn create_private(path: &Path) -> io::Result<File> {
+  let file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)?;
+  // the mode only applies when the file is created
+  file.set_permissions(fs::Permissions::from_mode(0o600))?;
+  Ok(file)
+}
+
+fn parse_line(line: &str) -> Option<Credential> {
+  let mut fields = line.split(':');
+  let user = fields.next()?;
+  if !is_valid_user(user) || fields.next()? != SCHEME {
+    return None;
+  }
+  let iterations = fields.next()?.parse::<u32>().ok()?;
+  let mut salt = [0u8; SALT_SIZE];
+  decode_field(fields.next()?, &mut salt)?;
+  let mut hash = [0u8; HASH_SIZE];
+  decode_field(fields.next()?, &mut hash)?;
+  if fields.next().is_some() || iterations == 0 {
+    return None;
+  }
+  Some(Credential { user: user.to_owned(), iterations, salt, hash })
+}
+
+fn decode_field(field: &str, dst: &mut [u8]) -> Option<()> {
+  let mut buffer = [0u8; 64];
+  let buffer = buffer.get_mut(.. field.len())?;
+  buffer.copy_from_slice(field.as_bytes());
+  let decoded = base64::decode(buffer)?;
+  if decoded.len() != dst.len() {
+    return None;
+  }
+  dst.copy_from_slice(decoded);
+  Some( () )
+}
+
+fn write_line(dst: &mut dyn Write, user: &str, iterations: u32, salt: &[u8], hash: &[u8]) -> io::Result<()> {
+  let mut salt_buffer = [0u8; 64];
+  let mut hash_buffer = [0u8; 64];
+  let salt = base64::encode(salt, &mut salt_buffer).unwrap();
+  let hash = base64::encode(hash, &mut hash_buffer).unwrap();
+  writeln!(dst, "{}:{}:{}:{}:{}", user, SCHEME, iterations, salt, hash)
+}
+
+fn is_valid_user(user: &str) -> bool {
+  !user.is_empty() && !user.bytes().any(|b| b == b':' || b <= 0x20 || b == 0x7F)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+  use std::fs;
+  use std::os::unix::fs::PermissionsExt;
+  use std::process;
+  use super::{CredentialStore, write_line, create_private};
+
+  fn store_with(user: &str, password: &str, iterations: u32) -> CredentialStore {
+    let salt = [7u8; 16];
+    let mut hash = [0u8; 32];
+    super::pbkdf2_hmac_sha256(password.as_bytes(), &salt, iterations, &mut hash);
+    let mut text = b"# users\n\n".to_vec();
+    write_line(&mut text, user, iterations, &salt, &hash).unwrap();
+    CredentialStore::parse(&String::from_utf8(text).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_validate() {
+    let store = store_with("foo", "bar", 10);
+    assert!(store.validate("foo", "bar"));
+    assert!(!store.validate("foo", "baz"));
+    assert!(!store.validate("foo", ""));
+    assert!(!store.validate("fo", "bar"));
+  }
+
+  #[test]
+  fn test_create_private() {
+    let path = env::temp_dir().join(format!("wwwee-credentials-{}", process::id()));
+    fs::write(&path, b"").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    create_private(&path).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+
+  #[test]
+  fn test_parse_invalid() {
+    assert!(CredentialStore::parse("foo:bar").is_err());
+    assert!(CredentialStore::parse("foo:plain:1:AAAAAAAAAAAAAAAAAAAAAA==:AAAA").is_err());
+    assert!(CredentialStore::parse("foo:pbkdf2-sha256:x:AAAAAAAAAAAAAAAAAAAAAA==:AAAA").is_err());
+    // hash too short
+    assert!(CredentialStore::parse("foo:pbkdf2-sha256:1:AAAAAAAAAAAAAAAAAAAAAA==:AAAA").is_err());
+  }
+}
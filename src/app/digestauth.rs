@@ -0,0 +1,385 @@
+use http;
+use http::headers::Authorization;
+use http::headers::authorization::DigestCredentials;
+use http::session::unix_time_now;
+use encoding::hex;
+use tls::{Md5, Sha256, Random, HmacKey, Hmac, mac_eq, SHA256_SIZE};
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::io;
+
+// issued time and random bytes
+const NONCE_ID_SIZE : usize = 16;
+const NONCE_MAC_SIZE : usize = 16;
+const NONCE_SIZE : usize = NONCE_ID_SIZE + NONCE_MAC_SIZE;
+
+#[derive(Clone, Copy)]
+struct Nonce {
+  id: [u8; NONCE_ID_SIZE],
+  // seconds since the unix epoch, 0 for a free slot
+  issued: u64,
+  // the highest nonce count (nc) seen for this nonce
+  count: u32
+}
+
+enum AuthState {
+  Valid,
+  Stale,
+  Invalid
+}
+
+/// The nonces handed out in Digest challenges. A nonce is its issued time
+/// and random bytes, signed with a key only the server knows, so handing one out
+/// doesn't take up any room. Each nonce is valid for `max_age` seconds.
+/// Once a client used one with the right password, its nonce count is tracked
+/// in a table of fixed capacity, and has to increase with every request
+/// so requests can't be replayed.
+/// Shared by the handlers of all connections, like a session store.
+pub struct DigestNonces {
+  key: HmacKey,
+  nonces: RefCell<Vec<Nonce>>,
+  // nonces issued up to this time that aren't in the table anymore
+  // were evicted while valid, so their count is unknown
+  evicted_until: Cell<u64>,
+  random: RefCell<Random>,
+  max_age: u64
+}
+
+impl DigestNonces {
+  pub fn new(capacity: usize, max_age: u64) -> io::Result<DigestNonces> {
+    let mut random = Random::from_system()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no system random source"))?;
+    let mut secret = [0u8; SHA256_SIZE];
+    random.fill(&mut secret);
+    let free = Nonce { id: [0u8; NONCE_ID_SIZE], issued: 0, count: 0 };
+    Ok(DigestNonces {
+      key: HmacKey::sha256(&secret),
+      nonces: RefCell::new(vec![free; capacity]),
+      evicted_until: Cell::new(0),
+      random: RefCell::new(random),
+      max_age
+    })
+  }
+
+  fn sign(&self, id: &[u8]) -> [u8; SHA256_SIZE] {
+    let mut hmac = Hmac::new(&self.key);
+    hmac.update(id);
+    hmac.finish()
+  }
+
+  fn issue(&self, now: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    for i in 0 .. 8 {
+      nonce[i] = (now >> ((7 - i) * 8)) as u8;
+    }
+    self.random.borrow_mut().fill(&mut nonce[8 .. NONCE_ID_SIZE]);
+    let mac = self.sign(&nonce[.. NONCE_ID_SIZE]);
+    nonce[NONCE_ID_SIZE ..].copy_from_slice(&mac[.. NONCE_MAC_SIZE]);
+    nonce
+  }
+
+  fn use_nonce(&self, encoded_nonce: &str, count: u32, now: u64) -> AuthState {
+    let mut nonce = [0u8; NONCE_SIZE];
+    let is_decoded = encoded_nonce.len() == NONCE_SIZE * 2 &&
+      hex::decode(encoded_nonce.as_bytes(), &mut nonce).is_some();
+    // not one of ours, maybe from before a restart
+    if !is_decoded || !mac_eq(&self.sign(&nonce[.. NONCE_ID_SIZE])[.. NONCE_MAC_SIZE], &nonce[NONCE_ID_SIZE ..]) {
+      return AuthState::Stale;
+    }
+    let issued = nonce[.. 8].iter().fold(0u64, |n, &b| n << 8 | b as u64);
+    if issued.saturating_add(self.max_age) <= now {
+      return AuthState::Stale;
+    }
+    let mut nonces = self.nonces.borrow_mut();
+    if let Some(known) = nonces.iter_mut().find(|n| n.issued != 0 && n.id[..] == nonce[.. NONCE_ID_SIZE]) {
+      if count <= known.count {
+        return AuthState::Invalid;
+      }
+      known.count = count;
+      return AuthState::Valid;
+    }
+    if issued <= self.evicted_until.get() {
+      return AuthState::Stale;
+    }
+    // first use: take a free or expired slot, or evict the oldest nonce
+    let max_age = self.max_age;
+    let slot = nonces.iter_mut()
+      .min_by_key(|n| (n.issued != 0 && n.issued.saturating_add(max_age) > now, n.issued));
+    if let Some(slot) = slot {
+      if slot.issued != 0 && slot.issued.saturating_add(max_age) > now {
+        self.evicted_until.set(self.evicted_until.get().max(slot.issued));
+      }
+      let mut id = [0u8; NONCE_ID_SIZE];
+      id.copy_from_slice(&nonce[.. NONCE_ID_SIZE]);
+      *slot = Nonce { id, issued, count };
+      AuthState::Valid
+    } else {
+      AuthState::Stale
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+  Md5,
+  Sha256
+}
+
+impl Algorithm {
+  // returns the algorithm and whether it is a -sess variant
+  fn parse(name: Option<&str>) -> Option<(Algorithm, bool)> {
+    match name {
+      None => Some((Algorithm::Md5, false)),
+      Some(name) if name.eq_ignore_ascii_case("MD5") => Some((Algorithm::Md5, false)),
+      Some(name) if name.eq_ignore_ascii_case("MD5-sess") => Some((Algorithm::Md5, true)),
+      Some(name) if name.eq_ignore_ascii_case("SHA-256") => Some((Algorithm::Sha256, false)),
+      Some(name) if name.eq_ignore_ascii_case("SHA-256-sess") => Some((Algorithm::Sha256, true)),
+      _ => None
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match *self {
+      Algorithm::Md5 => "MD5",
+      Algorithm::Sha256 => "SHA-256"
+    }
+  }
+
+  // hashes the parts joined by ':' as lowercase hex
+  fn hex_digest<'a>(&self, parts: &[&[u8]], dst: &'a mut [u8; SHA256_SIZE * 2]) -> &'a str {
+    match *self {
+      Algorithm::Md5 => {
+        let mut md5 = Md5::new();
+        for (i, part) in parts.iter().enumerate() {
+          if i != 0 {
+            md5.update(b":");
+          }
+          md5.update(part);
+        }
+        hex::encode(&md5.finish(), dst).unwrap()
+      },
+      Algorithm::Sha256 => {
+        let mut sha256 = Sha256::new();
+        for (i, part) in parts.iter().enumerate() {
+          if i != 0 {
+            sha256.update(b":");
+          }
+          sha256.update(part);
+        }
+        hex::encode(&sha256.finish(), dst).unwrap()
+      }
+    }
+  }
+}
+
+/// The expected `response` parameter for `qop=auth`, see RFC 7616 section 3.4.1
+fn expected_response<'a>(
+  algorithm: Algorithm,
+  is_session: bool,
+  credentials: &DigestCredentials,
+  cnonce: &str,
+  nc: &str,
+  method: &str,
+  password: &str,
+  dst: &'a mut [u8; SHA256_SIZE * 2]
+) -> &'a str {
+  let mut ha1 = [0u8; SHA256_SIZE * 2];
+  let mut ha1_sess = [0u8; SHA256_SIZE * 2];
+  let mut ha2 = [0u8; SHA256_SIZE * 2];
+  let mut ha1 = algorithm.hex_digest(&[
+    credentials.username.as_bytes(),
+    credentials.realm.as_bytes(),
+    password.as_bytes()], &mut ha1);
+  if is_session {
+    ha1 = algorithm.hex_digest(&[
+      ha1.as_bytes(),
+      credentials.nonce.as_bytes(),
+      cnonce.as_bytes()], &mut ha1_sess);
+  }
+  let ha2 = algorithm.hex_digest(&[method.as_bytes(), credentials.uri.as_bytes()], &mut ha2);
+  algorithm.hex_digest(&[
+    ha1.as_bytes(),
+    credentials.nonce.as_bytes(),
+    nc.as_bytes(),
+    cnonce.as_bytes(),
+    b"auth",
+    ha2.as_bytes()], dst)
+}
+
+/// HTTP Digest authentication (RFC 7616), with SHA-256 and MD5.
+/// Only `qop=auth` is supported. Digest needs the plaintext password
+/// (or a hash only usable for this realm) on the server,
+/// prefer `BasicAuthHandler` with a `CredentialStore` over TLS.
+pub struct DigestAuthHandler<'a, T, P> {
+  child_handler: T,
+  passwords: P,
+  realm: &'a str,
+  nonces: &'a DigestNonces
+}
+
+impl<'a, T, P> DigestAuthHandler<'a, T, P>
+where
+  T: http::RequestHandler,
+  P: Fn(&str) -> Option<&'a str>
+{
+  pub fn new(child_handler: T, realm: &'a str, nonces: &'a DigestNonces, passwords: P) -> DigestAuthHandler<'a, T, P> {
+    DigestAuthHandler { child_handler, passwords, realm, nonces }
+  }
+
+  fn check(&self, request: &http::Request, credentials: &DigestCredentials) -> AuthState {
+    let (algorithm, is_session) = match Algorithm::parse(credentials.algorithm) {
+      Some(algorithm) => algorithm,
+      None => return AuthState::Invalid
+    };
+    let params = (credentials.cnonce, credentials.nc, credentials.qop);
+    let (cnonce, nc, nonce_count) = match params {
+      (Some(cnonce), Some(nc), Some("auth")) => match u32::from_str_radix(nc, 16) {
+        Ok(nonce_count) => (cnonce, nc, nonce_count),
+        Err(_) => return AuthState::Invalid
+      },
+      _ => return AuthState::Invalid
+    };
+    if credentials.userhash || credentials.realm != self.realm || credentials.uri != request.url() {
+      return AuthState::Invalid;
+    }
+    let password = match (self.passwords)(credentials.username) {
+      Some(password) => password,
+      None => return AuthState::Invalid
+    };
+    let mut expected = [0u8; SHA256_SIZE * 2];
+    let expected = expected_response(algorithm, is_session, credentials,
+      cnonce, nc, request.method(), password, &mut expected);
+    if !mac_eq(expected.as_bytes(), credentials.response.as_bytes()) {
+      return AuthState::Invalid;
+    }
+    // only look at the nonce once we know the client has the password
+    self.nonces.use_nonce(credentials.nonce, nonce_count, unix_time_now())
+  }
+
+  fn respond_unauthorized(&self, is_stale: bool, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let nonce = self.nonces.issue(unix_time_now());
+    let mut encoded_nonce = [0u8; NONCE_SIZE * 2];
+    let nonce = hex::encode(&nonce, &mut encoded_nonce).unwrap();
+    let mut response = responder.respond(http::status::UNAUTHORIZED)?;
+    // clients pick the first challenge they support, so prefer SHA-256
+    for algorithm in [Algorithm::Sha256, Algorithm::Md5].iter() {
+      response.set_header_writer("WWW-Authenticate", |ref mut value| {
+        write!(value, "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"",
+          self.realm, algorithm.name(), nonce)?;
+        if is_stale {
+          write!(value, ", stale=true")?;
+        }
+        Ok( () )
+      })?;
+    }
+    Ok(Some(response.into_body()?.finish()))
+  }
+}
+
+impl<'a, T, P> http::RequestHandler for DigestAuthHandler<'a, T, P>
+where
+  T: http::RequestHandler,
+  P: Fn(&str) -> Option<&'a str>
+{
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let state = match request.headers().authorization {
+      Some(Authorization::Digest(ref credentials)) => self.check(request, credentials),
+      _ => AuthState::Invalid
+    };
+    match state {
+      AuthState::Valid => self.child_handler.read_headers(request, responder),
+      AuthState::Stale => self.respond_unauthorized(true, responder),
+      AuthState::Invalid => self.respond_unauthorized(false, responder)
+    }
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    // like BasicAuthHandler, only called when read_headers didn't respond with 401
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Algorithm, DigestNonces, AuthState, expected_response};
+  use http::headers::Authorization;
+
+  #[test]
+  fn test_rfc7616_example() {
+    // example from RFC 7616 section 3.9.1
+    let mut header = b"Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+      uri=\"/dir/index.html\", algorithm=SHA-256, \
+      nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", nc=00000001, \
+      cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+      response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\", \
+      opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"".to_vec();
+    let auth = Authorization::parse(&mut header).unwrap();
+    let credentials = match auth {
+      Authorization::Digest(credentials) => credentials,
+      _ => panic!("not digest")
+    };
+    let (algorithm, is_session) = Algorithm::parse(credentials.algorithm).unwrap();
+    let mut response = [0u8; 64];
+    let response = expected_response(algorithm, is_session, &credentials,
+      credentials.cnonce.unwrap(), credentials.nc.unwrap(), "GET", "Circle of Life", &mut response);
+    assert_eq!(response, credentials.response);
+    let mut response = [0u8; 64];
+    let response = expected_response(Algorithm::Md5, false, &credentials,
+      credentials.cnonce.unwrap(), credentials.nc.unwrap(), "GET", "Circle of Life", &mut response);
+    assert_eq!(response, "8ca523f5e9506fed4657c9700eebdbec");
+  }
+
+  #[test]
+  fn test_nonce_count() {
+    let nonces = DigestNonces::new(2, 60).unwrap();
+    let mut encoded = [0u8; 64];
+    let nonce = ::encoding::hex::encode(&nonces.issue(1000), &mut encoded).unwrap();
+    assert!(match nonces.use_nonce(nonce, 1, 1000) { AuthState::Valid => true, _ => false });
+    assert!(match nonces.use_nonce(nonce, 1, 1000) { AuthState::Invalid => true, _ => false });
+    assert!(match nonces.use_nonce(nonce, 2, 1059) { AuthState::Valid => true, _ => false });
+    assert!(match nonces.use_nonce(nonce, 3, 1060) { AuthState::Stale => true, _ => false });
+  }
+
+  #[test]
+  fn test_issuing_keeps_nonces_in_use() {
+    let nonces = DigestNonces::new(1, 60).unwrap();
+    let mut encoded = [0u8; 64];
+    let nonce = ::encoding::hex::encode(&nonces.issue(1000), &mut encoded).unwrap();
+    assert!(match nonces.use_nonce(nonce, 1, 1000) { AuthState::Valid => true, _ => false });
+    for _ in 0 .. 10 {
+      nonces.issue(1001);
+    }
+    assert!(match nonces.use_nonce(nonce, 2, 1001) { AuthState::Valid => true, _ => false });
+  }
+
+  #[test]
+  fn test_forged_nonce() {
+    let nonces = DigestNonces::new(1, 60).unwrap();
+    let mut nonce = nonces.issue(1000);
+    nonce[0] ^= 1;
+    let mut encoded = [0u8; 64];
+    let nonce = ::encoding::hex::encode(&nonce, &mut encoded).unwrap();
+    assert!(match nonces.use_nonce(nonce, 1, 1000) { AuthState::Stale => true, _ => false });
+  }
+
+  #[test]
+  fn test_evicted_nonce_is_stale() {
+    let nonces = DigestNonces::new(1, 60).unwrap();
+    let mut first = [0u8; 64];
+    let first = ::encoding::hex::encode(&nonces.issue(1000), &mut first).unwrap();
+    let mut second = [0u8; 64];
+    let second = ::encoding::hex::encode(&nonces.issue(1001), &mut second).unwrap();
+    assert!(match nonces.use_nonce(first, 1, 1001) { AuthState::Valid => true, _ => false });
+    assert!(match nonces.use_nonce(second, 1, 1001) { AuthState::Valid => true, _ => false });
+    // its count is gone, so it could be replayed
+    assert!(match nonces.use_nonce(first, 2, 1001) { AuthState::Stale => true, _ => false });
+  }
+}
@@ -2,19 +2,64 @@ use std;
 use std::io::Write;
 use http;
 use io::sources::file;
+use super::FileCache;
+use super::{ListingOptions, ListingStream};
 pub struct StaticDirectoryHandler<'a> {
   root_dir: &'a file::Directory,
-  index_file: &'a str
+  index_file: &'a str,
+  cache: Option<&'a FileCache>,
+  autoindex: bool
 }
 
 impl<'a> StaticDirectoryHandler<'a> {
   pub fn new(root_dir: &'a file::Directory, index_file: &'a str) -> StaticDirectoryHandler<'a> {
     StaticDirectoryHandler {
       root_dir,
-      index_file
+      index_file,
+      cache: None,
+      autoindex: false
     }
   }
 
+  /// Serves files that fit in the cache from memory.
+  pub fn with_cache(mut self, cache: &'a FileCache) -> StaticDirectoryHandler<'a> {
+    self.cache = Some(cache);
+    self
+  }
+
+  /// Lists the entries of directories without an index file,
+  /// as HTML or as JSON with `?format=json`, see `ListingOptions`.
+  pub fn with_autoindex(mut self) -> StaticDirectoryHandler<'a> {
+    self.autoindex = true;
+    self
+  }
+
+  fn respond_with_listing(&self, request: &http::Request, res: &http::Responder) -> std::io::Result<Option<http::Response>> {
+    let options = match ListingOptions::from_query_params(request.query_params()) {
+      Some(options) => options,
+      None => {
+        let mut response = res.respond(http::status::BAD_REQUEST)?;
+        response.set_header("Content-Type", "text/plain")?;
+        let mut body = response.into_body()?;
+        write!(body, "unknown format, sort or order for directory listing")?;
+        return Ok(Some(body.finish()));
+      }
+    };
+    let relative_url = &request.url()[1..];
+    let listing = if relative_url.is_empty() {
+      file::DirectoryListing::open(self.root_dir)?
+    } else {
+      file::DirectoryListing::open(&self.root_dir.sub_path(relative_url)?)?
+    };
+    let mut response = res.respond(http::status::OK)?;
+    response.set_header("Content-Type", options.content_type())?;
+    if request.method() == "HEAD" {
+      return Ok(Some(response.into_body()?.finish()));
+    }
+    let stream = ListingStream::new(listing, request.url(), options)?;
+    response.finish_with_stream(Box::new(stream))
+  }
+
   fn file_path(&self, request_url: &'a str) -> std::io::Result<file::RelativePath<'a, 'a>> {
     let relative_url = request_url.get(1..).and_then(|relative_url| {
       if relative_url.is_empty() {
@@ -38,9 +83,24 @@ impl<'a> StaticDirectoryHandler<'a> {
 
 impl<'a> http::RequestHandler for StaticDirectoryHandler <'a> {
   fn read_headers(&mut self, request: &http::Request, res: &http::Responder) -> std::io::Result<Option<http::Response>> {
+    if request.method() != "GET" && request.method() != "HEAD" {
+      let mut response = res.respond(http::status::METHOD_NOT_ALLOWED)?;
+      response.set_header("Allow", "GET, HEAD")?;
+      response.set_header("Content-Type", "text/plain")?;
+      let mut body = response.into_body()?;
+      write!(body, "{}", http::status::METHOD_NOT_ALLOWED.1)?;
+      return Ok(Some(body.finish()));
+    }
     // TODO: use content_range header
     let path = self.file_path(request.url())?;
-    let (reader, content_hash_fields) = file::Reader::open(&path, None)?;
+    let (reader, content_hash_fields) = match file::Reader::open(&path, None) {
+      Ok(opened) => opened,
+      Err(ref err) if err.kind() == std::io::ErrorKind::NotFound &&
+        self.autoindex && request.url().ends_with("/") => {
+        return self.respond_with_listing(request, res);
+      },
+      Err(err) => return Err(err)
+    };
     let etag = FileETag::from_content_hash_fields(&content_hash_fields)?;
 
     if let Some(http::headers::ETagMatch::ETag(etag_to_match)) = request.headers().if_none_match {
@@ -56,7 +116,17 @@ impl<'a> http::RequestHandler for StaticDirectoryHandler <'a> {
     response.set_header("Content-Type", mime_type)?;
     response.set_header_usize("Content-Length", reader.request_size()?)?;
     response.set_header_writer("ETag", |value| write!(value, "\"{}\"", etag.as_str()))?;
-    response.finish_with_file(reader)
+    if request.method() == "HEAD" {
+      return Ok(Some(response.into_body()?.finish()));
+    }
+    match self.cache {
+      Some(cache) if cache.fits(&content_hash_fields) => {
+        let mut body = response.into_body()?;
+        cache.write_file(&content_hash_fields, reader, &mut body)?;
+        Ok(Some(body.finish()))
+      },
+      _ => response.finish_with_file(reader)
+    }
   }
 }
 
@@ -89,6 +159,35 @@ impl FileETag {
     }
   }
 }
+#[cfg(test)]
+mod tests {
+  use http::{self, RequestHandler};
+  use io::sources::file::Directory;
+  use app::FileCache;
+  use super::StaticDirectoryHandler;
+
+  fn head(handler: &mut StaticDirectoryHandler) -> Vec<u8> {
+    let mut bytes = b"HEAD /small.txt HTTP/1.1".to_vec();
+    let request = http::Request::parse(&mut bytes).unwrap();
+    let response = handler.read_headers(&request, &http::Responder::new()).unwrap().unwrap();
+    let (_, headers, _) = response.into_parts();
+    headers.as_slice().to_vec()
+  }
+
+  #[test]
+  fn test_head_without_body() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let cache = FileCache::new(4, 1024);
+    let mut handler = StaticDirectoryHandler::new(&dir, "index.html");
+    let response = head(&mut handler);
+    assert!(response.ends_with(b"\r\n\r\n"));
+    // small files are written into the response from the cache
+    let mut handler = StaticDirectoryHandler::new(&dir, "index.html").with_cache(&cache);
+    let response = head(&mut handler);
+    assert!(response.ends_with(b"\r\n\r\n"));
+    assert!(String::from_utf8(response).unwrap().contains("Content-Length:31"));
+  }
+}
 /*
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,224 @@
+use libc;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::os::unix::io::{RawFd, AsRawFd};
+use io::sources::file;
+
+// a version of a file, a new mtime or size means new contents
+#[derive(Clone, Copy, PartialEq)]
+struct CacheKey {
+  dev: u64,
+  inode: usize,
+  size: usize,
+  mtime: i64,
+  mtime_nsec: i64
+}
+
+impl CacheKey {
+  fn from_content_hash_fields(fields: &file::ContentHashFields) -> CacheKey {
+    CacheKey {
+      dev: fields.dev,
+      inode: fields.inode,
+      size: fields.size,
+      mtime: fields.mtime,
+      mtime_nsec: fields.mtime_nsec
+    }
+  }
+
+  fn is_same_file(&self, other: &CacheKey) -> bool {
+    self.dev == other.dev && self.inode == other.inode
+  }
+}
+
+struct Entry {
+  key: Option<CacheKey>,
+  last_used: u64,
+  contents: Vec<u8>
+}
+
+struct Entries {
+  entries: Vec<Entry>,
+  // counts lookups, to find the least recently used entry
+  clock: u64
+}
+
+/// Keeps the contents of small files in memory, in a table of fixed capacity,
+/// so they are served straight from the response buffer instead of being read
+/// asynchronously. Files are identified by device, inode and mtime, so a file that
+/// was modified takes over the entry of its previous version. Once the table is full,
+/// the least recently used file makes room.
+pub struct FileCache {
+  entries: RefCell<Entries>,
+  max_file_size: usize
+}
+
+impl FileCache {
+  /// allocates room for `capacity` files of up to `max_file_size` bytes up front
+  pub fn new(capacity: usize, max_file_size: usize) -> FileCache {
+    let entries = (0 .. capacity).map(|_| Entry {
+      key: None,
+      last_used: 0,
+      contents: Vec::with_capacity(max_file_size)
+    }).collect();
+    FileCache {
+      entries: RefCell::new(Entries { entries, clock: 0 }),
+      max_file_size
+    }
+  }
+
+  /// whether the file is small enough to be cached
+  pub fn fits(&self, fields: &file::ContentHashFields) -> bool {
+    fields.size <= self.max_file_size
+  }
+
+  /// Writes the whole file to `dst`, from the cache if it's there. Otherwise it's
+  /// read right away and kept in the cache. Only for files that fit.
+  pub fn write_file(&self, fields: &file::ContentHashFields, reader: file::Reader, dst: &mut dyn Write) -> io::Result<()> {
+    let key = CacheKey::from_content_hash_fields(fields);
+    let mut entries = self.entries.borrow_mut();
+    entries.clock += 1;
+    let now = entries.clock;
+    let idx = match Self::find(&entries.entries, &key) {
+      Some(idx) => idx,
+      None => {
+        if key.size > self.max_file_size {
+          return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is too big to be cached"));
+        }
+        let idx = Self::find_room(&entries.entries, &key)
+          .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "file cache has no entries"))?;
+        let entry = &mut entries.entries[idx];
+        entry.key = None;
+        let (fd, _) = reader.into_buffered_file()?;
+        read_file(fd.as_raw_fd(), key.size, &mut entry.contents)?;
+        entry.key = Some(key);
+        idx
+      }
+    };
+    let entry = &mut entries.entries[idx];
+    entry.last_used = now;
+    dst.write_all(&entry.contents)
+  }
+
+  fn find(entries: &[Entry], key: &CacheKey) -> Option<usize> {
+    entries.iter().position(|e| e.key == Some(*key))
+  }
+
+  // the previous version of the file, a free entry,
+  // or the least recently used one, in that order
+  fn find_room(entries: &[Entry], key: &CacheKey) -> Option<usize> {
+    entries.iter().position(|e| e.key.map(|k| k.is_same_file(key)).unwrap_or(false))
+      .or_else(|| {
+        entries.iter().enumerate()
+          .min_by_key(|&(_, e)| (e.key.is_some(), e.last_used))
+          .map(|(idx, _)| idx)
+      })
+  }
+}
+
+// the file should be `size` bytes long, or it changed while reading it
+fn read_file(fd: RawFd, size: usize, contents: &mut Vec<u8>) -> io::Result<()> {
+  contents.clear();
+  contents.resize(size, 0);
+  let mut offset = 0;
+  while offset < size {
+    let read = unsafe {
+      libc::pread64(
+        fd,
+        contents[offset ..].as_mut_ptr() as *mut libc::c_void,
+        size - offset,
+        offset as libc::off64_t)
+    };
+    match read {
+      -1 => {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+          return Err(err);
+        }
+      },
+      0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file got shorter while reading it")),
+      read => offset += read as usize
+    }
+  }
+  Ok( () )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::FileCache;
+  use io::sources::file::{Reader, Directory, ContentHashFields};
+
+  const SMALL_MSG : &'static [u8] =
+    include_bytes!("../../test_fixtures/aio/small.txt");
+
+  fn open_small() -> (Reader, ContentHashFields) {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let path = dir.sub_path("small.txt").unwrap();
+    Reader::open(&path, None).unwrap()
+  }
+
+  fn with_inode(fields: &ContentHashFields, inode: usize, mtime: i64) -> ContentHashFields {
+    ContentHashFields {
+      dev: fields.dev,
+      inode,
+      size: fields.size,
+      mtime,
+      mtime_nsec: 0
+    }
+  }
+
+  fn is_cached(cache: &FileCache, fields: &ContentHashFields) -> bool {
+    let key = super::CacheKey::from_content_hash_fields(fields);
+    FileCache::find(&cache.entries.borrow().entries, &key).is_some()
+  }
+
+  #[test]
+  fn test_write_file_from_cache() {
+    let cache = FileCache::new(2, 1024);
+    let (reader, fields) = open_small();
+    assert!(cache.fits(&fields));
+    let mut body = Vec::new();
+    cache.write_file(&fields, reader, &mut body).unwrap();
+    assert_eq!(body.as_slice(), SMALL_MSG);
+    assert!(is_cached(&cache, &fields));
+
+    let (reader, fields) = open_small();
+    let mut body = Vec::new();
+    cache.write_file(&fields, reader, &mut body).unwrap();
+    assert_eq!(body.as_slice(), SMALL_MSG);
+  }
+
+  #[test]
+  fn test_modified_file_replaces_previous_version() {
+    let cache = FileCache::new(2, 1024);
+    let (reader, fields) = open_small();
+    let v1 = with_inode(&fields, 1, 100);
+    cache.write_file(&v1, reader, &mut Vec::new()).unwrap();
+    let (reader, _) = open_small();
+    let other = with_inode(&fields, 2, 100);
+    cache.write_file(&other, reader, &mut Vec::new()).unwrap();
+    let (reader, _) = open_small();
+    let v2 = with_inode(&fields, 1, 200);
+    cache.write_file(&v2, reader, &mut Vec::new()).unwrap();
+
+    assert!(!is_cached(&cache, &v1));
+    assert!(is_cached(&cache, &v2));
+    assert!(is_cached(&cache, &other));
+  }
+
+  #[test]
+  fn test_evicts_least_recently_used() {
+    let cache = FileCache::new(2, 1024);
+    let (_, fields) = open_small();
+    let a = with_inode(&fields, 1, 100);
+    let b = with_inode(&fields, 2, 100);
+    let c = with_inode(&fields, 3, 100);
+    cache.write_file(&a, open_small().0, &mut Vec::new()).unwrap();
+    cache.write_file(&b, open_small().0, &mut Vec::new()).unwrap();
+    cache.write_file(&a, open_small().0, &mut Vec::new()).unwrap();
+    cache.write_file(&c, open_small().0, &mut Vec::new()).unwrap();
+
+    assert!(is_cached(&cache, &a));
+    assert!(!is_cached(&cache, &b));
+    assert!(is_cached(&cache, &c));
+  }
+}
@@ -0,0 +1,361 @@
+use std::str;
+use encoding::base64;
+use tls::{HmacKey, Hmac, Sha256, mac_eq, verify_p256_raw};
+use super::TokenVerifier;
+
+/// The claims of a verified token, as a JSON object.
+///
+/// Values are looked up without building a tree: `get` returns
+/// the JSON text of a top level member. Strings with escape sequences
+/// aren't unescaped, `str` returns `None` for them.
+pub struct Claims<'a> {
+  json: &'a str
+}
+
+impl<'a> Claims<'a> {
+  pub fn from_json(json: &'a str) -> Option<Claims<'a>> {
+    let bytes = json.as_bytes();
+    let end = skip_whitespace(bytes, skip_value(bytes, skip_whitespace(bytes, 0))?);
+    if bytes.first() != Some(&b'{') || end != bytes.len() {
+      return None;
+    }
+    Some(Claims { json })
+  }
+
+  /// The JSON text of the member with the given name
+  pub fn get(&self, name: &str) -> Option<&'a str> {
+    let bytes = self.json.as_bytes();
+    let mut idx = skip_whitespace(bytes, 0) + 1;
+    loop {
+      idx = skip_whitespace(bytes, idx);
+      if bytes[idx] == b'}' {
+        return None;
+      }
+      let key_end = skip_value(bytes, idx)?;
+      let key = &self.json[idx + 1 .. key_end - 1];
+      let value_start = skip_whitespace(bytes, skip_whitespace(bytes, key_end) + 1);
+      let value_end = skip_value(bytes, value_start)?;
+      if key == name {
+        return Some(&self.json[value_start .. value_end]);
+      }
+      idx = skip_whitespace(bytes, value_end);
+      if bytes[idx] == b',' {
+        idx += 1;
+      }
+    }
+  }
+
+  pub fn str(&self, name: &str) -> Option<&'a str> {
+    self.get(name).and_then(as_str)
+  }
+
+  pub fn integer(&self, name: &str) -> Option<i64> {
+    self.get(name).and_then(|value| {
+      value.parse::<i64>().ok().or_else(|| value.parse::<f64>().ok().map(|f| f as i64))
+    })
+  }
+
+  /// Whether `aud` is the given audience, or an array containing it.
+  pub fn has_audience(&self, audience: &str) -> bool {
+    let value = match self.get("aud") {
+      Some(value) => value,
+      None => return false
+    };
+    let bytes = value.as_bytes();
+    if bytes[0] != b'[' {
+      return as_str(value) == Some(audience);
+    }
+    let mut idx = 1;
+    loop {
+      idx = skip_whitespace(bytes, idx);
+      if idx >= bytes.len() || bytes[idx] == b']' {
+        return false;
+      }
+      let end = match skip_value(bytes, idx) {
+        Some(end) => end,
+        None => return false
+      };
+      if as_str(&value[idx .. end]) == Some(audience) {
+        return true;
+      }
+      idx = skip_whitespace(bytes, end) + 1;
+    }
+  }
+}
+
+fn as_str(value: &str) -> Option<&str> {
+  if value.len() >= 2 && value.starts_with('"') && !value.contains('\\') {
+    Some(&value[1 .. value.len() - 1])
+  } else {
+    None
+  }
+}
+
+fn skip_whitespace(bytes: &[u8], mut idx: usize) -> usize {
+  while idx < bytes.len() && (bytes[idx] == b' ' || bytes[idx] == b'\t' || bytes[idx] == b'\r' || bytes[idx] == b'\n') {
+    idx += 1;
+  }
+  idx
+}
+
+// returns the index after the JSON value starting at idx,
+// only checking the structure as far as needed to find the end.
+fn skip_value(bytes: &[u8], idx: usize) -> Option<usize> {
+  match *bytes.get(idx)? {
+    b'"' => {
+      let mut idx = idx + 1;
+      loop {
+        match *bytes.get(idx)? {
+          b'"' => return Some(idx + 1),
+          b'\\' => idx += 2,
+          _ => idx += 1
+        }
+      }
+    },
+    open @ b'{' | open @ b'[' => {
+      let close = if open == b'{' { b'}' } else { b']' };
+      let mut idx = skip_whitespace(bytes, idx + 1);
+      if *bytes.get(idx)? == close {
+        return Some(idx + 1);
+      }
+      loop {
+        if open == b'{' {
+          if *bytes.get(idx)? != b'"' {
+            return None;
+          }
+          idx = skip_whitespace(bytes, skip_value(bytes, idx)?);
+          if *bytes.get(idx)? != b':' {
+            return None;
+          }
+          idx = skip_whitespace(bytes, idx + 1);
+        }
+        idx = skip_whitespace(bytes, skip_value(bytes, idx)?);
+        match *bytes.get(idx)? {
+          b',' => idx = skip_whitespace(bytes, idx + 1),
+          b if b == close => return Some(idx + 1),
+          _ => return None
+        }
+      }
+    },
+    _ => {
+      // number, true, false or null
+      let len = bytes[idx ..].iter()
+        .position(|&b| !(b.is_ascii_alphanumeric() || b == b'-' || b == b'+' || b == b'.'))
+        .unwrap_or(bytes.len() - idx);
+      if len == 0 { None } else { Some(idx + len) }
+    }
+  }
+}
+
+enum JwtKey {
+  Hs256(HmacKey),
+  // uncompressed P-256 curve point
+  Es256([u8; 65])
+}
+
+/// Verifies JSON Web Tokens (RFC 7519) signed with HS256 or ES256,
+/// and checks the `exp`, `nbf` and optionally the `aud` claims.
+pub struct JwtVerifier<'a> {
+  key: JwtKey,
+  audience: Option<&'a str>,
+  // seconds of clock difference tolerated for exp and nbf
+  leeway: u64
+}
+
+impl<'a> JwtVerifier<'a> {
+  pub fn hs256(secret: &[u8]) -> JwtVerifier<'a> {
+    JwtVerifier {
+      key: JwtKey::Hs256(HmacKey::sha256(secret)),
+      audience: None,
+      leeway: 0
+    }
+  }
+
+  /// `public_key` is the P-256 public key as uncompressed curve point (`0x04 || x || y`).
+  pub fn es256(public_key: &[u8]) -> Option<JwtVerifier<'a>> {
+    if public_key.len() != 65 || public_key[0] != 0x04 {
+      return None;
+    }
+    let mut point = [0u8; 65];
+    point.copy_from_slice(public_key);
+    Some(JwtVerifier {
+      key: JwtKey::Es256(point),
+      audience: None,
+      leeway: 0
+    })
+  }
+
+  pub fn audience(mut self, audience: &'a str) -> JwtVerifier<'a> {
+    self.audience = Some(audience);
+    self
+  }
+
+  pub fn leeway(mut self, seconds: u64) -> JwtVerifier<'a> {
+    self.leeway = seconds;
+    self
+  }
+
+  fn algorithm(&self) -> &'static str {
+    match self.key {
+      JwtKey::Hs256(_) => "HS256",
+      JwtKey::Es256(_) => "ES256"
+    }
+  }
+
+  fn is_signature_valid(&self, signing_input: &[u8], signature: &[u8]) -> bool {
+    match self.key {
+      JwtKey::Hs256(ref key) => {
+        let mut hmac = Hmac::new(key);
+        hmac.update(signing_input);
+        mac_eq(&hmac.finish(), signature)
+      },
+      JwtKey::Es256(ref public_key) => {
+        let mut sha256 = Sha256::new();
+        sha256.update(signing_input);
+        signature.len() == 64 && verify_p256_raw(public_key, &sha256.finish(), signature)
+      }
+    }
+  }
+
+  fn are_claims_valid(&self, claims: &Claims, now: u64) -> bool {
+    let now = now.min(i64::max_value() as u64) as i64;
+    let leeway = self.leeway.min(i64::max_value() as u64) as i64;
+    // the claims come from the client, so they can be anywhere near the limits
+    if claims.get("exp").is_some() {
+      match claims.integer("exp") {
+        Some(expires) if now < expires.saturating_add(leeway) => {},
+        _ => return false
+      }
+    }
+    if claims.get("nbf").is_some() {
+      match claims.integer("nbf") {
+        Some(not_before) if now >= not_before.saturating_sub(leeway) => {},
+        _ => return false
+      }
+    }
+    self.audience.map(|audience| claims.has_audience(audience)).unwrap_or(true)
+  }
+}
+
+impl<'a> TokenVerifier for JwtVerifier<'a> {
+  fn verify<'t>(&self, token: &'t mut [u8], now: u64) -> Option<Claims<'t>> {
+    let header_end = token.iter().position(|&b| b == b'.')?;
+    let payload_end = header_end + 1 + token[header_end + 1 ..].iter().position(|&b| b == b'.')?;
+    let (signing_input, signature) = token.split_at_mut(payload_end);
+    // check the signature first, decoding the header and payload in place overwrites them
+    let signature = base64::decode_url(&mut signature[1 ..])?;
+    if !self.is_signature_valid(signing_input, signature) {
+      return None;
+    }
+    let (header, payload) = signing_input.split_at_mut(header_end);
+    let header = str::from_utf8(base64::decode_url(header)?).ok()?;
+    let header = Claims::from_json(header)?;
+    // the algorithm is fixed by the key, never by the token
+    if header.str("alg") != Some(self.algorithm()) || header.get("crit").is_some() {
+      return None;
+    }
+    let payload = base64::decode_url(&mut payload[1 ..])?;
+    let claims = Claims::from_json(str::from_utf8(payload).ok()?)?;
+    if !self.are_claims_valid(&claims, now) {
+      return None;
+    }
+    Some(claims)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use encoding::base64;
+  use super::{Claims, JwtVerifier};
+  use super::super::TokenVerifier;
+
+  // the examples from RFC 7515 appendix A.1 and A.3, which expire at 1300819380
+  const HS256_TOKEN : &'static [u8] = b"eyJ0eXAiOiJKV1QiLA0KICJhbGciOiJIUzI1NiJ9.\
+    eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ.\
+    dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+  const HS256_KEY : &'static [u8] = b"AyM1SysPpbyDfgZld3umj1qzKObwVMkoqQ-EstJQLr_T-1qS0gZH75aKtMN3Yj0iPS4hcgUuTwjAzZr1Z9CAow";
+  const ES256_TOKEN : &'static [u8] = b"eyJhbGciOiJFUzI1NiJ9.\
+    eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ.\
+    DtEhU3ljbEg8L38VWAfUAqOyKAM6-Xx-F4GawxaepmXFCgfTjDxw5djxLa8ISlSApmWQxfKTUJqPP3-Kg6NU1Q";
+  const ES256_X : &'static [u8] = b"f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU";
+  const ES256_Y : &'static [u8] = b"x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0";
+
+  fn hs256_verifier() -> JwtVerifier<'static> {
+    let mut key = HS256_KEY.to_vec();
+    JwtVerifier::hs256(base64::decode_url(&mut key).unwrap())
+  }
+
+  #[test]
+  fn test_hs256() {
+    let verifier = hs256_verifier();
+    let mut token = HS256_TOKEN.to_vec();
+    let claims = verifier.verify(&mut token, 1300819379).unwrap();
+    assert_eq!(claims.str("iss"), Some("joe"));
+    assert_eq!(claims.integer("exp"), Some(1300819380));
+    assert_eq!(claims.get("http://example.com/is_root"), Some("true"));
+    let mut token = HS256_TOKEN.to_vec();
+    assert!(verifier.verify(&mut token, 1300819380).is_none());
+  }
+
+  #[test]
+  fn test_hs256_tampered() {
+    let verifier = hs256_verifier();
+    let mut token = HS256_TOKEN.to_vec();
+    let last = token.len() - 1;
+    token[last] = b'Y';
+    assert!(verifier.verify(&mut token, 0).is_none());
+    let mut token = HS256_TOKEN.to_vec();
+    assert!(JwtVerifier::hs256(b"other key").verify(&mut token, 0).is_none());
+  }
+
+  #[test]
+  fn test_es256() {
+    let mut point = vec![0x04];
+    point.extend_from_slice(base64::decode_url(&mut ES256_X.to_vec()).unwrap());
+    point.extend_from_slice(base64::decode_url(&mut ES256_Y.to_vec()).unwrap());
+    let verifier = JwtVerifier::es256(&point).unwrap();
+    let mut token = ES256_TOKEN.to_vec();
+    let claims = verifier.verify(&mut token, 0).unwrap();
+    assert_eq!(claims.str("iss"), Some("joe"));
+    // a HS256 verifier doesn't accept an ES256 token, even with a valid signature
+    let mut token = ES256_TOKEN.to_vec();
+    assert!(hs256_verifier().verify(&mut token, 0).is_none());
+  }
+
+  #[test]
+  fn test_claims() {
+    let json = r#"{ "aud": ["a", "b"], "nbf": 10, "sub": "x\"y", "n": {"m": [1, {}]}, "e": 1.5e3 }"#;
+    let claims = Claims::from_json(json).unwrap();
+    assert!(claims.has_audience("b"));
+    assert!(!claims.has_audience("c"));
+    assert_eq!(claims.integer("nbf"), Some(10));
+    assert_eq!(claims.integer("e"), Some(1500));
+    assert_eq!(claims.str("sub"), None);
+    assert_eq!(claims.get("n"), Some(r#"{"m": [1, {}]}"#));
+    assert_eq!(claims.get("missing"), None);
+    assert!(Claims::from_json(r#"{"a": 1"#).is_none());
+    assert!(Claims::from_json(r#"[1]"#).is_none());
+    let claims = Claims::from_json(r#"{"aud": "a"}"#).unwrap();
+    assert!(claims.has_audience("a"));
+  }
+
+  #[test]
+  fn test_nbf_and_audience() {
+    let verifier = hs256_verifier().audience("wwwee").leeway(5);
+    let claims = Claims::from_json(r#"{"nbf": 100, "exp": 200, "aud": "wwwee"}"#).unwrap();
+    assert!(verifier.are_claims_valid(&claims, 95));
+    assert!(!verifier.are_claims_valid(&claims, 94));
+    assert!(verifier.are_claims_valid(&claims, 204));
+    assert!(!verifier.are_claims_valid(&claims, 205));
+    let claims = Claims::from_json(r#"{"aud": "other"}"#).unwrap();
+    assert!(!verifier.are_claims_valid(&claims, 0));  }
+
+  #[test]
+  fn test_claims_near_limits() {
+    let verifier = hs256_verifier().leeway(5);
+    let claims = Claims::from_json(r#"{"exp": 9223372036854775807}"#).unwrap();
+    assert!(verifier.are_claims_valid(&claims, 1000));
+    let claims = Claims::from_json(r#"{"nbf": -9223372036854775808}"#).unwrap();
+    assert!(verifier.are_claims_valid(&claims, 1000));
+  }
+}
@@ -19,8 +19,14 @@ impl<H> http::RequestHandler for Logger<H>
 {
   fn read_headers(&mut self, request: &http::Request, res: &http::Responder) -> std::io::Result<Option<http::Response>> {
     let (day, mon, year, hour, min, sec) = get_date_components();
-    print!("{}/{}/{} {}:{}:{}: {} {} HTTP/{} with host {:?} => ",
-      day, mon, year, hour, min, sec,
+    print!("{}/{}/{} {}:{}:{}: ", day, mon, year, hour, min, sec);
+    if let Some(connection) = request.connection() {
+      print!("{} ", connection.peer_addr);
+      if let Some(tls) = connection.tls {
+        print!("({} {:#06x}) ", tls.version_name(), tls.cipher_suite);
+      }
+    }
+    print!("{} {} HTTP/{} with host {:?} => ",
       request.method(),
       request.url(),
       request.version(),
@@ -35,6 +41,15 @@ impl<H> http::RequestHandler for Logger<H>
     }
     response
   }
+
+  fn read_body(&mut self, body: &mut [u8], res: &http::Responder) -> std::io::Result<Option<http::Response>> {
+    self.handler.read_body(body, res)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, res: &http::Responder) -> std::io::Result<Option<http::Response>> {
+    println!("{}: {}", status.0, message);
+    self.handler.render_error(status, message, res)
+  }
 }
 
 fn get_date_components() -> (c_int, c_int, c_int, c_int, c_int, c_int) {
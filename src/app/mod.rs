@@ -1,12 +1,28 @@
 mod helloworld;
 mod file;
 mod dir;
+mod autoindex;
+mod filecache;
 mod router;
 mod logger;
 mod basicauth;
+mod session;
+mod credentials;
+mod digestauth;
+mod bearerauth;
+mod jwt;
+mod ratelimit;
+mod clientcertauth;
 pub use self::helloworld::*;
 pub use self::file::*;
 pub use self::dir::*;
+pub use self::autoindex::*;
+pub use self::filecache::*;
 pub use self::router::*;
 pub use self::logger::*;
 pub use self::basicauth::*;
+pub use self::session::*;
+pub use self::credentials::*;
+pub use self::bearerauth::*;
+pub use self::jwt::*;
+pub use self::ratelimit::*;
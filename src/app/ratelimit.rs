@@ -0,0 +1,360 @@
+use http;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+type BucketKey = [u8; 16];
+
+#[derive(Clone, Copy)]
+struct Bucket {
+  key: Option<BucketKey>,
+  tokens: u32,
+  refilled: Instant
+}
+
+/// Token buckets per client address, kept in a table of fixed capacity.
+///
+/// Every client starts with `burst` tokens, and gets one back every `interval`.
+/// IPv6 clients are grouped per /64, as that is what a single host usually gets.
+/// Once the table is full, a bucket that has refilled completely
+/// makes room for the new client, as forgetting it changes nothing.
+/// If there is none, new clients have to wait until there is.
+pub struct RateLimiter {
+  buckets: RefCell<Vec<Bucket>>,
+  burst: u32,
+  interval: Duration
+}
+
+impl RateLimiter {
+  pub fn new(capacity: usize, burst: u32, interval: Duration) -> RateLimiter {
+    let empty = Bucket { key: None, tokens: 0, refilled: Instant::now() };
+    RateLimiter {
+      buckets: RefCell::new(vec![empty; capacity]),
+      burst,
+      interval
+    }
+  }
+
+  /// Takes a token from the bucket of the address,
+  /// or returns how long to wait for the next one if it is empty.
+  pub fn take(&self, addr: IpAddr, now: Instant) -> Result<(), Duration> {
+    let key = bucket_key(addr);
+    let mut buckets = self.buckets.borrow_mut();
+    let idx = match buckets.iter().position(|b| b.key == Some(key)) {
+      Some(idx) => idx,
+      None => {
+        let idx = self.free_bucket(&mut buckets, now)?;
+        buckets[idx] = Bucket { key: Some(key), tokens: self.burst, refilled: now };
+        idx
+      }
+    };
+    let bucket = &mut buckets[idx];
+    self.refill(bucket, now);
+    if bucket.tokens == 0 {
+      return Err(self.wait_time(bucket, now));
+    }
+    bucket.tokens -= 1;
+    Ok( () )
+  }
+
+  /// Like `take`, but without taking the token.
+  /// An address without a bucket fails as well when there is no room for one,
+  /// as `take` would.
+  pub fn check(&self, addr: IpAddr, now: Instant) -> Result<(), Duration> {
+    let key = bucket_key(addr);
+    let mut buckets = self.buckets.borrow_mut();
+    match buckets.iter_mut().find(|b| b.key == Some(key)) {
+      Some(bucket) => {
+        self.refill(bucket, now);
+        if bucket.tokens == 0 {
+          Err(self.wait_time(bucket, now))
+        } else {
+          Ok( () )
+        }
+      },
+      None => self.free_bucket(&mut buckets, now).map(|_| ())
+    }
+  }
+
+  // a bucket that can be given to a new client,
+  // or how long until there is one
+  fn free_bucket(&self, buckets: &mut [Bucket], now: Instant) -> Result<usize, Duration> {
+    for bucket in buckets.iter_mut() {
+      self.refill(bucket, now);
+    }
+    // evicting a bucket that isn't full would lift its limit
+    buckets.iter()
+      .position(|b| b.key.is_none() || b.tokens == self.burst)
+      .ok_or_else(|| {
+        buckets.iter()
+          .map(|b| self.full_time(b, now))
+          .min()
+          .unwrap_or(self.interval)
+      })
+  }
+
+  fn refill(&self, bucket: &mut Bucket, now: Instant) {
+    let interval = millis(self.interval).max(1);
+    let elapsed = millis(elapsed_since(bucket.refilled, now));
+    let refills = elapsed / interval;
+    if refills == 0 {
+      return;
+    }
+    let tokens = bucket.tokens as u64 + refills;
+    if tokens >= self.burst as u64 {
+      bucket.tokens = self.burst;
+      bucket.refilled = now;
+    } else {
+      bucket.tokens = tokens as u32;
+      // keep the time towards the next token
+      bucket.refilled += Duration::from_millis(refills * interval);
+    }
+  }
+
+  // how long until the bucket is full again
+  fn full_time(&self, bucket: &Bucket, now: Instant) -> Duration {
+    let missing = self.burst.saturating_sub(bucket.tokens);
+    if missing == 0 {
+      return Duration::from_millis(0);
+    }
+    (self.interval * missing).checked_sub(elapsed_since(bucket.refilled, now))
+      .unwrap_or(Duration::from_millis(0))
+  }
+
+  fn wait_time(&self, bucket: &Bucket, now: Instant) -> Duration {
+    let elapsed = elapsed_since(bucket.refilled, now);
+    self.interval.checked_sub(elapsed).unwrap_or(Duration::from_millis(0))
+  }
+}
+
+fn bucket_key(addr: IpAddr) -> BucketKey {
+  match addr {
+    IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+    IpAddr::V6(addr) => {
+      let mut key = addr.octets();
+      for b in key[8 ..].iter_mut() {
+        *b = 0;
+      }
+      key
+    }
+  }
+}
+
+fn elapsed_since(earlier: Instant, now: Instant) -> Duration {
+  if now > earlier { now - earlier } else { Duration::from_millis(0) }
+}
+
+fn millis(duration: Duration) -> u64 {
+  duration.as_secs() * 1000 + duration.subsec_millis() as u64
+}
+
+fn respond_too_many_requests(retry_after: Duration, responder: &http::Responder)
+  -> io::Result<Option<http::Response>>
+{
+  // round up, so clients don't come back too early
+  let seconds = retry_after.as_secs() + if retry_after.subsec_nanos() != 0 { 1 } else { 0 };
+  let mut response = responder.respond(http::status::TOO_MANY_REQUESTS)?;
+  response.set_header_usize("Retry-After", seconds.max(1) as usize)?;
+  response.set_header("Content-Type", "text/plain")?;
+  let mut body = response.into_body()?;
+  write!(body, "{}", http::status::TOO_MANY_REQUESTS.1)?;
+  Ok(Some(body.finish()))
+}
+
+/// Answers with `429 Too Many Requests` when a client
+/// sends requests faster than the rate limiter allows.
+pub struct RateLimitHandler<'a, T> {
+  child_handler: T,
+  limiter: &'a RateLimiter
+}
+
+impl<'a, T: http::RequestHandler> RateLimitHandler<'a, T> {
+  pub fn new(child_handler: T, limiter: &'a RateLimiter) -> RateLimitHandler<'a, T> {
+    RateLimitHandler { child_handler, limiter }
+  }
+}
+
+impl<'a, T: http::RequestHandler> http::RequestHandler for RateLimitHandler<'a, T> {
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    if let Some(addr) = request.peer_addr() {
+      if let Err(retry_after) = self.limiter.take(addr.ip(), Instant::now()) {
+        return respond_too_many_requests(retry_after, responder);
+      }
+    }
+    self.child_handler.read_headers(request, responder)
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
+
+/// Locks out clients that keep sending wrong credentials,
+/// meant to wrap `BasicAuthHandler` (or any other handler answering `401`).
+///
+/// Only a `401` for a request with an `Authorization` header counts as a failed login,
+/// so the challenge a browser gets before asking for credentials doesn't.
+/// Every failed login takes a token from the client's bucket,
+/// once it is empty requests are answered with `429` until it refills.
+/// New clients are answered with `429` as well while there is no room
+/// for their bucket, as their failed logins couldn't be counted.
+pub struct LoginLockoutHandler<'a, T> {
+  child_handler: T,
+  failures: &'a RateLimiter
+}
+
+impl<'a, T: http::RequestHandler> LoginLockoutHandler<'a, T> {
+  pub fn new(child_handler: T, failures: &'a RateLimiter) -> LoginLockoutHandler<'a, T> {
+    LoginLockoutHandler { child_handler, failures }
+  }
+}
+
+impl<'a, T: http::RequestHandler> http::RequestHandler for LoginLockoutHandler<'a, T> {
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let addr = match request.peer_addr() {
+      Some(addr) => addr.ip(),
+      None => return self.child_handler.read_headers(request, responder)
+    };
+    let now = Instant::now();
+    if let Err(retry_after) = self.failures.check(addr, now) {
+      return respond_too_many_requests(retry_after, responder);
+    }
+    let response = self.child_handler.read_headers(request, responder)?;
+    let is_failed_login = request.headers().authorization.is_some() &&
+      response.as_ref().map(|r| r.status_code() == http::status::UNAUTHORIZED.0).unwrap_or(false);
+    if is_failed_login {
+      // an empty bucket is noticed on the next request,
+      // and `check` made sure there is one for the address
+      let _ = self.failures.take(addr, now);
+    }
+    Ok(response)
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.read_body(body, responder)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+  use std::net::{IpAddr, SocketAddr};
+  use std::time::{Duration, Instant};
+  use http::{self, RequestHandler};
+  use io::ConnectionInfo;
+  use super::{RateLimiter, LoginLockoutHandler};
+
+  fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+  }
+
+  #[test]
+  fn test_burst_and_refill() {
+    let limiter = RateLimiter::new(4, 2, Duration::from_secs(10));
+    let now = Instant::now();
+    assert!(limiter.take(ip("192.0.2.1"), now).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now).is_ok());
+    assert_eq!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(4)), Err(Duration::from_secs(6)));
+    // other clients have their own bucket
+    assert!(limiter.take(ip("192.0.2.2"), now).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(10)).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(10)).is_err());
+    // refills don't go over the burst
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(100)).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(100)).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(100)).is_err());
+  }
+
+  #[test]
+  fn test_check_doesnt_take() {
+    let limiter = RateLimiter::new(4, 1, Duration::from_secs(10));
+    let now = Instant::now();
+    assert!(limiter.check(ip("192.0.2.1"), now).is_ok());
+    assert!(limiter.check(ip("192.0.2.1"), now).is_ok());
+    assert!(limiter.take(ip("192.0.2.1"), now).is_ok());
+    assert_eq!(limiter.check(ip("192.0.2.1"), now), Err(Duration::from_secs(10)));
+  }
+
+  #[test]
+  fn test_check_full() {
+    let limiter = RateLimiter::new(1, 1, Duration::from_secs(10));
+    let now = Instant::now();
+    assert!(limiter.take(ip("192.0.2.1"), now).is_ok());
+    assert_eq!(limiter.check(ip("192.0.2.2"), now + Duration::from_secs(4)), Err(Duration::from_secs(6)));
+    assert!(limiter.check(ip("192.0.2.2"), now + Duration::from_secs(10)).is_ok());
+  }
+
+  #[test]
+  fn test_ipv6_grouped_per_64() {
+    let limiter = RateLimiter::new(4, 1, Duration::from_secs(10));
+    let now = Instant::now();
+    assert!(limiter.take(ip("2001:db8:0:1::1"), now).is_ok());
+    assert!(limiter.take(ip("2001:db8:0:1::2"), now).is_err());
+    assert!(limiter.take(ip("2001:db8:0:2::1"), now).is_ok());
+  }
+
+  #[test]
+  fn test_full_evicts_refilled() {
+    let limiter = RateLimiter::new(2, 1, Duration::from_secs(10));
+    let now = Instant::now();
+    assert!(limiter.take(ip("192.0.2.1"), now).is_ok());
+    assert!(limiter.take(ip("192.0.2.2"), now + Duration::from_secs(1)).is_ok());
+    // both buckets are empty, so there is no room
+    assert_eq!(limiter.take(ip("192.0.2.3"), now + Duration::from_secs(2)), Err(Duration::from_secs(8)));
+    // and the empty buckets stay locked out
+    assert!(limiter.take(ip("192.0.2.1"), now + Duration::from_secs(2)).is_err());
+    // 192.0.2.1 has refilled and makes room
+    assert!(limiter.take(ip("192.0.2.3"), now + Duration::from_secs(10)).is_ok());
+    assert!(limiter.take(ip("192.0.2.2"), now + Duration::from_secs(10)).is_err());
+  }
+
+  // rejects all credentials
+  struct RejectingHandler {
+    requests: usize
+  }
+
+  impl http::RequestHandler for RejectingHandler {
+    fn read_headers(&mut self, _request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+      self.requests += 1;
+      let response = responder.respond(http::status::UNAUTHORIZED)?;
+      Ok(Some(response.into_body()?.finish()))
+    }
+  }
+
+  fn login(handler: &mut LoginLockoutHandler<RejectingHandler>, peer_addr: &str) -> u16 {
+    let mut bytes = b"GET / HTTP/1.1\r\nAuthorization: Basic Zm9vOmJhcg==".to_vec();
+    let mut request = http::Request::parse(&mut bytes).unwrap();
+    request.set_connection(ConnectionInfo::new(peer_addr.parse::<SocketAddr>().unwrap()));
+    let response = handler.read_headers(&request, &http::Responder::new()).unwrap();
+    response.unwrap().status_code()
+  }
+
+  #[test]
+  fn test_lockout_when_full() {
+    let failures = RateLimiter::new(1, 2, Duration::from_secs(60));
+    let mut handler = LoginLockoutHandler::new(RejectingHandler { requests: 0 }, &failures);
+    assert_eq!(login(&mut handler, "192.0.2.1:1234"), 401);
+    // the only bucket isn't full, so there is no room to count the failures of another client
+    assert_eq!(login(&mut handler, "192.0.2.2:1234"), 429);
+    assert_eq!(handler.child_handler.requests, 1);
+    assert_eq!(login(&mut handler, "192.0.2.1:1234"), 401);
+    assert_eq!(login(&mut handler, "192.0.2.1:1234"), 429);
+  }
+}
@@ -49,4 +49,8 @@ impl<F, H, I, D> http::RequestHandler for Router<F, H, I, D>
       Ok(Some(body.finish()))
     }
   }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, res: &http::Responder) -> std::io::Result<Option<http::Response>> {
+    self.default.render_error(status, message, res)
+  }
 }
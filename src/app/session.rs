@@ -0,0 +1,103 @@
+use http;
+use http::cookie::{SetCookie, SameSite};
+use http::session::{
+  Session,
+  SessionStore,
+  SessionRequestHandler,
+  MAX_COOKIE_VALUE_LEN,
+  unix_time_now
+};
+use std::io;
+use std::str;
+
+/// Loads the session from the cookie before passing the request on,
+/// and sets the cookie again on the response when the child changed the session.
+pub struct SessionHandler<'a, T, S: 'a> {
+  child_handler: T,
+  store: &'a S,
+  cookie_name: &'a str,
+  session: Session,
+  // browsers drop Secure cookies set over plain http
+  is_secure: bool
+}
+
+impl<'a, T, S> SessionHandler<'a, T, S>
+where
+  T: SessionRequestHandler,
+  S: SessionStore
+{
+  pub fn new(child_handler: T, store: &'a S, cookie_name: &'a str) -> SessionHandler<'a, T, S> {
+    SessionHandler {
+      child_handler,
+      store,
+      cookie_name,
+      session: Session::new(),
+      is_secure: true
+    }
+  }
+
+  fn save_session(&mut self, response: Option<http::Response>)
+    -> io::Result<Option<http::Response>>
+  {
+    let mut response = match response {
+      Some(response) => response,
+      // wait for the body
+      None => return Ok(None)
+    };
+    if self.session.is_destroyed() {
+      if !self.session.is_new() {
+        self.store.remove(&self.session)?;
+        response.set_cookie(&self.cookie(SetCookie::removal(self.cookie_name)))?;
+      }
+    }
+    else if self.session.is_modified() {
+      let mut value = [0u8; MAX_COOKIE_VALUE_LEN];
+      let value_len = {
+        let mut dst = &mut value[..];
+        self.store.save(&mut self.session, unix_time_now(), &mut dst)?;
+        MAX_COOKIE_VALUE_LEN - dst.len()
+      };
+      let value = str::from_utf8(&value[.. value_len])
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "session cookie is not UTF8"))?;
+      let cookie = SetCookie::new(self.cookie_name, value).max_age(self.store.max_age());
+      response.set_cookie(&self.cookie(cookie))?;
+    }
+    Ok(Some(response))
+  }
+
+  fn cookie<'c>(&self, cookie: SetCookie<'c>) -> SetCookie<'c> {
+    let cookie = cookie
+      .path("/")
+      .http_only()
+      .same_site(SameSite::Lax);
+    if self.is_secure { cookie.secure() } else { cookie }
+  }
+}
+
+impl<'a, T, S> http::RequestHandler for SessionHandler<'a, T, S>
+where
+  T: SessionRequestHandler,
+  S: SessionStore
+{
+  fn read_headers(&mut self, request: &http::Request, responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let now = unix_time_now();
+    let store = self.store;
+    self.is_secure = request.connection().map(|c| c.uses_tls()).unwrap_or(true);
+    self.session = request.cookie(self.cookie_name)
+      .and_then(|value| store.load(value, now))
+      .unwrap_or_else(Session::new);
+    let response = self.child_handler.read_headers(request, &mut self.session, responder)?;
+    self.save_session(response)
+  }
+
+  fn read_body(&mut self, body: &mut [u8], responder: &http::Responder) -> io::Result<Option<http::Response>> {
+    let response = self.child_handler.read_body(body, &mut self.session, responder)?;
+    self.save_session(response)
+  }
+
+  fn render_error(&mut self, status: http::status::Status, message: &str, responder: &http::Responder)
+    -> io::Result<Option<http::Response>>
+  {
+    self.child_handler.render_error(status, message, responder)
+  }
+}
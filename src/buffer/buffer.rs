@@ -21,6 +21,19 @@ impl Buffer {
     self.len = 0;
   }
 
+  // removes the first count bytes, moving the remaining bytes to the front
+  pub fn remove_head(&mut self, count: usize) {
+    let count = ::std::cmp::min(count, self.len);
+    let remaining = self.len - count;
+    if remaining != 0 {
+      let start_ptr = self.page_buffer.as_mut_slice().as_mut_ptr();
+      unsafe {
+        ptr::copy(start_ptr.offset(count as isize), start_ptr, remaining);
+      }
+    }
+    self.len = remaining;
+  }
+
   pub fn len(&self) -> usize {
     self.len
   }
@@ -29,6 +42,10 @@ impl Buffer {
     self.page_buffer.size()
   }
 
+  pub fn is_full(&self) -> bool {
+    self.len == self.capacity()
+  }
+
   pub unsafe fn set_len(&mut self, len: usize) {
     if len <= self.capacity() {
       self.len = len;
@@ -51,7 +68,12 @@ impl ::io::ReadDst for Buffer {
     //this could be optimized with an extra ReadHint trait that gives an Option<usize>
     //for the available size. This way we could only do one allocation if a lot of
     //data is available.
-    let bytes_read = reader.read(self.page_buffer.as_mut_slice())?;
+    //reading doesn't grow the buffer, so the size of what is read stays bounded
+    if self.is_full() {
+      return Err(io::Error::new(io::ErrorKind::Other, "buffer is full"));
+    }
+    let len = self.len;
+    let bytes_read = reader.read(&mut self.page_buffer.as_mut_slice()[len ..])?;
     self.len += bytes_read;
     Ok(bytes_read)
   }
@@ -109,6 +131,16 @@ mod tests {
     assert_eq!(buffer.as_slice(), b"hello 1 world 2");
   }
 
+  #[test]
+  fn test_remove_head() {
+    let mut buffer = Buffer::new();
+    write!(buffer, "hello world").unwrap();
+    buffer.remove_head(6);
+    assert_eq!(buffer.as_slice(), b"world");
+    buffer.remove_head(10);
+    assert_eq!(buffer.as_slice(), b"");
+  }
+
   #[test]
   fn test_clear() {
     let mut buffer = Buffer::new();
@@ -138,4 +170,23 @@ mod tests {
     assert_eq!(buffer.len(), 5);
     assert_eq!(buffer.as_slice(), b"hello");
   }
+
+  #[test]
+  fn test_read_from_appends() {
+    let mut buffer = Buffer::new();
+    buffer.read_from(&mut b"hello".as_ref()).unwrap();
+    buffer.read_from(&mut b" world".as_ref()).unwrap();
+    assert_eq!(buffer.as_slice(), b"hello world");
+  }
+
+  #[test]
+  fn test_read_from_full_buffer() {
+    let mut buffer = Buffer::new();
+    let capacity = buffer.capacity();
+    let data = vec![b'a'; capacity + 10];
+    let mut reader = data.as_slice();
+    assert_eq!(buffer.read_from(&mut reader).ok(), Some(capacity));
+    assert!(buffer.is_full());
+    assert!(buffer.read_from(&mut reader).is_err());
+  }
 }
@@ -1,3 +1,31 @@
+const ALPHABET : &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encoded_len(src_len: usize) -> usize {
+  ((src_len + 2) / 3) * 4
+}
+
+// encodes src into dst with padding, returns None if dst is too small.
+pub fn encode<'a>(src: &[u8], dst: &'a mut [u8]) -> Option<&'a str> {
+  let output_len = encoded_len(src.len());
+  if dst.len() < output_len {
+    return None;
+  }
+  for (chunk, dst_chunk) in src.chunks(3).zip(dst.chunks_mut(4)) {
+    let b0 = chunk[0];
+    let b1 = chunk.get(1).cloned().unwrap_or(0);
+    let b2 = chunk.get(2).cloned().unwrap_or(0);
+    dst_chunk[0] = ALPHABET[(b0 >> 2) as usize];
+    dst_chunk[1] = ALPHABET[((b0 & 0b11) << 4 | b1 >> 4) as usize];
+    dst_chunk[2] = if chunk.len() > 1 {
+      ALPHABET[((b1 & 0b1111) << 2 | b2 >> 6) as usize]
+    } else { b'=' };
+    dst_chunk[3] = if chunk.len() > 2 {
+      ALPHABET[(b2 & 0b11_1111) as usize]
+    } else { b'=' };
+  }
+  ::std::str::from_utf8(&dst[.. output_len]).ok()
+}
+
 pub fn decode<'a>(src: &'a mut [u8]) -> Option<&'a mut [u8]> {
   if src.len() == 0 || src.len() % 4 != 0 {
     return None;
@@ -43,10 +71,40 @@ pub fn decode<'a>(src: &'a mut [u8]) -> Option<&'a mut [u8]> {
   Some(&mut src[0 .. output_len])
 }
 
+/// decodes the URL and filename safe variant without padding (RFC 4648 section 5),
+/// as used in JWTs.
+pub fn decode_url<'a>(src: &'a mut [u8]) -> Option<&'a mut [u8]> {
+  if src.len() % 4 == 1 {
+    return None;
+  }
+  let mut bits = 0u32;
+  let mut bit_count = 0;
+  let mut output_len = 0;
+  for i in 0..src.len() {
+    let current = src[i];
+    let decoded_6bit = match current {
+      b'A' ..= b'Z' => current - b'A',
+      b'a' ..= b'z' => current - b'a' + 26,
+      b'0' ..= b'9' => current - b'0' + 52,
+      b'-' => 62,
+      b'_' => 63,
+      _ => return None
+    };
+    bits = (bits << 6 | decoded_6bit as u32) & 0xFFFF;
+    bit_count += 6;
+    if bit_count >= 8 {
+      bit_count -= 8;
+      src[output_len] = (bits >> bit_count) as u8;
+      output_len += 1;
+    }
+  }
+  Some(&mut src[0 .. output_len])
+}
+
 #[cfg(test)]
 mod tests {
 
-  use super::decode;
+  use super::{decode, decode_url, encode};
   use test_helpers;
 
   #[test]
@@ -103,4 +161,34 @@ mod tests {
     test_helpers::copy_str(&mut buffer, b"aGVlbGxvIHdvcmxk");
     assert_eq!(decode(&mut buffer).unwrap() as &[u8], b"heello world");
   }
+
+  #[test]
+  fn test_encode() {
+    let mut buffer = [0u8; 16];
+    assert_eq!(encode(b"", &mut buffer), Some(""));
+    assert_eq!(encode(b"a", &mut buffer), Some("YQ=="));
+    assert_eq!(encode(b"ab", &mut buffer), Some("YWI="));
+    assert_eq!(encode(b"abc", &mut buffer), Some("YWJj"));
+    assert_eq!(encode(b"hello world", &mut buffer), Some("aGVsbG8gd29ybGQ="));
+  }
+
+  #[test]
+  fn test_encode_dst_too_small() {
+    let mut buffer = [0u8; 3];
+    assert_eq!(encode(b"a", &mut buffer), None);
+  }
+
+  #[test]
+  fn test_decode_url() {
+    let mut buffer = [0u8; 15];
+    test_helpers::copy_str(&mut buffer, b"aGVsbG8gd29ybGQ");
+    assert_eq!(decode_url(&mut buffer).unwrap() as &[u8], b"hello world");
+    let mut buffer = [0u8; 3];
+    test_helpers::copy_str(&mut buffer, b"-_8");
+    assert_eq!(decode_url(&mut buffer).unwrap() as &[u8], &[0xfb, 0xff]);
+    let mut buffer = [0u8; 4];
+    test_helpers::copy_str(&mut buffer, b"YQ==");
+    assert_eq!(decode_url(&mut buffer), None);
+    assert_eq!(decode_url(&mut buffer[.. 1]), None);
+  }
 }
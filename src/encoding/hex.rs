@@ -0,0 +1,57 @@
+const DIGITS : &'static [u8; 16] = b"0123456789abcdef";
+
+/// encodes src into dst as lowercase hex, returns None if dst is too small.
+pub fn encode<'a>(src: &[u8], dst: &'a mut [u8]) -> Option<&'a str> {
+  let output_len = src.len() * 2;
+  if dst.len() < output_len {
+    return None;
+  }
+  for (b, dst_pair) in src.iter().zip(dst.chunks_mut(2)) {
+    dst_pair[0] = DIGITS[(b >> 4) as usize];
+    dst_pair[1] = DIGITS[(b & 0xF) as usize];
+  }
+  ::std::str::from_utf8(&dst[.. output_len]).ok()
+}
+
+/// decodes src into dst, which has to be exactly half as long.
+/// Both upper and lower case digits are accepted.
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Option<()> {
+  if src.len() != dst.len() * 2 {
+    return None;
+  }
+  for (b, pair) in dst.iter_mut().zip(src.chunks(2)) {
+    *b = decode_digit(pair[0])? << 4 | decode_digit(pair[1])?;
+  }
+  Some( () )
+}
+
+fn decode_digit(digit: u8) -> Option<u8> {
+  match digit {
+    b'0' ..= b'9' => Some(digit - b'0'),
+    b'a' ..= b'f' => Some(digit - b'a' + 10),
+    b'A' ..= b'F' => Some(digit - b'A' + 10),
+    _ => None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{decode, encode};
+
+  #[test]
+  fn test_encode() {
+    let mut buffer = [0u8; 6];
+    assert_eq!(encode(b"", &mut buffer), Some(""));
+    assert_eq!(encode(&[0x00, 0x9f, 0xa5], &mut buffer), Some("009fa5"));
+    assert_eq!(encode(&[0, 0, 0, 0], &mut buffer), None);
+  }
+
+  #[test]
+  fn test_decode() {
+    let mut buffer = [0u8; 3];
+    assert_eq!(decode(b"009fA5", &mut buffer), Some( () ));
+    assert_eq!(buffer, [0x00, 0x9f, 0xa5]);
+    assert_eq!(decode(b"009fa", &mut buffer), None);
+    assert_eq!(decode(b"009fag", &mut buffer), None);
+  }
+}
@@ -1 +1,2 @@
-pub mod base64;
\ No newline at end of file
+pub mod base64;
+pub mod hex;
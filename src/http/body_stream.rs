@@ -0,0 +1,79 @@
+use std::io;
+use buffer::Buffer;
+use io::{Handler, Event, Context};
+use io::handlers::send_buffer;
+
+/// A response body that stays open after the headers are sent,
+/// and is written incrementally as events arrive on the connection.
+pub trait BodyStream {
+  /// Called for every event on the connection, including socket readiness.
+  /// Appends whatever body data is available to `body`,
+  /// and returns true once the body is complete.
+  /// As long as everything appended on a socket event could be sent,
+  /// it is called again for the same event.
+  fn on_event(&mut self, event: &Event, ctx: &mut Context, body: &mut Buffer) -> io::Result<bool>;
+}
+
+pub struct StreamResponder {
+  stream: Box<dyn BodyStream>,
+  buffer: Buffer,
+  bytes_written: usize,
+  is_finished: bool
+}
+
+impl StreamResponder {
+  pub fn new(stream: Box<dyn BodyStream>) -> StreamResponder {
+    StreamResponder {
+      stream,
+      buffer: Buffer::new(),
+      bytes_written: 0,
+      is_finished: false
+    }
+  }
+
+  fn write_socket(&mut self, ctx: &mut Context) -> io::Result<()> {
+    if self.buffer.len() == 0 {
+      return Ok( () );
+    }
+    let mut socket = ctx.socket();
+    let report = send_buffer(&mut socket, self.buffer.as_slice())?;
+    self.bytes_written += report.byte_count();
+    self.buffer.remove_head(report.byte_count());
+    // a stream is written in small parts, don't leave them in the tls engine
+    socket.flush()
+  }
+}
+
+impl Handler<usize> for StreamResponder {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<usize> {
+    loop {
+      let len_before = self.buffer.len();
+      if !self.is_finished {
+        match self.stream.on_event(event, ctx, &mut self.buffer) {
+          Ok(is_finished) => self.is_finished = is_finished,
+          Err(err) => {
+            println!("closing response stream due to error: {:?}", err);
+            return Some(self.bytes_written);
+          }
+        }
+      }
+      let has_new_data = self.buffer.len() > len_before;
+      if let Err(err) = self.write_socket(ctx) {
+        println!("closing response stream due to io error: {:?}", err);
+        return Some(self.bytes_written);
+      }
+      if self.is_finished && self.buffer.len() == 0 {
+        return Some(self.bytes_written);
+      }
+      // the socket only becomes writable again after it was full,
+      // so a stream that produces data on demand has to keep going
+      // as long as everything it produced was sent
+      let can_continue = has_new_data &&
+        self.buffer.len() == 0 &&
+        ctx.socket().is_source_of(event);
+      if !can_continue {
+        return None;
+      }
+    }
+  }
+}
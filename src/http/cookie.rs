@@ -0,0 +1,365 @@
+use std::io::{self, Write};
+use std::str;
+use std::time::SystemTime;
+use http::url_decode::{percent_decode, contains_percent_values};
+use http::date::write_http_date;
+use http::str::{trim_mut, is_whitespace};
+use split::buffer_split_mut;
+
+const NUL : u8 = 0;
+const SEMICOLON : u8 = 0x3B;
+const ASSIGN : u8 = 0x3D;
+const QUOTE : u8 = 0x22;
+
+/// The cookies sent in a `Cookie` request header.
+///
+/// Values are decoded in place, like `UrlEncodedParams`:
+/// a value that is quoted or percent encoded is rewritten
+/// as a NUL marker, followed by the decoded value and NUL padding
+/// up to the original length. A decoded value can contain `;`,
+/// but never NUL, so the iterator can still find where it ends.
+/// Cookies that can't be decoded are blanked out and skipped,
+/// rather than failing the whole request.
+pub struct Cookies<'a> {
+  decoded: &'a [u8]
+}
+
+impl<'a> Cookies<'a> {
+  pub fn decode_and_create(header_value: &'a mut [u8]) -> Cookies<'a> {
+    for pair in buffer_split_mut(header_value, b";") {
+      if decode_pair(pair).is_none() {
+        for b in pair.iter_mut() {
+          *b = SEMICOLON;
+        }
+      }
+    }
+    Cookies { decoded: header_value }
+  }
+
+  pub fn iter(&self) -> CookieIterator<'a> {
+    CookieIterator { remaining: self.decoded }
+  }
+
+  /// the value of the first cookie with the given name
+  pub fn get(&self, name: &str) -> Option<&'a str> {
+    self.iter().find(|c| c.name == name).map(|c| c.value)
+  }
+}
+
+fn is_token(name: &[u8]) -> bool {
+  !name.is_empty() && name.iter().all(|&b| {
+    b > 0x20 && b < 0x7F && !b"()<>@,;:\\\"/[]?={}".contains(&b)
+  })
+}
+
+fn decode_pair(pair: &mut [u8]) -> Option<()> {
+  let pair = trim_mut(pair, is_whitespace);
+  if pair.is_empty() {
+    return Some( () );
+  }
+  let assign_idx = pair.iter().position(|&b| b == ASSIGN)?;
+  let (name, value) = pair.split_at_mut(assign_idx);
+  if !is_token(trim_mut(name, is_whitespace)) {
+    return None;
+  }
+  let value = trim_mut(&mut value[1 ..], is_whitespace);
+  decode_value(value)
+}
+
+fn decode_value(value: &mut [u8]) -> Option<()> {
+  let len = value.len();
+  let is_quoted = len >= 2 && value[0] == QUOTE && value[len - 1] == QUOTE;
+  if !is_quoted && !contains_percent_values(value) {
+    return validate_value(value);
+  }
+  // both quotes and escapes shrink the value by at least 2,
+  // so there is room for the marker and at least one NUL after it
+  let decoded_len = {
+    let content = if is_quoted { &mut value[1 .. len - 1] } else { &mut value[..] };
+    let content_start = if is_quoted { 1 } else { 0 };
+    let decoded_len = percent_decode(content).len();
+    validate_value(&value[content_start .. content_start + decoded_len])?;
+    decoded_len
+  };
+  let content_start = if is_quoted { 1 } else { 0 };
+  for i in (0 .. decoded_len).rev() {
+    value[i + 1] = value[content_start + i];
+  }
+  value[0] = NUL;
+  for b in value[decoded_len + 1 ..].iter_mut() {
+    *b = NUL;
+  }
+  Some( () )
+}
+
+fn validate_value(value: &[u8]) -> Option<()> {
+  if value.contains(&NUL) || str::from_utf8(value).is_err() {
+    None
+  } else {
+    Some( () )
+  }
+}
+
+pub struct CookieIterator<'a> {
+  remaining: &'a [u8]
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cookie<'a> {
+  pub name: &'a str,
+  pub value: &'a str
+}
+
+impl<'a> Iterator for CookieIterator<'a> {
+  type Item = Cookie<'a>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let start = self.remaining.iter()
+      .position(|&b| b != SEMICOLON && b != NUL && !is_whitespace(b))?;
+    let pair = &self.remaining[start ..];
+    let name_end = pair.iter().position(|&b| b == ASSIGN)
+      .unwrap_or(pair.len());
+    let value_start = pair.iter().skip(name_end + 1).position(|&b| !is_whitespace(b))
+      .map(|idx| name_end + 1 + idx)
+      .unwrap_or(pair.len());
+    let (value, next_idx) = match pair.get(value_start) {
+      Some(&NUL) => {
+        let value = &pair[value_start + 1 ..];
+        let value_len = value.iter().position(|&b| b == NUL).unwrap_or(value.len());
+        let end = value_start + 1 + value_len;
+        let next_idx = pair[end ..].iter().position(|&b| b == SEMICOLON)
+          .map(|idx| end + idx)
+          .unwrap_or(pair.len());
+        (&value[.. value_len], next_idx)
+      },
+      _ => {
+        let value_len = pair[value_start ..].iter().position(|&b| b == SEMICOLON)
+          .unwrap_or(pair.len() - value_start);
+        (&pair[value_start .. value_start + value_len], value_start + value_len)
+      }
+    };
+    self.remaining = &pair[next_idx ..];
+    // pairs have been validated for utf8 and names to be tokens
+    // in `decode_pair`, so we don't have to return errors in the iterator
+    let name = unsafe { str::from_utf8_unchecked(&pair[.. name_end]) };
+    let value = unsafe { str::from_utf8_unchecked(value) };
+    Some(Cookie { name: name.trim(), value: value.trim() })
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+  Strict,
+  Lax,
+  None
+}
+
+/// A cookie to send to the client with `HeaderWriter::set_cookie`.
+pub struct SetCookie<'a> {
+  name: &'a str,
+  value: &'a str,
+  path: Option<&'a str>,
+  domain: Option<&'a str>,
+  max_age: Option<u64>,
+  expires: Option<SystemTime>,
+  secure: bool,
+  http_only: bool,
+  same_site: Option<SameSite>
+}
+
+impl<'a> SetCookie<'a> {
+  pub fn new(name: &'a str, value: &'a str) -> SetCookie<'a> {
+    SetCookie {
+      name,
+      value,
+      path: None,
+      domain: None,
+      max_age: None,
+      expires: None,
+      secure: false,
+      http_only: false,
+      same_site: None
+    }
+  }
+
+  /// a cookie that tells the client to delete the cookie with this name
+  pub fn removal(name: &'a str) -> SetCookie<'a> {
+    SetCookie::new(name, "").max_age(0)
+  }
+
+  pub fn path(mut self, path: &'a str) -> SetCookie<'a> {
+    self.path = Some(path);
+    self
+  }
+
+  pub fn domain(mut self, domain: &'a str) -> SetCookie<'a> {
+    self.domain = Some(domain);
+    self
+  }
+
+  pub fn max_age(mut self, seconds: u64) -> SetCookie<'a> {
+    self.max_age = Some(seconds);
+    self
+  }
+
+  pub fn expires(mut self, time: SystemTime) -> SetCookie<'a> {
+    self.expires = Some(time);
+    self
+  }
+
+  pub fn secure(mut self) -> SetCookie<'a> {
+    self.secure = true;
+    self
+  }
+
+  pub fn http_only(mut self) -> SetCookie<'a> {
+    self.http_only = true;
+    self
+  }
+
+  pub fn same_site(mut self, same_site: SameSite) -> SetCookie<'a> {
+    self.same_site = Some(same_site);
+    self
+  }
+
+  /// checks everything that can't be escaped,
+  /// so nothing is written for an invalid cookie.
+  pub fn validate(&self) -> io::Result<()> {
+    if !is_token(self.name.as_bytes()) {
+      return Err(invalid_input("cookie name is not a valid token"));
+    }
+    let is_valid_attribute = |value: &Option<&str>| {
+      value.map(|v| !v.bytes().any(|b| b == SEMICOLON || b < 0x20 || b == 0x7F)).unwrap_or(true)
+    };
+    if !is_valid_attribute(&self.path) || !is_valid_attribute(&self.domain) {
+      return Err(invalid_input("cookie attribute contains a control character or ';'"));
+    }
+    if self.same_site == Some(SameSite::None) && !self.secure {
+      return Err(invalid_input("SameSite=None cookies have to be Secure"));
+    }
+    Ok( () )
+  }
+
+  /// writes the header value, percent encoding the cookie value where needed.
+  pub fn write(&self, dst: &mut dyn Write) -> io::Result<()> {
+    self.validate()?;
+    write!(dst, "{}=", self.name)?;
+    write_escaped_value(dst, self.value)?;
+    if let Some(path) = self.path {
+      write!(dst, "; Path={}", path)?;
+    }
+    if let Some(domain) = self.domain {
+      write!(dst, "; Domain={}", domain)?;
+    }
+    if let Some(max_age) = self.max_age {
+      write!(dst, "; Max-Age={}", max_age)?;
+    }
+    if let Some(expires) = self.expires {
+      write!(dst, "; Expires=")?;
+      write_http_date(dst, expires)?;
+    }
+    if self.secure {
+      write!(dst, "; Secure")?;
+    }
+    if self.http_only {
+      write!(dst, "; HttpOnly")?;
+    }
+    match self.same_site {
+      Some(SameSite::Strict) => write!(dst, "; SameSite=Strict"),
+      Some(SameSite::Lax) => write!(dst, "; SameSite=Lax"),
+      Some(SameSite::None) => write!(dst, "; SameSite=None"),
+      None => Ok( () )
+    }
+  }
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// cookie-octet from RFC 6265, minus '%' which is used for escaping
+fn is_cookie_octet(b: u8) -> bool {
+  match b {
+    0x21 | 0x23 ..= 0x24 | 0x26 ..= 0x2B | 0x2D ..= 0x3A | 0x3C ..= 0x5B | 0x5D ..= 0x7E => true,
+    _ => false
+  }
+}
+
+fn write_escaped_value(dst: &mut dyn Write, value: &str) -> io::Result<()> {
+  let bytes = value.as_bytes();
+  let mut start = 0;
+  for (idx, &b) in bytes.iter().enumerate() {
+    if !is_cookie_octet(b) {
+      dst.write_all(&bytes[start .. idx])?;
+      write!(dst, "%{:02X}", b)?;
+      start = idx + 1;
+    }
+  }
+  dst.write_all(&bytes[start ..])
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, UNIX_EPOCH};
+  use super::{Cookies, Cookie, SetCookie, SameSite};
+
+  fn cookies(header: &str) -> Vec<(String, String)> {
+    let mut buffer = header.as_bytes().to_vec();
+    let cookies = Cookies::decode_and_create(&mut buffer);
+    cookies.iter().map(|c| (c.name.to_string(), c.value.to_string())).collect()
+  }
+
+  fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+  }
+
+  #[test]
+  fn test_plain_cookies() {
+    assert_eq!(cookies("a=1; b=hello+world;c="), pairs(&[("a", "1"), ("b", "hello+world"), ("c", "")]));
+  }
+
+  #[test]
+  fn test_decoded_cookies() {
+    assert_eq!(
+      cookies("session=\"abc\"; data=a%3Bb%3Dc; last=1"),
+      pairs(&[("session", "abc"), ("data", "a;b=c"), ("last", "1")]));
+  }
+
+  #[test]
+  fn test_skip_invalid_cookies() {
+    assert_eq!(cookies("bad name=1; nul=%00; no_value; ok=1"), pairs(&[("ok", "1")]));
+  }
+
+  #[test]
+  fn test_get() {
+    let mut buffer = b"a=1; b=%32".to_vec();
+    let cookies = Cookies::decode_and_create(&mut buffer);
+    assert_eq!(cookies.get("b"), Some("2"));
+    assert_eq!(cookies.get("c"), None);
+    assert_eq!(cookies.iter().next(), Some(Cookie {name: "a", value: "1"}));
+  }
+
+  #[test]
+  fn test_set_cookie() {
+    let mut header = Vec::new();
+    SetCookie::new("id", "a b;c%")
+      .path("/")
+      .domain("example.com")
+      .max_age(3600)
+      .expires(UNIX_EPOCH + Duration::from_secs(784_111_777))
+      .secure()
+      .http_only()
+      .same_site(SameSite::Lax)
+      .write(&mut header).unwrap();
+    assert_eq!(header, &b"id=a%20b%3Bc%25; Path=/; Domain=example.com; Max-Age=3600; \
+      Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Lax"[..]);
+  }
+
+  #[test]
+  fn test_set_cookie_invalid() {
+    let mut header = Vec::new();
+    assert!(SetCookie::new("a b", "1").write(&mut header).is_err());
+    assert!(SetCookie::new("a", "1").path("/;x").write(&mut header).is_err());
+    assert!(SetCookie::new("a", "1").same_site(SameSite::None).write(&mut header).is_err());
+  }
+}
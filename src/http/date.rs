@@ -0,0 +1,55 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAY_NAMES : [&'static str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES : [&'static str; 12] = [
+  "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+];
+
+// year, month (1-12) and day (1-31) from days since 1970-01-01,
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, usize, i64) {
+  let z = days + 719_468;
+  let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+  let day_of_era = z - era * 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month as usize, day)
+}
+
+/// Writes the time in the IMF-fixdate format used by HTTP,
+/// e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. Times before 1970 are clamped.
+pub fn write_http_date(dst: &mut dyn Write, time: SystemTime) -> io::Result<()> {
+  let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+  let days = secs / 86_400;
+  let secs_of_day = secs % 86_400;
+  let (year, month, day) = civil_from_days(days);
+  write!(dst, "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+    DAY_NAMES[(days % 7) as usize],
+    day,
+    MONTH_NAMES[month - 1],
+    year,
+    secs_of_day / 3600,
+    (secs_of_day % 3600) / 60,
+    secs_of_day % 60)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, UNIX_EPOCH};
+  use super::write_http_date;
+
+  #[test]
+  fn test_write_http_date() {
+    let mut date = Vec::new();
+    write_http_date(&mut date, UNIX_EPOCH + Duration::from_secs(784_111_777)).unwrap();
+    assert_eq!(date, b"Sun, 06 Nov 1994 08:49:37 GMT");
+    let mut date = Vec::new();
+    write_http_date(&mut date, UNIX_EPOCH + Duration::from_secs(951_782_400)).unwrap();
+    assert_eq!(date, b"Tue, 29 Feb 2000 00:00:00 GMT");
+  }
+}
@@ -1,9 +1,49 @@
+use http::status::{self, Status};
+
 #[derive(Debug, PartialEq)]
 pub enum RequestError {
   InvalidRequestLine,
   InvalidHeader,
   InvalidEncoding,
-  UrlEncodedNul
+  UrlEncodedNul,
+  UriTooLong,
+  HeadersTooLarge,
+  PayloadTooLarge,
+  LengthRequired,
+  UnsupportedTransferEncoding,
+  UnsupportedVersion
+}
+
+impl RequestError {
+  pub fn status(&self) -> Status {
+    match *self {
+      RequestError::InvalidRequestLine |
+      RequestError::InvalidHeader |
+      RequestError::InvalidEncoding |
+      RequestError::UrlEncodedNul => status::BAD_REQUEST,
+      RequestError::UriTooLong => status::URI_TOO_LONG,
+      RequestError::HeadersTooLarge => status::REQUEST_HEADER_FIELDS_TOO_LARGE,
+      RequestError::PayloadTooLarge => status::PAYLOAD_TOO_LARGE,
+      RequestError::LengthRequired => status::LENGTH_REQUIRED,
+      RequestError::UnsupportedTransferEncoding => status::NOT_IMPLEMENTED,
+      RequestError::UnsupportedVersion => status::HTTP_VERSION_NOT_SUPPORTED
+    }
+  }
+
+  pub fn message(&self) -> &'static str {
+    match *self {
+      RequestError::InvalidRequestLine => "Invalid request line",
+      RequestError::InvalidHeader => "Invalid header",
+      RequestError::InvalidEncoding => "Request not encoded with UTF8",
+      RequestError::UrlEncodedNul => "URL encoded value contains NUL character",
+      RequestError::UriTooLong => "Request URI is too long",
+      RequestError::HeadersTooLarge => "Request headers are too large",
+      RequestError::PayloadTooLarge => "Request body is too large",
+      RequestError::LengthRequired => "Request body needs a Content-Length",
+      RequestError::UnsupportedTransferEncoding => "Transfer-Encoding is not supported",
+      RequestError::UnsupportedVersion => "HTTP version is not supported"
+    }
+  }
 }
 
-pub type RequestResult<T> = Result<T, RequestError>;
\ No newline at end of file
+pub type RequestResult<T> = Result<T, RequestError>;
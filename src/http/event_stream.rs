@@ -0,0 +1,154 @@
+use std::io::{self, Write};
+use buffer::Buffer;
+use io::{Event, Context};
+use super::body_stream::BodyStream;
+
+/// Application side of a `text/event-stream` response (Server-Sent Events).
+/// The stream stays open until `on_event` returns false.
+pub trait EventStreamHandler {
+  fn on_open(&mut self, _ctx: &mut Context, _events: &mut EventWriter) -> io::Result<()> {
+    Ok( () )
+  }
+
+  /// called for every event on the connection,
+  /// e.g. a message on a channel subscribed to in `on_open`.
+  fn on_event(&mut self, event: &Event, ctx: &mut Context, events: &mut EventWriter) -> io::Result<bool>;
+}
+
+pub struct EventWriter<'a> {
+  buffer: &'a mut Buffer
+}
+
+fn invalid_field() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, "event stream field contains a newline")
+}
+
+fn has_newline(value: &str) -> bool {
+  value.bytes().any(|b| b == b'\n' || b == b'\r')
+}
+
+impl<'a> EventWriter<'a> {
+  pub fn new(buffer: &'a mut Buffer) -> EventWriter<'a> {
+    EventWriter { buffer }
+  }
+
+  /// writes an event with an optional type and id,
+  /// every line of data is sent as a separate `data:` field.
+  pub fn send(&mut self, event: Option<&str>, data: &str, id: Option<&str>) -> io::Result<()> {
+    if event.map(has_newline).unwrap_or(false) || id.map(has_newline).unwrap_or(false) {
+      return Err(invalid_field());
+    }
+    if let Some(event) = event {
+      write!(self.buffer, "event: {}\n", event)?;
+    }
+    // clients end a line on "\r\n", "\n" and a lone "\r",
+    // so all of them start a new data field
+    let mut remaining = data;
+    while !remaining.is_empty() {
+      let (line, next) = match remaining.find(|c| c == '\r' || c == '\n') {
+        Some(pos) if remaining[pos ..].starts_with("\r\n") => (&remaining[.. pos], &remaining[pos + 2 ..]),
+        Some(pos) => (&remaining[.. pos], &remaining[pos + 1 ..]),
+        None => (remaining, "")
+      };
+      write!(self.buffer, "data: {}\n", line)?;
+      remaining = next;
+    }
+    if data.is_empty() {
+      write!(self.buffer, "data\n")?;
+    }
+    if let Some(id) = id {
+      write!(self.buffer, "id: {}\n", id)?;
+    }
+    write!(self.buffer, "\n")
+  }
+
+  pub fn send_data(&mut self, data: &str) -> io::Result<()> {
+    self.send(None, data, None)
+  }
+
+  /// tells the client how long to wait before reconnecting
+  pub fn retry(&mut self, milliseconds: u32) -> io::Result<()> {
+    write!(self.buffer, "retry: {}\n\n", milliseconds)
+  }
+
+  /// comment lines are ignored by the client,
+  /// useful to keep the connection from timing out.
+  pub fn comment(&mut self, comment: &str) -> io::Result<()> {
+    if has_newline(comment) {
+      return Err(invalid_field());
+    }
+    write!(self.buffer, ": {}\n\n", comment)
+  }
+}
+
+pub struct EventStream<H> {
+  handler: H,
+  is_opened: bool
+}
+
+impl<H> EventStream<H> {
+  pub fn new(handler: H) -> EventStream<H> {
+    EventStream { handler, is_opened: false }
+  }
+}
+
+impl<H: EventStreamHandler> BodyStream for EventStream<H> {
+  fn on_event(&mut self, event: &Event, ctx: &mut Context, body: &mut Buffer) -> io::Result<bool> {
+    let mut events = EventWriter::new(body);
+    if !self.is_opened {
+      self.is_opened = true;
+      self.handler.on_open(ctx, &mut events)?;
+    }
+    if ctx.socket().is_source_of(event) {
+      Ok(false)
+    }
+    else {
+      self.handler.on_event(event, ctx, &mut events).map(|is_open| !is_open)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::EventWriter;
+  use buffer::Buffer;
+
+  #[test]
+  fn test_send() {
+    let mut buffer = Buffer::new();
+    {
+      let mut events = EventWriter::new(&mut buffer);
+      events.send(Some("update"), "hello", Some("42")).unwrap();
+    }
+    assert_eq!(buffer.as_slice(), &b"event: update\ndata: hello\nid: 42\n\n"[..]);
+  }
+
+  #[test]
+  fn test_send_multiline_data() {
+    let mut buffer = Buffer::new();
+    {
+      let mut events = EventWriter::new(&mut buffer);
+      events.send_data("hello\nworld").unwrap();
+    }
+    assert_eq!(buffer.as_slice(), &b"data: hello\ndata: world\n\n"[..]);
+  }
+
+  #[test]
+  fn test_carriage_return_in_data() {
+    let mut buffer = Buffer::new();
+    {
+      let mut events = EventWriter::new(&mut buffer);
+      events.send_data("hello\rid: 1\r\nworld\n").unwrap();
+    }
+    assert_eq!(buffer.as_slice(), &b"data: hello\ndata: id: 1\ndata: world\n\n"[..]);
+  }
+
+  #[test]
+  fn test_reject_newline_in_field() {
+    let mut buffer = Buffer::new();
+    let mut events = EventWriter::new(&mut buffer);
+    assert!(events.send(Some("up\ndate"), "hello", None).is_err());
+    assert!(events.send(None, "hello", Some("4\r2")).is_err());
+    assert!(events.comment("a\nb").is_err());
+  }
+}
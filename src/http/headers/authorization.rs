@@ -1,5 +1,5 @@
 use http::{RequestResult, RequestError};
-use http::str::{try_split_two_mut, trim_mut, is_whitespace, slice_to_str};
+use http::str::{try_split_two_mut, trim_mut, trim_left_mut, is_whitespace, slice_to_str};
 use encoding::base64;
 
 pub struct BasicCredentials<'a> {
@@ -7,9 +7,108 @@ pub struct BasicCredentials<'a> {
 	pub password: &'a str
 }
 
+/// The parameters of a `Digest` authorization (RFC 7616),
+/// with quoted values unescaped in place.
+pub struct DigestCredentials<'a> {
+  pub username: &'a str,
+  pub realm: &'a str,
+  pub nonce: &'a str,
+  pub uri: &'a str,
+  pub response: &'a str,
+  pub algorithm: Option<&'a str>,
+  pub cnonce: Option<&'a str>,
+  pub opaque: Option<&'a str>,
+  pub qop: Option<&'a str>,
+  pub nc: Option<&'a str>,
+  pub userhash: bool
+}
+
+impl<'a> DigestCredentials<'a> {
+  pub fn parse(params: &'a mut [u8]) -> RequestResult<DigestCredentials<'a>> {
+    let mut username = None;
+    let mut realm = None;
+    let mut nonce = None;
+    let mut uri = None;
+    let mut response = None;
+    let mut credentials = DigestCredentials {
+      username: "", realm: "", nonce: "", uri: "", response: "",
+      algorithm: None, cnonce: None, opaque: None, qop: None, nc: None,
+      userhash: false
+    };
+    let mut remaining = params;
+    while let Some((name, value, next)) = next_digest_param(remaining)? {
+      remaining = next;
+      match name.to_ascii_lowercase().as_str() {
+        "username" => username = Some(value),
+        "realm" => realm = Some(value),
+        "nonce" => nonce = Some(value),
+        "uri" => uri = Some(value),
+        "response" => response = Some(value),
+        "algorithm" => credentials.algorithm = Some(value),
+        "cnonce" => credentials.cnonce = Some(value),
+        "opaque" => credentials.opaque = Some(value),
+        "qop" => credentials.qop = Some(value),
+        "nc" => credentials.nc = Some(value),
+        "userhash" => credentials.userhash = value.eq_ignore_ascii_case("true"),
+        _ => {}
+      }
+    }
+    credentials.username = username.ok_or(RequestError::InvalidHeader)?;
+    credentials.realm = realm.ok_or(RequestError::InvalidHeader)?;
+    credentials.nonce = nonce.ok_or(RequestError::InvalidHeader)?;
+    credentials.uri = uri.ok_or(RequestError::InvalidHeader)?;
+    credentials.response = response.ok_or(RequestError::InvalidHeader)?;
+    Ok(credentials)
+  }
+}
+
+// parses the next `name=value` or `name="quoted value"` pair,
+// returning it with the remaining parameters
+fn next_digest_param<'a>(params: &'a mut [u8])
+  -> RequestResult<Option<(&'a str, &'a str, &'a mut [u8])>>
+{
+  let start = match params.iter().position(|&b| !is_whitespace(b) && b != b',') {
+    Some(idx) => idx,
+    None => return Ok(None)
+  };
+  let params = &mut params[start ..];
+  let assign_idx = params.iter().position(|&b| b == b'=').ok_or(RequestError::InvalidHeader)?;
+  let (name, value) = params.split_at_mut(assign_idx);
+  let name = slice_to_str(trim_mut(name, is_whitespace))?;
+  let value = trim_left_mut(&mut value[1 ..], is_whitespace);
+
+  if value.first() == Some(&b'"') {
+    // unescape quoted-pair's while looking for the closing quote
+    let mut read_idx = 1;
+    let mut write_idx = 0;
+    loop {
+      let mut b = *value.get(read_idx).ok_or(RequestError::InvalidHeader)?;
+      if b == b'"' {
+        break;
+      }
+      if b == b'\\' {
+        read_idx += 1;
+        b = *value.get(read_idx).ok_or(RequestError::InvalidHeader)?;
+      }
+      value[write_idx] = b;
+      write_idx += 1;
+      read_idx += 1;
+    }
+    let (value, remaining) = value.split_at_mut(read_idx + 1);
+    let value = slice_to_str(&value[.. write_idx])?;
+    Ok(Some((name, value, remaining)))
+  }
+  else {
+    let end_idx = value.iter().position(|&b| b == b',').unwrap_or(value.len());
+    let (value, remaining) = value.split_at_mut(end_idx);
+    let value = slice_to_str(trim_mut(value, is_whitespace))?;
+    Ok(Some((name, value, remaining)))
+  }
+}
+
 pub enum Authorization<'a> {
   Basic(BasicCredentials<'a>),
-  Digest(&'a str),
+  Digest(DigestCredentials<'a>),
   Bearer(&'a str),  //should be mut to decode inline whatever the format is?
   Other(&'a str, &'a mut [u8])
 }
@@ -30,7 +129,7 @@ impl<'a> Authorization<'a> {
         Ok(Authorization::Basic( BasicCredentials { user, password } ))
       },
       "Digest" => {
-        Ok(Authorization::Digest(slice_to_str(credentials)?))
+        Ok(Authorization::Digest(DigestCredentials::parse(credentials)?))
       },
       "Bearer" => {
         Ok(Authorization::Bearer(slice_to_str(credentials)?))
@@ -60,4 +159,37 @@ mod tests {
       assert!(false);
     }
   }
+
+  #[test]
+  fn test_digest_auth() {
+    let header = b"Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+      uri=\"/dir/index.html\", algorithm=SHA-256, nonce=\"7ypf\", nc=00000001, \
+      cnonce=\"f2/wE4q\", qop=auth, response=\"753927fa\", opaque=\"FQhe\\\"/\"";
+    let mut buffer = header.to_vec();
+    let auth = Authorization::parse(&mut buffer).unwrap();
+    if let Authorization::Digest(ref credentials) = auth {
+      assert_eq!(credentials.username, "Mufasa");
+      assert_eq!(credentials.realm, "http-auth@example.org");
+      assert_eq!(credentials.uri, "/dir/index.html");
+      assert_eq!(credentials.algorithm, Some("SHA-256"));
+      assert_eq!(credentials.nonce, "7ypf");
+      assert_eq!(credentials.nc, Some("00000001"));
+      assert_eq!(credentials.cnonce, Some("f2/wE4q"));
+      assert_eq!(credentials.qop, Some("auth"));
+      assert_eq!(credentials.response, "753927fa");
+      assert_eq!(credentials.opaque, Some("FQhe\"/"));
+      assert!(!credentials.userhash);
+    } else {
+      assert!(false);
+    }
+  }
+
+  #[test]
+  fn test_digest_auth_invalid() {
+    let mut buffer = b"Digest username=\"Mufasa".to_vec();
+    assert!(Authorization::parse(&mut buffer).is_err());
+    // no response
+    let mut buffer = b"Digest username=a, realm=b, nonce=c, uri=/".to_vec();
+    assert!(Authorization::parse(&mut buffer).is_err());
+  }
 }
@@ -1,4 +1,3 @@
-use http::str::{trim_mut, is_whitespace, slice_to_str};
 use http::RequestResult;
 
 pub enum ETagMatch<'a> {
@@ -7,13 +6,12 @@ pub enum ETagMatch<'a> {
 }
 
 impl<'a> ETagMatch<'a> {
-  pub fn parse(header_value: &'a mut [u8]) -> RequestResult<ETagMatch<'a>> {
-    let value = trim_mut(header_value, is_whitespace);
-    let value = trim_mut(value, |b| b == 34);  // trim "
-    if value == b"*" {
+  pub fn parse(header_value: &'a str) -> RequestResult<ETagMatch<'a>> {
+    let value = header_value.trim().trim_matches('"');
+    if value == "*" {
       Ok(ETagMatch::Any)
     } else {
-      Ok(ETagMatch::ETag(slice_to_str(value)?))
+      Ok(ETagMatch::ETag(value))
     }
   }
 }
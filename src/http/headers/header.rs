@@ -1,6 +1,7 @@
 use super::{MimeType, Authorization, ContentRange, RawHeader, ETagMatch};
 use http::{RequestResult, RequestError};
 use http::str::slice_to_str;
+use http::cookie::Cookies;
 use std::str::FromStr;
 
 pub enum Header<'a> {
@@ -10,27 +11,104 @@ pub enum Header<'a> {
   Authorization(Authorization<'a>),
   Referer(&'a str),
   Range(ContentRange<'a>),
-  Other(RawHeader<'a>),
+  Other(&'a str, &'a str),
   IfNoneMatch(ETagMatch<'a>),
+  Upgrade(&'a str),
+  Connection(&'a str),
+  SecWebsocketKey(&'a str),
+  SecWebsocketVersion(u64),
+  LastEventId(&'a str),
+  TransferEncoding(&'a str),
+  Cookie(Cookies<'a>),
 }
 
 fn parse_u64(num_str: &str) -> RequestResult<u64> {
   u64::from_str(num_str).map_err(|_| RequestError::InvalidHeader)
 }
 
+/// Headers whose value is modified while parsing,
+/// and so can't be kept around as received.
+pub fn is_decoded_in_place(name: &str) -> bool {
+  name.eq_ignore_ascii_case("Authorization") || name.eq_ignore_ascii_case("Cookie")
+}
+
 impl<'a> Header<'a> {
   pub fn from_raw(raw_header: RawHeader<'a>) -> RequestResult<Header<'a>> {
-    let header = match raw_header.name {
-      "Host" => Header::Host(slice_to_str(raw_header.value)?),
-      "Authorization" => Header::Authorization(Authorization::parse(raw_header.value)?),
-      "Referer" => Header::Referer(slice_to_str(raw_header.value)?),
-      "Content-Type" => Header::ContentType(MimeType::parse(slice_to_str(raw_header.value)?)?),
-      "Content-Length" => Header::ContentLength(parse_u64(slice_to_str(raw_header.value)?)?),
-      "Range" => Header::Range(ContentRange::parse(slice_to_str(raw_header.value)?)?),
-      "If-None-Match" => Header::IfNoneMatch(ETagMatch::parse(raw_header.value)?),
-      _ => Header::Other(raw_header)
+    if raw_header.name.eq_ignore_ascii_case("Authorization") {
+      return Ok(Header::Authorization(Authorization::parse(raw_header.value)?));
+    }
+    if raw_header.name.eq_ignore_ascii_case("Cookie") {
+      return Ok(Header::Cookie(Cookies::decode_and_create(raw_header.value)));
+    }
+    Header::parse(raw_header.name, slice_to_str(raw_header.value)?)
+  }
+
+  pub fn parse(name: &'a str, value: &'a str) -> RequestResult<Header<'a>> {
+    let is = |n: &str| name.eq_ignore_ascii_case(n);
+    let header = if is("Host") {
+      Header::Host(value)
+    } else if is("Referer") {
+      Header::Referer(value)
+    } else if is("Content-Type") {
+      Header::ContentType(MimeType::parse(value)?)
+    } else if is("Content-Length") {
+      Header::ContentLength(parse_u64(value)?)
+    } else if is("Range") {
+      Header::Range(ContentRange::parse(value)?)
+    } else if is("If-None-Match") {
+      Header::IfNoneMatch(ETagMatch::parse(value)?)
+    } else if is("Upgrade") {
+      Header::Upgrade(value)
+    } else if is("Connection") {
+      Header::Connection(value)
+    } else if is("Sec-Websocket-Key") {
+      Header::SecWebsocketKey(value)
+    } else if is("Sec-Websocket-Version") {
+      Header::SecWebsocketVersion(parse_u64(value)?)
+    } else if is("Last-Event-Id") {
+      Header::LastEventId(value)
+    } else if is("Transfer-Encoding") {
+      Header::TransferEncoding(value)
+    } else {
+      Header::Other(name, value)
     };
     Ok(header)
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::Header;
+
+  #[test]
+  fn test_case_insensitive_name() {
+    match Header::parse("content-length", "42") {
+      Ok(Header::ContentLength(len)) => assert_eq!(len, 42),
+      _ => panic!("should be a content length")
+    };
+    match Header::parse("X-Foo", "bar") {
+      Ok(Header::Other(name, value)) => assert_eq!((name, value), ("X-Foo", "bar")),
+      _ => panic!("should be another header")
+    };
+  }
+
+  #[test]
+  fn test_lowercase_websocket_headers() {
+    match Header::parse("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==") {
+      Ok(Header::SecWebsocketKey(key)) => assert_eq!(key, "dGhlIHNhbXBsZSBub25jZQ=="),
+      _ => panic!("should be a websocket key")
+    };
+    match Header::parse("sec-websocket-version", "13") {
+      Ok(Header::SecWebsocketVersion(version)) => assert_eq!(version, 13),
+      _ => panic!("should be a websocket version")
+    };
+  }
+
+  #[test]
+  fn test_lowercase_last_event_id() {
+    match Header::parse("last-event-id", "42") {
+      Ok(Header::LastEventId(id)) => assert_eq!(id, "42"),
+      _ => panic!("should be a last event id")
+    };
+  }
+}
@@ -1,15 +1,78 @@
-use super::Header;
-use http::RequestResult;
+use std::slice;
+use http::{RequestResult, RequestError};
 
+pub const MAX_HEADER_COUNT : usize = 64;
 
-pub struct HeaderIterator<'a> {
-  headers: &'a str
+/// The headers of a request as received, with normalized names.
+/// Stored inline so parsing a request doesn't allocate.
+pub struct HeaderList<'a> {
+  headers: [(&'a str, &'a str); MAX_HEADER_COUNT],
+  len: usize
 }
 
-impl<'a> Iterator for HeaderIterator<'a> {
-  type Item = RequestResult<Header<'a>>;
+impl<'a> HeaderList<'a> {
+  pub fn new() -> HeaderList<'a> {
+    HeaderList {
+      headers: [("", ""); MAX_HEADER_COUNT],
+      len: 0
+    }
+  }
+
+  pub fn push(&mut self, name: &'a str, value: &'a str) -> RequestResult<()> {
+    if self.len == MAX_HEADER_COUNT {
+      return Err(RequestError::HeadersTooLarge);
+    }
+    self.headers[self.len] = (name, value);
+    self.len += 1;
+    Ok( () )
+  }
+
+  /// the value of the first header with the given name, ignoring case
+  pub fn get(&self, name: &str) -> Option<&'a str> {
+    self.iter()
+      .find(|&(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value)
+  }
+
+  pub fn iter<'l>(&'l self) -> HeaderIterator<'l, 'a> {
+    HeaderIterator { headers: self.headers[.. self.len].iter() }
+  }
+}
+
+pub struct HeaderIterator<'l, 'a: 'l> {
+  headers: slice::Iter<'l, (&'a str, &'a str)>
+}
+
+impl<'l, 'a> Iterator for HeaderIterator<'l, 'a> {
+  type Item = (&'a str, &'a str);
 
   fn next(&mut self) -> Option<Self::Item> {
-    None
+    self.headers.next().map(|&header| header)
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{HeaderList, MAX_HEADER_COUNT};
+
+  #[test]
+  fn test_get_ignores_case() {
+    let mut headers = HeaderList::new();
+    headers.push("Accept", "text/html").unwrap();
+    headers.push("X-Foo", "bar").unwrap();
+    headers.push("X-Foo", "baz").unwrap();
+    assert_eq!(headers.get("x-foo"), Some("bar"));
+    assert_eq!(headers.get("ACCEPT"), Some("text/html"));
+    assert_eq!(headers.get("User-Agent"), None);
+    assert_eq!(headers.iter().count(), 3);
+  }
+
+  #[test]
+  fn test_too_many_headers() {
+    let mut headers = HeaderList::new();
+    for _ in 0 .. MAX_HEADER_COUNT {
+      headers.push("X-Foo", "bar").unwrap();
+    }
+    assert!(headers.push("X-Foo", "bar").is_err());
+  }
+}
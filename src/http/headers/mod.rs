@@ -5,6 +5,7 @@ mod etag_match;
 pub mod authorization;
 mod content_range;
 mod mime_type;
+mod iterator;
 
 pub use self::header::*;
 pub use self::raw_header::*;
@@ -13,3 +14,4 @@ pub use self::authorization::Authorization;
 pub use self::content_range::*;
 pub use self::mime_type::*;
 pub use self::etag_match::*;
+pub use self::iterator::{HeaderList, HeaderIterator};
@@ -13,12 +13,17 @@ impl<'a> RawHeader<'a> {
     // TODO: should this be try_split_two_mut?
     if let Some(idx) = line.position(b":") {
       let (name, value) = line.split_at_mut(idx);
-      name.make_ascii_uppercase();
+      let name = trim_mut(name, is_whitespace);
+      if name.is_empty() {
+        return Err(RequestError::InvalidHeader);
+      }
+      // normalize to Capitalized-Words, e.g. Content-Type
+      name.make_ascii_lowercase();
       for name_word in buffer_split_mut(name, b"-") {
-        name_word[0..1].make_ascii_uppercase();
-        name_word[1..].make_ascii_lowercase();
+        if let Some(first_char) = name_word.first_mut() {
+          first_char.make_ascii_uppercase();
+        }
       }
-      let name = trim_mut(name, is_whitespace);
       let name = slice_to_str(name)?;
       let value = &mut value[1..];  //cut ':' off
       let value = trim_mut(value, is_whitespace);
@@ -52,4 +57,18 @@ mod tests {
     assert_eq!(header.name, "Content-Type");
     assert_eq!(header.value, b"text/plain");
   }
+  #[test]
+  fn test_parse_header_leading_whitespace() {
+    let mut s = [0u8; 22];
+    copy_str(&mut s, b" x--custom-HEADER: foo");
+    let header = super::RawHeader::parse(&mut s).unwrap();
+    assert_eq!(header.name, "X--Custom-Header");
+    assert_eq!(header.value, b"foo");
+  }
+  #[test]
+  fn test_parse_header_empty_name() {
+    let mut s = [0u8; 6];
+    copy_str(&mut s, b" : foo");
+    assert!(super::RawHeader::parse(&mut s).is_err());
+  }
 }
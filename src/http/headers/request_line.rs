@@ -15,6 +15,14 @@ impl HttpVersion {
   }
 }
 
+// major.minor, as in HTTP/1.1
+fn is_version_number(version: &[u8]) -> bool {
+  version.len() == 3 &&
+    version[0].is_ascii_digit() &&
+    version[1] == b'.' &&
+    version[2].is_ascii_digit()
+}
+
 pub struct RequestLine<'a> {
   pub method: &'a str,
   pub url: &'a str,
@@ -30,6 +38,9 @@ impl<'a> RequestLine<'a> {
     let http_version = words.next();
 
     if let (Some(method), Some(url), Some(http_version)) = (method, url, http_version) {
+      if !http_version.starts_with(b"HTTP/") || !is_version_number(&http_version[5..]) {
+        return Err(RequestError::InvalidRequestLine);
+      }
       if let Some(version) = http_version.get(5..) {
         method.make_ascii_uppercase();
         let (url, querystring) = try_split_two_mut(url, b"?");
@@ -79,4 +90,13 @@ mod tests {
     let bar = qs_iter.next().unwrap();
     assert_eq!(bar.name, "?bar");
   }
+  #[test]
+  fn test_invalid_version() {
+    let mut s = [0u8; 17];
+    copy_str(&mut s, b"GET /foo FTP/1.1 ");
+    assert!(super::RequestLine::parse(&mut s).is_err());
+    let mut s = [0u8; 15];
+    copy_str(&mut s, b"GET /foo HTTP/1");
+    assert!(super::RequestLine::parse(&mut s).is_err());
+  }
 }
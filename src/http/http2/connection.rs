@@ -0,0 +1,1162 @@
+use std;
+use std::cmp;
+use std::str;
+use std::io::{self, Write, ErrorKind};
+use buffer::Buffer;
+use io::{Context, Event, Registered};
+use io::sources::file::Reader;
+use io::handlers::send_buffer;
+use http::{Request, Responder, Response, RequestHandler, RequestError, RequestResult, status};
+use http::internal::ResponseBody;
+use http::request_handler::{handle_io_error, handle_request_error, handle_no_response, respond_with_error};
+use http::str::{trim_mut, is_whitespace};
+use split::{buffer_split_mut, BufferExt};
+use super::frame::{self, FrameHeader, kind, flag, setting, error_code, HEADER_LEN};
+use super::hpack;
+
+const PREFACE : &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+// limits advertised to the client, kept low to bound the memory per connection
+pub const MAX_CONCURRENT_STREAMS : usize = 8;
+const MAX_FRAME_SIZE : usize = 16_384;
+const MAX_HEADER_LIST_SIZE : usize = 4000;
+const HEADER_TABLE_SIZE : usize = hpack::MAX_TABLE_SIZE;
+// a header block can span several CONTINUATION frames
+const MAX_HEADER_BLOCK_SIZE : usize = 4 * MAX_FRAME_SIZE;
+const DEFAULT_WINDOW_SIZE : i64 = 65_535;
+const MAX_WINDOW_SIZE : i64 = 0x7FFF_FFFF;
+// stop producing DATA frames until the socket has drained
+const MAX_OUTGOING_SIZE : usize = 64 * 1024;
+const MAX_METHOD_LEN : usize = 16;
+const MAX_PATH_LEN : usize = 2048;
+const MAX_AUTHORITY_LEN : usize = 256;
+// request bodies are passed to the handler in one piece, once complete
+const MAX_REQUEST_BODY_SIZE : usize = 64 * 1024;
+// the largest frame we accept, so at most one frame is buffered
+const MAX_INCOMING_SIZE : usize = MAX_FRAME_SIZE + HEADER_LEN;
+
+enum Error {
+  Connection(u32),
+  Io(io::Error)
+}
+
+impl From<io::Error> for Error {
+  fn from(err: io::Error) -> Error {
+    Error::Io(err)
+  }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+enum Body {
+  // the response buffer, and the offset of the body bytes still to send
+  Buffer(Buffer, usize),
+  // the reader, and how many bytes of the current read have been sent
+  File(Registered<Reader>, usize)
+}
+
+struct Stream<H> {
+  id: u32,
+  send_window: i64,
+  is_request_complete: bool,
+  is_responding: bool,
+  body: Option<Body>,
+  // every stream is a request of its own, so it gets its own handler
+  handler: H,
+  // the request body received so far, created by the first DATA frame
+  request_body: Option<Buffer>
+}
+
+impl<H> Stream<H> {
+  fn new(id: u32, send_window: i64, is_request_complete: bool, handler: H) -> Stream<H> {
+    Stream {
+      id,
+      send_window,
+      is_request_complete,
+      is_responding: false,
+      body: None,
+      handler,
+      request_body: None
+    }
+  }
+}
+
+/// Serves requests multiplexed over a single HTTP/2 connection,
+/// creating a RequestHandler for every stream, like HTTP/1.1 does per connection.
+/// Responses that need the connection to themselves
+/// (long-polling, upgrades and streams) get a `501 Not Implemented`,
+/// which is why TLS only offers HTTP/2 when enabled with `tls::Policy::http2`.
+pub struct Connection<H, F> {
+  create_handler: F,
+  incoming: Option<Buffer>,
+  outgoing: Buffer,
+  header_block: Buffer,
+  request_text: Buffer,
+  cookie_text: Buffer,
+  decoder: hpack::Decoder,
+  streams: [Option<Stream<H>>; MAX_CONCURRENT_STREAMS],
+  // stream and END_STREAM flag of a header block continued in CONTINUATION frames
+  header_stream: Option<(u32, bool)>,
+  last_stream_id: u32,
+  send_window: i64,
+  initial_window_size: i64,
+  max_frame_size: usize,
+  is_started: bool,
+  is_preface_received: bool,
+  is_closing: bool
+}
+
+impl<H: RequestHandler, F: Fn() -> H> Connection<H, F> {
+  pub fn new(create_handler: F) -> Connection<H, F> {
+    Connection {
+      create_handler,
+      incoming: Some(Buffer::new()),
+      outgoing: Buffer::new(),
+      header_block: Buffer::new(),
+      request_text: Buffer::new(),
+      cookie_text: Buffer::new(),
+      decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+      streams: [None, None, None, None, None, None, None, None],
+      header_stream: None,
+      last_stream_id: 0,
+      send_window: DEFAULT_WINDOW_SIZE,
+      initial_window_size: DEFAULT_WINDOW_SIZE,
+      max_frame_size: MAX_FRAME_SIZE,
+      is_started: false,
+      is_preface_received: false,
+      is_closing: false
+    }
+  }
+
+  fn process_event(&mut self, event: &Event, ctx: &mut Context) -> Result<()> {
+    if !self.is_started {
+      self.is_started = true;
+      frame::write_settings(&mut self.outgoing, &[
+        (setting::ENABLE_PUSH, 0),
+        (setting::MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS as u32),
+        (setting::HEADER_TABLE_SIZE, HEADER_TABLE_SIZE as u32),
+        (setting::MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE as u32)
+      ])?;
+    }
+    if ctx.socket().is_source_of(event) && event.kind().is_readable() {
+      let mut incoming = self.incoming.take().unwrap();
+      let result = self.read_incoming(&mut incoming, ctx);
+      self.incoming = Some(incoming);
+      result?;
+    }
+    // file reads or window updates might allow to send more
+    self.send_bodies(ctx)?;
+    Ok( () )
+  }
+
+  // reads no more than a frame at a time, and handles it before reading on
+  fn read_incoming(&mut self, incoming: &mut Buffer, ctx: &mut Context) -> Result<()> {
+    loop {
+      let is_full = read_socket(incoming, MAX_INCOMING_SIZE, ctx)?;
+      let len_before = incoming.len();
+      self.read_frames(incoming, ctx)?;
+      if !is_full {
+        return Ok( () );
+      }
+      if incoming.len() == len_before {
+        return Err(Error::Connection(error_code::FRAME_SIZE_ERROR));
+      }
+    }
+  }
+
+  fn read_frames(&mut self, incoming: &mut Buffer, ctx: &mut Context) -> Result<()> {
+    if !self.is_preface_received {
+      if incoming.len() < PREFACE.len() {
+        return Ok( () );
+      }
+      if &incoming.as_slice()[.. PREFACE.len()] != PREFACE {
+        return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+      }
+      incoming.remove_head(PREFACE.len());
+      self.is_preface_received = true;
+    }
+    while let Some(header) = FrameHeader::parse(incoming.as_slice()) {
+      if header.len > MAX_FRAME_SIZE {
+        return Err(Error::Connection(error_code::FRAME_SIZE_ERROR));
+      }
+      let frame_len = HEADER_LEN + header.len;
+      if incoming.len() < frame_len {
+        break;
+      }
+      self.handle_frame(&header, &incoming.as_slice()[HEADER_LEN .. frame_len], ctx)?;
+      incoming.remove_head(frame_len);
+    }
+    Ok( () )
+  }
+
+  fn handle_frame(&mut self, header: &FrameHeader, payload: &[u8], ctx: &mut Context) -> Result<()> {
+    if let Some((id, _)) = self.header_stream {
+      if header.kind != kind::CONTINUATION || header.stream_id != id {
+        return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+      }
+    }
+    match header.kind {
+      kind::DATA => self.handle_data(header, payload, ctx),
+      kind::HEADERS => self.handle_headers(header, payload, ctx),
+      kind::CONTINUATION => self.handle_continuation(header, payload, ctx),
+      kind::SETTINGS => self.handle_settings(header, payload),
+      kind::WINDOW_UPDATE => self.handle_window_update(header, payload, ctx),
+      kind::PING => {
+        if header.stream_id != 0 {
+          return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+        }
+        if payload.len() != 8 {
+          return Err(Error::Connection(error_code::FRAME_SIZE_ERROR));
+        }
+        if !header.has_flag(flag::ACK) {
+          frame::write_frame(&mut self.outgoing, kind::PING, flag::ACK, 0, payload)?;
+        }
+        Ok( () )
+      },
+      kind::RST_STREAM => {
+        if header.stream_id == 0 {
+          return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+        }
+        if let Some(idx) = self.stream_index(header.stream_id) {
+          self.remove_stream(idx, ctx)?;
+        }
+        Ok( () )
+      },
+      kind::GOAWAY => {
+        // finish the streams in progress, then close
+        self.is_closing = true;
+        Ok( () )
+      },
+      kind::PUSH_PROMISE => Err(Error::Connection(error_code::PROTOCOL_ERROR)),
+      // PRIORITY is only a hint, and unknown frames must be ignored
+      _ => Ok( () )
+    }
+  }
+
+  fn handle_headers(&mut self, header: &FrameHeader, payload: &[u8], ctx: &mut Context) -> Result<()> {
+    if header.stream_id == 0 {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    let mut block = frame::strip_padding(header, payload)
+      .ok_or(Error::Connection(error_code::PROTOCOL_ERROR))?;
+    if header.has_flag(flag::PRIORITY) {
+      block = block.get(5 ..).ok_or(Error::Connection(error_code::PROTOCOL_ERROR))?;
+    }
+    self.header_block.clear();
+    self.header_block.write_all(block)?;
+    let end_stream = header.has_flag(flag::END_STREAM);
+    if header.has_flag(flag::END_HEADERS) {
+      self.finish_header_block(header.stream_id, end_stream, ctx)
+    }
+    else {
+      self.header_stream = Some((header.stream_id, end_stream));
+      Ok( () )
+    }
+  }
+
+  fn handle_continuation(&mut self, header: &FrameHeader, payload: &[u8], ctx: &mut Context) -> Result<()> {
+    let (id, end_stream) = self.header_stream
+      .ok_or(Error::Connection(error_code::PROTOCOL_ERROR))?;
+    if self.header_block.len() + payload.len() > MAX_HEADER_BLOCK_SIZE {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    self.header_block.write_all(payload)?;
+    if header.has_flag(flag::END_HEADERS) {
+      self.header_stream = None;
+      self.finish_header_block(id, end_stream, ctx)
+    }
+    else {
+      Ok( () )
+    }
+  }
+
+  fn finish_header_block(&mut self, id: u32, end_stream: bool, ctx: &mut Context) -> Result<()> {
+    // always decode, to keep the hpack table in sync with the client
+    let request_result = {
+      let mut builder = RequestBuilder::new(&mut self.request_text, &mut self.cookie_text);
+      self.decoder.decode(self.header_block.as_slice(), |name, value| builder.add(name, value))
+        .map_err(|_| Error::Connection(error_code::COMPRESSION_ERROR))?;
+      builder.finish()
+    };
+    if id <= self.last_stream_id {
+      // trailers, which have to end the stream
+      return match self.stream_index(id) {
+        Some(idx) if end_stream => self.finish_request(idx, ctx),
+        Some(_) => Err(Error::Connection(error_code::PROTOCOL_ERROR)),
+        None => Err(Error::Connection(error_code::STREAM_CLOSED))
+      };
+    }
+    if id % 2 == 0 {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    self.last_stream_id = id;
+    if self.is_closing {
+      return Ok( () );
+    }
+    let idx = match self.streams.iter().position(|s| s.is_none()) {
+      Some(idx) => idx,
+      None => {
+        frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, id, error_code::REFUSED_STREAM)?;
+        return Ok( () );
+      }
+    };
+    let handler = (self.create_handler)();
+    self.streams[idx] = Some(Stream::new(id, self.initial_window_size, end_stream, handler));
+    let response = {
+      let responder = Responder::new();
+      let request_text = &mut self.request_text;
+      let connection = *ctx.connection();
+      let handler = &mut self.streams[idx].as_mut().unwrap().handler;
+      let request = request_result
+        .and_then(|_| Request::parse(request_text.as_mut_slice()))
+        .and_then(|mut request| {
+          request.set_connection(connection);
+          check_request(&request).map(|_| request)
+        });
+      match request {
+        Ok(request) => {
+          handler.read_headers(&request, &responder)
+            .unwrap_or_else(|err| handle_io_error(handler, err, &responder))
+        },
+        Err(err) => handle_request_error(handler, err, &responder)
+      }
+    };
+    match response {
+      Some(response) => self.start_response(idx, response, ctx),
+      None if end_stream => self.start_response(idx, handle_no_response(), ctx),
+      None => Ok( () )
+    }
+  }
+
+  fn handle_data(&mut self, header: &FrameHeader, payload: &[u8], ctx: &mut Context) -> Result<()> {
+    if header.stream_id == 0 {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    // the data is consumed straight away, so give back the window
+    if header.len != 0 {
+      frame::write_u32_frame(&mut self.outgoing, kind::WINDOW_UPDATE, 0, header.len as u32)?;
+    }
+    let data = frame::strip_padding(header, payload)
+      .ok_or(Error::Connection(error_code::PROTOCOL_ERROR))?;
+    let end_stream = header.has_flag(flag::END_STREAM);
+    let idx = match self.stream_index(header.stream_id) {
+      Some(idx) => idx,
+      None if header.stream_id > self.last_stream_id => {
+        return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+      },
+      None => {
+        frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, header.stream_id, error_code::STREAM_CLOSED)?;
+        return Ok( () );
+      }
+    };
+    let is_responding = {
+      let stream = self.streams[idx].as_ref().unwrap();
+      if stream.is_request_complete {
+        return Err(Error::Connection(error_code::STREAM_CLOSED));
+      }
+      stream.is_responding
+    };
+    if header.len != 0 && !end_stream {
+      frame::write_u32_frame(&mut self.outgoing, kind::WINDOW_UPDATE, header.stream_id, header.len as u32)?;
+    }
+    if !is_responding {
+      let is_too_large = {
+        let stream = self.streams[idx].as_mut().unwrap();
+        let body = stream.request_body.get_or_insert_with(Buffer::new);
+        if body.len() + data.len() > MAX_REQUEST_BODY_SIZE {
+          true
+        } else {
+          body.write_all(data)?;
+          false
+        }
+      };
+      if is_too_large {
+        let response = {
+          let stream = self.streams[idx].as_mut().unwrap();
+          stream.request_body = None;
+          handle_request_error(&mut stream.handler, RequestError::PayloadTooLarge, &Responder::new())
+        };
+        self.start_response(idx, response.unwrap_or_else(|| handle_no_response()), ctx)?;
+      }
+    }
+    if end_stream {
+      self.finish_request(idx, ctx)?;
+    }
+    Ok( () )
+  }
+
+  fn handle_settings(&mut self, header: &FrameHeader, payload: &[u8]) -> Result<()> {
+    if header.stream_id != 0 {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    if header.has_flag(flag::ACK) {
+      return if payload.len() == 0 {
+        Ok( () )
+      } else {
+        Err(Error::Connection(error_code::FRAME_SIZE_ERROR))
+      };
+    }
+    if payload.len() % 6 != 0 {
+      return Err(Error::Connection(error_code::FRAME_SIZE_ERROR));
+    }
+    for param in payload.chunks(6) {
+      let id = (param[0] as u16) << 8 | param[1] as u16;
+      let value = frame::read_u32(&param[2 ..]);
+      match id {
+        setting::INITIAL_WINDOW_SIZE => {
+          let window_size = value as i64;
+          if window_size > MAX_WINDOW_SIZE {
+            return Err(Error::Connection(error_code::FLOW_CONTROL_ERROR));
+          }
+          let delta = window_size - self.initial_window_size;
+          self.initial_window_size = window_size;
+          for stream in self.streams.iter_mut().filter_map(|s| s.as_mut()) {
+            stream.send_window += delta;
+          }
+        },
+        setting::MAX_FRAME_SIZE => {
+          if value < MAX_FRAME_SIZE as u32 || value > 0xFF_FFFF {
+            return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+          }
+          self.max_frame_size = value as usize;
+        },
+        // we never push, and only send literal headers
+        // so the other settings don't affect us
+        _ => {}
+      }
+    }
+    frame::write_frame(&mut self.outgoing, kind::SETTINGS, flag::ACK, 0, &[])?;
+    Ok( () )
+  }
+
+  fn handle_window_update(&mut self, header: &FrameHeader, payload: &[u8], ctx: &mut Context) -> Result<()> {
+    if payload.len() != 4 {
+      return Err(Error::Connection(error_code::FRAME_SIZE_ERROR));
+    }
+    let increment = (frame::read_u32(payload) & 0x7FFF_FFFF) as i64;
+    if increment == 0 {
+      return Err(Error::Connection(error_code::PROTOCOL_ERROR));
+    }
+    if header.stream_id == 0 {
+      self.send_window += increment;
+      if self.send_window > MAX_WINDOW_SIZE {
+        return Err(Error::Connection(error_code::FLOW_CONTROL_ERROR));
+      }
+    }
+    else if let Some(idx) = self.stream_index(header.stream_id) {
+      let is_overflow = {
+        let stream = self.streams[idx].as_mut().unwrap();
+        stream.send_window += increment;
+        stream.send_window > MAX_WINDOW_SIZE
+      };
+      if is_overflow {
+        frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, header.stream_id, error_code::FLOW_CONTROL_ERROR)?;
+        self.remove_stream(idx, ctx)?;
+      }
+    }
+    Ok( () )
+  }
+
+  fn start_response(&mut self, idx: usize, response: Response, ctx: &mut Context) -> Result<()> {
+    let (_, mut buffer, body) = response.into_parts();
+    let file_reader = match body {
+      ResponseBody::InBuffer => None,
+      ResponseBody::File(reader) => Some(reader),
+      _ => {
+        let responder = Responder::new();
+        let message = "Response body is not supported over HTTP/2";
+        let handler = &mut self.streams[idx].as_mut().unwrap().handler;
+        let response = respond_with_error(handler, status::NOT_IMPLEMENTED, message, &responder)
+          .unwrap_or_else(|| handle_no_response());
+        return self.start_response(idx, response, ctx);
+      }
+    };
+    let head_len = buffer.as_slice().position(b"\r\n\r\n")
+      .map(|pos| pos + 4)
+      .unwrap_or(buffer.len());
+    let has_body = file_reader.is_some() || head_len < buffer.len();
+    let id = self.streams[idx].as_ref().unwrap().id;
+
+    // the request's header block has been decoded already
+    self.header_block.clear();
+    encode_response_head(&mut buffer.as_mut_slice()[.. head_len], &mut self.header_block)?;
+    write_header_block(&mut self.outgoing, id, self.header_block.as_slice(), !has_body, self.max_frame_size)?;
+
+    let body = match file_reader {
+      Some(reader) => {
+        let mut reader = ctx.register_file(reader)?;
+        match reader.try_queue_read() {
+          Ok(_) => Some(Body::File(reader, 0)),
+          Err(err) => {
+            println!("could not read file for http2 response: {:?}", err);
+            reader.into_deregistered(ctx)?;
+            frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, id, error_code::INTERNAL_ERROR)?;
+            return self.remove_stream(idx, ctx);
+          }
+        }
+      },
+      None if has_body => Some(Body::Buffer(buffer, head_len)),
+      None => None
+    };
+    {
+      let stream = self.streams[idx].as_mut().unwrap();
+      stream.is_responding = true;
+      stream.body = body;
+      stream.request_body = None;
+    }
+    if !has_body {
+      self.finish_response(idx, ctx)?;
+    }
+    Ok( () )
+  }
+
+  // the whole body has been received, so it can be passed to the handler
+  fn finish_request(&mut self, idx: usize, ctx: &mut Context) -> Result<()> {
+    let response = {
+      let stream = self.streams[idx].as_mut().unwrap();
+      stream.is_request_complete = true;
+      if stream.is_responding {
+        return Ok( () );
+      }
+      match stream.request_body.take() {
+        Some(mut body) => {
+          let responder = Responder::new();
+          let handler = &mut stream.handler;
+          handler.read_body(body.as_mut_slice(), &responder)
+            .unwrap_or_else(|err| handle_io_error(handler, err, &responder))
+        },
+        None => None
+      }
+    };
+    self.start_response(idx, response.unwrap_or_else(|| handle_no_response()), ctx)
+  }
+
+  fn finish_response(&mut self, idx: usize, ctx: &mut Context) -> Result<()> {
+    let is_request_complete = self.streams[idx].as_ref().map(|s| s.is_request_complete);
+    if let Some(false) = is_request_complete {
+      // tell the client it can stop sending the request body
+      let id = self.streams[idx].as_ref().unwrap().id;
+      frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, id, error_code::NO_ERROR)?;
+    }
+    self.remove_stream(idx, ctx)
+  }
+
+  fn remove_stream(&mut self, idx: usize, ctx: &mut Context) -> Result<()> {
+    if let Some(stream) = self.streams[idx].take() {
+      if let Some(Body::File(reader, _)) = stream.body {
+        reader.into_deregistered(ctx)?;
+      }
+    }
+    Ok( () )
+  }
+
+  fn stream_index(&self, id: u32) -> Option<usize> {
+    self.streams.iter().position(|s| s.as_ref().map(|s| s.id == id).unwrap_or(false))
+  }
+
+  fn send_bodies(&mut self, ctx: &mut Context) -> Result<()> {
+    for idx in 0 .. self.streams.len() {
+      let result = match self.streams[idx] {
+        Some(ref mut stream) => send_body(stream, &mut self.outgoing, &mut self.send_window, self.max_frame_size),
+        None => Ok(false)
+      };
+      match result {
+        Ok(true) => self.finish_response(idx, ctx)?,
+        Ok(false) => {},
+        Err(err) => {
+          println!("resetting http2 stream due to io error: {:?}", err);
+          let id = self.streams[idx].as_ref().unwrap().id;
+          frame::write_u32_frame(&mut self.outgoing, kind::RST_STREAM, id, error_code::INTERNAL_ERROR)?;
+          self.remove_stream(idx, ctx)?;
+        }
+      }
+    }
+    Ok( () )
+  }
+
+  fn go_away(&mut self, error_code: u32, ctx: &mut Context) -> Result<()> {
+    self.is_closing = true;
+    self.incoming.as_mut().map(|incoming| incoming.clear());
+    for idx in 0 .. self.streams.len() {
+      self.remove_stream(idx, ctx)?;
+    }
+    frame::write_goaway(&mut self.outgoing, self.last_stream_id, error_code)?;
+    Ok( () )
+  }
+
+  fn write_socket(&mut self, ctx: &mut Context) -> io::Result<bool> {
+    if self.outgoing.len() == 0 {
+      return Ok(true);
+    }
+    let mut socket = ctx.socket();
+    let report = send_buffer(&mut socket, self.outgoing.as_slice())?;
+    self.outgoing.remove_head(report.byte_count());
+    // don't leave the frames behind in the tls engine
+    socket.flush()?;
+    Ok(self.outgoing.len() == 0)
+  }
+}
+
+// reads until `incoming` holds `max_len` bytes,
+// returns whether it stopped because of that
+fn read_socket(incoming: &mut Buffer, max_len: usize, ctx: &mut Context) -> io::Result<bool> {
+  let socket = ctx.socket();
+  let mut chunk = [0u8; 1024];
+  loop {
+    let chunk_len = cmp::min(chunk.len(), max_len - incoming.len());
+    if chunk_len == 0 {
+      return Ok(true);
+    }
+    match socket.read(&mut chunk[.. chunk_len]) {
+      Ok(0) => return Ok(false),
+      Ok(len) => { incoming.write_all(&chunk[.. len])?; },
+      Err(err) => match err.kind() {
+        ErrorKind::Interrupted => {},
+        ErrorKind::WouldBlock => return Ok(false),
+        _ => return Err(err)
+      }
+    }
+  }
+}
+
+// a HEADERS frame, followed by CONTINUATION frames for what doesn't fit in it
+fn write_header_block(outgoing: &mut Buffer, stream_id: u32, mut block: &[u8], end_stream: bool, max_frame_size: usize) -> io::Result<()> {
+  let mut frame_kind = kind::HEADERS;
+  let mut flags = if end_stream { flag::END_STREAM } else { 0 };
+  loop {
+    let (fragment, rest) = block.split_at(cmp::min(block.len(), max_frame_size));
+    if rest.is_empty() {
+      return frame::write_frame(outgoing, frame_kind, flags | flag::END_HEADERS, stream_id, fragment);
+    }
+    frame::write_frame(outgoing, frame_kind, flags, stream_id, fragment)?;
+    frame_kind = kind::CONTINUATION;
+    flags = 0;
+    block = rest;
+  }
+}
+
+// writes as many DATA frames as flow control allows,
+// returns true once the whole body has been sent.
+fn send_body<H>(stream: &mut Stream<H>, outgoing: &mut Buffer, connection_window: &mut i64, max_frame_size: usize) -> io::Result<bool> {
+  loop {
+    if outgoing.len() >= MAX_OUTGOING_SIZE {
+      return Ok(false);
+    }
+    let window = cmp::max(0, cmp::min(stream.send_window, *connection_window)) as usize;
+    let max_len = cmp::min(window, max_frame_size);
+    let (len, is_last) = match stream.body {
+      None => return Ok(false),
+      Some(Body::Buffer(ref buffer, ref mut offset)) => {
+        let remaining = &buffer.as_slice()[*offset ..];
+        let len = cmp::min(remaining.len(), max_len);
+        let is_last = len == remaining.len();
+        if len == 0 && !is_last {
+          return Ok(false);
+        }
+        let flags = if is_last { flag::END_STREAM } else { 0 };
+        frame::write_frame(outgoing, kind::DATA, flags, stream.id, &remaining[.. len])?;
+        *offset += len;
+        (len, is_last)
+      },
+      Some(Body::File(ref mut reader, ref mut bytes_sent)) => {
+        let remaining_len = match reader.try_get_read_bytes() {
+          Ok(bytes) => bytes.len() - *bytes_sent,
+          Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+          Err(err) => return Err(err)
+        };
+        if remaining_len == 0 {
+          *bytes_sent = 0;
+          if reader.try_queue_read()? {
+            return Ok(false); //wait for the next read to finish
+          }
+          frame::write_frame(outgoing, kind::DATA, flag::END_STREAM, stream.id, &[])?;
+          (0, true)
+        }
+        else {
+          let len = cmp::min(remaining_len, max_len);
+          if len == 0 {
+            return Ok(false);
+          }
+          let bytes = reader.try_get_read_bytes()?;
+          frame::write_frame(outgoing, kind::DATA, 0, stream.id, &bytes[*bytes_sent .. *bytes_sent + len])?;
+          *bytes_sent += len;
+          (len, false)
+        }
+      }
+    };
+    stream.send_window -= len as i64;
+    *connection_window -= len as i64;
+    if is_last {
+      return Ok(true);
+    }
+  }
+}
+
+// what HTTP/1.1 checks before calling the handler, as far as it applies
+fn check_request(request: &Request) -> RequestResult<()> {
+  match request.headers().content_length {
+    Some(len) if len > MAX_REQUEST_BODY_SIZE as u64 => Err(RequestError::PayloadTooLarge),
+    _ => Ok( () )
+  }
+}
+
+fn is_connection_specific(name: &[u8]) -> bool {
+  let name = str::from_utf8(name).unwrap_or("");
+  ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade", "te"]
+    .iter()
+    .any(|h| h.eq_ignore_ascii_case(name))
+}
+
+// converts the HTTP/1 style head written by the Responder to a header block
+fn encode_response_head(head: &mut [u8], dst: &mut dyn Write) -> io::Result<()> {
+  let mut lines = buffer_split_mut(head, b"\r\n");
+  let status = lines.next()
+    .and_then(|status_line| status_line.get(9 .. 12))
+    .and_then(|code| str::from_utf8(code).ok())
+    .and_then(|code| code.parse::<u16>().ok())
+    .unwrap_or(500);
+  hpack::encode_status(dst, status)?;
+  for line in lines {
+    if let Some(idx) = line.position(b":") {
+      let (name, value) = line.split_at_mut(idx);
+      let name = trim_mut(name, is_whitespace);
+      let value = trim_mut(&mut value[1 ..], is_whitespace);
+      if !is_connection_specific(name) {
+        hpack::encode_header(dst, name, value)?;
+      }
+    }
+  }
+  Ok( () )
+}
+
+// builds an HTTP/1 style request head from the decoded headers,
+// so it can be parsed with Request::parse.
+struct RequestBuilder<'a> {
+  text: &'a mut Buffer,
+  // cookie fields can be split up, and are joined into one header
+  cookies: &'a mut Buffer,
+  method: [u8; MAX_METHOD_LEN],
+  method_len: usize,
+  path: [u8; MAX_PATH_LEN],
+  path_len: usize,
+  authority: [u8; MAX_AUTHORITY_LEN],
+  authority_len: usize,
+  is_head_written: bool,
+  error: Option<RequestError>
+}
+
+fn copy_field(dst: &mut [u8], len: &mut usize, value: &[u8], err: RequestError) -> RequestResult<()> {
+  if value.len() > dst.len() {
+    return Err(err);
+  }
+  dst[.. value.len()].copy_from_slice(value);
+  *len = value.len();
+  Ok( () )
+}
+
+impl<'a> RequestBuilder<'a> {
+  fn new(text: &'a mut Buffer, cookies: &'a mut Buffer) -> RequestBuilder<'a> {
+    text.clear();
+    cookies.clear();
+    RequestBuilder {
+      text,
+      cookies,
+      method: [0u8; MAX_METHOD_LEN],
+      method_len: 0,
+      path: [0u8; MAX_PATH_LEN],
+      path_len: 0,
+      authority: [0u8; MAX_AUTHORITY_LEN],
+      authority_len: 0,
+      is_head_written: false,
+      error: None
+    }
+  }
+
+  fn add(&mut self, name: &[u8], value: &[u8]) {
+    if self.error.is_none() {
+      self.error = self.try_add(name, value).err();
+    }
+  }
+
+  fn try_add(&mut self, name: &[u8], value: &[u8]) -> RequestResult<()> {
+    if value.iter().any(|b| *b == b'\r' || *b == b'\n' || *b == 0) {
+      return Err(RequestError::InvalidHeader);
+    }
+    if name.starts_with(b":") {
+      // pseudo headers have to come first
+      if self.is_head_written {
+        return Err(RequestError::InvalidHeader);
+      }
+      return match name {
+        b":method" => copy_field(&mut self.method, &mut self.method_len, value, RequestError::InvalidRequestLine),
+        b":path" => copy_field(&mut self.path, &mut self.path_len, value, RequestError::UriTooLong),
+        b":authority" => copy_field(&mut self.authority, &mut self.authority_len, value, RequestError::InvalidHeader),
+        b":scheme" => Ok( () ),
+        _ => Err(RequestError::InvalidHeader)
+      };
+    }
+    if name.iter().any(|b| b.is_ascii_uppercase()) {
+      return Err(RequestError::InvalidHeader);
+    }
+    if name == b"cookie" {
+      let separator : &[u8] = if self.cookies.len() != 0 { b"; " } else { b"" };
+      let is_written = self.cookies.write_all(separator).is_ok() &&
+        self.cookies.write_all(value).is_ok();
+      if !is_written || self.text.len() + self.cookies.len() > MAX_HEADER_LIST_SIZE {
+        return Err(RequestError::HeadersTooLarge);
+      }
+    }
+    else if !is_connection_specific(name) {
+      self.write_head().map_err(|_| RequestError::HeadersTooLarge)?;
+      let is_written = self.text.write_all(b"\r\n").is_ok() &&
+        self.text.write_all(name).is_ok() &&
+        self.text.write_all(b":").is_ok() &&
+        self.text.write_all(value).is_ok();
+      if !is_written || self.text.len() > MAX_HEADER_LIST_SIZE {
+        return Err(RequestError::HeadersTooLarge);
+      }
+    }
+    Ok( () )
+  }
+
+  fn write_head(&mut self) -> io::Result<()> {
+    if self.is_head_written {
+      return Ok( () );
+    }
+    self.is_head_written = true;
+    self.text.write_all(&self.method[.. self.method_len])?;
+    self.text.write_all(b" ")?;
+    self.text.write_all(&self.path[.. self.path_len])?;
+    self.text.write_all(b" HTTP/2.0")?;
+    if self.authority_len != 0 {
+      self.text.write_all(b"\r\nHost:")?;
+      self.text.write_all(&self.authority[.. self.authority_len])?;
+    }
+    Ok( () )
+  }
+
+  fn finish(mut self) -> RequestResult<()> {
+    if let Some(err) = self.error.take() {
+      return Err(err);
+    }
+    if self.method_len == 0 || self.path_len == 0 {
+      return Err(RequestError::InvalidRequestLine);
+    }
+    self.write_head().map_err(|_| RequestError::HeadersTooLarge)?;
+    if self.cookies.len() != 0 {
+      let is_written = self.text.write_all(b"\r\ncookie:").is_ok() &&
+        self.text.write_all(self.cookies.as_slice()).is_ok();
+      if !is_written {
+        return Err(RequestError::HeadersTooLarge);
+      }
+    }
+    Ok( () )
+  }
+}
+
+impl<H: RequestHandler, F: Fn() -> H> ::io::Handler<()> for Connection<H, F> {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<()> {
+    let result = match self.process_event(event, ctx) {
+      Err(Error::Connection(code)) => self.go_away(code, ctx),
+      result => result
+    };
+    if let Err(Error::Io(err)) = result {
+      println!("closing http2 connection due to io error: {:?}", err);
+      return Some( () );
+    }
+    match self.write_socket(ctx) {
+      Ok(is_flushed) => {
+        let is_done = is_flushed && self.is_closing &&
+          self.streams.iter().all(|s| s.is_none());
+        if is_done { Some( () ) } else { None }
+      },
+      Err(err) => {
+        println!("closing http2 connection due to io error: {:?}", err);
+        Some( () )
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Connection, RequestBuilder, encode_response_head, write_header_block, PREFACE};
+  use super::super::frame::{self, FrameHeader, kind, flag, error_code, HEADER_LEN};
+  use super::super::hpack;
+  use http::{self, RequestError, RequestHandler, Request, Responder, Response};
+  use buffer::Buffer;
+  use http::http2::hpack::Decoder;
+  use io::{Context, ConnectionId, AsyncToken, AsyncTokenSource, Broker, Channel,
+    ConnectionInfo, TcpPolicy, TcpSocket, Event, EventKind, EventSource, ReadSizeHint, SocketFd, Handler};
+  use io::sources::file::ReadQueue;
+  use std;
+  use std::cell::Cell;
+  use std::io::{Read, Write};
+  use std::rc::Rc;
+  use std::time::Duration;
+  use mio;
+
+  // the client side of the connection, in memory
+  struct MemorySocket {
+    input: Vec<u8>,
+    output: Vec<u8>
+  }
+
+  impl Read for MemorySocket {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      if self.input.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "no more input"));
+      }
+      let len = std::cmp::min(buf.len(), self.input.len());
+      buf[.. len].copy_from_slice(&self.input[.. len]);
+      self.input.drain(.. len);
+      Ok(len)
+    }
+  }
+
+  impl Write for MemorySocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.output.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok( () )
+    }
+  }
+
+  impl EventSource for MemorySocket {
+    fn token(&self) -> AsyncToken {
+      AsyncToken(0)
+    }
+  }
+
+  impl ReadSizeHint for MemorySocket {}
+  impl SocketFd for MemorySocket {}
+
+  // answers GET requests with "hello", other requests with the size of the body,
+  // and requests for /poll by waiting on a channel
+  struct TestHandler {
+    body_reads: Rc<Cell<usize>>,
+    is_get: bool,
+    is_poll: bool
+  }
+
+  impl RequestHandler for TestHandler {
+    fn read_headers(&mut self, request: &Request, responder: &Responder) -> std::io::Result<Option<Response>> {
+      self.is_poll = request.url() == "/poll";
+      if self.is_poll {
+        return responder.respond(http::status::OK)?
+          .finish_with_channel(Channel::named("test"), Duration::from_secs(10));
+      }
+      self.is_get = request.method() == "GET";
+      if self.is_get {
+        let mut body = responder.respond(http::status::OK)?.into_body()?;
+        write!(body, "hello")?;
+        return Ok(Some(body.finish()));
+      }
+      Ok(None)
+    }
+
+    fn read_body(&mut self, body: &mut [u8], responder: &Responder) -> std::io::Result<Option<Response>> {
+      self.body_reads.set(self.body_reads.get() + 1);
+      let mut response = responder.respond(http::status::OK)?.into_body()?;
+      write!(response, "{}", body.len())?;
+      Ok(Some(response.finish()))
+    }
+  }
+
+  fn request_frame(stream_id: u32, method: &str, path: &str, end_stream: bool) -> Vec<u8> {
+    let mut block = Vec::new();
+    hpack::encode_header(&mut block, b":method", method.as_bytes()).unwrap();
+    hpack::encode_header(&mut block, b":scheme", b"https").unwrap();
+    hpack::encode_header(&mut block, b":path", path.as_bytes()).unwrap();
+    hpack::encode_header(&mut block, b":authority", b"localhost").unwrap();
+    let flags = if end_stream { flag::END_HEADERS | flag::END_STREAM } else { flag::END_HEADERS };
+    let mut frame = Vec::new();
+    frame::write_frame(&mut frame, kind::HEADERS, flags, stream_id, &block).unwrap();
+    frame
+  }
+
+  fn client_preface() -> Vec<u8> {
+    let mut input = PREFACE.to_vec();
+    frame::write_settings(&mut input, &[]).unwrap();
+    input
+  }
+
+  // feeds the input to the connection in a single readable event,
+  // and returns the frames it wrote
+  fn exchange<H: RequestHandler, F: Fn() -> H>(connection: &mut Connection<H, F>, input: Vec<u8>) -> Vec<(FrameHeader, Vec<u8>)> {
+    let poll = mio::Poll::new().unwrap();
+    let mut token_source = AsyncTokenSource::starting_from(AsyncToken(1));
+    let mut broker = Broker::new();
+    let info = ConnectionInfo::new("127.0.0.1:4433".parse().unwrap());
+    let policy = TcpPolicy::new();
+    let queue = ReadQueue::with_aio(1).unwrap();
+    let mut socket = MemorySocket { input, output: Vec::new() };
+    {
+      let mut ctx = Context::new(&poll, ConnectionId(1), &mut token_source, &mut broker,
+        &mut socket, &info, TcpSocket::new(-1, &policy), &queue);
+      let event = Event::new(AsyncToken(0), EventKind::new().with_readable(true).with_writable(true));
+      connection.handle_event(&event, &mut ctx);
+    }
+    let mut frames = Vec::new();
+    let mut output = &socket.output[..];
+    while let Some(header) = FrameHeader::parse(output) {
+      let payload = output[HEADER_LEN .. HEADER_LEN + header.len].to_vec();
+      output = &output[HEADER_LEN + header.len ..];
+      frames.push((header, payload));
+    }
+    assert!(output.is_empty());
+    frames
+  }
+
+  fn decode_headers(block: &[u8]) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    Decoder::new(4096).decode(block, |name, value| {
+      headers.push((String::from_utf8(name.to_vec()).unwrap(), String::from_utf8(value.to_vec()).unwrap()));
+    }).unwrap();
+    headers
+  }
+
+  fn status_of(block: &[u8]) -> String {
+    decode_headers(block).into_iter().find(|&(ref name, _)| name == ":status").unwrap().1
+  }
+
+  fn test_connection(body_reads: &Rc<Cell<usize>>) -> Connection<TestHandler, impl Fn() -> TestHandler> {
+    let body_reads = body_reads.clone();
+    Connection::new(move || TestHandler { body_reads: body_reads.clone(), is_get: false, is_poll: false })
+  }
+
+  #[test]
+  fn test_get_request() {
+    let body_reads = Rc::new(Cell::new(0));
+    let mut connection = test_connection(&body_reads);
+    let mut input = client_preface();
+    input.extend(request_frame(1, "GET", "/", true));
+    let frames = exchange(&mut connection, input);
+    let kinds : Vec<u8> = frames.iter().map(|&(ref header, _)| header.kind).collect();
+    assert_eq!(kinds, [kind::SETTINGS, kind::SETTINGS, kind::HEADERS, kind::DATA]);
+    assert!(frames[1].0.has_flag(flag::ACK));
+    let (ref headers, ref block) = frames[2];
+    assert_eq!(headers.stream_id, 1);
+    assert_eq!(headers.flags, flag::END_HEADERS);
+    assert_eq!(status_of(block), "200");
+    let (ref data, ref body) = frames[3];
+    assert_eq!(data.stream_id, 1);
+    assert_eq!(data.flags, flag::END_STREAM);
+    assert_eq!(&body[..], b"hello");
+  }
+
+  #[test]
+  fn test_request_body_read_once() {
+    let body_reads = Rc::new(Cell::new(0));
+    let mut connection = test_connection(&body_reads);
+    let mut input = client_preface();
+    input.extend(request_frame(1, "POST", "/", false));
+    frame::write_frame(&mut input, kind::DATA, 0, 1, b"hello ").unwrap();
+    frame::write_frame(&mut input, kind::DATA, flag::END_STREAM, 1, b"world").unwrap();
+    let frames = exchange(&mut connection, input);
+    assert_eq!(body_reads.get(), 1);
+    let (_, ref body) = *frames.iter().find(|&&(ref header, _)| header.kind == kind::DATA).unwrap();
+    assert_eq!(&body[..], b"11");
+  }
+
+  #[test]
+  fn test_parked_response_not_implemented() {
+    let body_reads = Rc::new(Cell::new(0));
+    let mut connection = test_connection(&body_reads);
+    let mut input = client_preface();
+    input.extend(request_frame(1, "GET", "/poll", true));
+    let frames = exchange(&mut connection, input);
+    let (_, ref block) = *frames.iter().find(|&&(ref header, _)| header.kind == kind::HEADERS).unwrap();
+    assert_eq!(status_of(block), "501");
+  }
+
+  #[test]
+  fn test_invalid_preface() {
+    let body_reads = Rc::new(Cell::new(0));
+    let mut connection = test_connection(&body_reads);
+    let frames = exchange(&mut connection, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec());
+    let (ref header, ref payload) = *frames.last().unwrap();
+    assert_eq!(header.kind, kind::GOAWAY);
+    assert_eq!(frame::read_u32(&payload[4 ..]), error_code::PROTOCOL_ERROR);
+  }
+
+  #[test]
+  fn test_header_block_continuation() {
+    let block : Vec<u8> = (0 .. 250u8).collect();
+    let mut outgoing = Buffer::new();
+    write_header_block(&mut outgoing, 3, &block, true, 100).unwrap();
+    let mut bytes = outgoing.as_slice();
+    let mut fragments = Vec::new();
+    while let Some(header) = FrameHeader::parse(bytes) {
+      fragments.push((header.kind, header.flags, header.len));
+      bytes = &bytes[HEADER_LEN + header.len ..];
+    }
+    assert_eq!(fragments, [
+      (kind::HEADERS, flag::END_STREAM, 100),
+      (kind::CONTINUATION, 0, 100),
+      (kind::CONTINUATION, flag::END_HEADERS, 50)
+    ]);
+  }
+
+  #[test]
+  fn test_request_builder() {
+    let mut text = Buffer::new();
+    let mut cookies = Buffer::new();
+    let result = {
+      let mut builder = RequestBuilder::new(&mut text, &mut cookies);
+      builder.add(b":method", b"GET");
+      builder.add(b":scheme", b"https");
+      builder.add(b":path", b"/foo?bar=1");
+      builder.add(b":authority", b"example.com");
+      builder.add(b"cookie", b"a=1");
+      builder.add(b"accept", b"text/html");
+      builder.add(b"connection", b"keep-alive");
+      builder.add(b"cookie", b"b=2");
+      builder.finish()
+    };
+    assert_eq!(result, Ok( () ));
+    assert_eq!(text.as_slice(), &b"GET /foo?bar=1 HTTP/2.0\r\nHost:example.com\r\naccept:text/html\r\ncookie:a=1; b=2"[..]);
+  }
+
+  #[test]
+  fn test_request_builder_invalid() {
+    let mut text = Buffer::new();
+    let mut cookies = Buffer::new();
+    let mut builder = RequestBuilder::new(&mut text, &mut cookies);
+    builder.add(b":method", b"GET");
+    builder.add(b"accept", b"text/html");
+    builder.add(b":path", b"/");
+    assert_eq!(builder.finish(), Err(RequestError::InvalidHeader));
+
+    let mut text = Buffer::new();
+    let mut cookies = Buffer::new();
+    let mut builder = RequestBuilder::new(&mut text, &mut cookies);
+    builder.add(b":method", b"GET");
+    builder.add(b":path", b"/");
+    builder.add(b"Accept", b"text/html");
+    assert_eq!(builder.finish(), Err(RequestError::InvalidHeader));
+
+    let mut text = Buffer::new();
+    let mut cookies = Buffer::new();
+    let mut builder = RequestBuilder::new(&mut text, &mut cookies);
+    builder.add(b":method", b"GET");
+    assert_eq!(builder.finish(), Err(RequestError::InvalidRequestLine));
+  }
+
+  #[test]
+  fn test_encode_response_head() {
+    let mut head = b"HTTP/1.1 404 Not Found\r\nContent-Type:text/plain\r\nConnection: close\r\n\r\n".to_vec();
+    let mut block = Vec::new();
+    encode_response_head(&mut head, &mut block).unwrap();
+    let mut headers = Vec::new();
+    Decoder::new(4096).decode(&block, |name, value| {
+      headers.push((name.to_vec(), value.to_vec()));
+    }).unwrap();
+    assert_eq!(headers, vec![
+      (b":status".to_vec(), b"404".to_vec()),
+      (b"content-type".to_vec(), b"text/plain".to_vec())
+    ]);
+  }
+}
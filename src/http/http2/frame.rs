@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+
+pub const HEADER_LEN : usize = 9;
+
+pub mod kind {
+  pub const DATA : u8 = 0x0;
+  pub const HEADERS : u8 = 0x1;
+  pub const PRIORITY : u8 = 0x2;
+  pub const RST_STREAM : u8 = 0x3;
+  pub const SETTINGS : u8 = 0x4;
+  pub const PUSH_PROMISE : u8 = 0x5;
+  pub const PING : u8 = 0x6;
+  pub const GOAWAY : u8 = 0x7;
+  pub const WINDOW_UPDATE : u8 = 0x8;
+  pub const CONTINUATION : u8 = 0x9;
+}
+
+pub mod flag {
+  pub const END_STREAM : u8 = 0x1;
+  pub const ACK : u8 = 0x1;
+  pub const END_HEADERS : u8 = 0x4;
+  pub const PADDED : u8 = 0x8;
+  pub const PRIORITY : u8 = 0x20;
+}
+
+pub mod setting {
+  pub const HEADER_TABLE_SIZE : u16 = 0x1;
+  pub const ENABLE_PUSH : u16 = 0x2;
+  pub const MAX_CONCURRENT_STREAMS : u16 = 0x3;
+  pub const INITIAL_WINDOW_SIZE : u16 = 0x4;
+  pub const MAX_FRAME_SIZE : u16 = 0x5;
+  pub const MAX_HEADER_LIST_SIZE : u16 = 0x6;
+}
+
+pub mod error_code {
+  pub const NO_ERROR : u32 = 0x0;
+  pub const PROTOCOL_ERROR : u32 = 0x1;
+  pub const INTERNAL_ERROR : u32 = 0x2;
+  pub const FLOW_CONTROL_ERROR : u32 = 0x3;
+  pub const STREAM_CLOSED : u32 = 0x5;
+  pub const FRAME_SIZE_ERROR : u32 = 0x6;
+  pub const REFUSED_STREAM : u32 = 0x7;
+  pub const COMPRESSION_ERROR : u32 = 0x9;
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FrameHeader {
+  pub len: usize,
+  pub kind: u8,
+  pub flags: u8,
+  pub stream_id: u32
+}
+
+pub fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+impl FrameHeader {
+  pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < HEADER_LEN {
+      return None;
+    }
+    let len = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+    Some(FrameHeader {
+      len,
+      kind: bytes[3],
+      flags: bytes[4],
+      stream_id: read_u32(&bytes[5 .. 9]) & 0x7FFF_FFFF  //ignore reserved bit
+    })
+  }
+
+  pub fn has_flag(&self, flag: u8) -> bool {
+    self.flags & flag != 0
+  }
+
+  pub fn write(&self, dst: &mut dyn Write) -> io::Result<()> {
+    let len = self.len;
+    let id = self.stream_id;
+    dst.write_all(&[
+      (len >> 16) as u8, (len >> 8) as u8, len as u8,
+      self.kind,
+      self.flags,
+      (id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8
+    ])
+  }
+}
+
+pub fn write_frame(dst: &mut dyn Write, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> io::Result<()> {
+  FrameHeader {len: payload.len(), kind, flags, stream_id}.write(dst)?;
+  dst.write_all(payload)
+}
+
+pub fn write_settings(dst: &mut dyn Write, settings: &[(u16, u32)]) -> io::Result<()> {
+  FrameHeader {len: settings.len() * 6, kind: kind::SETTINGS, flags: 0, stream_id: 0}.write(dst)?;
+  for &(id, value) in settings {
+    dst.write_all(&[
+      (id >> 8) as u8, id as u8,
+      (value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8
+    ])?;
+  }
+  Ok( () )
+}
+
+pub fn write_u32_frame(dst: &mut dyn Write, kind: u8, stream_id: u32, value: u32) -> io::Result<()> {
+  write_frame(dst, kind, 0, stream_id, &[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+pub fn write_goaway(dst: &mut dyn Write, last_stream_id: u32, error_code: u32) -> io::Result<()> {
+  let mut payload = [0u8; 8];
+  for i in 0 .. 4 {
+    payload[i] = (last_stream_id >> (24 - i * 8)) as u8;
+    payload[4 + i] = (error_code >> (24 - i * 8)) as u8;
+  }
+  write_frame(dst, kind::GOAWAY, 0, 0, &payload)
+}
+
+// removes the padding from DATA and HEADERS frames
+pub fn strip_padding<'a>(header: &FrameHeader, payload: &'a [u8]) -> Option<&'a [u8]> {
+  if !header.has_flag(flag::PADDED) {
+    return Some(payload);
+  }
+  let pad_len = *payload.get(0)? as usize;
+  if pad_len + 1 > payload.len() {
+    return None;
+  }
+  Some(&payload[1 .. payload.len() - pad_len])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{FrameHeader, strip_padding, write_settings, kind, flag};
+
+  #[test]
+  fn test_header_roundtrip() {
+    let header = FrameHeader {len: 16384, kind: kind::HEADERS, flags: flag::END_HEADERS, stream_id: 3};
+    let mut bytes = Vec::new();
+    header.write(&mut bytes).unwrap();
+    assert_eq!(bytes, [0x00, 0x40, 0x00, 0x01, 0x04, 0, 0, 0, 3]);
+    assert_eq!(FrameHeader::parse(&bytes), Some(header));
+    assert_eq!(FrameHeader::parse(&bytes[.. 8]), None);
+  }
+
+  #[test]
+  fn test_strip_padding() {
+    let header = FrameHeader {len: 6, kind: kind::DATA, flags: flag::PADDED, stream_id: 1};
+    assert_eq!(strip_padding(&header, &[2, b'h', b'i', b'!', 0, 0]), Some(&b"hi!"[..]));
+    assert_eq!(strip_padding(&header, &[6, 0, 0]), None);
+  }
+
+  #[test]
+  fn test_settings() {
+    let mut bytes = Vec::new();
+    write_settings(&mut bytes, &[(0x3, 100)]).unwrap();
+    assert_eq!(bytes, [0, 0, 6, 0x4, 0, 0, 0, 0, 0, 0, 0x3, 0, 0, 0, 100]);
+  }
+}
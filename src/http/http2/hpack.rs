@@ -0,0 +1,462 @@
+use std::cmp;
+use std::io::{self, Write};
+use super::huffman;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  Truncated,
+  IntegerOverflow,
+  InvalidIndex,
+  InvalidHuffmanCode,
+  InvalidTableSize
+}
+
+// RFC 7541, Appendix A
+const STATIC_TABLE : [(&'static str, &'static str); 61] = [
+  (":authority", ""),
+  (":method", "GET"),
+  (":method", "POST"),
+  (":path", "/"),
+  (":path", "/index.html"),
+  (":scheme", "http"),
+  (":scheme", "https"),
+  (":status", "200"),
+  (":status", "204"),
+  (":status", "206"),
+  (":status", "304"),
+  (":status", "400"),
+  (":status", "404"),
+  (":status", "500"),
+  ("accept-charset", ""),
+  ("accept-encoding", "gzip, deflate"),
+  ("accept-language", ""),
+  ("accept-ranges", ""),
+  ("accept", ""),
+  ("access-control-allow-origin", ""),
+  ("age", ""),
+  ("allow", ""),
+  ("authorization", ""),
+  ("cache-control", ""),
+  ("content-disposition", ""),
+  ("content-encoding", ""),
+  ("content-language", ""),
+  ("content-length", ""),
+  ("content-location", ""),
+  ("content-range", ""),
+  ("content-type", ""),
+  ("cookie", ""),
+  ("date", ""),
+  ("etag", ""),
+  ("expect", ""),
+  ("expires", ""),
+  ("from", ""),
+  ("host", ""),
+  ("if-match", ""),
+  ("if-modified-since", ""),
+  ("if-none-match", ""),
+  ("if-range", ""),
+  ("if-unmodified-since", ""),
+  ("last-modified", ""),
+  ("link", ""),
+  ("location", ""),
+  ("max-forwards", ""),
+  ("proxy-authenticate", ""),
+  ("proxy-authorization", ""),
+  ("range", ""),
+  ("referer", ""),
+  ("refresh", ""),
+  ("retry-after", ""),
+  ("server", ""),
+  ("set-cookie", ""),
+  ("strict-transport-security", ""),
+  ("transfer-encoding", ""),
+  ("user-agent", ""),
+  ("vary", ""),
+  ("via", ""),
+  ("www-authenticate", ""),
+];
+
+// entries added to the dynamic table are accounted with 32 bytes of overhead
+const ENTRY_OVERHEAD : usize = 32;
+/// the largest dynamic table a decoder can keep
+pub const MAX_TABLE_SIZE : usize = 4096;
+// every entry takes up at least its overhead
+const MAX_ENTRIES : usize = MAX_TABLE_SIZE / ENTRY_OVERHEAD;
+
+#[derive(Clone, Copy)]
+struct Entry {
+  start: usize,
+  name_len: usize,
+  value_len: usize
+}
+
+// the dynamic table in fixed buffers, so decoding doesn't allocate
+struct DynamicTable {
+  // the names and values of the entries, oldest first, wrapping around.
+  // They take up less than the size of the table,
+  // so a new entry never overwrites an entry still in the table.
+  data: [u8; MAX_TABLE_SIZE],
+  // oldest first, wrapping around
+  entries: [Entry; MAX_ENTRIES],
+  oldest: usize,
+  count: usize,
+  // where the next entry goes in data
+  data_end: usize,
+  size: usize,
+  max_size: usize,
+  // the limit we advertised in SETTINGS_HEADER_TABLE_SIZE
+  size_limit: usize
+}
+
+impl DynamicTable {
+  fn new(size_limit: usize) -> DynamicTable {
+    assert!(size_limit <= MAX_TABLE_SIZE);
+    DynamicTable {
+      data: [0u8; MAX_TABLE_SIZE],
+      entries: [Entry { start: 0, name_len: 0, value_len: 0 }; MAX_ENTRIES],
+      oldest: 0,
+      count: 0,
+      data_end: 0,
+      size: 0,
+      max_size: size_limit,
+      size_limit
+    }
+  }
+
+  // appends the name, and the value if given, of the entry at index (newest first)
+  fn copy_entry(&self, index: usize, name: &mut Vec<u8>, value: Option<&mut Vec<u8>>) -> bool {
+    if index >= self.count {
+      return false;
+    }
+    let entry = self.entries[(self.oldest + self.count - 1 - index) % MAX_ENTRIES];
+    self.copy_data(entry.start, entry.name_len, name);
+    if let Some(value) = value {
+      self.copy_data(entry.start + entry.name_len, entry.value_len, value);
+    }
+    true
+  }
+
+  fn copy_data(&self, start: usize, len: usize, dst: &mut Vec<u8>) {
+    let start = start % MAX_TABLE_SIZE;
+    let first_len = cmp::min(len, MAX_TABLE_SIZE - start);
+    dst.extend_from_slice(&self.data[start .. start + first_len]);
+    dst.extend_from_slice(&self.data[.. len - first_len]);
+  }
+
+  fn write_data(&mut self, bytes: &[u8]) {
+    let first_len = cmp::min(bytes.len(), MAX_TABLE_SIZE - self.data_end);
+    self.data[self.data_end .. self.data_end + first_len].copy_from_slice(&bytes[.. first_len]);
+    self.data[.. bytes.len() - first_len].copy_from_slice(&bytes[first_len ..]);
+    self.data_end = (self.data_end + bytes.len()) % MAX_TABLE_SIZE;
+  }
+
+  fn set_max_size(&mut self, max_size: usize) -> Result<(), Error> {
+    if max_size > self.size_limit {
+      return Err(Error::InvalidTableSize);
+    }
+    self.max_size = max_size;
+    self.evict(0);
+    Ok( () )
+  }
+
+  fn evict(&mut self, needed_size: usize) {
+    while self.size + needed_size > self.max_size && self.count != 0 {
+      let entry = self.entries[self.oldest];
+      self.size -= entry.name_len + entry.value_len + ENTRY_OVERHEAD;
+      self.oldest = (self.oldest + 1) % MAX_ENTRIES;
+      self.count -= 1;
+    }
+  }
+
+  fn insert(&mut self, name: &[u8], value: &[u8]) {
+    let entry_size = name.len() + value.len() + ENTRY_OVERHEAD;
+    self.evict(entry_size);
+    // an entry bigger than the table just empties it
+    if entry_size <= self.max_size {
+      let entry = Entry { start: self.data_end, name_len: name.len(), value_len: value.len() };
+      self.write_data(name);
+      self.write_data(value);
+      self.entries[(self.oldest + self.count) % MAX_ENTRIES] = entry;
+      self.count += 1;
+      self.size += entry_size;
+    }
+  }
+}
+
+fn decode_int(src: &[u8], prefix_bits: u8) -> Result<(usize, usize), Error> {
+  let first = *src.get(0).ok_or(Error::Truncated)?;
+  let prefix_max = (1usize << prefix_bits) - 1;
+  let value = first as usize & prefix_max;
+  if value < prefix_max {
+    return Ok((value, 1));
+  }
+  let mut value = value as u64;
+  let mut shift = 0;
+  for (i, byte) in src[1 ..].iter().enumerate() {
+    value += ((byte & 0x7f) as u64) << shift;
+    if byte & 0x80 == 0 {
+      // nothing in a header block needs more than 32 bits
+      return if value > u32::max_value() as u64 {
+        Err(Error::IntegerOverflow)
+      } else {
+        Ok((value as usize, i + 2))
+      };
+    }
+    shift += 7;
+    if shift > 28 {
+      return Err(Error::IntegerOverflow);
+    }
+  }
+  Err(Error::Truncated)
+}
+
+fn encode_int(dst: &mut dyn Write, value: usize, prefix_bits: u8, flags: u8) -> io::Result<()> {
+  let prefix_max = (1usize << prefix_bits) - 1;
+  if value < prefix_max {
+    return dst.write_all(&[flags | value as u8]);
+  }
+  dst.write_all(&[flags | prefix_max as u8])?;
+  let mut value = value - prefix_max;
+  while value >= 0x80 {
+    dst.write_all(&[(value & 0x7f) as u8 | 0x80])?;
+    value >>= 7;
+  }
+  dst.write_all(&[value as u8])
+}
+
+/// Decodes header blocks, keeping the dynamic table
+/// in sync with the encoder of the peer.
+pub struct Decoder {
+  table: DynamicTable,
+  name: Vec<u8>,
+  value: Vec<u8>
+}
+
+impl Decoder {
+  pub fn new(table_size_limit: usize) -> Decoder {
+    Decoder {
+      table: DynamicTable::new(table_size_limit),
+      name: Vec::new(),
+      value: Vec::new()
+    }
+  }
+
+  /// calls on_header for every header in the block, in order.
+  pub fn decode<F: FnMut(&[u8], &[u8])>(&mut self, mut block: &[u8], mut on_header: F) -> Result<(), Error> {
+    while let Some(&first) = block.get(0) {
+      let len = if first & 0x80 != 0 {
+        // indexed header field
+        let (index, len) = decode_int(block, 7)?;
+        self.copy_entry(index, true)?;
+        on_header(&self.name, &self.value);
+        len
+      }
+      else if first & 0xc0 == 0x40 {
+        // literal with incremental indexing
+        let len = self.decode_literal(block, 6)?;
+        on_header(&self.name, &self.value);
+        self.table.insert(&self.name, &self.value);
+        len
+      }
+      else if first & 0xe0 == 0x20 {
+        let (max_size, len) = decode_int(block, 5)?;
+        self.table.set_max_size(max_size)?;
+        len
+      }
+      else {
+        // literal without indexing or never indexed
+        let len = self.decode_literal(block, 4)?;
+        on_header(&self.name, &self.value);
+        len
+      };
+      block = &block[len ..];
+    }
+    Ok( () )
+  }
+
+  // copies the entry at the (1-based) index in name and value
+  fn copy_entry(&mut self, index: usize, with_value: bool) -> Result<(), Error> {
+    self.name.clear();
+    self.value.clear();
+    if index == 0 {
+      return Err(Error::InvalidIndex);
+    }
+    if index <= STATIC_TABLE.len() {
+      let (name, value) = STATIC_TABLE[index - 1];
+      self.name.extend_from_slice(name.as_bytes());
+      if with_value {
+        self.value.extend_from_slice(value.as_bytes());
+      }
+    }
+    else {
+      let value = if with_value { Some(&mut self.value) } else { None };
+      if !self.table.copy_entry(index - STATIC_TABLE.len() - 1, &mut self.name, value) {
+        return Err(Error::InvalidIndex);
+      }
+    }
+    Ok( () )
+  }
+
+  fn decode_literal(&mut self, block: &[u8], prefix_bits: u8) -> Result<usize, Error> {
+    let (index, mut len) = decode_int(block, prefix_bits)?;
+    if index == 0 {
+      self.name.clear();
+      len += decode_string(&block[len ..], &mut self.name)?;
+    }
+    else {
+      self.copy_entry(index, false)?;
+    }
+    self.value.clear();
+    len += decode_string(&block[len ..], &mut self.value)?;
+    Ok(len)
+  }
+}
+
+fn decode_string(src: &[u8], dst: &mut Vec<u8>) -> Result<usize, Error> {
+  let is_huffman = src.get(0).map(|b| b & 0x80 != 0).ok_or(Error::Truncated)?;
+  let (str_len, len) = decode_int(src, 7)?;
+  let end = len.checked_add(str_len).ok_or(Error::IntegerOverflow)?;
+  let bytes = src.get(len .. end).ok_or(Error::Truncated)?;
+  if is_huffman {
+    huffman::decode(bytes, dst)?;
+  }
+  else {
+    dst.extend_from_slice(bytes);
+  }
+  Ok(end)
+}
+
+/// Encodes a response status, using the static table when possible.
+pub fn encode_status(dst: &mut dyn Write, status: u16) -> io::Result<()> {
+  let static_index = match status {
+    200 => Some(8),
+    204 => Some(9),
+    206 => Some(10),
+    304 => Some(11),
+    400 => Some(12),
+    404 => Some(13),
+    500 => Some(14),
+    _ => None
+  };
+  match static_index {
+    Some(index) => encode_int(dst, index, 7, 0x80),
+    None => {
+      // literal without indexing, with the name of entry 8 (:status)
+      encode_int(dst, 8, 4, 0)?;
+      let mut status_str = [0u8; 3];
+      write!(&mut status_str[..], "{:03}", status)?;
+      encode_int(dst, 3, 7, 0)?;
+      dst.write_all(&status_str)
+    }
+  }
+}
+
+/// Encodes a header as a literal without indexing, without huffman coding,
+/// so the encoder never has to keep a dynamic table.
+/// Header names are lowercased as required for HTTP/2.
+pub fn encode_header(dst: &mut dyn Write, name: &[u8], value: &[u8]) -> io::Result<()> {
+  dst.write_all(&[0])?;
+  encode_int(dst, name.len(), 7, 0)?;
+  for b in name {
+    dst.write_all(&[b.to_ascii_lowercase()])?;
+  }
+  encode_int(dst, value.len(), 7, 0)?;
+  dst.write_all(value)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Decoder, Error, decode_int, encode_int, encode_status, encode_header};
+
+  fn decode_all(decoder: &mut Decoder, block: &[u8]) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    decoder.decode(block, |name, value| {
+      headers.push((
+        String::from_utf8(name.to_vec()).unwrap(),
+        String::from_utf8(value.to_vec()).unwrap()));
+    }).unwrap();
+    headers
+  }
+
+  fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+    headers.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+  }
+
+  #[test]
+  fn test_int() {
+    // examples from RFC 7541, Appendix C.1
+    assert_eq!(decode_int(&[0b0000_1010], 5), Ok((10, 1)));
+    assert_eq!(decode_int(&[0b0001_1111, 0b1001_1010, 0b0000_1010], 5), Ok((1337, 3)));
+    assert_eq!(decode_int(&[0b0001_1111, 0b1001_1010], 5), Err(Error::Truncated));
+    assert_eq!(decode_int(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0x0f], 5), Err(Error::IntegerOverflow));
+    assert_eq!(decode_int(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 5), Err(Error::IntegerOverflow));
+    let mut dst = Vec::new();
+    encode_int(&mut dst, 1337, 5, 0).unwrap();
+    assert_eq!(dst, [0b0001_1111, 0b1001_1010, 0b0000_1010]);
+  }
+
+  #[test]
+  fn test_decode_requests_with_huffman() {
+    // RFC 7541, Appendix C.4
+    let mut decoder = Decoder::new(4096);
+    let first = [0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+    assert_eq!(decode_all(&mut decoder, &first), pairs(&[
+      (":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]));
+    let second = [0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf];
+    assert_eq!(decode_all(&mut decoder, &second), pairs(&[
+      (":method", "GET"), (":scheme", "http"), (":path", "/"),
+      (":authority", "www.example.com"), ("cache-control", "no-cache")]));
+    let third = [0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d, 0x7f,
+      0x89, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf];
+    assert_eq!(decode_all(&mut decoder, &third), pairs(&[
+      (":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+      (":authority", "www.example.com"), ("custom-key", "custom-value")]));
+  }
+
+  #[test]
+  fn test_table_eviction() {
+    let mut decoder = Decoder::new(64);
+    // literal with incremental indexing, new name "a" with value "b"
+    decode_all(&mut decoder, &[0x40, 0x01, b'a', 0x01, b'b']);
+    assert_eq!(decode_all(&mut decoder, &[0xbe]), pairs(&[("a", "b")]));
+    // "c" => "d" evicts "a" => "b" as both don't fit in 64 bytes
+    decode_all(&mut decoder, &[0x40, 0x01, b'c', 0x01, b'd']);
+    assert_eq!(decode_all(&mut decoder, &[0xbe]), pairs(&[("c", "d")]));
+    assert_eq!(decoder.decode(&[0xbf], |_, _| {}), Err(Error::InvalidIndex));
+  }
+
+  #[test]
+  fn test_table_wraps_around() {
+    let mut decoder = Decoder::new(64);
+    // each entry takes 32 + 2 * 10 bytes, so only one fits at a time,
+    // and the data ends up wrapping around the end of the table
+    for i in 0 .. 200u8 {
+      let name = [b'a' + i % 26; 10];
+      let value = [b'0' + i % 10; 10];
+      let mut block = vec![0x40, 10];
+      block.extend_from_slice(&name);
+      block.push(10);
+      block.extend_from_slice(&value);
+      decode_all(&mut decoder, &block);
+      let expected = (String::from_utf8(name.to_vec()).unwrap(), String::from_utf8(value.to_vec()).unwrap());
+      assert_eq!(decode_all(&mut decoder, &[0xbe]), vec![expected]);
+    }
+  }
+
+  #[test]
+  fn test_table_size_update_over_limit() {
+    let mut decoder = Decoder::new(64);
+    assert_eq!(decoder.decode(&[0x3f, 0x22], |_, _| {}), Err(Error::InvalidTableSize));
+  }
+
+  #[test]
+  fn test_encode() {
+    let mut block = Vec::new();
+    encode_status(&mut block, 200).unwrap();
+    encode_status(&mut block, 418).unwrap();
+    encode_header(&mut block, b"Content-Type", b"text/plain").unwrap();
+    let mut decoder = Decoder::new(4096);
+    assert_eq!(decode_all(&mut decoder, &block), pairs(&[
+      (":status", "200"), (":status", "418"), ("content-type", "text/plain")]));
+  }
+}
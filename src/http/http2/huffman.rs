@@ -0,0 +1,97 @@
+use super::hpack::Error;
+
+// Canonical huffman code from RFC 7541, Appendix B:
+// the number of codes for every code length in bits,
+pub const CODE_COUNT_BY_LEN : [u16; 31] = [
+  0, 0, 0, 0, 0, 10, 26, 32, 6, 0, 5, 3, 2, 6, 2, 3, 0, 0, 0, 3, 8, 13, 26, 29, 12, 4, 15, 19, 29, 0, 4
+];
+
+// symbols ordered by code length, and then by code
+pub const SYMBOLS : [u16; 257] = [
+  48, 49, 50, 97, 99, 101, 105, 111, 115, 116, 32, 37, 45, 46, 47, 51,
+  52, 53, 54, 55, 56, 57, 61, 65, 95, 98, 100, 102, 103, 104, 108, 109,
+  110, 112, 114, 117, 58, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76,
+  77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 89, 106, 107, 113, 118,
+  119, 120, 121, 122, 38, 42, 44, 59, 88, 90, 33, 34, 40, 41, 63, 39,
+  43, 124, 35, 62, 0, 36, 64, 91, 93, 126, 94, 125, 60, 96, 123, 92,
+  195, 208, 128, 130, 131, 162, 184, 194, 224, 226, 153, 161, 167, 172, 176, 177,
+  179, 209, 216, 217, 227, 229, 230, 129, 132, 133, 134, 136, 146, 154, 156, 160,
+  163, 164, 169, 170, 173, 178, 181, 185, 186, 187, 189, 190, 196, 198, 228, 232,
+  233, 1, 135, 137, 138, 139, 140, 141, 143, 147, 149, 150, 151, 152, 155, 157,
+  158, 165, 166, 168, 174, 175, 180, 182, 183, 188, 191, 197, 231, 239, 9, 142,
+  144, 145, 148, 159, 171, 206, 215, 225, 236, 237, 199, 207, 234, 235, 192, 193,
+  200, 201, 202, 205, 210, 213, 218, 219, 238, 240, 242, 243, 255, 203, 204, 211,
+  212, 214, 221, 222, 223, 241, 244, 245, 246, 247, 248, 250, 251, 252, 253, 254,
+  2, 3, 4, 5, 6, 7, 8, 11, 12, 14, 15, 16, 17, 18, 19, 20,
+  21, 23, 24, 25, 26, 27, 28, 29, 30, 31, 127, 220, 249, 10, 13, 22,
+  256,
+];
+
+const EOS : u16 = 256;
+
+pub fn decode(src: &[u8], dst: &mut Vec<u8>) -> Result<(), Error> {
+  let mut code = 0u32;
+  let mut first_code = 0u32;
+  let mut first_index = 0u32;
+  let mut len = 0usize;
+  let mut is_padding = true; //only ones since the last symbol
+  for byte in src {
+    for shift in (0 .. 8).rev() {
+      let bit = ((byte >> shift) & 1) as u32;
+      code |= bit;
+      len += 1;
+      is_padding = is_padding && bit == 1;
+      let count = CODE_COUNT_BY_LEN[len] as u32;
+      if code - first_code < count {
+        let symbol = SYMBOLS[(first_index + code - first_code) as usize];
+        if symbol == EOS {
+          return Err(Error::InvalidHuffmanCode);
+        }
+        dst.push(symbol as u8);
+        code = 0;
+        first_code = 0;
+        first_index = 0;
+        len = 0;
+        is_padding = true;
+      }
+      else {
+        if len == CODE_COUNT_BY_LEN.len() - 1 {
+          return Err(Error::InvalidHuffmanCode);
+        }
+        first_index += count;
+        first_code = (first_code + count) << 1;
+        code <<= 1;
+      }
+    }
+  }
+  // the string can only be padded with the first (up to 7) bits of EOS, which are all ones
+  if len > 7 || !is_padding {
+    return Err(Error::InvalidHuffmanCode);
+  }
+  Ok( () )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::decode;
+
+  #[test]
+  fn test_decode() {
+    // examples from RFC 7541, Appendix C.4
+    let mut dst = Vec::new();
+    decode(&[0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff], &mut dst).unwrap();
+    assert_eq!(dst, b"www.example.com");
+    dst.clear();
+    decode(&[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf], &mut dst).unwrap();
+    assert_eq!(dst, b"no-cache");
+  }
+
+  #[test]
+  fn test_decode_invalid_padding() {
+    let mut dst = Vec::new();
+    // 'a' is 00011, followed by zero padding
+    assert!(decode(&[0b0001_1000], &mut dst).is_err());
+    // more than 7 bits of padding
+    assert!(decode(&[0b0001_1111, 0xff], &mut dst).is_err());
+  }
+}
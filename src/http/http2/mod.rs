@@ -0,0 +1,9 @@
+mod frame;
+mod huffman;
+mod hpack;
+mod connection;
+
+pub use self::connection::{Connection, MAX_CONCURRENT_STREAMS};
+
+/// protocol id negotiated with ALPN during the tls handshake
+pub const ALPN_PROTOCOL : &'static str = "h2";
@@ -0,0 +1,76 @@
+use std::io::Write;
+use std::time::Duration;
+use buffer::Buffer;
+use io::{Handler, Event, Context, EventSource, Channel, Subscription};
+use http::status;
+
+// parks the connection on a channel until a message is published,
+// and then returns the complete response with the message as body.
+pub struct LongPoll {
+  headers: Option<Buffer>,
+  channel: Channel,
+  timeout: Duration,
+  subscription: Option<Subscription>
+}
+
+impl LongPoll {
+  pub fn new(headers: Buffer, channel: Channel, timeout: Duration) -> LongPoll {
+    LongPoll {
+      headers: Some(headers),
+      channel,
+      timeout,
+      subscription: None
+    }
+  }
+
+  fn subscribe(&mut self, ctx: &mut Context) -> Option<Buffer> {
+    match ctx.subscribe(self.channel, Some(self.timeout)) {
+      Ok(subscription) => {
+        self.subscription = Some(subscription);
+        None
+      },
+      Err(err) => {
+        println!("could not park connection on channel: {:?}", err);
+        Some(status_response(status::SERVICE_UNAVAILABLE))
+      }
+    }
+  }
+}
+
+impl Handler<Buffer> for LongPoll {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<Buffer> {
+    let is_delivered = match self.subscription {
+      None => return self.subscribe(ctx),
+      Some(ref subscription) => subscription.is_source_of(event)
+    };
+    if !is_delivered {
+      return None;
+    }
+    let subscription = self.subscription.take().unwrap();
+    let response = match (ctx.message(&subscription), self.headers.take()) {
+      (Some(message), Some(headers)) => message_response(headers, message),
+      _ => status_response(status::NO_CONTENT)
+    };
+    ctx.unsubscribe(subscription);
+    Some(response)
+  }
+}
+
+#[allow(unused_must_use)]
+fn message_response(mut headers: Buffer, message: &[u8]) -> Buffer {
+  write!(headers, "\r\nContent-Length:{}\r\n\r\n", message.len());
+  headers.write(message);
+  headers
+}
+
+#[allow(unused_must_use)]
+fn status_response(status: status::Status) -> Buffer {
+  let mut buffer = Buffer::new();
+  write!(buffer, "HTTP/1.1 {} {}\r\n", status.0, status.1);
+  // 204 responses can't have a Content-Length
+  if status != status::NO_CONTENT {
+    write!(buffer, "Content-Length:0\r\n");
+  }
+  write!(buffer, "\r\n");
+  buffer
+}
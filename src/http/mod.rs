@@ -9,6 +9,15 @@ pub mod mime_type;
 pub mod status;
 mod response;
 mod response_writer;
+mod long_poll;
+pub mod websocket;
+pub mod body_stream;
+pub mod event_stream;
+pub mod http2;
+pub mod cookie;
+pub mod session;
+pub mod date;
+mod protocol;
 
 pub mod request_handler;
 
@@ -19,6 +28,7 @@ pub use self::url_decode::*;
 pub use self::url_params::*;
 pub use self::response::{Responder, Response};
 pub use self::request_handler::RequestHandler;
+pub use self::protocol::ProtocolHandler;
 
 mod internal {
   pub use super::response::ResponseMetaInfo;
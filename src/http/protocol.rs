@@ -0,0 +1,51 @@
+use io::{Handler, Event, Context};
+use query_connection::QueryConnection;
+use super::RequestHandler;
+use super::request_handler;
+use super::internal::ResponseWriter;
+use super::http2;
+
+enum Protocol<H, F> {
+  Unknown(F),
+  Http1(QueryConnection<request_handler::Handler<H>, ResponseWriter>),
+  Http2(http2::Connection<H, F>)
+}
+
+/// Serves a connection over HTTP/2 when it was negotiated
+/// during the tls handshake, and over HTTP/1.1 otherwise.
+pub struct ProtocolHandler<H, F> {
+  protocol: Option<Protocol<H, F>>
+}
+
+impl<H, F: Fn() -> H> ProtocolHandler<H, F> {
+  /// `create_handler` is called for every request, which is once
+  /// for an HTTP/1.1 connection and for every stream of an HTTP/2 connection.
+  pub fn new(create_handler: F) -> ProtocolHandler<H, F> {
+    ProtocolHandler {
+      protocol: Some(Protocol::Unknown(create_handler))
+    }
+  }
+}
+
+impl<H: RequestHandler, F: Fn() -> H> Handler<()> for ProtocolHandler<H, F> {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<()> {
+    let protocol = match self.protocol.take() {
+      Some(Protocol::Unknown(create_handler)) => {
+        if ctx.protocol() == Some(http2::ALPN_PROTOCOL) {
+          Protocol::Http2(http2::Connection::new(create_handler))
+        }
+        else {
+          Protocol::Http1(QueryConnection::new(request_handler::Handler::new(create_handler())))
+        }
+      },
+      Some(protocol) => protocol,
+      None => return Some( () )
+    };
+    self.protocol = Some(protocol);
+    match self.protocol {
+      Some(Protocol::Http1(ref mut connection)) => connection.handle_event(event, ctx),
+      Some(Protocol::Http2(ref mut connection)) => connection.handle_event(event, ctx),
+      _ => None
+    }
+  }
+}
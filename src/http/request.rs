@@ -1,6 +1,10 @@
 use http::headers::*;
 use http::{RequestResult, RequestError, UrlEncodedParamsIterator};
+use http::str::slice_to_str;
+use http::cookie::Cookies;
 use split::buffer_split_mut;
+use std::net::SocketAddr;
+use io::ConnectionInfo;
 use std::str;
 
 pub struct CommonHeaders<'a> {
@@ -10,6 +14,13 @@ pub struct CommonHeaders<'a> {
   pub content_type: Option<MimeType<'a>>,
   pub authorization: Option<Authorization<'a>>,
   pub if_none_match: Option<ETagMatch<'a>>,
+  pub upgrade: Option<&'a str>,
+  pub connection: Option<&'a str>,
+  pub sec_websocket_key: Option<&'a str>,
+  pub sec_websocket_version: Option<u64>,
+  pub last_event_id: Option<&'a str>,
+  pub transfer_encoding: Option<&'a str>,
+  pub cookies: Option<Cookies<'a>>,
 }
 
 impl<'a> CommonHeaders<'a> {
@@ -21,6 +32,13 @@ impl<'a> CommonHeaders<'a> {
       content_type: None,
       authorization: None,
       if_none_match: None,
+      upgrade: None,
+      connection: None,
+      sec_websocket_key: None,
+      sec_websocket_version: None,
+      last_event_id: None,
+      transfer_encoding: None,
+      cookies: None,
     }
   }
 
@@ -32,6 +50,13 @@ impl<'a> CommonHeaders<'a> {
       Header::Authorization(a) => self.authorization = Some(a),
       Header::Referer(r) => self.referer = Some(r),
       Header::IfNoneMatch(etag_match) => self.if_none_match = Some(etag_match),
+      Header::Upgrade(u) => self.upgrade = Some(u),
+      Header::Connection(c) => self.connection = Some(c),
+      Header::SecWebsocketKey(k) => self.sec_websocket_key = Some(k),
+      Header::SecWebsocketVersion(v) => self.sec_websocket_version = Some(v),
+      Header::LastEventId(id) => self.last_event_id = Some(id),
+      Header::TransferEncoding(te) => self.transfer_encoding = Some(te),
+      Header::Cookie(c) => self.cookies = Some(c),
       _ => ()
     };
   }
@@ -39,12 +64,15 @@ impl<'a> CommonHeaders<'a> {
 
 pub struct Request<'a> {
   request_line: RequestLine<'a>,
-  headers: CommonHeaders<'a>
+  headers: CommonHeaders<'a>,
+  raw_headers: HeaderList<'a>,
+  connection: Option<ConnectionInfo>
 }
 
 impl<'a> Request<'a> {
   pub fn parse(header_bytes: &'a mut [u8]) -> RequestResult<Request<'a>> {
     let mut headers = CommonHeaders::new();
+    let mut raw_headers = HeaderList::new();
     let mut request_line: Option<RequestLine> = None;
 
     for line in buffer_split_mut(header_bytes, b"\r\n") {
@@ -53,7 +81,14 @@ impl<'a> Request<'a> {
       }
       else {
         let raw_header = RawHeader::parse(line)?;
-        let header = Header::from_raw(raw_header)?;
+        let header = if is_decoded_in_place(raw_header.name) {
+          Header::from_raw(raw_header)?
+        } else {
+          let name = raw_header.name;
+          let value = slice_to_str(raw_header.value)?;
+          raw_headers.push(name, value)?;
+          Header::parse(name, value)?
+        };
         headers.set_header(header);
       }
     }
@@ -61,7 +96,9 @@ impl<'a> Request<'a> {
     if let Some(request_line) = request_line {
       Ok(Request {
         request_line,
-        headers
+        headers,
+        raw_headers,
+        connection: None
       })
     }
     else {
@@ -88,5 +125,69 @@ impl<'a> Request<'a> {
   pub fn headers(&self) -> &'a CommonHeaders {
     &self.headers
   }
+
+  /// Looks up a header by name, ignoring case.
+  /// Headers that are decoded in place while parsing are not kept as received,
+  /// so this returns `None` for `Authorization` and `Cookie`:
+  /// use `headers().authorization` and `cookie()` instead.
+  pub fn header(&self, name: &str) -> Option<&'a str> {
+    self.raw_headers.get(name)
+  }
+
+  /// all headers in the order they were received,
+  /// with the same exception as `header`.
+  pub fn raw_headers<'r>(&'r self) -> HeaderIterator<'r, 'a> {
+    self.raw_headers.iter()
+  }
+
+  pub fn cookie(&self, name: &str) -> Option<&'a str> {
+    self.headers.cookies.as_ref().and_then(|cookies| cookies.get(name))
+  }
+
+  /// the connection the request was received on, with the client address
+  /// and TLS parameters. `None` for requests that weren't received over a connection.
+  pub fn connection(&self) -> Option<&ConnectionInfo> {
+    self.connection.as_ref()
+  }
+
+  /// the address of the client that sent the request
+  pub fn peer_addr(&self) -> Option<SocketAddr> {
+    self.connection.map(|c| c.peer_addr)
+  }
+
+  pub fn set_connection(&mut self, connection: ConnectionInfo) {
+    self.connection = Some(connection);
+  }
   
 }
+
+#[cfg(test)]
+mod tests {
+  use super::Request;
+
+  #[test]
+  fn test_header_lookup() {
+    let mut bytes = b"GET / HTTP/1.1\r\nhost: example.com\r\nX-Foo:  bar \r\ncontent-length: 3".to_vec();
+    let request = Request::parse(&mut bytes).unwrap();
+    assert_eq!(request.header("x-foo"), Some("bar"));
+    assert_eq!(request.header("HOST"), Some("example.com"));
+    assert_eq!(request.header("accept"), None);
+    assert_eq!(request.headers().host, Some("example.com"));
+    assert_eq!(request.headers().content_length, Some(3));
+    let names : Vec<&str> = request.raw_headers().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["Host", "X-Foo", "Content-Length"]);
+  }
+
+  #[test]
+  fn test_decoded_headers_not_raw() {
+    let mut bytes = b"GET / HTTP/1.1\r\nCookie: a=%31; b=2\r\nAuthorization: Basic Zm9vOmJhcg==\r\nHost: example.com".to_vec();
+    let request = Request::parse(&mut bytes).unwrap();
+    assert_eq!(request.header("cookie"), None);
+    assert_eq!(request.header("authorization"), None);
+    assert_eq!(request.cookie("a"), Some("1"));
+    assert_eq!(request.cookie("b"), Some("2"));
+    assert!(request.headers().authorization.is_some());
+    let names : Vec<&str> = request.raw_headers().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["Host"]);
+  }
+}
@@ -3,8 +3,11 @@ use http::{
   Responder,
   Response,
   RequestError,
+  RequestResult,
   status
 };
+use http::status::Status;
+use split::BufferExt;
 use http::internal::*;
 use buffer::Buffer;
 use io;
@@ -26,12 +29,23 @@ pub trait RequestHandler {
     Ok(None)
   }
 
+  /// Renders the response for errors the server runs into by itself,
+  /// like malformed or oversized requests. Return `Ok(None)`
+  /// to fall back to a plain text page with the message.
+  fn render_error(&mut self, _status: Status, _message: &str, _responder: &Responder)
+    -> std::io::Result<Option<Response>>
+  {
+    Ok(None)
+  }
+
 }
 
 pub struct Handler<T> {
   header_body_splitter: HeaderBodySplitter,
   handler: T,
   read_buffer: Buffer,
+  // offset and length of the body, once the headers have been read
+  body_range: Option<(usize, usize)>
 }
 
 impl<T> Handler<T> {
@@ -40,16 +54,76 @@ impl<T> Handler<T> {
     Handler {
       header_body_splitter: HeaderBodySplitter::new(),
       handler,
-      read_buffer: Buffer::new()
+      read_buffer: Buffer::new(),
+      body_range: None
     }
   }
 }
 
+impl<T: RequestHandler> Handler<T> {
+
+  fn try_read_headers(&mut self, connection: &io::ConnectionInfo) -> Option<Response> {
+    let buffer_capacity = self.read_buffer.capacity();
+    let is_buffer_full = self.read_buffer.is_full();
+    let responder = Responder::new();
+    let mut read_buffer = self.read_buffer.as_mut_slice();
+    let buffer_len = read_buffer.len();
+
+    let (header_buf, body_len) = match self.header_body_splitter.try_split(&mut read_buffer) {
+      Some((header_buf, body_buf)) => (header_buf, body_buf.len()),
+      None if is_buffer_full => {
+        let err = if read_buffer.position(b"\r\n").is_some() {
+          RequestError::HeadersTooLarge
+        } else {
+          RequestError::UriTooLong
+        };
+        return handle_request_error(&mut self.handler, err, &responder);
+      },
+      None => return None //in progress
+    };
+    let body_start = buffer_len - body_len;
+    let max_body_len = buffer_capacity - body_start;
+
+    let request = Request::parse(header_buf)
+      .and_then(|mut req| {
+        req.set_connection(*connection);
+        check_request(&req, max_body_len).map(|len| (req, len))
+      });
+    let (response, content_length) = match request {
+      Ok((req, content_length)) => {
+        let response = self.handler.read_headers(&req, &responder)
+          .unwrap_or_else(|err| handle_io_error(&mut self.handler, err, &responder));
+        (response, content_length)
+      },
+      Err(err) => (handle_request_error(&mut self.handler, err, &responder), 0)
+    };
+    if response.is_some() || content_length == 0 {
+      return Some(response.unwrap_or_else(|| handle_no_response()));
+    }
+    self.body_range = Some((body_start, content_length));
+    self.try_read_body()
+  }
+
+  fn try_read_body(&mut self) -> Option<Response> {
+    let (body_start, body_len) = self.body_range?;
+    let body_end = body_start + body_len;
+    if self.read_buffer.len() < body_end {
+      return None;  //in progress
+    }
+    let responder = Responder::new();
+    let body = &mut self.read_buffer.as_mut_slice()[body_start .. body_end];
+    let response = self.handler.read_body(body, &responder)
+      .unwrap_or_else(|err| handle_io_error(&mut self.handler, err, &responder));
+    Some(response.unwrap_or_else(|| handle_no_response()))
+  }
+}
+
 impl<T: RequestHandler> io::Handler<Option<ResponseWriter>> for Handler<T>
 {
 
   fn handle_event(&mut self, event: &io::Event, ctx: &mut io::Context) -> Option<Option<ResponseWriter>>
   {
+    let connection = *ctx.connection();
     let mut socket = ctx.socket();
 
     if !event.kind().is_readable() {
@@ -65,65 +139,101 @@ impl<T: RequestHandler> io::Handler<Option<ResponseWriter>> for Handler<T>
         None //spurious wake-up, ignore and wait for next socket event
       },
       Ok(_) => {
-        let mut read_buffer = self.read_buffer.as_mut_slice();
-        if let Some((header_buf, _)) = 
-          self.header_body_splitter.try_split(&mut read_buffer)
-        {
-          let request = Request::parse(header_buf);
-          let response = {
-            let responder = Responder::new();
-            {
-              match request {
-                Ok(req) => {
-                  self.handler.read_headers(&req, &responder)
-                    .unwrap_or_else(|err| handle_io_error(err, &responder))
-                },
-                Err(err) => handle_request_error(err, &responder)
-              }
-            }
-          };
-          let response = response.unwrap_or_else(|| handle_no_response());          
-          let response_handler = response.into_handler();
-          Some(Some(response_handler))
-        }
-        else {
-          None  //in progress
-        }
+        let response = if self.body_range.is_some() {
+          self.try_read_body()
+        } else {
+          self.try_read_headers(&connection)
+        };
+        response.map(|response| Some(response.into_handler()))
       }
     }
   }
 }
 
+// checks what the request handler can't do anything about,
+// and returns the length of the body.
+fn check_request(request: &Request, max_body_len: usize) -> RequestResult<usize> {
+  if request.version() != "1.1" && request.version() != "1.0" {
+    return Err(RequestError::UnsupportedVersion);
+  }
+  if let Some(transfer_encoding) = request.headers().transfer_encoding {
+    let is_chunked = transfer_encoding.split(',')
+      .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+    // only bodies of a known length fit in the read buffer
+    return Err(if is_chunked {
+      RequestError::LengthRequired
+    } else {
+      RequestError::UnsupportedTransferEncoding
+    });
+  }
+  let content_length = request.headers().content_length.unwrap_or(0);
+  if content_length > max_body_len as u64 {
+    return Err(RequestError::PayloadTooLarge);
+  }
+  Ok(content_length as usize)
+}
+
 #[allow(unused_must_use)]
-fn handle_no_response() -> Response {
+pub fn handle_no_response() -> Response {
   let response = b"HTTP/1.1 500\r\n\r\nNo response";
   let mut buffer = Buffer::new();
   buffer.write(response);
   Response::from_buffer(ResponseMetaInfo::from_status(500), buffer)
 }
 
-fn handle_io_error(err: std::io::Error, responder: &Responder) -> Option<Response> {
-  let mut resp = responder.respond(status::INTERNAL_SERVER_ERROR).ok()?;
-  /*let msg = match err.kind() {
-    std::io::ErrorKind::WriteZero => "Response too big for buffer",
-    _ => "Unknown IO error:"
-  };*/
+/// Responds with the error page of the request handler,
+/// or a plain text page if it doesn't render one.
+pub fn respond_with_error<T: RequestHandler>(handler: &mut T, status: Status, message: &str, responder: &Responder) -> Option<Response> {
+  match handler.render_error(status, message, responder) {
+    Ok(Some(response)) => return Some(response),
+    Ok(None) => {},
+    Err(err) => println!("could not render error page for {}: {:?}", status.0, err)
+  };
+  let mut resp = responder.respond(status).ok()?;
   resp.set_header("Content-Type", "text/plain").ok()?;
   let mut body = resp.into_body().ok()?;
-  write!(body, "io error: {:?}", err).ok()?;
+  write!(body, "{}", message).ok()?;
   Some(body.finish())
 }
 
-fn handle_request_error(err: RequestError, responder: &Responder) -> Option<Response> {
-  let mut resp = responder.respond(status::BAD_REQUEST).ok()?;
-  let msg = match err {
-    RequestError::InvalidRequestLine => "Invalid request line",
-    RequestError::InvalidHeader => "Invalid header",
-    RequestError::InvalidEncoding => "Request not encoded with UTF8",
-    RequestError::UrlEncodedNul => "URL encoded value contains NUL character"
+pub fn handle_io_error<T: RequestHandler>(handler: &mut T, err: std::io::Error, responder: &Responder) -> Option<Response> {
+  let status = match err.kind() {
+    std::io::ErrorKind::NotFound => status::NOT_FOUND,
+    std::io::ErrorKind::PermissionDenied => status::FORBIDDEN,
+    _ => {
+      println!("responding with 500 because of io error: {:?}", err);
+      status::INTERNAL_SERVER_ERROR
+    }
   };
-  resp.set_header("Content-Type", "text/plain").ok()?;
-  let mut body = resp.into_body().ok()?;
-  write!(body, "{}", msg).ok()?;
-  Some(body.finish())
+  respond_with_error(handler, status, status.1, responder)
+}
+
+pub fn handle_request_error<T: RequestHandler>(handler: &mut T, err: RequestError, responder: &Responder) -> Option<Response> {
+  respond_with_error(handler, err.status(), err.message(), responder)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::check_request;
+  use http::{Request, RequestError};
+
+  fn check(request: &str, max_body_len: usize) -> Result<usize, RequestError> {
+    let mut bytes = request.as_bytes().to_vec();
+    let request = Request::parse(&mut bytes).unwrap();
+    check_request(&request, max_body_len)
+  }
+
+  #[test]
+  fn test_check_request() {
+    assert_eq!(check("POST / HTTP/1.1\r\nContent-Length: 5", 10), Ok(5));
+    assert_eq!(check("GET / HTTP/1.0", 10), Ok(0));
+  }
+
+  #[test]
+  fn test_check_request_errors() {
+    assert_eq!(check("POST / HTTP/1.1\r\nContent-Length: 11", 10), Err(RequestError::PayloadTooLarge));
+    assert_eq!(check("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked", 10), Err(RequestError::LengthRequired));
+    assert_eq!(check("POST / HTTP/1.1\r\nTransfer-Encoding: br", 10), Err(RequestError::UnsupportedTransferEncoding));
+    assert_eq!(check("GET / HTTP/3.0", 10), Err(RequestError::UnsupportedVersion));
+  }
 }
@@ -1,13 +1,22 @@
 use std::io::Write;
 use std::io;
+use std::time::Duration;
 use buffer::Buffer;
 use io::sources::file;
+use io::{Channel, Handler};
 use http::status::Status;
 use super::response_writer::ResponseWriter;
+use super::body_stream::BodyStream;
+use super::event_stream::{EventStream, EventStreamHandler};
+use super::cookie::SetCookie;
+use split::BufferExt;
 
 pub enum ResponseBody {
   InBuffer,
-  File(file::Reader)
+  File(file::Reader),
+  Channel(Channel, Duration),
+  Upgrade(Box<dyn Handler<()>>),
+  Stream(Box<dyn BodyStream>)
 }
 
 pub struct Response {
@@ -35,6 +44,22 @@ impl Response {
     Response {meta, buffer: headers, body: ResponseBody::File(file)}
   }
 
+  pub fn from_channel(meta: ResponseMetaInfo, headers: Buffer, channel: Channel, timeout: Duration) -> Response {
+    Response {meta, buffer: headers, body: ResponseBody::Channel(channel, timeout)}
+  }
+
+  pub fn from_upgrade(meta: ResponseMetaInfo, headers: Buffer, handler: Box<dyn Handler<()>>) -> Response {
+    Response {meta, buffer: headers, body: ResponseBody::Upgrade(handler)}
+  }
+
+  pub fn from_stream(meta: ResponseMetaInfo, headers: Buffer, stream: Box<dyn BodyStream>) -> Response {
+    Response {meta, buffer: headers, body: ResponseBody::Stream(stream)}
+  }
+
+  pub fn into_parts(self) -> (ResponseMetaInfo, Buffer, ResponseBody) {
+    (self.meta, self.buffer, self.body)
+  }
+
   pub fn into_handler(self) -> ResponseWriter {
     ResponseWriter::new(self.buffer, self.body)
   }
@@ -42,6 +67,20 @@ impl Response {
   pub fn status_code(&self) -> u16 {
     self.meta.status
   }
+
+  /// Adds a `Set-Cookie` header to a response that was already created,
+  /// for wrapping handlers that only get to see the response of their child.
+  pub fn set_cookie(&mut self, cookie: &SetCookie) -> io::Result<()> {
+    cookie.validate()?;
+    let status_line_len = self.buffer.as_slice().position(b"\r\n").unwrap_or(self.buffer.len());
+    let len_before = self.buffer.len();
+    write!(&mut self.buffer, "\r\nSet-Cookie:")?;
+    cookie.write(&mut self.buffer)?;
+    let header_len = self.buffer.len() - len_before;
+    // move the header in front of the others, and the body if any
+    self.buffer.as_mut_slice()[status_line_len ..].rotate_right(header_len);
+    Ok( () )
+  }
 }
 
 pub struct Responder {
@@ -80,6 +119,12 @@ impl HeaderWriter {
     write!(&mut self.buffer, "\r\n{}:{}", name, value)
   }
 
+  pub fn set_cookie(&mut self, cookie: &SetCookie) -> io::Result<()> {
+    cookie.validate()?;
+    write!(&mut self.buffer, "\r\nSet-Cookie:")?;
+    cookie.write(&mut self.buffer)
+  }
+
   pub fn set_header_writer<F>(&mut self, name: &str, callback: F)
     -> io::Result<()>
     where
@@ -99,6 +144,34 @@ impl HeaderWriter {
     write!(&mut self.buffer, "\r\n\r\n")?;
     Ok(Some(Response::from_file(self.meta, self.buffer, file)))
   }
+
+  /// Long-polling: parks the connection on the channel and only sends the response
+  /// once a message is published on it, with the message as body.
+  /// If no message is published before the timeout, `204 No Content` is sent instead.
+  pub fn finish_with_channel(self, channel: Channel, timeout: Duration) -> io::Result<Option<Response>> {
+    Ok(Some(Response::from_channel(self.meta, self.buffer, channel, timeout)))
+  }
+
+  /// Switches protocols: once the headers are written,
+  /// the connection is handed over to the given handler.
+  pub fn finish_with_upgrade(mut self, handler: Box<dyn Handler<()>>) -> io::Result<Option<Response>> {
+    write!(&mut self.buffer, "\r\n\r\n")?;
+    Ok(Some(Response::from_upgrade(self.meta, self.buffer, handler)))
+  }
+
+  /// Keeps the response open after the headers, and writes the body
+  /// as the stream produces it. The body is terminated by closing the connection.
+  pub fn finish_with_stream(mut self, stream: Box<dyn BodyStream>) -> io::Result<Option<Response>> {
+    write!(&mut self.buffer, "\r\n\r\n")?;
+    Ok(Some(Response::from_stream(self.meta, self.buffer, stream)))
+  }
+
+  /// Server-Sent Events: sends events from the handler until it ends the stream.
+  pub fn finish_with_event_stream<H: EventStreamHandler + 'static>(mut self, handler: H) -> io::Result<Option<Response>> {
+    self.set_header("Content-Type", "text/event-stream")?;
+    self.set_header("Cache-Control", "no-cache")?;
+    self.finish_with_stream(Box::new(EventStream::new(handler)))
+  }
 }
 
 pub struct BodyWriter {
@@ -1,32 +1,109 @@
-use io::{Handler, Context, Event};
-use io::handlers::{BufferResponder, FileResponder};
+use io::{Handler, Context, Event, EventKind, ResponseKind, ResponseTuning};
+use io::handlers::{BufferResponder, FileResponder, SendFileResponder};
 use buffer::Buffer;
+use http::status;
 use super::internal::ResponseBody;
+use super::response::Responder;
+use super::long_poll::LongPoll;
+use super::body_stream::StreamResponder;
 
 enum State {
   Headers(BufferResponder, ResponseBody),
-  FileBody(FileResponder)
+  // the initial read of the file is queued before sending the headers
+  FileHeaders(BufferResponder, FileResponder),
+  FileBody(FileResponder),
+  SendFileBody(SendFileResponder),
+  Parked(LongPoll),
+  Streaming(StreamResponder),
+  Upgraded(Box<dyn Handler<()>>)
 }
 
 pub struct ResponseWriter {
-  state: Option<State>
+  state: Option<State>,
+  kind: ResponseKind,
+  is_started: bool,
+  tuning: ResponseTuning
 }
 
 impl ResponseWriter {
   pub fn new(headers: Buffer, body: ResponseBody) -> ResponseWriter {
+    let kind = match body {
+      ResponseBody::InBuffer => ResponseKind::InBuffer,
+      ResponseBody::File(_) => ResponseKind::File,
+      ResponseBody::Channel(_, _) => ResponseKind::LongPoll,
+      ResponseBody::Upgrade(_) | ResponseBody::Stream(_) => ResponseKind::Stream
+    };
+    let state = match body {
+      ResponseBody::Channel(channel, timeout) =>
+        State::Parked(LongPoll::new(headers, channel, timeout)),
+      body =>
+        State::Headers(BufferResponder::new(headers), body)
+    };
     ResponseWriter {
-      state: Some(State::Headers(BufferResponder::new(headers), body))
+      state: Some(state),
+      kind,
+      is_started: false,
+      tuning: ResponseTuning::default()
+    }
+  }
+
+  /// The read queue is shared by all connections and can be full,
+  /// so files that are read into a buffer get their first read queued
+  /// before the headers are sent, answering 503 if that fails.
+  fn queue_file_read(&mut self, ctx: &mut Context) {
+    // plain connections and kernel TLS can send the file around userspace,
+    // without reading it into a buffer first
+    if ctx.socket().socket_fd().is_some() {
+      return;
     }
+    let state = self.state.take();
+    self.state = match state {
+      Some(State::Headers(header_writer, ResponseBody::File(file_reader))) => {
+        let queued = ctx.register_file(file_reader).and_then(|mut file_reader| {
+          match file_reader.try_queue_read() {
+            Ok(_) => Ok(file_reader),
+            Err(err) => {
+              file_reader.into_deregistered(ctx)?;
+              Err(err)
+            }
+          }
+        });
+        match queued {
+          Ok(file_reader) =>
+            Some(State::FileHeaders(header_writer, FileResponder::new(file_reader))),
+          Err(err) => {
+            println!("could not read file for response: {:?}", err);
+            service_unavailable().map(|headers| {
+              State::Headers(BufferResponder::new(headers), ResponseBody::InBuffer)
+            })
+          }
+        }
+      },
+      state => state
+    };
   }
 
   fn next_state(&mut self, ctx: &mut Context) -> Option<State> {
     let state = self.state.take();
     let new_state = match state {
       Some(State::Headers(_, ResponseBody::File(file_reader))) => {
-        let file_reader = ctx.register(file_reader).unwrap();
-        let mut file_writer = FileResponder::start(file_reader).unwrap();
+        match SendFileResponder::start(file_reader) {
+          Ok(file_sender) => Some(State::SendFileBody(file_sender)),
+          Err(err) => {
+            println!("could not send file for response: {:?}", err);
+            None
+          }
+        }
+      },
+      Some(State::FileHeaders(_, file_writer)) => {
         Some(State::FileBody(file_writer))
       },
+      Some(State::Headers(_, ResponseBody::Upgrade(handler))) => {
+        Some(State::Upgraded(handler))
+      },
+      Some(State::Headers(_, ResponseBody::Stream(stream))) => {
+        Some(State::Streaming(StreamResponder::new(stream)))
+      },
       Some(State::FileBody(file_writer)) => {
         let reader = file_writer.into_reader();
         reader.into_deregistered(ctx).unwrap();
@@ -40,21 +117,145 @@ impl ResponseWriter {
 
 impl Handler<()> for ResponseWriter {
   fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<()> {
+    if !self.is_started {
+      self.is_started = true;
+      // tuning the socket is best effort, the response is sent either way
+      self.tuning = ctx.tcp().start_response(self.kind).unwrap_or_default();
+      self.queue_file_read(ctx);
+    }
     let result = match self.state {
-      Some(State::Headers(ref mut header_writer, _)) => {
+      Some(State::Headers(ref mut header_writer, _)) |
+      Some(State::FileHeaders(ref mut header_writer, _)) => {
         header_writer.handle_event(event, ctx)
       },
       Some(State::FileBody(ref mut file_writer)) => {
         file_writer.handle_event(event, ctx)
       },
+      Some(State::SendFileBody(ref mut file_sender)) => {
+        file_sender.handle_event(event, ctx)
+      },
+      Some(State::Streaming(ref mut stream_writer)) => {
+        stream_writer.handle_event(event, ctx)
+      },
+      Some(State::Upgraded(ref mut handler)) => {
+        handler.handle_event(event, ctx).map(|_| 0)
+      },
+      Some(State::Parked(ref mut long_poll)) => {
+        if let Some(response) = long_poll.handle_event(event, ctx) {
+          self.state = Some(State::Headers(BufferResponder::new(response), ResponseBody::InBuffer));
+          // the socket probably didn't change readiness while parked,
+          // so don't wait for a socket event to start writing
+          let socket_event = Event::new(ctx.socket().token(), EventKind::new().with_writable(true));
+          return self.handle_event(&socket_event, ctx);
+        }
+        None
+      },
       _ => None
     };
     if result.is_some() { //a subhandler has finished, switch to the next
       self.state = self.next_state(ctx);
+      // give the new protocol handler or stream a chance to read
+      // what was already received and send its first messages
+      let is_open_ended = match self.state {
+        Some(State::Upgraded(_)) | Some(State::Streaming(_)) => true,
+        _ => false
+      };
+      if is_open_ended {
+        let socket_event = Event::new(ctx.socket().token(), EventKind::new().with_readable(true).with_writable(true));
+        return self.handle_event(&socket_event, ctx);
+      }
+      // sendfile is driven by the socket, which is likely still writable
+      if let Some(State::SendFileBody(_)) = self.state {
+        let socket_event = Event::new(ctx.socket().token(), EventKind::new().with_writable(true));
+        return self.handle_event(&socket_event, ctx);
+      }
     }
     match self.state {
       Some(_) => None,  //in progress
-      None => Some( () ) // done
+      None => {
+        let _ = ctx.tcp().uncork(self.tuning);
+        Some( () ) // done
+      }
+    }
+  }
+}
+
+fn service_unavailable() -> Option<Buffer> {
+  let mut response = Responder::new().respond(status::SERVICE_UNAVAILABLE).ok()?;
+  response.set_header_usize("Content-Length", 0).ok()?;
+  let (_, headers, _) = response.into_body().ok()?.finish().into_parts();
+  Some(headers)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ResponseWriter;
+  use http::internal::ResponseBody;
+  use buffer::Buffer;
+  use io::{Context, ConnectionId, AsyncToken, AsyncTokenSource, Broker, ConnectionInfo,
+    TcpPolicy, TcpSocket, Event, EventKind, EventSource, ReadSizeHint, SocketFd, Handler};
+  use io::sources::file::{Reader, ReadQueue, Directory};
+  use std;
+  use std::io::{Read, Write};
+  use mio;
+
+  struct MemorySocket {
+    output: Vec<u8>
+  }
+
+  impl Read for MemorySocket {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+      Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "no input"))
+    }
+  }
+
+  impl Write for MemorySocket {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.output.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok( () )
+    }
+  }
+
+  impl EventSource for MemorySocket {
+    fn token(&self) -> AsyncToken {
+      AsyncToken(0)
+    }
+  }
+
+  impl ReadSizeHint for MemorySocket {}
+  impl SocketFd for MemorySocket {}
+
+  #[test]
+  fn test_read_queue_full() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let path = dir.sub_path("small.txt").unwrap();
+    let poll = mio::Poll::new().unwrap();
+    let mut token_source = AsyncTokenSource::starting_from(AsyncToken(1));
+    let mut broker = Broker::new();
+    let info = ConnectionInfo::new("127.0.0.1:4433".parse().unwrap());
+    let policy = TcpPolicy::new();
+    let queue = ReadQueue::with_aio(1).unwrap();
+    let mut socket = MemorySocket { output: Vec::new() };
+    {
+      let mut ctx = Context::new(&poll, ConnectionId(1), &mut token_source, &mut broker,
+        &mut socket, &info, TcpSocket::new(-1, &policy), &queue);
+      // another response takes the only read of the queue
+      let (other_reader, _) = Reader::open(&path, None).unwrap();
+      let mut other_reader = ctx.register_file(other_reader).unwrap();
+      assert!(other_reader.try_queue_read().unwrap());
+
+      let (reader, _) = Reader::open(&path, None).unwrap();
+      let mut headers = Buffer::new();
+      write!(headers, "HTTP/1.1 200 OK\r\nContent-Length:31\r\n\r\n").unwrap();
+      let mut writer = ResponseWriter::new(headers, ResponseBody::File(reader));
+      let event = Event::new(AsyncToken(0), EventKind::new().with_writable(true));
+      assert_eq!(writer.handle_event(&event, &mut ctx), Some( () ));
+      other_reader.into_deregistered(&mut ctx).unwrap();
     }
+    assert!(socket.output.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
   }
 }
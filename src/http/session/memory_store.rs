@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tls::{Random, mac_eq};
+use encoding::hex;
+use super::{Session, SessionId, SessionStore, SESSION_ID_SIZE, MAX_SESSION_DATA};
+
+// id, expires, data length, data
+const RECORD_SIZE : usize = SESSION_ID_SIZE + 8 + 1 + MAX_SESSION_DATA;
+
+/// Keeps sessions in memory, referred to by a random session id in the cookie.
+///
+/// The capacity is fixed when creating the store. Once it is full,
+/// the session that would expire first makes room for the new one.
+pub struct MemoryStore {
+  sessions: RefCell<Vec<Session>>,
+  random: RefCell<Random>,
+  max_age: u64,
+  path: Option<PathBuf>
+}
+
+impl MemoryStore {
+  pub fn new(capacity: usize, max_age: u64) -> io::Result<MemoryStore> {
+    let random = Random::from_system()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no system random source"))?;
+    Ok(MemoryStore {
+      sessions: RefCell::new(vec![Session::new(); capacity]),
+      random: RefCell::new(random),
+      max_age,
+      path: None
+    })
+  }
+
+  /// Like `new`, but also keeps the sessions in a file, so they survive a restart.
+  /// Sessions in the file are loaded now, and the file is rewritten
+  /// (blocking the event loop) every time a session is saved or removed.
+  pub fn with_file(capacity: usize, max_age: u64, path: &Path) -> io::Result<MemoryStore> {
+    let mut store = MemoryStore::new(capacity, max_age)?;
+    match File::open(path) {
+      Ok(file) => store.read_from(file, super::unix_time_now())?,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+      Err(err) => return Err(err)
+    };
+    store.path = Some(path.to_owned());
+    Ok(store)
+  }
+
+  fn read_from<R: Read>(&mut self, mut src: R, now: u64) -> io::Result<()> {
+    let mut sessions = self.sessions.borrow_mut();
+    let mut slots = sessions.iter_mut();
+    let mut record = [0u8; RECORD_SIZE];
+    loop {
+      match src.read_exact(&mut record) {
+        Ok(()) => {},
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok( () ),
+        Err(err) => return Err(err)
+      }
+      let (id, remainder) = record.split_at(SESSION_ID_SIZE);
+      let (expires, remainder) = remainder.split_at(8);
+      let expires = expires.iter().fold(0u64, |n, &b| n << 8 | b as u64);
+      let data_len = remainder[0] as usize;
+      if expires <= now || data_len > MAX_SESSION_DATA {
+        continue;
+      }
+      let mut session_id = [0u8; SESSION_ID_SIZE];
+      session_id.copy_from_slice(id);
+      match slots.next() {
+        Some(slot) => *slot = Session::loaded(Some(session_id), &remainder[1 .. 1 + data_len], expires),
+        None => return Ok( () )
+      }
+    }
+  }
+
+  fn write_to<W: Write>(&self, mut dst: W, now: u64) -> io::Result<()> {
+    for session in self.sessions.borrow().iter() {
+      if let Some(ref id) = session.id {
+        if session.expires <= now {
+          continue;
+        }
+        let mut record = [0u8; RECORD_SIZE];
+        record[.. SESSION_ID_SIZE].copy_from_slice(id);
+        for i in 0 .. 8 {
+          record[SESSION_ID_SIZE + i] = (session.expires >> ((7 - i) * 8)) as u8;
+        }
+        let data = session.data();
+        record[SESSION_ID_SIZE + 8] = data.len() as u8;
+        record[SESSION_ID_SIZE + 9 .. SESSION_ID_SIZE + 9 + data.len()].copy_from_slice(data);
+        dst.write_all(&record)?;
+      }
+    }
+    Ok( () )
+  }
+
+  fn persist(&self, now: u64) -> io::Result<()> {
+    if let Some(ref path) = self.path {
+      // write to a temporary file first, so a crash doesn't leave a truncated file
+      let tmp_path = path.with_extension("tmp");
+      self.write_to(create_private(&tmp_path)?, now)?;
+      fs::rename(&tmp_path, path)?;
+    }
+    Ok( () )
+  }
+}
+
+// only readable by the owner, anyone reading the session ids can take over the sessions
+fn create_private(path: &Path) -> io::Result<File> {
+  let file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)?;
+  // the mode only applies when the file is created
+  file.set_permissions(fs::Permissions::from_mode(0o600))?;
+  Ok(file)
+}
+
+impl SessionStore for MemoryStore {
+  fn load(&self, cookie_value: &str, now: u64) -> Option<Session> {
+    let id = decode_id(cookie_value)?;
+    self.sessions.borrow().iter()
+      .find(|s| s.expires > now && s.id.map(|ref sid| mac_eq(sid, &id)).unwrap_or(false))
+      .map(|s| Session::loaded(s.id, s.data(), s.expires))
+  }
+
+  fn save(&self, session: &mut Session, now: u64, cookie_value: &mut dyn Write) -> io::Result<()> {
+    session.expires = now + self.max_age;
+    {
+      let mut sessions = self.sessions.borrow_mut();
+      let existing_idx = session.id.and_then(|id| {
+        sessions.iter().position(|s| s.id == Some(id))
+      });
+      let idx = match existing_idx {
+        Some(idx) => idx,
+        None => {
+          let mut id = [0u8; SESSION_ID_SIZE];
+          self.random.borrow_mut().fill(&mut id);
+          session.id = Some(id);
+          // a free or expired slot sorts first
+          sessions.iter().enumerate()
+            .min_by_key(|&(_, s)| if s.id.is_some() { s.expires } else { 0 })
+            .map(|(idx, _)| idx)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "session store has no capacity"))?
+        }
+      };
+      sessions[idx] = Session::loaded(session.id, session.data(), session.expires);
+    }
+    self.persist(now)?;
+    let mut encoded_id = [0u8; SESSION_ID_SIZE * 2];
+    let encoded_id = hex::encode(&session.id.unwrap(), &mut encoded_id).unwrap();
+    cookie_value.write_all(encoded_id.as_bytes())
+  }
+
+  fn remove(&self, session: &Session) -> io::Result<()> {
+    if let Some(id) = session.id {
+      {
+        let mut sessions = self.sessions.borrow_mut();
+        if let Some(slot) = sessions.iter_mut().find(|s| s.id == Some(id)) {
+          *slot = Session::new();
+        }
+      }
+      self.persist(super::unix_time_now())?;
+    }
+    Ok( () )
+  }
+
+  fn max_age(&self) -> u64 {
+    self.max_age
+  }
+}
+
+fn decode_id(encoded_id: &str) -> Option<SessionId> {
+  let mut id = [0u8; SESSION_ID_SIZE];
+  hex::decode(encoded_id.as_bytes(), &mut id)?;
+  Some(id)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process, str};
+  use std::os::unix::fs::PermissionsExt;
+  use super::MemoryStore;
+  use super::super::{Session, SessionStore};
+
+  fn save_new(store: &MemoryStore, data: &[u8], now: u64) -> String {
+    let mut session = Session::new();
+    session.set_data(data).unwrap();
+    let mut id = Vec::new();
+    store.save(&mut session, now, &mut id).unwrap();
+    String::from_utf8(id).unwrap()
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let store = MemoryStore::new(2, 60).unwrap();
+    let alice = save_new(&store, b"alice", 1000);
+    let bob = save_new(&store, b"bob", 1000);
+    assert_eq!(alice.len(), 32);
+    assert!(alice != bob);
+    assert_eq!(store.load(&alice, 1000).unwrap().data(), b"alice");
+    assert_eq!(store.load(&bob, 1000).unwrap().data(), b"bob");
+    assert!(store.load(&alice, 1060).is_none());
+    assert!(store.load("00000000000000000000000000000000", 1000).is_none());
+    assert!(store.load("not hex", 1000).is_none());
+  }
+
+  #[test]
+  fn test_update_keeps_id() {
+    let store = MemoryStore::new(2, 60).unwrap();
+    let alice = save_new(&store, b"alice", 1000);
+    let mut session = store.load(&alice, 1000).unwrap();
+    session.set_data(b"alice2").unwrap();
+    let mut id = Vec::new();
+    store.save(&mut session, 1030, &mut id).unwrap();
+    assert_eq!(str::from_utf8(&id).unwrap(), alice);
+    let session = store.load(&alice, 1080).unwrap();
+    assert_eq!(session.data(), b"alice2");
+  }
+
+  #[test]
+  fn test_full_evicts_first_to_expire() {
+    let store = MemoryStore::new(2, 60).unwrap();
+    let alice = save_new(&store, b"alice", 1000);
+    let bob = save_new(&store, b"bob", 1010);
+    let carol = save_new(&store, b"carol", 1020);
+    assert!(store.load(&alice, 1020).is_none());
+    assert!(store.load(&bob, 1020).is_some());
+    assert!(store.load(&carol, 1020).is_some());
+  }
+
+  #[test]
+  fn test_remove() {
+    let store = MemoryStore::new(2, 60).unwrap();
+    let alice = save_new(&store, b"alice", 1000);
+    let session = store.load(&alice, 1000).unwrap();
+    store.remove(&session).unwrap();
+    assert!(store.load(&alice, 1000).is_none());
+  }
+
+  #[test]
+  fn test_write_and_read() {
+    let store = MemoryStore::new(2, 60).unwrap();
+    let alice = save_new(&store, b"alice", 1000);
+    let mut file = Vec::new();
+    store.write_to(&mut file, 1000).unwrap();
+    let mut restored = MemoryStore::new(2, 60).unwrap();
+    restored.read_from(&file[..], 1000).unwrap();
+    assert_eq!(restored.load(&alice, 1000).unwrap().data(), b"alice");
+    let mut restored = MemoryStore::new(2, 60).unwrap();
+    restored.read_from(&file[..], 1060).unwrap();
+    assert!(restored.load(&alice, 1000).is_none());
+  }
+
+  #[test]
+  fn test_file_private() {
+    let path = env::temp_dir().join(format!("wwwee-sessions-{}", process::id()));
+    let store = MemoryStore::with_file(2, 60, &path).unwrap();
+    save_new(&store, b"alice", 1000);
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(mode & 0o777, 0o600);
+  }
+}
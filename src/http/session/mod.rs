@@ -0,0 +1,133 @@
+mod memory_store;
+mod signed_token;
+
+pub use self::memory_store::MemoryStore;
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use http::{Request, Responder, Response};
+use http::status::Status;
+
+pub const MAX_SESSION_DATA : usize = 128;
+pub const SESSION_ID_SIZE : usize = 16;
+/// enough for a signed token with `MAX_SESSION_DATA` bytes of data
+pub const MAX_COOKIE_VALUE_LEN : usize = 256;
+
+pub type SessionId = [u8; SESSION_ID_SIZE];
+
+/// The state kept for a client between requests.
+/// The data is a fixed size inline blob, so sessions can be
+/// stored and copied around without allocating.
+#[derive(Clone, Copy)]
+pub struct Session {
+  id: Option<SessionId>,
+  data: [u8; MAX_SESSION_DATA],
+  data_len: usize,
+  // seconds since the unix epoch
+  expires: u64,
+  is_new: bool,
+  is_modified: bool,
+  is_destroyed: bool
+}
+
+impl Session {
+  pub fn new() -> Session {
+    Session {
+      id: None,
+      data: [0u8; MAX_SESSION_DATA],
+      data_len: 0,
+      expires: 0,
+      is_new: true,
+      is_modified: false,
+      is_destroyed: false
+    }
+  }
+
+  fn loaded(id: Option<SessionId>, data: &[u8], expires: u64) -> Session {
+    let mut session = Session::new();
+    session.id = id;
+    session.data[.. data.len()].copy_from_slice(data);
+    session.data_len = data.len();
+    session.expires = expires;
+    session.is_new = false;
+    session
+  }
+
+  pub fn data(&self) -> &[u8] {
+    &self.data[.. self.data_len]
+  }
+
+  pub fn set_data(&mut self, data: &[u8]) -> io::Result<()> {
+    if data.len() > MAX_SESSION_DATA {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "session data too large"));
+    }
+    self.data[.. data.len()].copy_from_slice(data);
+    self.data_len = data.len();
+    self.is_modified = true;
+    self.is_destroyed = false;
+    Ok( () )
+  }
+
+  /// Forgets the session, on the server and in the client.
+  pub fn destroy(&mut self) {
+    self.data_len = 0;
+    self.is_destroyed = true;
+  }
+
+  /// Whether the client didn't send a (valid) session cookie.
+  pub fn is_new(&self) -> bool {
+    self.is_new
+  }
+
+  pub fn is_modified(&self) -> bool {
+    self.is_modified
+  }
+
+  pub fn is_destroyed(&self) -> bool {
+    self.is_destroyed
+  }
+
+  pub fn expires(&self) -> u64 {
+    self.expires
+  }
+}
+
+/// Where sessions are kept, and how the cookie refers to them.
+/// Methods take `&self` so one store can be shared
+/// by the handlers of all connections.
+pub trait SessionStore {
+  /// The session the cookie value refers to, if it is valid and not expired.
+  fn load(&self, cookie_value: &str, now: u64) -> Option<Session>;
+  /// Stores the session until `now + max_age()`,
+  /// and writes the cookie value that refers to it.
+  fn save(&self, session: &mut Session, now: u64, cookie_value: &mut dyn Write) -> io::Result<()>;
+  fn remove(&self, session: &Session) -> io::Result<()>;
+  /// How long sessions last, in seconds.
+  fn max_age(&self) -> u64;
+}
+
+/// Like `RequestHandler`, with access to the session of the request.
+pub trait SessionRequestHandler {
+
+  fn read_headers(&mut self, _request: &Request, _session: &mut Session, _responder: &Responder)
+    -> io::Result<Option<Response>>
+  {
+    Ok(None)
+  }
+
+  fn read_body(&mut self, _body: &mut [u8], _session: &mut Session, _responder: &Responder)
+    -> io::Result<Option<Response>>
+  {
+    Ok(None)
+  }
+
+  fn render_error(&mut self, _status: Status, _message: &str, _responder: &Responder)
+    -> io::Result<Option<Response>>
+  {
+    Ok(None)
+  }
+}
+
+pub fn unix_time_now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+use tls::{HmacKey, Hmac, mac_eq, SHA256_SIZE};
+use encoding::base64;
+use super::{Session, SessionStore, MAX_SESSION_DATA, MAX_COOKIE_VALUE_LEN};
+
+const EXPIRES_SIZE : usize = 8;
+const MAX_PAYLOAD_SIZE : usize = EXPIRES_SIZE + MAX_SESSION_DATA;
+
+/// Stateless sessions: the session data is kept in the cookie itself,
+/// as `base64(expires || data) "." base64(HMAC-SHA256(expires || data))`.
+///
+/// Nothing is stored on the server, so a token stays valid until it expires,
+/// even after the session is destroyed. The data is signed, not encrypted,
+/// so the client can read it.
+pub struct SignedTokenStore {
+  key: HmacKey,
+  max_age: u64
+}
+
+impl SignedTokenStore {
+  pub fn new(secret: &[u8], max_age: u64) -> SignedTokenStore {
+    SignedTokenStore {
+      key: HmacKey::sha256(secret),
+      max_age
+    }
+  }
+
+  fn sign(&self, payload: &[u8]) -> [u8; SHA256_SIZE] {
+    let mut hmac = Hmac::new(&self.key);
+    hmac.update(payload);
+    hmac.finish()
+  }
+}
+
+impl SessionStore for SignedTokenStore {
+  fn load(&self, cookie_value: &str, now: u64) -> Option<Session> {
+    let mut token = [0u8; MAX_COOKIE_VALUE_LEN];
+    let token = token.get_mut(.. cookie_value.len())?;
+    token.copy_from_slice(cookie_value.as_bytes());
+    let separator_idx = token.iter().position(|&b| b == b'.')?;
+    let (payload, mac) = token.split_at_mut(separator_idx);
+    let payload = base64::decode(payload)?;
+    let mac = base64::decode(&mut mac[1 ..])?;
+    if payload.len() < EXPIRES_SIZE || !mac_eq(&self.sign(payload), mac) {
+      return None;
+    }
+    let (expires, data) = payload.split_at(EXPIRES_SIZE);
+    let expires = expires.iter().fold(0u64, |n, &b| n << 8 | b as u64);
+    if expires <= now || data.len() > MAX_SESSION_DATA {
+      return None;
+    }
+    Some(Session::loaded(None, data, expires))
+  }
+
+  fn save(&self, session: &mut Session, now: u64, cookie_value: &mut dyn Write) -> io::Result<()> {
+    session.expires = now + self.max_age;
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    for i in 0 .. EXPIRES_SIZE {
+      payload[i] = (session.expires >> ((EXPIRES_SIZE - 1 - i) * 8)) as u8;
+    }
+    let data = session.data();
+    payload[EXPIRES_SIZE .. EXPIRES_SIZE + data.len()].copy_from_slice(data);
+    let payload = &payload[.. EXPIRES_SIZE + data.len()];
+
+    let mut encoded = [0u8; MAX_COOKIE_VALUE_LEN];
+    let encoded_payload = base64::encode(payload, &mut encoded)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "token too large"))?;
+    write!(cookie_value, "{}.", encoded_payload)?;
+    let encoded_mac = base64::encode(&self.sign(payload), &mut encoded)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "token too large"))?;
+    write!(cookie_value, "{}", encoded_mac)
+  }
+
+  fn remove(&self, _session: &Session) -> io::Result<()> {
+    Ok( () )
+  }
+
+  fn max_age(&self) -> u64 {
+    self.max_age
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str;
+  use super::SignedTokenStore;
+  use super::super::{Session, SessionStore};
+
+  fn save(store: &SignedTokenStore, session: &mut Session, now: u64) -> Vec<u8> {
+    let mut token = Vec::new();
+    store.save(session, now, &mut token).unwrap();
+    token
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let store = SignedTokenStore::new(b"secret", 60);
+    let mut session = Session::new();
+    session.set_data(b"alice").unwrap();
+    let token = save(&store, &mut session, 1000);
+    let loaded = store.load(str::from_utf8(&token).unwrap(), 1059).unwrap();
+    assert_eq!(loaded.data(), b"alice");
+    assert_eq!(loaded.expires(), 1060);
+    assert!(!loaded.is_new());
+    assert!(!loaded.is_modified());
+  }
+
+  #[test]
+  fn test_expired() {
+    let store = SignedTokenStore::new(b"secret", 60);
+    let mut session = Session::new();
+    session.set_data(b"alice").unwrap();
+    let token = save(&store, &mut session, 1000);
+    assert!(store.load(str::from_utf8(&token).unwrap(), 1060).is_none());
+  }
+
+  #[test]
+  fn test_tampered() {
+    let store = SignedTokenStore::new(b"secret", 60);
+    let mut session = Session::new();
+    session.set_data(b"alice").unwrap();
+    let mut token = save(&store, &mut session, 1000);
+    // extend the expiry date, 'A' becomes 'a'
+    token[2] ^= 0x20;
+    assert!(store.load(str::from_utf8(&token).unwrap(), 1000).is_none());
+    let other_store = SignedTokenStore::new(b"other secret", 60);
+    let token = save(&store, &mut session, 1000);
+    assert!(other_store.load(str::from_utf8(&token).unwrap(), 1000).is_none());
+  }
+
+  #[test]
+  fn test_malformed() {
+    let store = SignedTokenStore::new(b"secret", 60);
+    assert!(store.load("", 0).is_none());
+    assert!(store.load("abc", 0).is_none());
+    assert!(store.load("YWJj.", 0).is_none());
+    assert!(store.load(".YWJj", 0).is_none());
+  }
+}
@@ -1,7 +1,98 @@
 pub type Status = (u16, &'static str);
-pub const OK:                     Status = (200, "OK");
-pub const NOT_MODIFIED:           Status = (304, "Not Modified");
-pub const BAD_REQUEST:            Status = (400, "Bad Request");
-pub const UNAUTHORIZED:           Status = (401, "Unauthorized");
-pub const NOT_FOUND:              Status = (404, "Not Found");
-pub const INTERNAL_SERVER_ERROR:  Status = (500, "Internal Server Error");
+pub const CONTINUE:                        Status = (100, "Continue");
+pub const SWITCHING_PROTOCOLS:             Status = (101, "Switching Protocols");
+pub const PROCESSING:                      Status = (102, "Processing");
+pub const EARLY_HINTS:                     Status = (103, "Early Hints");
+pub const OK:                              Status = (200, "OK");
+pub const CREATED:                         Status = (201, "Created");
+pub const ACCEPTED:                        Status = (202, "Accepted");
+pub const NON_AUTHORITATIVE_INFORMATION:   Status = (203, "Non-Authoritative Information");
+pub const NO_CONTENT:                      Status = (204, "No Content");
+pub const RESET_CONTENT:                   Status = (205, "Reset Content");
+pub const PARTIAL_CONTENT:                 Status = (206, "Partial Content");
+pub const MULTI_STATUS:                    Status = (207, "Multi-Status");
+pub const ALREADY_REPORTED:                Status = (208, "Already Reported");
+pub const IM_USED:                         Status = (226, "IM Used");
+pub const MULTIPLE_CHOICES:                Status = (300, "Multiple Choices");
+pub const MOVED_PERMANENTLY:               Status = (301, "Moved Permanently");
+pub const FOUND:                           Status = (302, "Found");
+pub const SEE_OTHER:                       Status = (303, "See Other");
+pub const NOT_MODIFIED:                    Status = (304, "Not Modified");
+pub const USE_PROXY:                       Status = (305, "Use Proxy");
+pub const TEMPORARY_REDIRECT:              Status = (307, "Temporary Redirect");
+pub const PERMANENT_REDIRECT:              Status = (308, "Permanent Redirect");
+pub const BAD_REQUEST:                     Status = (400, "Bad Request");
+pub const UNAUTHORIZED:                    Status = (401, "Unauthorized");
+pub const PAYMENT_REQUIRED:                Status = (402, "Payment Required");
+pub const FORBIDDEN:                       Status = (403, "Forbidden");
+pub const NOT_FOUND:                       Status = (404, "Not Found");
+pub const METHOD_NOT_ALLOWED:              Status = (405, "Method Not Allowed");
+pub const NOT_ACCEPTABLE:                  Status = (406, "Not Acceptable");
+pub const PROXY_AUTHENTICATION_REQUIRED:   Status = (407, "Proxy Authentication Required");
+pub const REQUEST_TIMEOUT:                 Status = (408, "Request Timeout");
+pub const CONFLICT:                        Status = (409, "Conflict");
+pub const GONE:                            Status = (410, "Gone");
+pub const LENGTH_REQUIRED:                 Status = (411, "Length Required");
+pub const PRECONDITION_FAILED:             Status = (412, "Precondition Failed");
+pub const PAYLOAD_TOO_LARGE:               Status = (413, "Payload Too Large");
+pub const URI_TOO_LONG:                    Status = (414, "URI Too Long");
+pub const UNSUPPORTED_MEDIA_TYPE:          Status = (415, "Unsupported Media Type");
+pub const RANGE_NOT_SATISFIABLE:           Status = (416, "Range Not Satisfiable");
+pub const EXPECTATION_FAILED:              Status = (417, "Expectation Failed");
+pub const MISDIRECTED_REQUEST:             Status = (421, "Misdirected Request");
+pub const UNPROCESSABLE_ENTITY:            Status = (422, "Unprocessable Entity");
+pub const LOCKED:                          Status = (423, "Locked");
+pub const FAILED_DEPENDENCY:               Status = (424, "Failed Dependency");
+pub const TOO_EARLY:                       Status = (425, "Too Early");
+pub const UPGRADE_REQUIRED:                Status = (426, "Upgrade Required");
+pub const PRECONDITION_REQUIRED:           Status = (428, "Precondition Required");
+pub const TOO_MANY_REQUESTS:               Status = (429, "Too Many Requests");
+pub const REQUEST_HEADER_FIELDS_TOO_LARGE: Status = (431, "Request Header Fields Too Large");
+pub const UNAVAILABLE_FOR_LEGAL_REASONS:   Status = (451, "Unavailable For Legal Reasons");
+pub const INTERNAL_SERVER_ERROR:           Status = (500, "Internal Server Error");
+pub const NOT_IMPLEMENTED:                 Status = (501, "Not Implemented");
+pub const BAD_GATEWAY:                     Status = (502, "Bad Gateway");
+pub const SERVICE_UNAVAILABLE:             Status = (503, "Service Unavailable");
+pub const GATEWAY_TIMEOUT:                 Status = (504, "Gateway Timeout");
+pub const HTTP_VERSION_NOT_SUPPORTED:      Status = (505, "HTTP Version Not Supported");
+pub const VARIANT_ALSO_NEGOTIATES:         Status = (506, "Variant Also Negotiates");
+pub const INSUFFICIENT_STORAGE:            Status = (507, "Insufficient Storage");
+pub const LOOP_DETECTED:                   Status = (508, "Loop Detected");
+pub const NOT_EXTENDED:                    Status = (510, "Not Extended");
+pub const NETWORK_AUTHENTICATION_REQUIRED: Status = (511, "Network Authentication Required");
+
+// the IANA HTTP status code registry
+const REGISTRY : &'static [Status] = &[
+  CONTINUE, SWITCHING_PROTOCOLS, PROCESSING, EARLY_HINTS,
+  OK, CREATED, ACCEPTED, NON_AUTHORITATIVE_INFORMATION, NO_CONTENT,
+  RESET_CONTENT, PARTIAL_CONTENT, MULTI_STATUS, ALREADY_REPORTED, IM_USED,
+  MULTIPLE_CHOICES, MOVED_PERMANENTLY, FOUND, SEE_OTHER, NOT_MODIFIED,
+  USE_PROXY, TEMPORARY_REDIRECT, PERMANENT_REDIRECT,
+  BAD_REQUEST, UNAUTHORIZED, PAYMENT_REQUIRED, FORBIDDEN, NOT_FOUND,
+  METHOD_NOT_ALLOWED, NOT_ACCEPTABLE, PROXY_AUTHENTICATION_REQUIRED,
+  REQUEST_TIMEOUT, CONFLICT, GONE, LENGTH_REQUIRED, PRECONDITION_FAILED,
+  PAYLOAD_TOO_LARGE, URI_TOO_LONG, UNSUPPORTED_MEDIA_TYPE, RANGE_NOT_SATISFIABLE,
+  EXPECTATION_FAILED, MISDIRECTED_REQUEST, UNPROCESSABLE_ENTITY, LOCKED,
+  FAILED_DEPENDENCY, TOO_EARLY, UPGRADE_REQUIRED, PRECONDITION_REQUIRED,
+  TOO_MANY_REQUESTS, REQUEST_HEADER_FIELDS_TOO_LARGE, UNAVAILABLE_FOR_LEGAL_REASONS,
+  INTERNAL_SERVER_ERROR, NOT_IMPLEMENTED, BAD_GATEWAY, SERVICE_UNAVAILABLE,
+  GATEWAY_TIMEOUT, HTTP_VERSION_NOT_SUPPORTED, VARIANT_ALSO_NEGOTIATES,
+  INSUFFICIENT_STORAGE, LOOP_DETECTED, NOT_EXTENDED, NETWORK_AUTHENTICATION_REQUIRED
+];
+
+/// looks up the reason phrase for a registered status code
+pub fn from_code(code: u16) -> Option<Status> {
+  REGISTRY.iter().find(|s| s.0 == code).map(|s| *s)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_code, NOT_FOUND};
+
+  #[test]
+  fn test_from_code() {
+    assert_eq!(from_code(404), Some(NOT_FOUND));
+    assert_eq!(from_code(431).map(|s| s.1), Some("Request Header Fields Too Large"));
+    assert_eq!(from_code(299), None);
+  }
+}
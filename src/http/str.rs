@@ -7,17 +7,13 @@ pub fn is_whitespace(b: u8) -> bool {
 }
 
 pub fn trim_left_mut<P>(buffer: &mut [u8], predicate: P) -> &mut [u8] where P: Fn(u8) -> bool {
-  if let Some(idx) = buffer.iter().position(|&b| !predicate(b)) {
-    return &mut buffer[idx..];
-  }
-  buffer
+  let idx = buffer.iter().position(|&b| !predicate(b)).unwrap_or(buffer.len());
+  &mut buffer[idx..]
 }
 
 pub fn trim_right_mut<P>(buffer: &mut [u8], predicate: P) -> &mut [u8] where P: Fn(u8) -> bool {
-  if let Some(idx) = buffer.iter().rposition(|&b| !predicate(b)) {
-    return &mut buffer[..idx + 1];
-  }
-  buffer
+  let len = buffer.iter().rposition(|&b| !predicate(b)).map(|idx| idx + 1).unwrap_or(0);
+  &mut buffer[..len]
 }
 
 pub fn trim_mut<P>(buffer: &mut [u8], predicate: P) -> &mut [u8] where P: Fn(u8) -> bool {
@@ -68,5 +64,11 @@ mod tests {
     copy_str(&mut buffer, b" \t hello \t ");
     assert_eq!(&trim_mut(&mut buffer, is_whitespace), b"hello");
   }
+  #[test]
+  fn test_trim_mut_only_whitespace() {
+    let mut buffer = [0u8; 3];
+    copy_str(&mut buffer, b" \t ");
+    assert_eq!(trim_mut(&mut buffer, is_whitespace).len(), 0);
+  }
   
 }
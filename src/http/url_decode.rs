@@ -91,6 +91,28 @@ fn url_decode_with_offset_flag(buffer: &mut [u8], write_offset_1: bool) -> &mut
   &mut buffer[ offset .. write_idx]
 }
 
+/// decodes %XX escapes in place, but unlike `url_decode` leaves '+' as is.
+pub fn percent_decode(buffer: &mut [u8]) -> &mut [u8] {
+  let mut read_idx = 0;
+  let mut write_idx = 0;
+  while read_idx < buffer.len() {
+    let escaped_byte = if buffer[read_idx] == PERCENT && read_idx + 2 < buffer.len() {
+      hex_to_byte(buffer[read_idx + 1], buffer[read_idx + 2])
+    } else {
+      None
+    };
+    if let Some(byte) = escaped_byte {
+      buffer[write_idx] = byte;
+      read_idx += 3;
+    } else {
+      buffer[write_idx] = buffer[read_idx];
+      read_idx += 1;
+    }
+    write_idx += 1;
+  }
+  &mut buffer[.. write_idx]
+}
+
 pub fn contains_percent_values(buffer: &[u8]) -> bool {
   let mut match_hex_digits = 0;
   for &byte in buffer {
@@ -130,6 +152,14 @@ mod tests {
     assert_eq!(decoded, b"hello world");
   }
 
+  #[test]
+  fn test_percent_decode() {
+    let mut buffer = [0u8; 13];
+    test_helpers::copy_str(&mut buffer, b"a+b%3Dc%2x%41");
+    let decoded = super::percent_decode(&mut buffer);
+    assert_eq!(decoded, b"a+b=c%2xA");
+  }
+
   #[test]
   fn test_empty() {
     let mut buffer = [0u8; 0];
@@ -0,0 +1,257 @@
+use std::io::{self, Write, ErrorKind};
+use std::str;
+use buffer::Buffer;
+use io::{Context, Event};
+use io::handlers::send_buffer;
+use super::frame::{self, Opcode, Frame, FrameError};
+use super::{Handler, Message, Sender, close_code};
+
+pub const MAX_MESSAGE_SIZE : usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CloseState {
+  Open,
+  // we sent a close frame, waiting for the one from the client
+  Closing,
+  // close handshake done, terminate after sending what's left
+  Closed
+}
+
+// io handler that takes over the connection after the upgrade,
+// decoding client frames and encoding the ones sent by the handler.
+pub struct Connection<H> {
+  handler: H,
+  incoming: Buffer,
+  message: Buffer,
+  message_opcode: Option<Opcode>,
+  outgoing: Buffer,
+  bytes_written: usize,
+  is_opened: bool,
+  close_state: CloseState
+}
+
+impl<H: Handler> Connection<H> {
+  pub fn new(handler: H) -> Connection<H> {
+    Connection {
+      handler,
+      incoming: Buffer::new(),
+      message: Buffer::new(),
+      message_opcode: None,
+      outgoing: Buffer::new(),
+      bytes_written: 0,
+      is_opened: false,
+      close_state: CloseState::Open
+    }
+  }
+
+  fn process_event(&mut self, event: &Event, ctx: &mut Context) -> io::Result<bool> {
+    if !self.is_opened {
+      self.is_opened = true;
+      let result = {
+        let mut sender = Sender::new(ctx, &mut self.outgoing, &mut self.close_state);
+        self.handler.on_open(&mut sender)
+      };
+      self.close_on_error(result)?;
+    }
+    if ctx.socket().is_source_of(event) {
+      if event.kind().is_readable() {
+        let is_eof = self.read_socket(ctx)?;
+        if is_eof {
+          // nobody left to send the rest to
+          if self.close_state != CloseState::Closed {
+            self.close_state = CloseState::Closed;
+            self.handler.on_close(close_code::ABNORMAL);
+          }
+          return Ok(true);
+        }
+      }
+    }
+    else if self.close_state == CloseState::Open {
+      let result = {
+        let mut sender = Sender::new(ctx, &mut self.outgoing, &mut self.close_state);
+        self.handler.on_event(event, &mut sender)
+      };
+      self.close_on_error(result)?;
+    }
+    let is_flushed = self.write_socket(ctx)?;
+    Ok(is_flushed && self.close_state == CloseState::Closed)
+  }
+
+  // reads until the socket would block, as it won't signal again before that,
+  // handling the frames as they come in so `incoming` only holds the one being received.
+  // returns whether the client closed the connection
+  fn read_socket(&mut self, ctx: &mut Context) -> io::Result<bool> {
+    let mut chunk = [0u8; 1024];
+    loop {
+      match ctx.socket().read(&mut chunk) {
+        Ok(0) => return Ok(true),
+        Ok(len) => {
+          self.incoming.write(&chunk[.. len])?;
+          self.read_frames(ctx)?;
+        },
+        Err(err) => match err.kind() {
+          ErrorKind::Interrupted => {},
+          ErrorKind::WouldBlock => return Ok(false),
+          _ => return Err(err)
+        }
+      }
+    }
+  }
+
+  fn read_frames(&mut self, ctx: &mut Context) -> io::Result<()> {
+    let Connection {
+      ref mut handler,
+      ref mut incoming,
+      ref mut message,
+      ref mut message_opcode,
+      ref mut outgoing,
+      ref mut close_state,
+      ..
+    } = *self;
+
+    loop {
+      let result = match frame::parse(incoming.as_mut_slice(), MAX_MESSAGE_SIZE) {
+        Ok(None) => break,
+        Ok(Some((frame, frame_len))) => {
+          let mut sender = Sender::new(ctx, outgoing, close_state);
+          handle_frame(frame, handler, message, message_opcode, &mut sender)
+            .map(|_| frame_len)
+        },
+        Err(FrameError::TooBig) => Err(close_code::MESSAGE_TOO_BIG),
+        Err(_) => Err(close_code::PROTOCOL_ERROR)
+      };
+      match result {
+        Ok(frame_len) => incoming.remove_head(frame_len),
+        Err(code) => {
+          incoming.clear();
+          fail(outgoing, close_state, code)?;
+          break;
+        }
+      }
+    }
+    Ok( () )
+  }
+
+  fn write_socket(&mut self, ctx: &mut Context) -> io::Result<bool> {
+    if self.outgoing.len() == 0 {
+      return Ok(true);
+    }
+    let mut socket = ctx.socket();
+    let report = send_buffer(&mut socket, &self.outgoing.as_slice()[self.bytes_written ..])?;
+    self.bytes_written += report.byte_count();
+    if self.bytes_written == self.outgoing.len() {
+      self.outgoing.clear();
+      self.bytes_written = 0;
+      // make sure the frames don't stay behind in the tls engine
+      socket.flush()?;
+      Ok(true)
+    }
+    else {
+      Ok(false)
+    }
+  }
+
+  fn close_on_error(&mut self, result: io::Result<()>) -> io::Result<()> {
+    if let Err(err) = result {
+      println!("closing websocket due to handler error: {:?}", err);
+      fail(&mut self.outgoing, &mut self.close_state, close_code::INTERNAL_ERROR)?;
+    }
+    Ok( () )
+  }
+}
+
+// returns the close code to fail the connection with on error
+fn handle_frame<H: Handler>(
+  frame: Frame,
+  handler: &mut H,
+  message: &mut Buffer,
+  message_opcode: &mut Option<Opcode>,
+  sender: &mut Sender)
+  -> Result<(), u16>
+{
+  if *sender.close_state == CloseState::Closed {
+    return Ok( () );  //ignore anything after the close handshake
+  }
+  match frame.opcode {
+    Opcode::Text | Opcode::Binary => {
+      if message_opcode.is_some() {
+        return Err(close_code::PROTOCOL_ERROR);
+      }
+      if frame.fin {
+        deliver(handler, frame.opcode, frame.payload, sender)?;
+      }
+      else {
+        message.clear();
+        message.write(frame.payload).map_err(|_| close_code::MESSAGE_TOO_BIG)?;
+        *message_opcode = Some(frame.opcode);
+      }
+    },
+    Opcode::Continuation => {
+      let opcode = message_opcode.ok_or(close_code::PROTOCOL_ERROR)?;
+      if message.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+        return Err(close_code::MESSAGE_TOO_BIG);
+      }
+      message.write(frame.payload).map_err(|_| close_code::MESSAGE_TOO_BIG)?;
+      if frame.fin {
+        *message_opcode = None;
+        deliver(handler, opcode, message.as_slice(), sender)?;
+        message.clear();
+      }
+    },
+    Opcode::Ping => {
+      frame::write(sender.outgoing, Opcode::Pong, frame.payload)
+        .map_err(|_| close_code::INTERNAL_ERROR)?;
+    },
+    Opcode::Pong => {},
+    Opcode::Close => {
+      let code = if frame.payload.len() >= 2 {
+        (frame.payload[0] as u16) << 8 | frame.payload[1] as u16
+      } else {
+        close_code::NORMAL
+      };
+      if *sender.close_state == CloseState::Open {
+        write_close(sender.outgoing, code).map_err(|_| close_code::INTERNAL_ERROR)?;
+      }
+      *sender.close_state = CloseState::Closed;
+      handler.on_close(code);
+    }
+  }
+  Ok( () )
+}
+
+fn deliver<H: Handler>(handler: &mut H, opcode: Opcode, payload: &[u8], sender: &mut Sender) -> Result<(), u16> {
+  let message = if opcode == Opcode::Text {
+    Message::Text(str::from_utf8(payload).map_err(|_| close_code::INVALID_DATA)?)
+  } else {
+    Message::Binary(payload)
+  };
+  handler.on_message(message, sender).map_err(|err| {
+    println!("closing websocket due to handler error: {:?}", err);
+    close_code::INTERNAL_ERROR
+  })
+}
+
+pub fn write_close(outgoing: &mut Buffer, code: u16) -> io::Result<()> {
+  frame::write(outgoing, Opcode::Close, &[(code >> 8) as u8, code as u8])
+}
+
+fn fail(outgoing: &mut Buffer, close_state: &mut CloseState, code: u16) -> io::Result<()> {
+  if *close_state == CloseState::Open {
+    write_close(outgoing, code)?;
+  }
+  *close_state = CloseState::Closed;
+  Ok( () )
+}
+
+impl<H: Handler> ::io::Handler<()> for Connection<H> {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<()> {
+    match self.process_event(event, ctx) {
+      Ok(true) => Some( () ),
+      Ok(false) => None,
+      Err(err) => {
+        println!("closing websocket due to io error: {:?}", err);
+        Some( () )
+      }
+    }
+  }
+}
@@ -0,0 +1,219 @@
+use std::io::{Write, Result};
+
+pub const MAX_CONTROL_PAYLOAD_LEN : usize = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+  Continuation,
+  Text,
+  Binary,
+  Close,
+  Ping,
+  Pong
+}
+
+impl Opcode {
+  fn from_u8(opcode: u8) -> Option<Opcode> {
+    match opcode {
+      0x0 => Some(Opcode::Continuation),
+      0x1 => Some(Opcode::Text),
+      0x2 => Some(Opcode::Binary),
+      0x8 => Some(Opcode::Close),
+      0x9 => Some(Opcode::Ping),
+      0xA => Some(Opcode::Pong),
+      _ => None
+    }
+  }
+
+  fn as_u8(self) -> u8 {
+    match self {
+      Opcode::Continuation => 0x0,
+      Opcode::Text => 0x1,
+      Opcode::Binary => 0x2,
+      Opcode::Close => 0x8,
+      Opcode::Ping => 0x9,
+      Opcode::Pong => 0xA
+    }
+  }
+
+  pub fn is_control(self) -> bool {
+    self.as_u8() & 0x8 != 0
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+  ReservedBits,
+  UnknownOpcode,
+  NotMasked,
+  InvalidControlFrame,
+  TooBig
+}
+
+pub struct Frame<'a> {
+  pub fin: bool,
+  pub opcode: Opcode,
+  pub payload: &'a [u8]
+}
+
+fn read_len(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0u64, |len, b| (len << 8) | *b as u64)
+}
+
+// parses (and unmasks in place) the client frame at the start of the buffer.
+// Returns the frame and the amount of bytes it takes up in the buffer,
+// or None if the frame hasn't been received completely yet.
+pub fn parse<'a>(buffer: &'a mut [u8], max_payload_len: usize)
+  -> ::std::result::Result<Option<(Frame<'a>, usize)>, FrameError>
+{
+  if buffer.len() < 2 {
+    return Ok(None);
+  }
+  let (b0, b1) = (buffer[0], buffer[1]);
+  if b0 & 0b0111_0000 != 0 {
+    return Err(FrameError::ReservedBits);
+  }
+  let fin = b0 & 0b1000_0000 != 0;
+  let opcode = Opcode::from_u8(b0 & 0b0000_1111).ok_or(FrameError::UnknownOpcode)?;
+  // all frames sent by a client must be masked
+  if b1 & 0b1000_0000 == 0 {
+    return Err(FrameError::NotMasked);
+  }
+  let (len_size, payload_len) = match b1 & 0b0111_1111 {
+    126 => (2, None),
+    127 => (8, None),
+    len => (0, Some(len as u64))
+  };
+  let mask_offset = 2 + len_size;
+  if buffer.len() < mask_offset + 4 {
+    return Ok(None);
+  }
+  let payload_len = payload_len.unwrap_or_else(|| read_len(&buffer[2 .. mask_offset]));
+  if opcode.is_control() && (!fin || payload_len > MAX_CONTROL_PAYLOAD_LEN as u64) {
+    return Err(FrameError::InvalidControlFrame);
+  }
+  if payload_len > max_payload_len as u64 {
+    return Err(FrameError::TooBig);
+  }
+  let payload_offset = mask_offset + 4;
+  let frame_len = payload_offset + payload_len as usize;
+  if buffer.len() < frame_len {
+    return Ok(None);
+  }
+  let mut mask = [0u8; 4];
+  mask.copy_from_slice(&buffer[mask_offset .. payload_offset]);
+  let payload = &mut buffer[payload_offset .. frame_len];
+  for (i, b) in payload.iter_mut().enumerate() {
+    *b ^= mask[i % 4];
+  }
+  Ok(Some((Frame {fin, opcode, payload}, frame_len)))
+}
+
+// writes an unfragmented, unmasked server frame
+pub fn write(writer: &mut dyn Write, opcode: Opcode, payload: &[u8]) -> Result<()> {
+  let b0 = 0b1000_0000 | opcode.as_u8();
+  let len = payload.len();
+  if len < 126 {
+    writer.write_all(&[b0, len as u8])?;
+  }
+  else if len <= 0xFFFF {
+    writer.write_all(&[b0, 126, (len >> 8) as u8, len as u8])?;
+  }
+  else {
+    let len = len as u64;
+    let mut header = [b0, 127, 0, 0, 0, 0, 0, 0, 0, 0];
+    for i in 0 .. 8 {
+      header[2 + i] = (len >> (56 - i * 8)) as u8;
+    }
+    writer.write_all(&header)?;
+  }
+  writer.write_all(payload)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse, write, Opcode, FrameError};
+
+  fn masked_frame(b0: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![b0];
+    if payload.len() < 126 {
+      frame.push(0x80 | payload.len() as u8);
+    }
+    else {
+      frame.push(0x80 | 126);
+      frame.push((payload.len() >> 8) as u8);
+      frame.push(payload.len() as u8);
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+  }
+
+  #[test]
+  fn test_parse_masked_text() {
+    // example from RFC 6455, section 5.7
+    let mut buffer = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+    let (frame, len) = parse(&mut buffer, 1024).unwrap().unwrap();
+    assert!(frame.fin);
+    assert_eq!(frame.opcode, Opcode::Text);
+    assert_eq!(frame.payload, b"Hello");
+    assert_eq!(len, 11);
+  }
+
+  #[test]
+  fn test_parse_incomplete() {
+    let mut frame = masked_frame(0x81, b"Hello");
+    let len = frame.len();
+    assert!(parse(&mut frame[.. 1], 1024).unwrap().is_none());
+    assert!(parse(&mut frame[.. 4], 1024).unwrap().is_none());
+    assert!(parse(&mut frame[.. len - 1], 1024).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_parse_extended_len() {
+    let payload = [b'a'; 300];
+    let mut frame = masked_frame(0x82, &payload);
+    let (frame, len) = parse(&mut frame, 1024).unwrap().unwrap();
+    assert_eq!(frame.opcode, Opcode::Binary);
+    assert_eq!(frame.payload, &payload[..]);
+    assert_eq!(len, 2 + 2 + 4 + 300);
+  }
+
+  #[test]
+  fn test_parse_fragment() {
+    let mut frame = masked_frame(0x01, b"Hel");
+    let (frame, _) = parse(&mut frame, 1024).unwrap().unwrap();
+    assert!(!frame.fin);
+    assert_eq!(frame.opcode, Opcode::Text);
+    let mut frame = masked_frame(0x80, b"lo");
+    let (frame, _) = parse(&mut frame, 1024).unwrap().unwrap();
+    assert!(frame.fin);
+    assert_eq!(frame.opcode, Opcode::Continuation);
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    let mut unmasked = [0x81, 0x02, b'h', b'i'];
+    assert_eq!(parse(&mut unmasked, 1024).err(), Some(FrameError::NotMasked));
+    let mut reserved = masked_frame(0xC1, b"hi");
+    assert_eq!(parse(&mut reserved, 1024).err(), Some(FrameError::ReservedBits));
+    let mut unknown = masked_frame(0x83, b"hi");
+    assert_eq!(parse(&mut unknown, 1024).err(), Some(FrameError::UnknownOpcode));
+    let mut fragmented_ping = masked_frame(0x09, b"hi");
+    assert_eq!(parse(&mut fragmented_ping, 1024).err(), Some(FrameError::InvalidControlFrame));
+    let mut too_big = masked_frame(0x81, b"hello");
+    assert_eq!(parse(&mut too_big, 4).err(), Some(FrameError::TooBig));
+  }
+
+  #[test]
+  fn test_write() {
+    let mut buffer = Vec::new();
+    write(&mut buffer, Opcode::Text, b"Hello").unwrap();
+    assert_eq!(buffer, [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+    let mut buffer = Vec::new();
+    write(&mut buffer, Opcode::Binary, &[0u8; 256]).unwrap();
+    assert_eq!(&buffer[.. 4], &[0x82, 126, 0x01, 0x00]);
+    assert_eq!(buffer.len(), 4 + 256);
+  }
+}
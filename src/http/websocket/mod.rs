@@ -0,0 +1,178 @@
+mod frame;
+mod connection;
+
+use std::io::{self, Write};
+use std::str;
+use buffer::Buffer;
+use encoding::base64;
+use http::{Request, Responder, Response, status};
+use io::{Context, Event, Subscription};
+use tls::Sha1;
+use self::frame::Opcode;
+use self::connection::{Connection, CloseState, write_close};
+
+pub mod close_code {
+  pub const NORMAL : u16 = 1000;
+  pub const GOING_AWAY : u16 = 1001;
+  pub const PROTOCOL_ERROR : u16 = 1002;
+  // never sent, given to `on_close` when the client went away without a close frame
+  pub const ABNORMAL : u16 = 1006;
+  pub const INVALID_DATA : u16 = 1007;
+  pub const MESSAGE_TOO_BIG : u16 = 1009;
+  pub const INTERNAL_ERROR : u16 = 1011;
+}
+
+const GUID : &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+pub const ACCEPT_KEY_LEN : usize = 28;
+
+pub enum Message<'a> {
+  Text(&'a str),
+  Binary(&'a [u8])
+}
+
+/// Application side of a websocket connection.
+/// Returning an error from any of the callbacks
+/// closes the connection with an internal error.
+pub trait Handler {
+  fn on_open(&mut self, _sender: &mut Sender) -> io::Result<()> {
+    Ok( () )
+  }
+
+  fn on_message(&mut self, message: Message, sender: &mut Sender) -> io::Result<()>;
+
+  /// called for events on other sources than the socket,
+  /// like a subscription to a channel.
+  fn on_event(&mut self, _event: &Event, _sender: &mut Sender) -> io::Result<()> {
+    Ok( () )
+  }
+
+  fn on_close(&mut self, _code: u16) {}
+}
+
+pub struct Sender<'a, 'c: 'a> {
+  ctx: &'a mut Context<'c>,
+  outgoing: &'a mut Buffer,
+  close_state: &'a mut CloseState
+}
+
+impl<'a, 'c> Sender<'a, 'c> {
+  fn new(ctx: &'a mut Context<'c>, outgoing: &'a mut Buffer, close_state: &'a mut CloseState) -> Sender<'a, 'c> {
+    Sender {ctx, outgoing, close_state}
+  }
+
+  pub fn send(&mut self, message: Message) -> io::Result<()> {
+    if *self.close_state != CloseState::Open {
+      return Err(io::Error::new(io::ErrorKind::NotConnected, "websocket is closing"));
+    }
+    match message {
+      Message::Text(text) => frame::write(self.outgoing, Opcode::Text, text.as_bytes()),
+      Message::Binary(data) => frame::write(self.outgoing, Opcode::Binary, data)
+    }
+  }
+
+  pub fn send_text(&mut self, text: &str) -> io::Result<()> {
+    self.send(Message::Text(text))
+  }
+
+  pub fn send_binary(&mut self, data: &[u8]) -> io::Result<()> {
+    self.send(Message::Binary(data))
+  }
+
+  /// sends the message currently being delivered on the subscription
+  /// as a text message (or binary if it isn't valid UTF-8).
+  /// Returns false if there was no message to send.
+  pub fn forward(&mut self, subscription: &Subscription) -> io::Result<bool> {
+    if *self.close_state != CloseState::Open {
+      return Ok(false);
+    }
+    match self.ctx.message(subscription) {
+      Some(message) => {
+        let opcode = if str::from_utf8(message).is_ok() { Opcode::Text } else { Opcode::Binary };
+        frame::write(self.outgoing, opcode, message)?;
+        Ok(true)
+      },
+      None => Ok(false)
+    }
+  }
+
+  /// starts the close handshake, the connection is
+  /// terminated once the client confirms.
+  pub fn close(&mut self, code: u16) -> io::Result<()> {
+    if *self.close_state == CloseState::Open {
+      write_close(self.outgoing, code)?;
+      *self.close_state = CloseState::Closing;
+    }
+    Ok( () )
+  }
+
+  pub fn context(&mut self) -> &mut Context<'c> {
+    self.ctx
+  }
+}
+
+pub fn accept_key<'a>(key: &str, dst: &'a mut [u8; ACCEPT_KEY_LEN]) -> &'a str {
+  let mut sha1 = Sha1::new();
+  sha1.update(key.as_bytes());
+  sha1.update(GUID.as_bytes());
+  base64::encode(&sha1.finish(), dst).unwrap()
+}
+
+fn has_token(header_value: &str, token: &str) -> bool {
+  header_value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+pub fn is_upgrade(request: &Request) -> bool {
+  let headers = request.headers();
+  request.method() == "GET" &&
+  headers.upgrade.map(|u| has_token(u, "websocket")).unwrap_or(false) &&
+  headers.connection.map(|c| has_token(c, "upgrade")).unwrap_or(false)
+}
+
+/// Accepts the websocket handshake and hands the connection over to the handler,
+/// or responds with an error if the request isn't a valid websocket upgrade.
+pub fn upgrade<H: Handler + 'static>(request: &Request, responder: &Responder, handler: H)
+  -> io::Result<Option<Response>>
+{
+  let key = match request.headers().sec_websocket_key {
+    Some(key) if is_upgrade(request) && key.len() == 24 => key,
+    _ => {
+      let mut response = responder.respond(status::BAD_REQUEST)?;
+      response.set_header("Content-Type", "text/plain")?;
+      let mut body = response.into_body()?;
+      write!(body, "Invalid websocket upgrade request")?;
+      return Ok(Some(body.finish()));
+    }
+  };
+  if request.headers().sec_websocket_version != Some(13) {
+    let mut response = responder.respond(status::UPGRADE_REQUIRED)?;
+    response.set_header("Sec-WebSocket-Version", "13")?;
+    response.set_header("Content-Length", "0")?;
+    return Ok(Some(response.into_body()?.finish()));
+  }
+  let mut accept_key_buffer = [0u8; ACCEPT_KEY_LEN];
+  let mut response = responder.respond(status::SWITCHING_PROTOCOLS)?;
+  response.set_header("Upgrade", "websocket")?;
+  response.set_header("Connection", "Upgrade")?;
+  response.set_header("Sec-WebSocket-Accept", accept_key(key, &mut accept_key_buffer))?;
+  response.finish_with_upgrade(Box::new(Connection::new(handler)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{accept_key, has_token, ACCEPT_KEY_LEN};
+
+  #[test]
+  fn test_accept_key() {
+    // example from RFC 6455, section 1.3
+    let mut buffer = [0u8; ACCEPT_KEY_LEN];
+    let key = accept_key("dGhlIHNhbXBsZSBub25jZQ==", &mut buffer);
+    assert_eq!(key, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+  }
+
+  #[test]
+  fn test_has_token() {
+    assert!(has_token("Upgrade", "upgrade"));
+    assert!(has_token("keep-alive, Upgrade", "upgrade"));
+    assert!(!has_token("keep-alive", "upgrade"));
+  }
+}
@@ -1,7 +1,8 @@
 use std;
 use mio;
 use std::ops::{Deref, DerefMut};
-use super::{Token, Event, AsyncToken, Context, ReadSizeHint};
+use super::{Token, Event, AsyncToken, Context, ReadSizeHint, SocketFd};
+use std::os::unix::io::RawFd;
 use std::io::{Write, Read};
 
 pub trait EventSource {
@@ -69,6 +70,12 @@ impl<R: ReadSizeHint> ReadSizeHint for Registered<R> {
   }
 }
 
+impl<R: SocketFd> SocketFd for Registered<R> {
+  fn socket_fd(&self) -> Option<RawFd> {
+    self.source.socket_fd()
+  }
+}
+
 impl<R> Deref for Registered<R> {
   type Target = R;
 
@@ -0,0 +1,130 @@
+use std::net::SocketAddr;
+use std::str;
+
+/// longest SNI name or common name that is kept,
+/// DNS names can't be longer than 253 bytes
+pub const MAX_NAME_LEN : usize = 255;
+
+/// What is known about the connection, apart from the data sent over it.
+#[derive(Clone, Copy)]
+pub struct ConnectionInfo {
+  pub peer_addr: SocketAddr,
+  pub tls: Option<TlsInfo>
+}
+
+impl ConnectionInfo {
+  pub fn new(peer_addr: SocketAddr) -> ConnectionInfo {
+    ConnectionInfo { peer_addr, tls: None }
+  }
+
+  pub fn with_tls(mut self, tls: TlsInfo) -> ConnectionInfo {
+    self.tls = Some(tls);
+    self
+  }
+
+  pub fn uses_tls(&self) -> bool {
+    self.tls.is_some()
+  }
+}
+
+/// The parameters negotiated in the TLS handshake.
+#[derive(Clone, Copy)]
+pub struct TlsInfo {
+  /// as sent on the wire, e.g. `0x0303` for TLS 1.2
+  pub version: u16,
+  /// the IANA number of the cipher suite
+  pub cipher_suite: u16,
+  /// the application protocol negotiated with ALPN, if any
+  pub protocol: Option<&'static str>,
+  server_name: Name,
+  client_common_name: Name
+}
+
+impl TlsInfo {
+  /// A server name that is too long or not UTF-8 is ignored.
+  pub fn new(version: u16, cipher_suite: u16, protocol: Option<&'static str>, server_name: &[u8]) -> TlsInfo {
+    TlsInfo {
+      version,
+      cipher_suite,
+      protocol,
+      server_name: Name::new(server_name),
+      client_common_name: Name::new(b"")
+    }
+  }
+
+  /// for a client that authenticated with a certificate, ignored like the server name.
+  pub fn with_client_common_name(mut self, common_name: &[u8]) -> TlsInfo {
+    self.client_common_name = Name::new(common_name);
+    self
+  }
+
+  pub fn version_name(&self) -> &'static str {
+    match self.version {
+      0x0300 => "SSLv3",
+      0x0301 => "TLSv1.0",
+      0x0302 => "TLSv1.1",
+      0x0303 => "TLSv1.2",
+      0x0304 => "TLSv1.3",
+      _ => "unknown"
+    }
+  }
+
+  /// the host name the client asked for with SNI, if any
+  pub fn server_name(&self) -> Option<&str> {
+    self.server_name.as_str()
+  }
+
+  /// The common name in the subject DN of the client certificate,
+  /// only if the client sent one issued by a trusted CA.
+  pub fn client_common_name(&self) -> Option<&str> {
+    self.client_common_name.as_str()
+  }
+}
+
+// a copy of a name, so the info can be passed around without borrowing the TLS engine
+#[derive(Clone, Copy)]
+struct Name {
+  bytes: [u8; MAX_NAME_LEN],
+  len: u8
+}
+
+impl Name {
+  fn new(name: &[u8]) -> Name {
+    let mut copy = Name { bytes: [0u8; MAX_NAME_LEN], len: 0 };
+    if name.len() <= MAX_NAME_LEN && str::from_utf8(name).is_ok() {
+      copy.bytes[.. name.len()].copy_from_slice(name);
+      copy.len = name.len() as u8;
+    }
+    copy
+  }
+
+  fn as_str(&self) -> Option<&str> {
+    match self.len {
+      0 => None,
+      len => str::from_utf8(&self.bytes[.. len as usize]).ok()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TlsInfo;
+
+  #[test]
+  fn test_server_name() {
+    let info = TlsInfo::new(0x0303, 0xC02F, Some("h2"), b"example.com");
+    assert_eq!(info.server_name(), Some("example.com"));
+    assert_eq!(info.version_name(), "TLSv1.2");
+    assert_eq!(TlsInfo::new(0x0303, 0xC02F, None, b"").server_name(), None);
+    assert_eq!(TlsInfo::new(0x0303, 0xC02F, None, b"\xFFexample.com").server_name(), None);
+    assert_eq!(TlsInfo::new(0x0303, 0xC02F, None, &[b'a'; 256]).server_name(), None);
+  }
+
+  #[test]
+  fn test_client_common_name() {
+    let info = TlsInfo::new(0x0303, 0xC02F, None, b"");
+    assert_eq!(info.client_common_name(), None);
+    let info = info.with_client_common_name(b"Grandma's tablet");
+    assert_eq!(info.client_common_name(), Some("Grandma's tablet"));
+  }
+}
@@ -1,4 +1,6 @@
 use std;
+use std::net::SocketAddr;
+use std::time::Duration;
 use mio;
 use super::{
   Token,
@@ -6,19 +8,30 @@ use super::{
   ConnectionId,
   Registered,
   AsyncSource,
-  EventSource
+  EventSource,
+  Broker,
+  Channel,
+  Subscription,
+  ConnectionInfo,
+  TcpSocket
 };
+use super::sources::file::{Reader, ReadQueue};
 
 pub struct ContextFactory<'a> {
   poll: &'a mio::Poll,
   conn_id: ConnectionId,
   token_source: &'a mut AsyncTokenSource,
-  uses_tls: bool
+  broker: &'a mut Broker,
+  connection: &'a ConnectionInfo,
+  tcp: TcpSocket<'a>,
+  read_queue: &'a ReadQueue
 }
 
 impl<'a> ContextFactory<'a> {
-  pub fn set_tls(&mut self, uses_tls: bool) {
-    self.uses_tls = uses_tls;
+  /// for handlers that learn more about the connection,
+  /// like the parameters of the TLS session.
+  pub fn set_connection(&mut self, connection: &'a ConnectionInfo) {
+    self.connection = connection;
   }
 
   pub fn into_context<'s>(self, socket: &'s mut Socket)
@@ -29,7 +42,10 @@ impl<'a> ContextFactory<'a> {
       poll: self.poll,
       conn_id: self.conn_id,
       token_source: self.token_source,
-      uses_tls: self.uses_tls,
+      broker: self.broker,
+      connection: self.connection,
+      tcp: self.tcp,
+      read_queue: self.read_queue,
       socket
     }
   }
@@ -41,14 +57,17 @@ pub struct Context<'a> {
   poll: &'a mio::Poll,
   conn_id: ConnectionId,
   token_source: &'a mut AsyncTokenSource,
+  broker: &'a mut Broker,
   socket: &'a mut Socket,
-  uses_tls: bool
+  connection: &'a ConnectionInfo,
+  tcp: TcpSocket<'a>,
+  read_queue: &'a ReadQueue
 }
 
 impl<'a> Context<'a>
 {
-  pub fn new(poll: &'a mio::Poll, conn_id: ConnectionId, token_source: &'a mut AsyncTokenSource, socket: &'a mut Socket) -> Context<'a> {
-    Context {poll, conn_id, token_source, socket, uses_tls: false}
+  pub fn new(poll: &'a mio::Poll, conn_id: ConnectionId, token_source: &'a mut AsyncTokenSource, broker: &'a mut Broker, socket: &'a mut Socket, connection: &'a ConnectionInfo, tcp: TcpSocket<'a>, read_queue: &'a ReadQueue) -> Context<'a> {
+    Context {poll, conn_id, token_source, broker, socket, connection, tcp, read_queue}
   }
 
   pub fn register<R: AsyncSource>(&mut self, registerable: R) -> std::io::Result<Registered<R>> {
@@ -57,6 +76,13 @@ impl<'a> Context<'a>
     Ok(registered_handler)
   }
 
+  /// registers a file reader, which reads through
+  /// the read queue of the server.
+  pub fn register_file(&mut self, mut reader: Reader) -> std::io::Result<Registered<Reader>> {
+    reader.use_queue(self.read_queue.clone())?;
+    self.register(reader)
+  }
+
   pub fn deregister<R: AsyncSource>(&self, registerable: &mut R) -> std::io::Result<()> {
     registerable.deregister(&self.poll)
   }
@@ -70,13 +96,62 @@ impl<'a> Context<'a>
       poll: &self.poll,
       conn_id: self.conn_id,
       token_source: &mut self.token_source,
-      uses_tls: self.uses_tls
+      broker: &mut self.broker,
+      connection: self.connection,
+      tcp: self.tcp,
+      read_queue: self.read_queue
     };
     (self.socket, factory)
   }
 
+  pub fn connection(&self) -> &ConnectionInfo {
+    self.connection
+  }
+
+  /// the tcp socket underneath any TLS, to tune it
+  pub fn tcp(&self) -> TcpSocket<'a> {
+    self.tcp
+  }
+
+  /// the address of the client on the other end of the connection
+  pub fn peer_addr(&self) -> SocketAddr {
+    self.connection.peer_addr
+  }
+
   pub fn uses_tls(&self) -> bool {
-    self.uses_tls
+    self.connection.uses_tls()
+  }
+
+  /// the application protocol negotiated with ALPN, if any
+  pub fn protocol(&self) -> Option<&'static str> {
+    self.connection.tls.and_then(|tls| tls.protocol)
+  }
+
+  /// parks the connection on the channel, until unsubscribed.
+  /// Every message published on the channel is delivered as an event
+  /// on the returned subscription, as well as a single event when the timeout expires.
+  pub fn subscribe(&mut self, channel: Channel, timeout: Option<Duration>) -> std::io::Result<Subscription> {
+    let token = self.alloc_token();
+    self.broker.subscribe(token, channel, timeout)?;
+    Ok(Subscription::new(token.async_token(), channel))
+  }
+
+  pub fn unsubscribe(&mut self, subscription: Subscription) {
+    let token = Token::from_parts(self.conn_id, subscription.token());
+    self.broker.unsubscribe(token);
+  }
+
+  /// the message being delivered to the subscription,
+  /// only available while handling the message event.
+  pub fn message(&self, subscription: &Subscription) -> Option<&[u8]> {
+    let token = Token::from_parts(self.conn_id, subscription.token());
+    self.broker.message(token)
+  }
+
+  /// wakes up all connections parked on the channel,
+  /// returns how many were woken up.
+  pub fn publish(&mut self, channel: Channel, message: &[u8]) -> std::io::Result<usize> {
+    self.broker.publish(channel, message)
   }
 
   fn alloc_token(&mut self) -> Token {
@@ -85,5 +160,5 @@ impl<'a> Context<'a>
   }
 }
 
-pub trait Socket : EventSource + std::io::Read + ::io::ReadSizeHint + std::io::Write {}
-impl<S: EventSource + std::io::Read + ::io::ReadSizeHint + std::io::Write> Socket for S {}
+pub trait Socket : EventSource + std::io::Read + ::io::ReadSizeHint + ::io::SocketFd + std::io::Write {}
+impl<S: EventSource + std::io::Read + ::io::ReadSizeHint + ::io::SocketFd + std::io::Write> Socket for S {}
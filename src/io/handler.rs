@@ -26,8 +26,10 @@ impl Event {
 #[derive(Clone, Copy)]
 pub struct EventKind(pub usize);
 
-const READABLE : usize = 0b01;
-const WRITABLE : usize = 0b10;
+const READABLE : usize = 0b0001;
+const WRITABLE : usize = 0b0010;
+const MESSAGE : usize  = 0b0100;
+const TIMEOUT : usize  = 0b1000;
 
 impl EventKind {
   pub fn new() -> EventKind {
@@ -63,6 +65,24 @@ impl EventKind {
     self.has_flag(WRITABLE)
   }
 
+  pub fn with_message(self, message: bool) -> EventKind {
+    self.with_flag(MESSAGE, message)
+  }
+
+  pub fn with_timeout(self, timeout: bool) -> EventKind {
+    self.with_flag(TIMEOUT, timeout)
+  }
+
+  /// a message was published on a channel the receiver is subscribed to
+  pub fn is_message(self) -> bool {
+    self.has_flag(MESSAGE)
+  }
+
+  /// a subscription to a channel timed out before any message was published
+  pub fn is_timeout(self) -> bool {
+    self.has_flag(TIMEOUT)
+  }
+
   pub fn has_any(self) -> bool {
     self.0 != 0
   }
@@ -110,5 +130,18 @@ mod tests {
     assert!(!no.is_writable());
     assert!(!no.has_any());
   }
+
+  #[test]
+  fn test_message_timeout() {
+    let message = EventKind::new().with_message(true);
+    assert!(message.is_message());
+    assert!(!message.is_timeout());
+    assert!(!message.is_readable());
+    assert!(message.has_any());
+    let timeout = EventKind::new().with_timeout(true);
+    assert!(timeout.is_timeout());
+    assert!(!timeout.is_message());
+    assert!(!timeout.is_writable());
+  }
   
 }
@@ -1,7 +1,6 @@
 use io::sources::file::Reader;
 use io::{Event, Handler, Registered, Context, EventSource};
 use io::handlers::send_buffer;
-use std;
 use std::io::Write;
 
 pub struct FileResponder {
@@ -13,14 +12,15 @@ pub struct FileResponder {
 
 impl FileResponder {
 
-  pub fn start(mut reader: Registered<Reader>) -> std::io::Result<FileResponder> {
-    reader.try_queue_read()?; //queue initial read to start getting events
-    Ok(FileResponder {
+  /// The initial read should already be queued on `reader`,
+  /// as the events of the read queue drive the responder.
+  pub fn new(reader: Registered<Reader>) -> FileResponder {
+    FileResponder {
       reader,
       total_bytes_sent: 0,
       buffer_bytes_sent: 0,
       socket_writeable: true
-    })
+    }
   }
 
   fn send_and_request_data(&mut self, socket: &mut Write) -> Option<usize> {
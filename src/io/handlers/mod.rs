@@ -1,6 +1,8 @@
 mod buffer;
 mod file;
+mod sendfile;
 mod buffer_io;
 pub use self::buffer_io::{send_buffer, receive_buffer, IoReport};
 pub use self::buffer::*;
 pub use self::file::*;
+pub use self::sendfile::*;
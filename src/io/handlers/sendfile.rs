@@ -0,0 +1,96 @@
+use io::sources::file::{Reader, OwnedFd};
+use io::{Event, Handler, Context};
+use std;
+use std::ops::Range;
+use std::os::unix::io::{RawFd, AsRawFd};
+use libc;
+
+/// Sends a file with sendfile, straight from the page cache to the socket,
+/// for sockets that give a `socket_fd`.
+pub struct SendFileResponder {
+  file_fd: OwnedFd,
+  range: Range<usize>,
+  total_bytes_sent: usize
+}
+
+impl SendFileResponder {
+  pub fn start(reader: Reader) -> std::io::Result<SendFileResponder> {
+    let (file_fd, range) = reader.into_buffered_file()?;
+    Ok(SendFileResponder {
+      file_fd,
+      range,
+      total_bytes_sent: 0
+    })
+  }
+
+  /// returns the amount of bytes sent when done
+  fn send(&mut self, socket_fd: RawFd) -> Option<usize> {
+    while self.range.start < self.range.end {
+      let mut offset = self.range.start as libc::off64_t;
+      let len = self.range.end - self.range.start;
+      let sent = unsafe {
+        libc::sendfile64(socket_fd, self.file_fd.as_raw_fd(), &mut offset as *mut libc::off64_t, len)
+      };
+      match sent {
+        -1 => {
+          match std::io::Error::last_os_error().kind() {
+            std::io::ErrorKind::Interrupted => {},
+            //wait until the socket is writable again
+            std::io::ErrorKind::WouldBlock => return None,
+            _ => return Some(self.total_bytes_sent)
+          }
+        },
+        //the file got shorter than the headers said
+        0 => return Some(self.total_bytes_sent),
+        sent => {
+          self.range.start += sent as usize;
+          self.total_bytes_sent += sent as usize;
+        }
+      }
+    }
+    Some(self.total_bytes_sent)
+  }
+}
+
+impl Handler<usize> for SendFileResponder {
+  fn handle_event(&mut self, event: &Event, ctx: &mut Context) -> Option<usize> {
+    let socket = ctx.socket();
+    if !socket.is_source_of(event) || !event.kind().is_writable() {
+      return None;
+    }
+    match socket.socket_fd() {
+      Some(socket_fd) => self.send(socket_fd),
+      //the socket doesn't allow sending around it
+      None => Some(self.total_bytes_sent)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SendFileResponder;
+  use io::sources::file::{Reader, Directory};
+  use std::io::Read;
+  use std::net::{TcpListener, TcpStream};
+  use std::os::unix::io::AsRawFd;
+
+  const SMALL_MSG : &'static [u8] =
+    include_bytes!("../../../test_fixtures/aio/small.txt");
+
+  #[test]
+  fn test_send_range() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let path = dir.sub_path("small.txt").unwrap();
+    let (reader, _) = Reader::open(&path, Some(4 .. 11)).unwrap();
+    let mut sender = SendFileResponder::start(reader).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    assert_eq!(sender.send(server.as_raw_fd()), Some(7));
+    drop(server);
+    let mut received = Vec::new();
+    client.read_to_end(&mut received).unwrap();
+    assert_eq!(received.as_slice(), &SMALL_MSG[4 .. 11]);
+  }
+}
@@ -1,13 +1,19 @@
 mod token;
 mod handler;
 mod context;
+mod connection_info;
 mod async_source;
 mod nocopy_io_traits;
+mod pubsub;
+mod tcp;
 pub mod sources;
 pub mod handlers;
 
 pub use self::token::{Token, AsyncToken, AsyncTokenSource, ConnectionId};
 pub use self::handler::*;
 pub use self::context::*;
+pub use self::connection_info::*;
 pub use self::async_source::*;
 pub use self::nocopy_io_traits::*;
+pub use self::tcp::*;
+pub use self::pubsub::{Broker, Publisher, Channel, Subscription};
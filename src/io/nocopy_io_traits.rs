@@ -1,4 +1,5 @@
 use std::io;
+use std::os::unix::io::RawFd;
 
 /// trait similar to Write but assumes it already has an internal buffer
 /// that can be written (read into) from R without copying.
@@ -23,3 +24,13 @@ pub trait ReadSizeHint : io::Read {
 pub trait WriteSrc {
   fn write_to(&mut self, writer: &mut io::Write) -> io::Result<usize>;
 }
+
+/// Gives the file descriptor of the socket underneath, so file data
+/// can be sent to it with sendfile without copying it through userspace.
+pub trait SocketFd {
+  /// None when data written to the socket still needs
+  /// to pass through userspace, e.g. to be encrypted.
+  fn socket_fd(&self) -> Option<RawFd> {
+    None
+  }
+}
@@ -0,0 +1,372 @@
+use std;
+use std::io;
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use mio;
+use super::{Token, AsyncToken, ConnectionId, AsyncSource, EventSource, EventKind};
+
+// how many connections can be parked on a channel at the same time
+const SUBSCRIPTION_COUNT : usize = 100;
+// how many messages from other threads can wait to be published
+const REMOTE_QUEUE_SIZE : usize = 64;
+pub const MESSAGE_MAX_SIZE : usize = 1024;
+
+/// A named channel connections can subscribe to.
+/// Only the hash of the name is kept, so channels are cheap to copy around.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Channel(u64);
+
+impl Channel {
+  pub fn named(name: &str) -> Channel {
+    //FNV-1a
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+      (hash ^ b as u64).wrapping_mul(0x100_0000_01b3)
+    });
+    Channel(hash)
+  }
+}
+
+/// Handle to a connection parked on a channel.
+/// Events for it have `EventKind::is_message` or `EventKind::is_timeout` set.
+pub struct Subscription {
+  token: AsyncToken,
+  channel: Channel
+}
+
+impl Subscription {
+  pub fn new(token: AsyncToken, channel: Channel) -> Subscription {
+    Subscription { token, channel }
+  }
+
+  pub fn channel(&self) -> Channel {
+    self.channel
+  }
+}
+
+impl EventSource for Subscription {
+  fn token(&self) -> AsyncToken {
+    self.token
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Delivery {
+  Nothing,
+  Message(usize),
+  Timeout
+}
+
+struct Subscriber {
+  token: Token,
+  channel: Channel,
+  deadline: Option<Instant>,
+  delivery: Delivery,
+  message: [u8; MESSAGE_MAX_SIZE]
+}
+
+type RemoteQueue = Arc<Mutex<VecDeque<(Channel, Vec<u8>)>>>;
+
+/// Keeps track of which connections are parked on which channel,
+/// and queues up the events to wake them up, either because
+/// a message was published or because they timed out.
+///
+/// The server dispatches the queued events after every round of socket events.
+pub struct Broker {
+  subscribers: Vec<Option<Subscriber>>,
+  remote_queue: RemoteQueue,
+  registration: mio::Registration,
+  set_readiness: mio::SetReadiness
+}
+
+impl Broker {
+  pub fn new() -> Broker {
+    let mut subscribers = Vec::with_capacity(SUBSCRIPTION_COUNT);
+    for _ in 0 .. SUBSCRIPTION_COUNT {
+      subscribers.push(None);
+    }
+    let (registration, set_readiness) = mio::Registration::new2();
+    Broker {
+      subscribers,
+      remote_queue: Arc::new(Mutex::new(VecDeque::with_capacity(REMOTE_QUEUE_SIZE))),
+      registration,
+      set_readiness
+    }
+  }
+
+  /// A handle that can publish messages from other threads,
+  /// or from handlers that don't have access to an `io::Context`.
+  pub fn publisher(&self) -> Publisher {
+    Publisher {
+      queue: self.remote_queue.clone(),
+      set_readiness: self.set_readiness.clone()
+    }
+  }
+
+  pub fn subscribe(&mut self, token: Token, channel: Channel, timeout: Option<Duration>) -> io::Result<()> {
+    let slot = self.subscribers.iter_mut()
+      .find(|s| s.is_none())
+      .ok_or(io::Error::new(io::ErrorKind::Other, "too many subscriptions"))?;
+    *slot = Some(Subscriber {
+      token,
+      channel,
+      deadline: timeout.map(|t| Instant::now() + t),
+      delivery: Delivery::Nothing,
+      message: [0u8; MESSAGE_MAX_SIZE]
+    });
+    Ok( () )
+  }
+
+  pub fn unsubscribe(&mut self, token: Token) {
+    for slot in self.subscribers.iter_mut() {
+      if slot.as_ref().map(|s| s.token == token).unwrap_or(false) {
+        *slot = None;
+      }
+    }
+  }
+
+  pub fn unsubscribe_connection(&mut self, conn_id: ConnectionId) {
+    for slot in self.subscribers.iter_mut() {
+      if slot.as_ref().map(|s| s.token.connection_id() == conn_id).unwrap_or(false) {
+        *slot = None;
+      }
+    }
+  }
+
+  /// Queues the message for all subscribers of the channel.
+  /// Subscribers that still have an undelivered message or timeout won't receive it.
+  /// Returns the amount of subscribers that will be woken up.
+  pub fn publish(&mut self, channel: Channel, message: &[u8]) -> io::Result<usize> {
+    if message.len() > MESSAGE_MAX_SIZE {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "message too big"));
+    }
+    let mut count = 0;
+    let subscribers = self.subscribers.iter_mut().filter_map(|s| s.as_mut());
+    for subscriber in subscribers {
+      if subscriber.channel == channel && subscriber.delivery == Delivery::Nothing {
+        subscriber.message[.. message.len()].copy_from_slice(message);
+        subscriber.delivery = Delivery::Message(message.len());
+        count += 1;
+      }
+    }
+    Ok(count)
+  }
+
+  /// The message being delivered to the subscriber, if any
+  pub fn message(&self, token: Token) -> Option<&[u8]> {
+    self.subscriber(token).and_then(|s| {
+      match s.delivery {
+        Delivery::Message(len) => Some(&s.message[.. len]),
+        _ => None
+      }
+    })
+  }
+
+  /// Queue timeout events for all subscribers past their deadline.
+  /// The deadline is cleared, so a subscriber times out only once.
+  pub fn expire(&mut self, now: Instant) {
+    let subscribers = self.subscribers.iter_mut().filter_map(|s| s.as_mut());
+    for subscriber in subscribers {
+      let expired = subscriber.deadline.map(|d| d <= now).unwrap_or(false);
+      if expired && subscriber.delivery == Delivery::Nothing {
+        subscriber.deadline = None;
+        subscriber.delivery = Delivery::Timeout;
+      }
+    }
+  }
+
+  /// How long the event loop can wait at most
+  /// before it needs to call `expire` or dispatch queued events.
+  pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+    let subscribers = self.subscribers.iter().filter_map(|s| s.as_ref());
+    subscribers.fold(None, |timeout, subscriber| {
+      let subscriber_timeout = if subscriber.delivery != Delivery::Nothing {
+        Some(Duration::from_secs(0))
+      } else {
+        subscriber.deadline.map(|d| {
+          if d > now { d - now } else { Duration::from_secs(0) }
+        })
+      };
+      match (timeout, subscriber_timeout) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b)
+      }
+    })
+  }
+
+  /// The next queued event, as the token of the subscriber and kind of the event.
+  /// Call `finish_delivery` once it has been handled.
+  pub fn next_delivery(&self) -> Option<(Token, EventKind)> {
+    let subscribers = self.subscribers.iter().filter_map(|s| s.as_ref());
+    for subscriber in subscribers {
+      let kind = match subscriber.delivery {
+        Delivery::Nothing => continue,
+        Delivery::Message(_) => EventKind::new().with_message(true),
+        Delivery::Timeout => EventKind::new().with_timeout(true)
+      };
+      return Some((subscriber.token, kind));
+    }
+    None
+  }
+
+  pub fn finish_delivery(&mut self, token: Token) {
+    if let Some(subscriber) = self.subscriber_mut(token) {
+      subscriber.delivery = Delivery::Nothing;
+    }
+  }
+
+  /// Publishes the messages sent through a `Publisher`
+  pub fn receive_remote(&mut self) {
+    if let Err(err) = self.set_readiness.set_readiness(mio::Ready::empty()) {
+      println!("could not reset readiness of broker: {:?}", err);
+    }
+    let messages = match self.remote_queue.lock() {
+      Ok(mut queue) => std::mem::replace(&mut *queue, VecDeque::new()),
+      Err(_) => return
+    };
+    for (channel, message) in messages {
+      if let Err(err) = self.publish(channel, &message) {
+        println!("could not publish message from other thread: {:?}", err);
+      }
+    }
+  }
+
+  fn subscriber(&self, token: Token) -> Option<&Subscriber> {
+    self.subscribers.iter()
+      .filter_map(|s| s.as_ref())
+      .find(|s| s.token == token)
+  }
+
+  fn subscriber_mut(&mut self, token: Token) -> Option<&mut Subscriber> {
+    self.subscribers.iter_mut()
+      .filter_map(|s| s.as_mut())
+      .find(|s| s.token == token)
+  }
+}
+
+impl AsyncSource for Broker {
+  fn register(&mut self, selector: &mio::Poll, token: Token) -> io::Result<()> {
+    selector.register(
+      &self.registration,
+      token.as_mio_token(),
+      mio::Ready::readable(),
+      mio::PollOpt::edge()
+    )
+  }
+
+  fn deregister(&mut self, selector: &mio::Poll) -> io::Result<()> {
+    selector.deregister(&self.registration)
+  }
+}
+
+/// Publishes messages to a `Broker` from any thread.
+/// The messages are published on the event loop thread the next time it wakes up.
+#[derive(Clone)]
+pub struct Publisher {
+  queue: RemoteQueue,
+  set_readiness: mio::SetReadiness
+}
+
+impl Publisher {
+  pub fn publish(&self, channel: Channel, message: &[u8]) -> io::Result<()> {
+    if message.len() > MESSAGE_MAX_SIZE {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "message too big"));
+    }
+    {
+      let mut queue = self.queue.lock()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "publish queue lock poisoned"))?;
+      if queue.len() >= REMOTE_QUEUE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::WouldBlock, "publish queue is full"));
+      }
+      queue.push_back((channel, message.to_vec()));
+    }
+    self.set_readiness.set_readiness(mio::Ready::readable())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Broker, Channel};
+  use io::{Token, ConnectionId, AsyncToken};
+  use std::time::{Duration, Instant};
+
+  fn token(conn: u32, async_token: u32) -> Token {
+    Token::from_parts(ConnectionId(conn), AsyncToken(async_token as _))
+  }
+
+  #[test]
+  fn test_channel_names() {
+    assert_eq!(Channel::named("chat"), Channel::named("chat"));
+    assert!(Channel::named("chat") != Channel::named("news"));
+  }
+
+  #[test]
+  fn test_publish_wakes_subscribers_of_channel() {
+    let mut broker = Broker::new();
+    broker.subscribe(token(1, 1), Channel::named("chat"), None).unwrap();
+    broker.subscribe(token(2, 1), Channel::named("news"), None).unwrap();
+    assert_eq!(broker.publish(Channel::named("chat"), b"hello").unwrap(), 1);
+    let (t, kind) = broker.next_delivery().unwrap();
+    assert!(t == token(1, 1));
+    assert!(kind.is_message());
+    assert_eq!(broker.message(t), Some(b"hello".as_ref()));
+    broker.finish_delivery(t);
+    assert!(broker.next_delivery().is_none());
+    assert_eq!(broker.message(t), None);
+  }
+
+  #[test]
+  fn test_undelivered_message_is_kept() {
+    let mut broker = Broker::new();
+    broker.subscribe(token(1, 1), Channel::named("chat"), None).unwrap();
+    broker.publish(Channel::named("chat"), b"first").unwrap();
+    assert_eq!(broker.publish(Channel::named("chat"), b"second").unwrap(), 0);
+    assert_eq!(broker.message(token(1, 1)), Some(b"first".as_ref()));
+  }
+
+  #[test]
+  fn test_timeout() {
+    let mut broker = Broker::new();
+    broker.subscribe(token(1, 1), Channel::named("chat"), Some(Duration::from_secs(10))).unwrap();
+    let now = Instant::now();
+    assert!(broker.next_timeout(now).unwrap() <= Duration::from_secs(10));
+    broker.expire(now);
+    assert!(broker.next_delivery().is_none());
+    broker.expire(now + Duration::from_secs(11));
+    let (t, kind) = broker.next_delivery().unwrap();
+    assert!(kind.is_timeout());
+    broker.finish_delivery(t);
+    assert_eq!(broker.next_timeout(now), None);
+  }
+
+  #[test]
+  fn test_unsubscribe_connection() {
+    let mut broker = Broker::new();
+    broker.subscribe(token(1, 1), Channel::named("chat"), None).unwrap();
+    broker.subscribe(token(1, 2), Channel::named("chat"), None).unwrap();
+    broker.subscribe(token(2, 1), Channel::named("chat"), None).unwrap();
+    broker.unsubscribe_connection(ConnectionId(1));
+    assert_eq!(broker.publish(Channel::named("chat"), b"hi").unwrap(), 1);
+  }
+
+  #[test]
+  fn test_message_too_big() {
+    let mut broker = Broker::new();
+    let message = [0u8; super::MESSAGE_MAX_SIZE + 1];
+    assert!(broker.publish(Channel::named("chat"), &message).is_err());
+  }
+
+  #[test]
+  fn test_remote_publish() {
+    let mut broker = Broker::new();
+    broker.subscribe(token(1, 1), Channel::named("chat"), None).unwrap();
+    let publisher = broker.publisher();
+    ::std::thread::spawn(move || {
+      publisher.publish(Channel::named("chat"), b"from worker").unwrap();
+    }).join().unwrap();
+    assert!(broker.next_delivery().is_none());
+    broker.receive_remote();
+    assert_eq!(broker.message(token(1, 1)), Some(b"from worker".as_ref()));
+  }
+}
@@ -1,11 +1,9 @@
 use std::io;
-use std::ops::Range;
 use std::os::unix::io::RawFd;
 use std::time::Duration;
 use std::mem;
 use std::ptr;
 use std::os::raw::c_long;
-use buffer::Buffer;
 use super::{ffi, to_result};
 #[repr(C)]
 #[derive(Default)]
@@ -13,28 +11,38 @@ pub struct Event {
   event: ffi::io_event
 }
 
+impl Event {
+  /// the data of the operation
+  pub fn data(&self) -> u64 {
+    self.event.data
+  }
+
+  /// the amount of bytes read or a negated errno
+  pub fn result(&self) -> i64 {
+    self.event.res
+  }
+}
+
 #[repr(C)]
 pub struct Operation {
-  iocb: ffi::iocb,
-  buffer: Buffer
+  iocb: ffi::iocb
 }
 
 impl Operation {
 
-  pub fn create_read(fd: RawFd, range: Range<usize>, mut buffer: Buffer) -> Operation {
-    let buffer_ptr = buffer.as_mut_slice().as_mut_ptr();
-    let len = range.end - range.start;
-    assert!(len <= buffer.capacity());
+  /// the kernel copies the operation when submitted,
+  /// but `dst` needs to stay valid until the operation completed.
+  pub fn create_read(fd: RawFd, offset: usize, len: usize, dst: *mut u8, data: u64) -> Operation {
     Operation {
       iocb: ffi::iocb {
+        aio_data:       data,
         aio_fildes:     fd as u32,
         aio_lio_opcode: ffi::IOCB_CMD_PREAD as u16,
-        aio_buf:        buffer_ptr as u64,
+        aio_buf:        dst as u64,
         aio_nbytes:     len as u64,
-        aio_offset:     range.start as i64,
+        aio_offset:     offset as i64,
         .. Default::default()
-      },
-      buffer
+      }
     }
   }
 
@@ -43,17 +51,6 @@ impl Operation {
     self.iocb.aio_resfd = event_fd as u32;
   }
 
-  pub fn into_read_result(mut self, event: &Event) -> io::Result<Buffer> {
-    //check event matches this operation?
-    if event.event.res < 0 {
-      Err(io::Error::from_raw_os_error( - event.event.res as i32))
-    }
-    else {
-      unsafe { self.buffer.set_len(event.event.res as usize) };
-      Ok(self.buffer)
-    }
-  }
-
   pub fn as_iocb<'a>(&'a self) -> &'a ffi::iocb {
     &self.iocb
   }
@@ -114,9 +111,13 @@ impl Context {
         min_events as c_long,
         events.len() as c_long,
         events_ptr,
-        timeout_ptr) as usize
+        timeout_ptr)
     };
-    &events[.. count]
+    // interrupted or failed
+    if count < 0 {
+      return &events[.. 0];
+    }
+    &events[.. count as usize]
   }
 }
 
@@ -0,0 +1,173 @@
+/*
+Directory entries are read in batches with getdents64 (see doc/prototypes/dir.c),
+straight into a fixed buffer. Every entry is a linux_dirent64:
+
+  u64 d_ino; i64 d_off; u16 d_reclen; u8 d_type; char d_name[] (NUL terminated)
+
+The size and mtime come from fstatat relative to the directory,
+so the type is taken from st_mode as well, d_type can be DT_UNKNOWN.
+*/
+use libc;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::str;
+use std::os::unix::io::AsRawFd;
+use super::{to_result, OwnedFd};
+use super::path::Path;
+
+const BUFFER_SIZE : usize = 4096;
+const RECLEN_OFFSET : usize = 16;
+const NAME_OFFSET : usize = 19;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EntryKind {
+  File,
+  Directory,
+  Symlink,
+  Other
+}
+
+impl EntryKind {
+  fn from_mode(mode: libc::mode_t) -> EntryKind {
+    match mode & libc::S_IFMT {
+      libc::S_IFREG => EntryKind::File,
+      libc::S_IFDIR => EntryKind::Directory,
+      libc::S_IFLNK => EntryKind::Symlink,
+      _ => EntryKind::Other
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EntryInfo {
+  pub kind: EntryKind,
+  pub size: u64,
+  pub mtime: i64
+}
+
+/// The entries of a directory, in the order the filesystem returns them.
+/// Hidden entries (starting with a dot, including `.` and `..`)
+/// and names that aren't valid UTF-8 are skipped.
+pub struct DirectoryListing {
+  dir_fd: OwnedFd,
+  buffer: [u8; BUFFER_SIZE],
+  pos: usize,
+  len: usize,
+  is_done: bool
+}
+
+impl DirectoryListing {
+  pub fn open(path: &dyn Path) -> io::Result<DirectoryListing> {
+    // getdents64 doesn't work on O_PATH descriptors, so open it for reading
+    let dir_fd = path.open(libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC)?;
+    Ok(DirectoryListing {
+      dir_fd,
+      buffer: [0u8; BUFFER_SIZE],
+      pos: 0,
+      len: 0,
+      is_done: false
+    })
+  }
+
+  /// The name and info of the next entry, None once all entries were returned.
+  /// Entries removed while listing are skipped.
+  pub fn next_entry(&mut self) -> io::Result<Option<(&str, EntryInfo)>> {
+    let next = self.next_name_range()?;
+    Ok(next.map(move |(start, len, info)| {
+      // checked in next_name_range
+      let name = unsafe { str::from_utf8_unchecked(&self.buffer[start .. start + len]) };
+      (name, info)
+    }))
+  }
+
+  // where the name of the next entry is in the buffer
+  fn next_name_range(&mut self) -> io::Result<Option<(usize, usize, EntryInfo)>> {
+    loop {
+      if self.pos == self.len {
+        if self.is_done || !self.read_entries()? {
+          return Ok(None);
+        }
+      }
+      let entry_pos = self.pos;
+      let reclen = unsafe {
+        ptr::read_unaligned(self.buffer.as_ptr().offset((entry_pos + RECLEN_OFFSET) as isize) as *const u16)
+      } as usize;
+      self.pos += reclen;
+
+      let name_start = entry_pos + NAME_OFFSET;
+      let name_len = self.buffer[name_start .. entry_pos + reclen].iter()
+        .position(|b| *b == 0)
+        .unwrap_or(reclen - NAME_OFFSET);
+      let name = &self.buffer[name_start .. name_start + name_len];
+      if name.first() == Some(&b'.') || str::from_utf8(name).is_err() {
+        continue;
+      }
+      // the name in the buffer is NUL terminated already
+      match stat_at(self.dir_fd.as_raw_fd(), name.as_ptr() as *const libc::c_char) {
+        Ok(stats) => {
+          let info = EntryInfo {
+            kind: EntryKind::from_mode(stats.st_mode),
+            size: stats.st_size as u64,
+            mtime: stats.st_mtime
+          };
+          return Ok(Some((name_start, name_len, info)));
+        },
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => return Err(err)
+      }
+    }
+  }
+
+  // returns false once the end of the directory was reached
+  fn read_entries(&mut self) -> io::Result<bool> {
+    let len = to_result(unsafe {
+      libc::syscall(
+        libc::SYS_getdents64,
+        self.dir_fd.as_raw_fd(),
+        self.buffer.as_mut_ptr(),
+        BUFFER_SIZE)
+    } as libc::c_int)? as usize;
+    self.pos = 0;
+    self.len = len;
+    self.is_done = len == 0;
+    Ok(!self.is_done)
+  }
+}
+
+fn stat_at(dir_fd: libc::c_int, name: *const libc::c_char) -> io::Result<libc::stat64> {
+  let mut stats : libc::stat64 = unsafe { mem::zeroed() };
+  let success = unsafe {
+    libc::fstatat64(dir_fd, name, &mut stats as *mut libc::stat64, libc::AT_SYMLINK_NOFOLLOW)
+  };
+  to_result(success).map(|_| stats)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{DirectoryListing, EntryKind};
+  use super::super::Directory;
+
+  #[test]
+  fn test_list_directory() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let mut listing = DirectoryListing::open(&dir).unwrap();
+    let mut names = Vec::new();
+    while let Some((name, info)) = listing.next_entry().unwrap() {
+      assert!(!name.starts_with("."));
+      if name == "small.txt" {
+        assert_eq!(info.kind, EntryKind::File);
+        assert_eq!(info.size as usize, include_bytes!("../../../../../test_fixtures/aio/small.txt").len());
+      }
+      names.push(name.to_owned());
+    }
+    assert!(names.iter().any(|name| name == "small.txt"));
+  }
+
+  #[test]
+  fn test_list_sub_directory() {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/")).unwrap();
+    let mut listing = DirectoryListing::open(&dir.sub_path("aio/").unwrap()).unwrap();
+    assert!(listing.next_entry().unwrap().is_some());
+  }
+}
@@ -1,11 +1,16 @@
 mod ffi;
 mod aio;
+mod uring;
+mod queue;
 mod reader;
 mod owned_fd;
 mod readrange;
 mod path;
+mod listing;
 pub use self::reader::{Reader, ContentHashFields};
+pub use self::queue::ReadQueue;
 pub use self::path::Directory;
+pub use self::listing::{DirectoryListing, EntryInfo, EntryKind};
 pub use self::path::Path;
 pub use self::path::RelativePath;
 pub use self::owned_fd::OwnedFd;
@@ -48,6 +48,16 @@ impl Directory {
   }
 }
 
+// the directory itself, e.g. to list its entries
+impl Path for Directory {
+  fn open(&self, flags: libc::c_int) -> Result<OwnedFd> {
+    let raw_fd = to_result( unsafe {
+      libc::openat(self.as_raw_fd(), b".\0".as_ptr() as *const i8, flags)
+    } )?;
+    Ok(OwnedFd::from_raw_fd(raw_fd))
+  }
+}
+
 impl AsRawFd for Directory {
   fn as_raw_fd(&self) -> RawFd {
     self.dir_fd.as_raw_fd()
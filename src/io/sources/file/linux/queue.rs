@@ -0,0 +1,502 @@
+use buffer::{Buffer, PageBuffer};
+use mio;
+use libc;
+use std::io;
+use std::mem;
+use std::slice;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+use std::os::unix::io::{RawFd, AsRawFd};
+use io::{AsyncSource, Token};
+use super::owned_fd::OwnedFd;
+use super::uring::{Ring, Destination};
+use super::{aio, to_result};
+// fits the largest buffer a reader asks for with 4K blocks
+const REGISTERED_BUFFER_SIZE : usize = 400 * 1024;
+// reads through the page cache with AIO happen on the event loop,
+// so they are kept to a couple of blocks
+const SYNC_READ_MAX_SIZE : usize = 8 * 1024;
+
+/// Memory a file is read into.
+pub enum ReadBuffer {
+  Owned(Buffer),
+  /// leased from the buffers registered on the ring of a `ReadQueue`
+  Registered(RegisteredBuffer)
+}
+
+impl ReadBuffer {
+  pub fn capacity(&self) -> usize {
+    match *self {
+      ReadBuffer::Owned(ref buffer) => buffer.capacity(),
+      ReadBuffer::Registered(ref buffer) => buffer.capacity
+    }
+  }
+
+  pub fn as_mut_slice(&mut self) -> &mut [u8] {
+    match *self {
+      ReadBuffer::Owned(ref mut buffer) => buffer.as_mut_slice(),
+      ReadBuffer::Registered(ref mut buffer) => unsafe {
+        slice::from_raw_parts_mut(buffer.ptr, buffer.len)
+      }
+    }
+  }
+
+  /// after reading `len` bytes into it
+  pub unsafe fn set_len(&mut self, len: usize) {
+    match *self {
+      ReadBuffer::Owned(ref mut buffer) => buffer.set_len(len),
+      ReadBuffer::Registered(ref mut buffer) => buffer.len = ::std::cmp::min(len, buffer.capacity)
+    }
+  }
+
+  fn as_mut_ptr(&mut self) -> *mut u8 {
+    match *self {
+      // as_mut_slice only covers what was read before
+      ReadBuffer::Owned(ref mut buffer) => buffer.as_mut_slice().as_mut_ptr(),
+      ReadBuffer::Registered(ref buffer) => buffer.ptr
+    }
+  }
+
+  fn destination(&mut self) -> Destination {
+    match *self {
+      ReadBuffer::Registered(ref buffer) => Destination::Registered(buffer.ptr, buffer.index),
+      _ => Destination::Memory(self.as_mut_ptr())
+    }
+  }
+}
+
+/// Goes back to the queue when dropped.
+pub struct RegisteredBuffer {
+  queue: Weak<RefCell<State>>,
+  index: u16,
+  ptr: *mut u8,
+  capacity: usize,
+  len: usize
+}
+
+impl Drop for RegisteredBuffer {
+  fn drop(&mut self) {
+    if let Some(state) = self.queue.upgrade() {
+      state.borrow_mut().free_buffers.push(self.index);
+    }
+  }
+}
+
+enum Slot {
+  Free,
+  Reading(Token),
+  Done(Token, i32),
+  /// the reader went away, but the kernel still writes into the buffer
+  Abandoned(ReadBuffer)
+}
+
+// The data given to the kernel with a read, to find its slot on completion.
+// Tokens are reused once a connection closes, so they can't identify a read,
+// and the generation tells a read apart from earlier ones in the same slot.
+fn read_data(slot_idx: usize, generation: u32) -> u64 {
+  (generation as u64) << 32 | slot_idx as u64
+}
+
+enum Backend {
+  Uring(Ring),
+  /// for kernels without io_uring, only for files opened with O_DIRECT
+  Aio(aio::Context)
+}
+
+struct State {
+  backend: Backend,
+  event_fd: OwnedFd,
+  slots: Vec<Slot>,
+  // bumped for every read submitted in the slot
+  generations: Vec<u32>,
+  completed: VecDeque<Token>,
+  // one allocation for all registered buffers
+  buffers: Option<PageBuffer>,
+  free_buffers: Vec<u16>
+}
+
+impl State {
+  // returns the buffer of an abandoned read, to drop it
+  // once the state isn't borrowed anymore
+  fn complete(&mut self, data: u64, result: i32) -> Option<ReadBuffer> {
+    let slot_idx = (data & 0xFFFF_FFFF) as usize;
+    let generation = (data >> 32) as u32;
+    if self.generations.get(slot_idx) != Some(&generation) {
+      return None;
+    }
+    let slot = &mut self.slots[slot_idx];
+    match mem::replace(slot, Slot::Free) {
+      Slot::Reading(token) => {
+        *slot = Slot::Done(token, result);
+        self.completed.push_back(token);
+        None
+      },
+      Slot::Abandoned(buffer) => Some(buffer),
+      s => {
+        *slot = s;
+        None
+      }
+    }
+  }
+}
+
+/// Reads files for all the connections of a server, through a single io_uring,
+/// or a single AIO context on kernels without it. The eventfd signaled on
+/// completion is registered once on the selector, and the server dispatches
+/// an event to the reader of every completed read, with the token the read
+/// was submitted for. Readers share the queue through a cheap handle.
+#[derive(Clone)]
+pub struct ReadQueue {
+  state: Rc<RefCell<State>>
+}
+
+impl ReadQueue {
+  /// Fails when io_uring is not available, so files
+  /// should be read with AIO instead. Registering the buffers
+  /// can fail on a low `RLIMIT_MEMLOCK`, after which all
+  /// reads use the buffer of the reader instead.
+  pub fn with_io_uring(entries: u32, registered_buffer_count: u16) -> io::Result<ReadQueue> {
+    let ring = Ring::setup(entries)?;
+    let event_fd = create_event_fd()?;
+    ring.register_event_fd(event_fd.as_raw_fd())?;
+
+    let mut buffers = None;
+    let mut free_buffers = Vec::new();
+    if registered_buffer_count != 0 {
+      let mut memory = PageBuffer::new(registered_buffer_count as usize * REGISTERED_BUFFER_SIZE);
+      let iovecs : Vec<libc::iovec> = memory.as_mut_slice()
+        .chunks_mut(REGISTERED_BUFFER_SIZE)
+        .take(registered_buffer_count as usize)
+        .map(|chunk| libc::iovec {
+          iov_base: chunk.as_mut_ptr() as *mut libc::c_void,
+          iov_len: chunk.len()
+        })
+        .collect();
+      match ring.register_buffers(&iovecs) {
+        Ok(_) => {
+          buffers = Some(memory);
+          free_buffers = (0 .. registered_buffer_count).rev().collect();
+        },
+        Err(err) => println!("could not register buffers on io_uring, reading into unregistered buffers: {:?}", err)
+      }
+    }
+
+    let entries = ring.entries();
+    Ok(Self::new(Backend::Uring(ring), entries, event_fd, buffers, free_buffers))
+  }
+
+  /// with a single AIO context, for at most `max_operations` reads at the same time
+  pub fn with_aio(max_operations: u32) -> io::Result<ReadQueue> {
+    let io_ctx = aio::Context::setup(max_operations)?;
+    let event_fd = create_event_fd()?;
+    Ok(Self::new(Backend::Aio(io_ctx), max_operations, event_fd, None, Vec::new()))
+  }
+
+  fn new(backend: Backend, entries: u32, event_fd: OwnedFd, buffers: Option<PageBuffer>, free_buffers: Vec<u16>) -> ReadQueue {
+    let slots = (0 .. entries).map(|_| Slot::Free).collect();
+    let generations = vec![0; entries as usize];
+    let completed = VecDeque::with_capacity(entries as usize);
+    ReadQueue {
+      state: Rc::new(RefCell::new(State {
+        backend,
+        event_fd,
+        slots,
+        generations,
+        completed,
+        buffers,
+        free_buffers
+      }))
+    }
+  }
+
+  /// Whether files are read through the page cache. If not,
+  /// they need to be opened with O_DIRECT to be read asynchronously.
+  pub fn reads_buffered(&self) -> bool {
+    match self.state.borrow().backend {
+      Backend::Uring(_) => true,
+      Backend::Aio(_) => false
+    }
+  }
+
+  /// The largest read `submit_read` takes for a file,
+  /// if reads of it are limited.
+  pub fn max_read_size(&self, is_direct: bool) -> Option<usize> {
+    match self.state.borrow().backend {
+      Backend::Aio(_) if !is_direct => Some(SYNC_READ_MAX_SIZE),
+      _ => None
+    }
+  }
+
+  /// Takes the completed reads from the kernel, after the eventfd was signaled.
+  /// Their readers are woken up by `next_completion`.
+  pub fn receive_completions(&self) {
+    let mut count = [0u8; 8];
+    unsafe {
+      libc::read(self.event_fd(), count.as_mut_ptr() as *mut libc::c_void, count.len())
+    };
+    if self.reads_buffered() {
+      self.receive_ring_completions();
+    }
+    else {
+      self.receive_aio_completions();
+    }
+  }
+
+  /// the token of the next reader that has a completed read
+  pub fn next_completion(&self) -> Option<Token> {
+    self.state.borrow_mut().completed.pop_front()
+  }
+
+  /// a registered buffer of at least `min_size`, if one is free
+  pub fn lease_buffer(&self, min_size: usize) -> Option<ReadBuffer> {
+    if min_size > REGISTERED_BUFFER_SIZE {
+      return None;
+    }
+    let mut state = self.state.borrow_mut();
+    let index = state.free_buffers.pop()?;
+    let ptr = match state.buffers {
+      Some(ref mut memory) => unsafe {
+        memory.as_mut_slice().as_mut_ptr().offset(index as isize * REGISTERED_BUFFER_SIZE as isize)
+      },
+      None => return None
+    };
+    Some(ReadBuffer::Registered(RegisteredBuffer {
+      queue: Rc::downgrade(&self.state),
+      index,
+      ptr,
+      capacity: REGISTERED_BUFFER_SIZE,
+      len: 0
+    }))
+  }
+
+  /// Submits a read for the reader with `token`, returns the slot to take the result
+  /// from. The buffer should be given to `abandon` if the reader goes away before that.
+  /// `is_direct` tells whether the file was opened with O_DIRECT.
+  pub fn submit_read(&self, token: Token, fd: RawFd, is_direct: bool, offset: usize, len: usize, buffer: &mut ReadBuffer) -> io::Result<usize> {
+    assert!(len <= buffer.capacity());
+    let mut state = self.state.borrow_mut();
+    let slot_idx = state.slots.iter().position(|slot| match *slot {
+      Slot::Free => true,
+      _ => false
+    }).ok_or_else(|| io::Error::new(io::ErrorKind::Other, "too many file reads at the same time"))?;
+    let generation = state.generations[slot_idx].wrapping_add(1);
+    state.generations[slot_idx] = generation;
+    let data = read_data(slot_idx, generation);
+    let event_fd = state.event_fd.as_raw_fd();
+    let slot = match state.backend {
+      Backend::Uring(ref mut ring) => {
+        unsafe { ring.submit_read(fd, offset, len, buffer.destination(), data)? };
+        Slot::Reading(token)
+      },
+      Backend::Aio(ref io_ctx) if is_direct => {
+        let mut read_op = aio::Operation::create_read(fd, offset, len, buffer.as_mut_ptr(), data);
+        read_op.set_event_fd(event_fd);
+        io_ctx.submit([read_op.as_iocb()].as_ref())?;
+        Slot::Reading(token)
+      },
+      // AIO blocks on submitting a read through the page cache anyway,
+      // so read right away, and signal the completion like the kernel would
+      Backend::Aio(_) if len > SYNC_READ_MAX_SIZE => {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+          "reads without O_DIRECT block the event loop on AIO, see ReadQueue::max_read_size"));
+      },
+      Backend::Aio(_) => {
+        let result = read_at(fd, offset, len, buffer.as_mut_ptr());
+        signal_event_fd(event_fd)?;
+        state.completed.push_back(token);
+        Slot::Done(token, result)
+      }
+    };
+    state.slots[slot_idx] = slot;
+    Ok(slot_idx)
+  }
+
+  /// the result of the read in the slot, once completed
+  pub fn take_result(&self, slot_idx: usize) -> Option<i32> {
+    let mut state = self.state.borrow_mut();
+    let slot = &mut state.slots[slot_idx];
+    match mem::replace(slot, Slot::Free) {
+      Slot::Done(_, result) => Some(result),
+      s => {
+        *slot = s;
+        None
+      }
+    }
+  }
+
+  /// keeps the buffer of a read that is still going on alive,
+  /// until it completes
+  pub fn abandon(&self, slot_idx: usize, buffer: ReadBuffer) {
+    let _completed_buffer = {
+      let mut state = self.state.borrow_mut();
+      let slot = &mut state.slots[slot_idx];
+      match mem::replace(slot, Slot::Free) {
+        Slot::Reading(_) => {
+          *slot = Slot::Abandoned(buffer);
+          None
+        },
+        _ => Some(buffer)
+      }
+    };
+  }
+
+  fn receive_ring_completions(&self) {
+    loop {
+      let completion = match self.state.borrow_mut().backend {
+        Backend::Uring(ref mut ring) => ring.pop_completion(),
+        Backend::Aio(_) => None
+      };
+      match completion {
+        Some((data, result)) => self.complete(data, result),
+        None => break
+      }
+    }
+  }
+
+  fn receive_aio_completions(&self) {
+    loop {
+      let mut events : [aio::Event; 16] = Default::default();
+      let count = match self.state.borrow().backend {
+        Backend::Aio(ref io_ctx) => io_ctx.get_events(0, &mut events, Some(Duration::from_secs(0))).len(),
+        Backend::Uring(_) => 0
+      };
+      for event in &events[.. count] {
+        self.complete(event.data(), event.result() as i32);
+      }
+      if count < events.len() {
+        break;
+      }
+    }
+  }
+
+  fn complete(&self, data: u64, result: i32) {
+    let _abandoned_buffer = self.state.borrow_mut().complete(data, result);
+  }
+
+  fn event_fd(&self) -> RawFd {
+    self.state.borrow().event_fd.as_raw_fd()
+  }
+}
+
+fn create_event_fd() -> io::Result<OwnedFd> {
+  let fd = to_result(unsafe {
+    libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC)
+  })?;
+  Ok(OwnedFd::from_raw_fd(fd as RawFd))
+}
+
+fn signal_event_fd(event_fd: RawFd) -> io::Result<()> {
+  let count = 1u64;
+  to_result(unsafe {
+    libc::write(event_fd, &count as *const u64 as *const libc::c_void, mem::size_of::<u64>())
+  } as libc::c_int).map(|_| ())
+}
+
+// the amount of bytes read or a negated errno, like a completion
+fn read_at(fd: RawFd, offset: usize, len: usize, dst: *mut u8) -> i32 {
+  loop {
+    let result = unsafe {
+      libc::pread64(fd, dst as *mut libc::c_void, len, offset as libc::off64_t)
+    };
+    if result >= 0 {
+      return result as i32;
+    }
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO);
+    if errno != libc::EINTR {
+      return -errno;
+    }
+  }
+}
+
+impl AsyncSource for ReadQueue {
+  fn register(&mut self, selector: &mio::Poll, token: Token) -> io::Result<()> {
+    selector.register(
+      &mio::unix::EventedFd(&self.event_fd()),
+      token.as_mio_token(),
+      mio::Ready::readable(),
+      mio::PollOpt::edge()
+    )
+  }
+
+  fn deregister(&mut self, selector: &mio::Poll) -> io::Result<()> {
+    selector.deregister(
+      &mio::unix::EventedFd(&self.event_fd())
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ReadQueue, read_data};
+  use super::super::{Reader, Directory};
+  use io::{AsyncSource, Token};
+  use mio;
+
+  const SMALL_MSG : &'static [u8] =
+    include_bytes!("../../../../../test_fixtures/aio/small.txt");
+
+  fn open_reader(queue: &ReadQueue, poll: &mio::Poll, token: Token) -> Reader {
+    let dir = Directory::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_fixtures/aio/")).unwrap();
+    let path = dir.sub_path("small.txt").unwrap();
+    let (mut reader, _) = Reader::open(&path, Some(4 .. 11)).unwrap();
+    reader.use_queue(queue.clone()).unwrap();
+    reader.register(poll, token).unwrap();
+    reader
+  }
+
+  #[test]
+  fn test_read_through_queue() {
+    let poll = mio::Poll::new().unwrap();
+    let mut queue = ReadQueue::with_io_uring(8, 1).unwrap();
+    queue.register(&poll, Token::from_mio_token(mio::Token(1))).unwrap();
+    let token = Token::from_mio_token(mio::Token(2));
+    let mut reader = open_reader(&queue, &poll, token);
+
+    assert_eq!(reader.try_queue_read().unwrap(), true);
+    let mut events = mio::Events::with_capacity(1);
+    poll.poll(&mut events, None).unwrap();
+    queue.receive_completions();
+    assert_eq!(queue.next_completion(), Some(token));
+    assert_eq!(queue.next_completion(), None);
+    assert_eq!(reader.try_get_read_bytes().unwrap(), &SMALL_MSG[4 .. 11]);
+    assert_eq!(reader.try_queue_read().unwrap(), false);
+  }
+
+  #[test]
+  fn test_stale_completion_ignored() {
+    let poll = mio::Poll::new().unwrap();
+    let mut queue = ReadQueue::with_io_uring(8, 1).unwrap();
+    queue.register(&poll, Token::from_mio_token(mio::Token(1))).unwrap();
+    let token = Token::from_mio_token(mio::Token(2));
+    let mut reader = open_reader(&queue, &poll, token);
+
+    assert_eq!(reader.try_queue_read().unwrap(), true);
+    // as if an earlier read in the same slot completed
+    queue.complete(read_data(0, 0), 0);
+    assert_eq!(queue.next_completion(), None);
+    let mut events = mio::Events::with_capacity(1);
+    poll.poll(&mut events, None).unwrap();
+    queue.receive_completions();
+    assert_eq!(queue.next_completion(), Some(token));
+    assert_eq!(reader.try_get_read_bytes().unwrap(), &SMALL_MSG[4 .. 11]);
+  }
+
+  #[test]
+  fn test_abandoned_read_returns_buffer() {
+    let poll = mio::Poll::new().unwrap();
+    let mut queue = ReadQueue::with_io_uring(8, 1).unwrap();
+    queue.register(&poll, Token::from_mio_token(mio::Token(1))).unwrap();
+    let mut reader = open_reader(&queue, &poll, Token::from_mio_token(mio::Token(2)));
+
+    assert_eq!(reader.try_queue_read().unwrap(), true);
+    assert!(queue.lease_buffer(1).is_none());
+    drop(reader);
+    let mut events = mio::Events::with_capacity(1);
+    poll.poll(&mut events, None).unwrap();
+    queue.receive_completions();
+    assert_eq!(queue.next_completion(), None);
+    assert!(queue.lease_buffer(1).is_some());
+  }
+}
@@ -10,7 +10,8 @@ use libc;
 use io::{AsyncSource, Token};
 use super::owned_fd::OwnedFd;
 use super::readrange::{ReadRangeConfig, ReadRange};
-use super::{aio, bytes_as_block_count, to_result};
+use super::queue::{ReadQueue, ReadBuffer};
+use super::{bytes_as_block_count, to_result};
 // ~400Kb, good size determined through testing ext4 and xfs
 const BUFFER_MAX_SIZE : usize = 400_000;
 
@@ -18,6 +19,7 @@ const BUFFER_MAX_SIZE : usize = 400_000;
 // that can be used to see
 // if the contents has changed
 pub struct ContentHashFields {
+  pub dev: u64,
   pub inode: usize,
   pub size: usize,
   pub mtime: i64,
@@ -26,15 +28,34 @@ pub struct ContentHashFields {
 
 enum OperationState {
   NotStarted(ReadRangeConfig),
-  Ready(ReadRange, Buffer),
-  Reading(ReadRange, aio::Operation)
+  Ready(ReadRange, ReadBuffer),
+  Queued(ReadRange, QueuedRead)
+}
+
+// a read submitted to a ReadQueue
+struct QueuedRead {
+  queue: ReadQueue,
+  slot: usize,
+  buffer: Option<ReadBuffer>
+}
+
+impl Drop for QueuedRead {
+  fn drop(&mut self) {
+    // the kernel might still write into the buffer
+    if let Some(buffer) = self.buffer.take() {
+      self.queue.abandon(self.slot, buffer);
+    }
+  }
 }
 
 pub struct Reader {
   state: Option<OperationState>,
   file_fd: OwnedFd,
-  event_fd: OwnedFd,
-  io_ctx: aio::Context
+  // opened with O_DIRECT
+  is_direct: bool,
+  // set by use_queue
+  queue: Option<ReadQueue>,
+  token: Option<Token>
 }
 
 impl Reader {
@@ -50,12 +71,7 @@ impl Reader {
     range: Option<Range<usize>>,
     buffer_size_hint: usize) -> io::Result<(Reader, ContentHashFields)>
   {
-    let file_fd = path.open(
-      libc::O_RDONLY |
-      libc::O_DIRECT |
-      libc::O_NOATIME |
-      libc::O_NONBLOCK
-    )?;
+    let (file_fd, is_direct) = open_file(path)?;
     let file_stats = stat(file_fd.as_raw_fd())?;
     
     if !is_regular_file(&file_stats) {
@@ -69,12 +85,8 @@ impl Reader {
       range, block_size as u16,
       buffer_block_capacity as u16);
 
-    let io_ctx = aio::Context::setup(1)?;
-    let event_fd = OwnedFd::from_raw_fd(to_result(unsafe {
-      libc::eventfd(0, libc::EFD_NONBLOCK)
-    } )? as RawFd);
-
     let file_content_id = ContentHashFields {
+      dev:        file_stats.st_dev as u64,
       size:       file_stats.st_size as usize,
       inode:      file_stats.st_ino as usize,
       mtime:      file_stats.st_mtime,
@@ -83,29 +95,42 @@ impl Reader {
 
     Ok((Reader {
       state: Some(OperationState::NotStarted(range_cfg)),
-      io_ctx,
       file_fd,
-      event_fd
+      is_direct,
+      queue: None,
+      token: None
     }, file_content_id))
   }
 
+  /// The queue to read through, usually the one of the server.
+  /// Only before the first read was queued.
+  pub fn use_queue(&mut self, queue: ReadQueue) -> io::Result<()> {
+    if queue.reads_buffered() && self.is_direct {
+      clear_direct_io(self.file_fd.as_raw_fd())?;
+      self.is_direct = false;
+    }
+    if let (Some(max_size), Some(&mut OperationState::NotStarted(ref mut range_cfg))) =
+      (queue.max_read_size(self.is_direct), self.state.as_mut())
+    {
+      range_cfg.limit_buffer_size(max_size);
+    }
+    self.queue = Some(queue);
+    Ok( () )
+  }
+
   /// returns: bool: whether the end hasn't been
   ///   reached yet and a new operation was queued
   pub fn try_queue_read(&mut self) -> io::Result<bool> {
     let state = self.state.take();
     let new_state = match state {
       Some(OperationState::NotStarted(range_cfg)) => {
-        let buffer = Buffer::page_sized_aligned(range_cfg.buffer_size());
         range_cfg.first_range().map(|r| {
-          let op = self.queue_read_operation(r.operation_range(), buffer)?;
-          Ok(OperationState::Reading(r, op))
+          let buffer = self.create_buffer(range_cfg.buffer_size());
+          self.queue_read(r, buffer)
         })
       },
       Some(OperationState::Ready(range, buffer)) => {
-        range.next().map(|r| {
-          let op = self.queue_read_operation(r.operation_range(), buffer)?;
-          Ok(OperationState::Reading(r, op))
-        })
+        range.next().map(|r| self.queue_read(r, buffer))
       },
       Some(s) => Some(Ok(s)),
       None => None
@@ -120,7 +145,7 @@ impl Reader {
       Some(OperationState::Ready(ref range, ref mut buffer)) => {
         Ok(&mut buffer.as_mut_slice()[range.buffer_range()])
       },
-      Some(OperationState::Reading(_, _)) => {
+      Some(OperationState::Queued(_, _)) => {
         Err(io::Error::new(io::ErrorKind::WouldBlock, "read has not finished yet"))
       },
       Some(OperationState::NotStarted(_)) => {
@@ -133,20 +158,36 @@ impl Reader {
   /// returns an error after eof or a previous error was
   /// returned from try_queue_read or try_get_read_bytes
   pub fn request_size(&self) -> io::Result<usize> {
+    self.request_range().map(|r| r.end - r.start)
+  }
+
+  /// the range of the file that was requested,
+  /// with the same errors as request_size
+  pub fn request_range(&self) -> io::Result<Range<usize>> {
     match self.state {
       Some(OperationState::Ready(ref range, _)) |
-      Some(OperationState::Reading(ref range, _)) => {
-        let r = range.total_range();
-        Ok(r.end - r.start)
+      Some(OperationState::Queued(ref range, _)) => {
+        Ok(range.total_range())
       },
       Some(OperationState::NotStarted(ref range_cfg)) => {
-        let r = range_cfg.total_range();
-        Ok(r.end - r.start)
+        Ok(range_cfg.total_range())
       }
       None => Err(io::Error::new(io::ErrorKind::Other, "previous error or eof"))
     }
   }
 
+  /// For sending the file with sendfile rather than reading it with AIO:
+  /// returns the file, switched to reading through the page cache,
+  /// and the requested range. Only before the first read was queued.
+  pub fn into_buffered_file(self) -> io::Result<(OwnedFd, Range<usize>)> {
+    let range = match self.state {
+      Some(OperationState::NotStarted(ref range_cfg)) => range_cfg.total_range(),
+      _ => return Err(io::Error::new(io::ErrorKind::Other, "reading already started"))
+    };
+    clear_direct_io(self.file_fd.as_raw_fd())?;
+    Ok((self.file_fd, range))
+  }
+
   #[cfg(test)]
   pub fn block_size(&self) -> Option<usize> {
     match self.state {
@@ -157,29 +198,43 @@ impl Reader {
     }
   }
 
-  fn queue_read_operation(&self, block_aligned_range: Range<usize>, buffer: Buffer) -> io::Result<aio::Operation> {
-    let mut read_op = aio::Operation::create_read(
-      self.file_fd.as_raw_fd(),
-      block_aligned_range,
-      buffer
-    );
-    read_op.set_event_fd(self.event_fd.as_raw_fd());
-    self.io_ctx.submit([read_op.as_iocb()].as_ref())?;
-    Ok(read_op)
+  fn create_buffer(&self, size: usize) -> ReadBuffer {
+    self.queue.as_ref()
+      .and_then(|queue| queue.lease_buffer(size))
+      .unwrap_or_else(|| ReadBuffer::Owned(Buffer::page_sized_aligned(size)))
+  }
+
+  fn queue_read(&self, range: ReadRange, mut buffer: ReadBuffer) -> io::Result<OperationState> {
+    let queue = self.queue.as_ref()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "reader has no read queue"))?;
+    let token = self.token
+      .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "reader is not registered"))?;
+    let op_range = range.operation_range();
+    let len = op_range.end - op_range.start;
+    let slot = queue.submit_read(token, self.file_fd.as_raw_fd(), self.is_direct, op_range.start, len, &mut buffer)?;
+    Ok(OperationState::Queued(range, QueuedRead {
+      queue: queue.clone(),
+      slot,
+      buffer: Some(buffer)
+    }))
   }
 
   fn finish_read(&mut self) -> io::Result<()> {
     let state = self.state.take();
     let new_state = match state {
-      Some(OperationState::Reading(range, op)) => {
-        let mut event_storage = [aio::Event::default()];
-        let events = self.io_ctx.get_events(1, event_storage.as_mut(), None);
-        let event_result = events.get(0)
-          .ok_or(io::Error::new(io::ErrorKind::Other, "no aio event"));
-        Some(event_result.and_then(|read_event| {
-          let buffer = op.into_read_result(read_event)?;
-          Ok(OperationState::Ready(range, buffer))
-        }))
+      Some(OperationState::Queued(range, mut read)) => {
+        match read.queue.take_result(read.slot) {
+          None => Some(Ok(OperationState::Queued(range, read))),
+          Some(result) if result < 0 => {
+            read.buffer = None;
+            Some(Err(io::Error::from_raw_os_error(-result)))
+          },
+          Some(len) => {
+            let mut buffer = read.buffer.take().unwrap();
+            unsafe { buffer.set_len(len as usize) };
+            Some(Ok(OperationState::Ready(range, buffer)))
+          }
+        }
       },
       Some(s) => Some(Ok(s)),
       None => None
@@ -198,23 +253,43 @@ impl Reader {
   }
 }
 
+// completions are dispatched by the read queue, which is registered once for all readers
 impl AsyncSource for Reader {
-  fn register(&mut self, selector: &mio::Poll, token: Token) -> io::Result<()> {
-    selector.register(
-      &mio::unix::EventedFd(&self.event_fd.as_raw_fd()),
-      token.as_mio_token(),
-      mio::Ready::readable(),
-      mio::PollOpt::edge()
-    )
+  fn register(&mut self, _selector: &mio::Poll, token: Token) -> io::Result<()> {
+    self.token = Some(token);
+    Ok( () )
   }
 
-  fn deregister(&mut self, selector: &mio::Poll) -> io::Result<()> {
-    selector.deregister(
-      &mio::unix::EventedFd(&self.event_fd.as_raw_fd())
-    )
+  fn deregister(&mut self, _selector: &mio::Poll) -> io::Result<()> {
+    Ok( () )
   }
 }
 
+// O_NOATIME is only allowed on files owned by the process, and
+// tmpfs and some FUSE mounts reject O_DIRECT. Returns whether
+// the file was opened with O_DIRECT.
+fn open_file(path: &dyn Path) -> io::Result<(OwnedFd, bool)> {
+  let mut flags = libc::O_RDONLY | libc::O_DIRECT | libc::O_NOATIME | libc::O_NONBLOCK;
+  loop {
+    match path.open(flags) {
+      Ok(fd) => return Ok((fd, flags & libc::O_DIRECT != 0)),
+      Err(ref err) if err.raw_os_error() == Some(libc::EPERM) && flags & libc::O_NOATIME != 0 => {
+        flags &= !libc::O_NOATIME;
+      },
+      Err(ref err) if err.raw_os_error() == Some(libc::EINVAL) && flags & libc::O_DIRECT != 0 => {
+        flags &= !libc::O_DIRECT;
+      },
+      Err(err) => return Err(err)
+    }
+  }
+}
+
+fn clear_direct_io(fd: RawFd) -> io::Result<()> {
+  let flags = to_result(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+  to_result(unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_DIRECT) })?;
+  Ok( () )
+}
+
 fn stat(fd: RawFd) -> io::Result<libc::stat64> {
   let mut file_stats : libc::stat64 = unsafe { mem::zeroed() };
   let success = unsafe {
@@ -248,6 +323,7 @@ mod tests {
   use super::Reader;
   use self::helpers::*;
   use io::AsyncSource;
+  use libc;
 
   const SMALL_MSG : &'static [u8] =
     include_bytes!("../../../../../test_fixtures/aio/small.txt");
@@ -311,11 +387,11 @@ mod tests {
       None,
       100
     ).unwrap();
-    let (mut events, poll) = setup_event_loop(&mut reader);
+    let (mut events, poll, queue) = setup_event_loop(&mut reader);
 
     let is_queued = reader.try_queue_read().unwrap();
     assert_eq!(is_queued, true);
-    poll.poll(&mut events, None).unwrap();
+    wait_for_read(&mut events, &poll, &queue);
 
     reader.try_get_read_bytes().unwrap();
 
@@ -350,11 +426,10 @@ mod tests {
       None,
       100
     ).unwrap();
-    let (mut events, mut poll) = setup_event_loop(&mut reader);
+    let (mut events, mut poll, queue) = setup_event_loop(&mut reader);
     while reader.try_queue_read().unwrap() {
-      //wait for read operation to finish
-      poll.poll(&mut events, None).unwrap();
-      
+      wait_for_read(&mut events, &poll, &queue);
+
       let first_len = reader.try_get_read_bytes().unwrap().len();
       let second_len = reader.try_get_read_bytes().unwrap().len();
       assert_eq!(first_len, second_len);
@@ -402,9 +477,73 @@ mod tests {
     assert_eq!(request_counter, 2);
   }
 
+  #[test]
+  fn test_read_without_direct_io() {
+    let path = rejecting_fixture_path("aio/small.txt\0", libc::O_DIRECT, libc::EINVAL).unwrap();
+    let (mut reader, _) = Reader::new_with_buffer_size_hint(
+      &path,
+      None,
+      100
+    ).unwrap();
+
+    let read_bytes = read_single(&mut reader);
+    assert_eq!(read_bytes, SMALL_MSG);
+  }
+
+  #[test]
+  fn test_u16_inc_read_range_without_direct_io() {
+    let path = rejecting_fixture_path("aio/u16-inc-small.bin\0", libc::O_DIRECT, libc::EINVAL).unwrap();
+    let (reader, _) = Reader::new_with_buffer_size_hint(
+      &path,
+      Some(1000 .. 8400),
+      100
+    ).unwrap();
+
+    let mut counter = 500u16;
+    read_until_end(reader, |read_bytes| {
+      for_each_u16(read_bytes, |n| {
+        assert_eq!(n, counter);
+        counter += 1;
+      });
+    });
+    assert_eq!(counter, 4200);
+  }
+
+  #[test]
+  fn test_small_reads_without_direct_io() {
+    let path = rejecting_fixture_path("aio/u16-inc-small.bin\0", libc::O_DIRECT, libc::EINVAL).unwrap();
+    let (reader, _) = Reader::open(&path, None).unwrap();
+
+    let mut counter = 0u16;
+    let mut read_count = 0;
+    read_until_end(reader, |read_bytes| {
+      assert!(read_bytes.len() <= 8 * 1024);
+      read_count += 1;
+      for_each_u16(read_bytes, |n| {
+        assert_eq!(n, counter);
+        counter += 1;
+      });
+    });
+    assert!(read_count > 1);
+    assert_eq!(counter, 4608);
+  }
+
+  #[test]
+  fn test_open_without_noatime() {
+    let path = rejecting_fixture_path("aio/small.txt\0", libc::O_NOATIME, libc::EPERM).unwrap();
+    let (mut reader, _) = Reader::new_with_buffer_size_hint(
+      &path,
+      None,
+      100
+    ).unwrap();
+
+    let read_bytes = read_single(&mut reader);
+    assert_eq!(read_bytes, SMALL_MSG);
+  }
+
   mod helpers {
     use super::super::Reader;
-    use super::super::super::{Path, to_result, OwnedFd};
+    use super::super::super::{Path, to_result, OwnedFd, ReadQueue};
     use std::env;
     use std::mem;
     use mio;
@@ -431,18 +570,49 @@ mod tests {
       Ok(FixturePath{ path })
     }
 
-    pub fn setup_event_loop(reader: &mut Reader) -> (mio::Events, mio::Poll) {
+    // like a mount that doesn't support an open flag
+    pub struct RejectingPath {
+      path: FixturePath,
+      flag: libc::c_int,
+      errno: libc::c_int
+    }
+
+    impl Path for RejectingPath {
+      fn open(&self, flags: libc::c_int) -> std::io::Result<OwnedFd> {
+        if flags & self.flag != 0 {
+          Err(std::io::Error::from_raw_os_error(self.errno))
+        }
+        else {
+          self.path.open(flags)
+        }
+      }
+    }
+
+    pub fn rejecting_fixture_path(fixture_path: &str, flag: libc::c_int, errno: libc::c_int) -> Result<RejectingPath, env::VarError> {
+      let path = self::fixture_path(fixture_path)?;
+      Ok(RejectingPath { path, flag, errno })
+    }
+
+    pub fn setup_event_loop(reader: &mut Reader) -> (mio::Events, mio::Poll, ReadQueue) {
       let mut poll = mio::Poll::new().unwrap();
-      let token = Token::from_mio_token(mio::Token(1));
-      reader.register(&mut poll, token).unwrap();
-      (mio::Events::with_capacity(1), poll)
+      let mut queue = ReadQueue::with_aio(1).unwrap();
+      queue.register(&mut poll, Token::from_mio_token(mio::Token(1))).unwrap();
+      reader.use_queue(queue.clone()).unwrap();
+      reader.register(&mut poll, Token::from_mio_token(mio::Token(2))).unwrap();
+      (mio::Events::with_capacity(1), poll, queue)
+    }
+
+    //wait for read operation to finish
+    pub fn wait_for_read(events: &mut mio::Events, poll: &mio::Poll, queue: &ReadQueue) {
+      poll.poll(events, None).unwrap();
+      queue.receive_completions();
+      assert_eq!(queue.next_completion(), Some(Token::from_mio_token(mio::Token(2))));
     }
 
     pub fn read_until_end<F: FnMut(&[u8])>(mut reader: Reader, mut callback: F) {
-      let (mut events, mut poll) = setup_event_loop(&mut reader);
+      let (mut events, mut poll, queue) = setup_event_loop(&mut reader);
       while reader.try_queue_read().unwrap() {
-        //wait for read operation to finish
-        poll.poll(&mut events, None).unwrap();
+        wait_for_read(&mut events, &poll, &queue);
         let read_bytes = reader.try_get_read_bytes().unwrap();
         callback(read_bytes);
       }
@@ -450,10 +620,10 @@ mod tests {
     }
 
     pub fn read_single(reader: &mut Reader) -> &[u8] {
-      let (mut events, poll) = setup_event_loop(reader);
+      let (mut events, poll, queue) = setup_event_loop(reader);
       let is_queued  = reader.try_queue_read().unwrap();
       assert!(is_queued);
-      poll.poll(&mut events, None).unwrap();
+      wait_for_read(&mut events, &poll, &queue);
       let read_bytes = reader.try_get_read_bytes().unwrap();
       read_bytes    
     }
@@ -33,6 +33,14 @@ impl ReadRangeConfig {
     }
   }
 
+  /// lowers the buffer capacity to `max_size`, but keeps at least one block
+  pub fn limit_buffer_size(&mut self, max_size: usize) {
+    let max_blocks = cmp::max(1, max_size / self.block_size as usize);
+    if max_blocks < self.buffer_block_capacity as usize {
+      self.buffer_block_capacity = max_blocks as u16;
+    }
+  }
+
   pub fn buffer_size(&self) -> usize {
     self.block_size as usize * self.buffer_block_capacity as usize
   } 
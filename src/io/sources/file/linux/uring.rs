@@ -0,0 +1,303 @@
+/*
+io_uring (linux 5.6+ for IORING_OP_READ): a submission and a completion
+queue shared with the kernel through mmap. Reads are put in the
+submission queue and submitted with io_uring_enter, and their results
+show up in the completion queue. The kernel signals an eventfd
+registered on the ring for every completion.
+
+Reads go through the page cache, so unlike AIO they don't need O_DIRECT
+and they don't block on submission for buffered files.
+*/
+use libc;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::os::raw::c_void;
+use std::os::unix::io::{RawFd, AsRawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use super::owned_fd::OwnedFd;
+use super::to_result;
+
+// the same on every architecture since the syscall table was unified
+const SYS_IO_URING_SETUP : libc::c_long = 425;
+const SYS_IO_URING_ENTER : libc::c_long = 426;
+const SYS_IO_URING_REGISTER : libc::c_long = 427;
+// from linux/io_uring.h
+const IORING_OFF_SQ_RING : libc::off_t = 0;
+const IORING_OFF_CQ_RING : libc::off_t = 0x8000000;
+const IORING_OFF_SQES : libc::off_t = 0x10000000;
+const IORING_OP_READ_FIXED : u8 = 4;
+const IORING_OP_READ : u8 = 22;
+const IORING_REGISTER_BUFFERS : libc::c_uint = 0;
+const IORING_REGISTER_EVENTFD : libc::c_uint = 4;
+// added in the same release as IORING_OP_READ
+const IORING_FEAT_RW_CUR_POS : u32 = 1 << 3;
+
+#[repr(C)]
+#[derive(Default)]
+struct SubmissionRingOffsets {
+  head: u32,
+  tail: u32,
+  ring_mask: u32,
+  ring_entries: u32,
+  flags: u32,
+  dropped: u32,
+  array: u32,
+  resv1: u32,
+  user_addr: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CompletionRingOffsets {
+  head: u32,
+  tail: u32,
+  ring_mask: u32,
+  ring_entries: u32,
+  overflow: u32,
+  cqes: u32,
+  flags: u32,
+  resv1: u32,
+  user_addr: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+  sq_entries: u32,
+  cq_entries: u32,
+  flags: u32,
+  sq_thread_cpu: u32,
+  sq_thread_idle: u32,
+  features: u32,
+  wq_fd: u32,
+  resv: [u32; 3],
+  sq_off: SubmissionRingOffsets,
+  cq_off: CompletionRingOffsets
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SubmissionEntry {
+  opcode: u8,
+  flags: u8,
+  ioprio: u16,
+  fd: i32,
+  off: u64,
+  addr: u64,
+  len: u32,
+  rw_flags: u32,
+  user_data: u64,
+  buf_index: u16,
+  personality: u16,
+  splice_fd_in: i32,
+  pad: [u64; 2]
+}
+
+#[repr(C)]
+struct CompletionEntry {
+  user_data: u64,
+  res: i32,
+  flags: u32
+}
+
+struct Mapping {
+  ptr: *mut c_void,
+  len: usize
+}
+
+impl Mapping {
+  fn map(fd: RawFd, offset: libc::off_t, len: usize) -> io::Result<Mapping> {
+    let ptr = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED | libc::MAP_POPULATE,
+        fd,
+        offset
+      )
+    };
+    if ptr == libc::MAP_FAILED {
+      Err(io::Error::last_os_error())
+    }
+    else {
+      Ok(Mapping {ptr, len})
+    }
+  }
+
+  fn at<T>(&self, offset: u32) -> *mut T {
+    unsafe { (self.ptr as *mut u8).offset(offset as isize) as *mut T }
+  }
+}
+
+impl Drop for Mapping {
+  fn drop(&mut self) {
+    unsafe { libc::munmap(self.ptr, self.len) };
+  }
+}
+
+/// Where to read into: any memory, or a buffer registered with `register_buffers`.
+pub enum Destination {
+  Memory(*mut u8),
+  Registered(*mut u8, u16)
+}
+
+pub struct Ring {
+  sq_head: *const AtomicU32,
+  sq_tail: *const AtomicU32,
+  sq_mask: u32,
+  sq_entries: u32,
+  sq_array: *mut u32,
+  sqes: *mut SubmissionEntry,
+  cq_head: *const AtomicU32,
+  cq_tail: *const AtomicU32,
+  cq_mask: u32,
+  cqes: *const CompletionEntry,
+  // the pointers above point into these
+  sq_ring: Mapping,
+  cq_ring: Mapping,
+  sqe_array: Mapping,
+  fd: OwnedFd
+}
+
+impl Ring {
+  pub fn setup(entries: u32) -> io::Result<Ring> {
+    let mut params = Params::default();
+    let fd = to_result(unsafe {
+      libc::syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params)
+    } as libc::c_int)?;
+    let fd = OwnedFd::from_raw_fd(fd);
+    if params.features & IORING_FEAT_RW_CUR_POS == 0 {
+      return Err(io::Error::new(io::ErrorKind::Other, "io_uring can't read files on this kernel, needs linux 5.6+"));
+    }
+
+    let sq_len = params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>();
+    let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<CompletionEntry>();
+    let sqe_len = params.sq_entries as usize * mem::size_of::<SubmissionEntry>();
+    let sq_ring = Mapping::map(fd.as_raw_fd(), IORING_OFF_SQ_RING, sq_len)?;
+    let cq_ring = Mapping::map(fd.as_raw_fd(), IORING_OFF_CQ_RING, cq_len)?;
+    let sqe_array = Mapping::map(fd.as_raw_fd(), IORING_OFF_SQES, sqe_len)?;
+
+    Ok(Ring {
+      sq_head: sq_ring.at(params.sq_off.head),
+      sq_tail: sq_ring.at(params.sq_off.tail),
+      sq_mask: unsafe { *sq_ring.at::<u32>(params.sq_off.ring_mask) },
+      sq_entries: params.sq_entries,
+      sq_array: sq_ring.at(params.sq_off.array),
+      sqes: sqe_array.at(0),
+      cq_head: cq_ring.at(params.cq_off.head),
+      cq_tail: cq_ring.at(params.cq_off.tail),
+      cq_mask: unsafe { *cq_ring.at::<u32>(params.cq_off.ring_mask) },
+      cqes: cq_ring.at(params.cq_off.cqes),
+      sq_ring,
+      cq_ring,
+      sqe_array,
+      fd
+    })
+  }
+
+  /// how many reads can be submitted before their completions are taken
+  pub fn entries(&self) -> u32 {
+    self.sq_entries
+  }
+
+  /// the eventfd is signaled every time a read completes
+  pub fn register_event_fd(&self, event_fd: RawFd) -> io::Result<()> {
+    self.register(IORING_REGISTER_EVENTFD, &event_fd as *const RawFd as *const c_void, 1)
+  }
+
+  /// pins the buffers, so the kernel doesn't have to map them for every read
+  pub fn register_buffers(&self, buffers: &[libc::iovec]) -> io::Result<()> {
+    self.register(IORING_REGISTER_BUFFERS, buffers.as_ptr() as *const c_void, buffers.len() as libc::c_uint)
+  }
+
+  /// The memory of the destination must stay valid
+  /// until the completion with `user_data` was taken.
+  pub unsafe fn submit_read(&mut self, fd: RawFd, offset: usize, len: usize, dst: Destination, user_data: u64) -> io::Result<()> {
+    let tail = (*self.sq_tail).load(Ordering::Relaxed);
+    let head = (*self.sq_head).load(Ordering::Acquire);
+    if tail.wrapping_sub(head) == self.sq_entries {
+      return Err(io::Error::new(io::ErrorKind::WouldBlock, "submission queue is full"));
+    }
+    let (opcode, addr, buf_index) = match dst {
+      Destination::Memory(ptr) => (IORING_OP_READ, ptr, 0),
+      Destination::Registered(ptr, index) => (IORING_OP_READ_FIXED, ptr, index)
+    };
+    let index = tail & self.sq_mask;
+    *self.sqes.offset(index as isize) = SubmissionEntry {
+      opcode,
+      fd,
+      off: offset as u64,
+      addr: addr as u64,
+      len: len as u32,
+      user_data,
+      buf_index,
+      .. Default::default()
+    };
+    *self.sq_array.offset(index as isize) = index;
+    (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+
+    match self.enter(1) {
+      Ok(_) => Ok( () ),
+      Err(err) => {
+        // without a kernel polling thread, entries are only taken from
+        // the queue in io_uring_enter, so one that wasn't taken can be taken back
+        if (*self.sq_head).load(Ordering::Acquire) == tail {
+          (*self.sq_tail).store(tail, Ordering::Release);
+          Err(err)
+        }
+        else {
+          Ok( () )
+        }
+      }
+    }
+  }
+
+  /// the user_data and result of the next completed read,
+  /// the amount of bytes read or a negated errno.
+  pub fn pop_completion(&mut self) -> Option<(u64, i32)> {
+    unsafe {
+      let head = (*self.cq_head).load(Ordering::Relaxed);
+      let tail = (*self.cq_tail).load(Ordering::Acquire);
+      if head == tail {
+        return None;
+      }
+      let cqe = &*self.cqes.offset((head & self.cq_mask) as isize);
+      let completion = (cqe.user_data, cqe.res);
+      (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+      Some(completion)
+    }
+  }
+
+  fn enter(&self, to_submit: u32) -> io::Result<usize> {
+    loop {
+      let result = unsafe {
+        libc::syscall(SYS_IO_URING_ENTER, self.fd.as_raw_fd(), to_submit, 0, 0, ptr::null::<c_void>(), 0)
+      };
+      match to_result(result as libc::c_int) {
+        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+        result => return result.map(|n| n as usize)
+      }
+    }
+  }
+
+  fn register(&self, opcode: libc::c_uint, arg: *const c_void, count: libc::c_uint) -> io::Result<()> {
+    to_result(unsafe {
+      libc::syscall(SYS_IO_URING_REGISTER, self.fd.as_raw_fd(), opcode, arg, count)
+    } as libc::c_int).map(|_| ())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem;
+  use super::*;
+
+  #[test]
+  fn test_structs_match_kernel_abi() {
+    assert_eq!(mem::size_of::<Params>(), 120);
+    assert_eq!(mem::size_of::<SubmissionEntry>(), 64);
+    assert_eq!(mem::size_of::<CompletionEntry>(), 16);
+  }
+}
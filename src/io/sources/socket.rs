@@ -1,6 +1,7 @@
 use mio;
 use std;
-use io::{AsyncSource, Token, ReadSizeHint};
+use std::os::unix::io::{RawFd, AsRawFd};
+use io::{AsyncSource, Token, ReadSizeHint, SocketFd};
 
 impl AsyncSource for mio::net::TcpStream {
   fn register(&mut self, selector: &mio::Poll, token: Token) -> std::io::Result<()> {
@@ -17,3 +18,9 @@ impl AsyncSource for mio::net::TcpStream {
 }
 
 impl ReadSizeHint for mio::net::TcpStream {}
+
+impl SocketFd for mio::net::TcpStream {
+  fn socket_fd(&self) -> Option<RawFd> {
+    Some(self.as_raw_fd())
+  }
+}
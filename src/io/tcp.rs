@@ -0,0 +1,273 @@
+use libc;
+use std::io;
+use std::mem;
+use std::os::raw::c_void;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/// The kinds of responses the socket can be tuned for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResponseKind {
+  InBuffer,
+  File,
+  /// event streams and upgraded protocols like websockets
+  Stream,
+  /// parked until a message is published on a channel
+  LongPoll
+}
+
+impl ResponseKind {
+  /// whether the response is sent in one go, and can be held back
+  /// until the end to fill up the segments
+  fn is_bulk(self) -> bool {
+    self == ResponseKind::InBuffer || self == ResponseKind::File
+  }
+
+  fn is_long_lived(self) -> bool {
+    self == ResponseKind::Stream || self == ResponseKind::LongPoll
+  }
+}
+
+/// Probes sent on an idle connection to notice the peer went away.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Keepalive {
+  /// how long the connection is idle before sending the first probe
+  pub idle: Duration,
+  pub interval: Duration,
+  /// how many unanswered probes before the connection is dropped
+  pub count: u32
+}
+
+/// How the sockets of the connections are tuned.
+#[derive(Clone, Copy)]
+pub struct TcpPolicy {
+  nodelay: bool,
+  cork: bool,
+  send_buffer_size: Option<usize>,
+  keepalive: Option<Keepalive>
+}
+
+impl TcpPolicy {
+  /// Sends small writes right away (`TCP_NODELAY`), but corks responses
+  /// that are sent in one go, so the headers and the start of the body
+  /// go out in the same segment. Leaves the send buffer size
+  /// to the kernel and doesn't send keepalive probes.
+  pub fn new() -> TcpPolicy {
+    TcpPolicy {
+      nodelay: true,
+      cork: true,
+      send_buffer_size: None,
+      keepalive: None
+    }
+  }
+
+  pub fn nodelay(mut self, nodelay: bool) -> TcpPolicy {
+    self.nodelay = nodelay;
+    self
+  }
+
+  /// Holds back partial segments (`TCP_CORK`) while writing
+  /// a buffered or file response, until it is complete.
+  pub fn cork(mut self, cork: bool) -> TcpPolicy {
+    self.cork = cork;
+    self
+  }
+
+  /// `SO_SNDBUF` of the connections, e.g. larger when serving mostly big files.
+  /// Setting it turns off the kernel's tuning of the send buffer for good,
+  /// so it is set once when a connection is accepted and stays for all of its responses.
+  pub fn send_buffer_size(mut self, size: usize) -> TcpPolicy {
+    self.send_buffer_size = Some(size);
+    self
+  }
+
+  /// Keepalive probes for connections that are parked or streaming,
+  /// as they can go a long time without sending anything.
+  pub fn keepalive(mut self, keepalive: Keepalive) -> TcpPolicy {
+    self.keepalive = Some(keepalive);
+    self
+  }
+}
+
+/// What `start_response` changed on the socket,
+/// for `uncork` to undo once the response is sent.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ResponseTuning {
+  is_corked: bool,
+  is_keepalive: bool
+}
+
+impl ResponseTuning {
+  pub fn is_corked(&self) -> bool {
+    self.is_corked
+  }
+}
+
+/// The tcp socket of a connection underneath any TLS,
+/// to tune it according to the policy of the server.
+#[derive(Clone, Copy)]
+pub struct TcpSocket<'a> {
+  fd: RawFd,
+  policy: &'a TcpPolicy
+}
+
+impl<'a> TcpSocket<'a> {
+  pub fn new(fd: RawFd, policy: &'a TcpPolicy) -> TcpSocket<'a> {
+    TcpSocket { fd, policy }
+  }
+
+  /// for a newly accepted connection
+  pub fn configure(&self) -> io::Result<()> {
+    set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_NODELAY, self.policy.nodelay as libc::c_int)?;
+    if let Some(size) = self.policy.send_buffer_size {
+      set_option(self.fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size as libc::c_int)?;
+    }
+    Ok( () )
+  }
+
+  /// Before writing a response, returns what was changed on the socket.
+  pub fn start_response(&self, kind: ResponseKind) -> io::Result<ResponseTuning> {
+    let mut tuning = ResponseTuning::default();
+    if let (true, Some(keepalive)) = (kind.is_long_lived(), self.policy.keepalive) {
+      self.set_keepalive(keepalive)?;
+      tuning.is_keepalive = true;
+    }
+    if self.policy.cork && kind.is_bulk() {
+      set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_CORK, 1)?;
+      tuning.is_corked = true;
+    }
+    Ok(tuning)
+  }
+
+  /// At the end of a response, sends out what was held back while corked,
+  /// and turns keepalive back off for the next response on the connection.
+  pub fn uncork(&self, tuning: ResponseTuning) -> io::Result<()> {
+    if tuning.is_corked {
+      set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_CORK, 0)?;
+    }
+    if tuning.is_keepalive {
+      set_option(self.fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 0)?;
+    }
+    Ok( () )
+  }
+
+  fn set_keepalive(&self, keepalive: Keepalive) -> io::Result<()> {
+    set_option(self.fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, seconds(keepalive.idle))?;
+    set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, seconds(keepalive.interval))?;
+    set_option(self.fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, keepalive.count as libc::c_int)
+  }
+}
+
+// the kernel counts in whole seconds, and at least one
+fn seconds(duration: Duration) -> libc::c_int {
+  duration.as_secs().max(1).min(libc::c_int::max_value() as u64) as libc::c_int
+}
+
+fn get_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+  let mut value : libc::c_int = 0;
+  let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+  let result = unsafe {
+    libc::getsockopt(
+      fd,
+      level,
+      name,
+      &mut value as *mut libc::c_int as *mut c_void,
+      &mut len as *mut libc::socklen_t)
+  };
+  if result == -1 {
+    Err(io::Error::last_os_error())
+  }
+  else {
+    Ok(value)
+  }
+}
+
+fn set_option(fd: RawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+  let result = unsafe {
+    libc::setsockopt(
+      fd,
+      level,
+      name,
+      &value as *const libc::c_int as *const c_void,
+      mem::size_of::<libc::c_int>() as libc::socklen_t)
+  };
+  if result == -1 {
+    Err(io::Error::last_os_error())
+  }
+  else {
+    Ok( () )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem;
+  use std::net::{TcpListener, TcpStream};
+  use std::os::unix::io::AsRawFd;
+  use std::time::Duration;
+  use libc;
+  use super::{TcpPolicy, TcpSocket, ResponseKind, Keepalive};
+
+  fn get_option(stream: &TcpStream, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+    let mut value : libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+      libc::getsockopt(stream.as_raw_fd(), level, name,
+        &mut value as *mut libc::c_int as *mut libc::c_void, &mut len as *mut libc::socklen_t)
+    };
+    assert_eq!(result, 0);
+    value
+  }
+
+  fn connected_stream() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    TcpStream::connect(listener.local_addr().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_cork_only_bulk_responses() {
+    let stream = connected_stream();
+    let policy = TcpPolicy::new();
+    let socket = TcpSocket::new(stream.as_raw_fd(), &policy);
+    assert_eq!(socket.start_response(ResponseKind::Stream).unwrap().is_corked(), false);
+    assert_eq!(get_option(&stream, libc::IPPROTO_TCP, libc::TCP_CORK), 0);
+    let tuning = socket.start_response(ResponseKind::File).unwrap();
+    assert_eq!(tuning.is_corked(), true);
+    assert_eq!(get_option(&stream, libc::IPPROTO_TCP, libc::TCP_CORK), 1);
+    socket.uncork(tuning).unwrap();
+    assert_eq!(get_option(&stream, libc::IPPROTO_TCP, libc::TCP_CORK), 0);
+  }
+
+  #[test]
+  fn test_keepalive_for_long_poll() {
+    let stream = connected_stream();
+    let policy = TcpPolicy::new().keepalive(Keepalive {
+      idle: Duration::from_secs(30),
+      interval: Duration::from_secs(10),
+      count: 3
+    });
+    let socket = TcpSocket::new(stream.as_raw_fd(), &policy);
+    socket.start_response(ResponseKind::InBuffer).unwrap();
+    assert_eq!(get_option(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+    let tuning = socket.start_response(ResponseKind::LongPoll).unwrap();
+    assert_eq!(get_option(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+    assert_eq!(get_option(&stream, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 30);
+    assert_eq!(get_option(&stream, libc::IPPROTO_TCP, libc::TCP_KEEPCNT), 3);
+    socket.uncork(tuning).unwrap();
+    assert_eq!(get_option(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+  }
+
+  #[test]
+  fn test_send_buffer_size_for_connection() {
+    let stream = connected_stream();
+    let policy = TcpPolicy::new().send_buffer_size(64 * 1024);
+    let socket = TcpSocket::new(stream.as_raw_fd(), &policy);
+    socket.configure().unwrap();
+    // the kernel doubles the size it is given, for its bookkeeping
+    assert_eq!(get_option(&stream, libc::SOL_SOCKET, libc::SO_SNDBUF), 128 * 1024);
+    let tuning = socket.start_response(ResponseKind::File).unwrap();
+    socket.uncork(tuning).unwrap();
+    assert_eq!(get_option(&stream, libc::SOL_SOCKET, libc::SO_SNDBUF), 128 * 1024);
+  }
+}
@@ -60,7 +60,7 @@ impl ConnectionId {
   }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Token {
   token: usize
 }
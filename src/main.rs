@@ -15,36 +15,118 @@ mod test_helpers;
 extern crate mio;
 extern crate libc;
 
-use query_connection::QueryConnection;
 use server::Server;
 use io::sources::file::Directory;
-use http::request_handler::Handler;
+use std::path::Path;
+use std::time::Duration;
 
 pub const GIT_HASH : &'static str = env!("GIT_HASH");
 
+const CREDENTIALS_PATH : &'static str = "./conf/passwd";
+const CLIENT_CA_PATH : &'static str = "./conf/tls/client_ca";
+// kept up to date with `openssl ocsp`, see tls::HandlerFactory::with_ocsp_response_file
+const OCSP_RESPONSE_PATH : &'static str = "./conf/tls/ocsp.der";
+// room for about 2600 TLS sessions to resume
+const TLS_SESSION_CACHE_SIZE : usize = 256 * 1024;
+// file reads in flight over all connections, one for every http2 stream
+const READ_QUEUE_ENTRIES : u32 = (server::CONNECTION_COUNT * http::http2::MAX_CONCURRENT_STREAMS) as u32;
+// 400Kb each, stays under the default RLIMIT_MEMLOCK of 8Mb
+const REGISTERED_READ_BUFFERS : u16 = 16;
+// 4Mb for the small files of the site, like icons, css and scripts
+const FILE_CACHE_CAPACITY : usize = 256;
+const FILE_CACHE_MAX_FILE_SIZE : usize = 16 * 1024;
+
 fn main() {
+  let args : Vec<String> = std::env::args().collect();
+  if args.len() > 1 {
+    std::process::exit(run_command(&args[1 ..]));
+  }
+
   #[cfg(debug_assertions)]
   set_dump_core_on_panic();
 
+  let credentials = app::CredentialStore::open(Path::new(CREDENTIALS_PATH))
+    .expect("couldn't read ./conf/passwd, add a user with: wwwee adduser <user>");
   let tls_handler_factory = create_tls_handler_factory();
   let www_root = Directory::open("./www/").expect("./www/ dir does not exist");
+  let file_cache = app::FileCache::new(FILE_CACHE_CAPACITY, FILE_CACHE_MAX_FILE_SIZE);
+
+  let session_store = http::session::MemoryStore::new(64, 3600).expect("couldn't create session store");
+  // bursts of 100 requests, then 20 per second
+  let rate_limiter = app::RateLimiter::new(1024, 100, Duration::from_millis(50));
+  // 5 wrong passwords, then one more attempt every minute
+  let login_failures = app::RateLimiter::new(1024, 5, Duration::from_secs(60));
 
   let addr = "0.0.0.0:4343".parse().unwrap();
+  let request_handler_creator = || {
+    let dir_handler = app::StaticDirectoryHandler::new(&www_root, "index.html")
+      .with_cache(&file_cache)
+      .with_autoindex();
+    let auth_handler = app::BasicAuthHandler::new(dir_handler, "Holiday Pictures", &credentials);
+    let session_handler = app::SessionHandler::new(auth_handler, &session_store, "session");
+    let lockout_handler = app::LoginLockoutHandler::new(session_handler, &login_failures);
+    let rate_limit_handler = app::RateLimitHandler::new(lockout_handler, &rate_limiter);
+    app::Logger::new(rate_limit_handler)
+  };
   let handler_creator = || {
-    let dir_handler = app::StaticDirectoryHandler::new(&www_root, "index.html");
-    let auth_handler = app::BasicAuthHandler::new(dir_handler, "Holiday Pictures", | credentials | {
-      credentials.user == "foo" && credentials.password == "bar"
-    });
-    let logger = app::Logger::new(auth_handler);
-    let responder = QueryConnection::new(Handler::new(logger));
-    let tls_handler = tls_handler_factory.create_handler(responder);
-    return tls_handler;
+    tls_handler_factory.create_handler(http::ProtocolHandler::new(&request_handler_creator))
   };
-  let mut server = Server::new(addr, handler_creator).unwrap();
+  let mut server = Server::new(addr, handler_creator).unwrap()
+    .with_io_uring(READ_QUEUE_ENTRIES, REGISTERED_READ_BUFFERS);
   println!("server version {} running ...", GIT_HASH);
   server.start().unwrap();
 }
 
+fn run_command(args: &[String]) -> i32 {
+  match (args[0].as_str(), args.len()) {
+    ("adduser", 2) => {
+      let user = &args[1];
+      eprint!("Password for {}: ", user);
+      let password = match read_password() {
+        Ok(password) => password,
+        Err(err) => {
+          eprintln!("couldn't read password: {}", err);
+          return 1;
+        }
+      };
+      let password = password.trim_end_matches(|c| c == '\r' || c == '\n');
+      match app::add_user(Path::new(CREDENTIALS_PATH), user, password) {
+        Ok(()) => 0,
+        Err(err) => {
+          eprintln!("couldn't add user: {}", err);
+          1
+        }
+      }
+    },
+    _ => {
+      eprintln!("usage: wwwee [adduser <user>]");
+      1
+    }
+  }
+}
+
+// reads a line from stdin without echoing it, when stdin is a terminal
+fn read_password() -> std::io::Result<String> {
+  let fd = libc::STDIN_FILENO;
+  let mut termios : libc::termios = unsafe { std::mem::zeroed() };
+  let is_terminal = unsafe { libc::tcgetattr(fd, &mut termios) } == 0;
+  if is_terminal {
+    let mut no_echo = termios;
+    no_echo.c_lflag &= !libc::ECHO;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &no_echo) } != 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+  }
+  let mut password = String::new();
+  let result = std::io::stdin().read_line(&mut password);
+  if is_terminal {
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+    // the newline wasn't echoed either
+    eprintln!();
+  }
+  result.map(|_| password)
+}
+
 #[cfg(debug_assertions)]
 fn set_dump_core_on_panic() {
   let prev_hook = std::panic::take_hook();
@@ -60,5 +142,39 @@ fn set_dump_core_on_panic() {
 fn create_tls_handler_factory() -> tls::HandlerFactory<'static> {
   let x509_cert_bytes = include_bytes!("../conf/tls/cert.der");
   let private_key_bytes = include_bytes!("../conf/tls/private_key.der");
-  tls::HandlerFactory::new(x509_cert_bytes, private_key_bytes).expect("couldn't create tls handler factory")
+  let mut factory = tls::HandlerFactory::new(x509_cert_bytes, private_key_bytes)
+    .expect("couldn't create tls handler factory")
+    .with_session_cache(TLS_SESSION_CACHE_SIZE)
+    .with_policy(tls::Policy::new()
+      .versions(tls::TLS11, tls::TLS12)
+      // falls back to encrypting in userspace without the tls kernel module
+      .kernel_tls(true));
+  if Path::new(OCSP_RESPONSE_PATH).exists() {
+    factory = factory.with_ocsp_response_file(Path::new(OCSP_RESPONSE_PATH))
+      .expect("couldn't load OCSP response");
+  }
+  let ca_certificates = read_client_ca_certificates(Path::new(CLIENT_CA_PATH))
+    .expect("couldn't read client CA certificates");
+  if ca_certificates.is_empty() {
+    return factory;
+  }
+  let ca_certificates : Vec<&[u8]> = ca_certificates.iter().map(|c| c.as_slice()).collect();
+  factory.with_client_auth(&ca_certificates).expect("couldn't decode client CA certificates")
+}
+
+/// the DER encoded certificates in the directory, if it exists
+fn read_client_ca_certificates(dir: &Path) -> std::io::Result<Vec<Vec<u8>>> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(err)
+  };
+  let mut certificates = Vec::new();
+  for entry in entries {
+    let path = entry?.path();
+    if path.extension().map(|ext| ext == "der").unwrap_or(false) {
+      certificates.push(std::fs::read(&path)?);
+    }
+  }
+  Ok(certificates)
 }
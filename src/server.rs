@@ -3,10 +3,19 @@ use mio;
 use std::net::SocketAddr;
 use std;
 use std::ops::DerefMut;
+use std::os::unix::io::AsRawFd;
+use std::time::Instant;
 use io;
 use io::AsyncSource;
+use http;
+use io::sources::file::ReadQueue;
 pub const CONNECTION_COUNT : usize = 100;
 const SERVER_TOKEN : mio::Token = mio::Token(0);
+const BROKER_TOKEN : mio::Token = mio::Token(1);
+const READ_QUEUE_TOKEN : mio::Token = mio::Token(2);
+// file reads in flight over all connections, when reading with AIO:
+// one for every response, of which an http2 connection has one per stream
+const AIO_OPERATION_COUNT : u32 = (CONNECTION_COUNT * http::http2::MAX_CONCURRENT_STREAMS) as u32;
 
 fn initialize_connections<T>() -> [Option<Connection<T>>; CONNECTION_COUNT] {
   let mut connections : [Option<Connection<T>>; CONNECTION_COUNT] = 
@@ -21,6 +30,7 @@ fn initialize_connections<T>() -> [Option<Connection<T>>; CONNECTION_COUNT] {
 struct Connection<T> {
   pub handler: T,
   pub socket: io::Registered<TcpStream>,
+  pub info: io::ConnectionInfo,
   pub token_source: io::AsyncTokenSource
 }
 
@@ -34,6 +44,9 @@ pub struct Server<T, F> {
   connections: [Option<Connection<T>>; CONNECTION_COUNT],
   poll: mio::Poll,
   server_socket: TcpListener,
+  broker: io::Broker,
+  tcp_policy: io::TcpPolicy,
+  read_queue: ReadQueue,
   handler_creator: F,
 }
 
@@ -49,6 +62,10 @@ impl<T, F> Server<T, F>
     // Start listening for incoming connections
     poll.register(&server_socket, SERVER_TOKEN, mio::Ready::readable() | mio::Ready::writable(),
       mio::PollOpt::edge())?;
+    let mut broker = io::Broker::new();
+    broker.register(&poll, io::Token::from_mio_token(BROKER_TOKEN))?;
+    let mut read_queue = ReadQueue::with_aio(AIO_OPERATION_COUNT)?;
+    read_queue.register(&poll, io::Token::from_mio_token(READ_QUEUE_TOKEN))?;
 
     let connections = initialize_connections();
 
@@ -56,16 +73,52 @@ impl<T, F> Server<T, F>
       connections,
       poll,
       server_socket,
+      broker,
+      tcp_policy: io::TcpPolicy::new(),
+      read_queue,
       handler_creator
     })
   }
 
+  /// How the sockets of the connections are tuned,
+  /// `io::TcpPolicy::new()` if not set.
+  pub fn with_tcp_policy(mut self, tcp_policy: io::TcpPolicy) -> Server<T, F> {
+    self.tcp_policy = tcp_policy;
+    self
+  }
+
+  /// Reads files through a single io_uring for all connections,
+  /// with `registered_buffer_count` buffers registered on it.
+  /// Keeps reading files with AIO when io_uring is not available.
+  pub fn with_io_uring(mut self, entries: u32, registered_buffer_count: u16) -> Server<T, F> {
+    let read_queue = ReadQueue::with_io_uring(entries, registered_buffer_count)
+      .and_then(|mut read_queue| {
+        read_queue.register(&self.poll, io::Token::from_mio_token(READ_QUEUE_TOKEN))?;
+        Ok(read_queue)
+      });
+    match read_queue {
+      // closing the eventfd of the AIO queue removes it from the selector
+      Ok(read_queue) => self.read_queue = read_queue,
+      Err(err) => println!("io_uring not available, reading files with AIO: {:?}", err)
+    }
+    self
+  }
+
+  /// To publish messages to connections parked on a channel
+  /// from outside of the event loop, e.g. from a worker thread.
+  pub fn publisher(&self) -> io::Publisher {
+    self.broker.publisher()
+  }
+
   pub fn start(&mut self) -> std::io::Result<()> {
     let mut events = mio::Events::with_capacity(self.connections.len());
     loop {
-      self.poll.poll(&mut events, None)?;
+      let timeout = self.broker.next_timeout(Instant::now());
+      self.poll.poll(&mut events, timeout)?;
 
       self.process_events(&events);
+      self.broker.expire(Instant::now());
+      self.dispatch_deliveries();
     }
   }
 
@@ -74,25 +127,64 @@ impl<T, F> Server<T, F>
       if event.token() == SERVER_TOKEN {
         self.accept_connections();
       }
+      else if event.token() == BROKER_TOKEN {
+        self.broker.receive_remote();
+      }
+      else if event.token() == READ_QUEUE_TOKEN {
+        self.dispatch_file_reads();
+      }
       else {
-        if let Some(conn_idx) = self.handle_event(&event) {
-          let conn_opt = self.connections[conn_idx].take();
-          if let Some(mut conn) = conn_opt {
-            println!("closing connection {:?}", conn_idx + 1);
-            if let Err(err) = conn.deregister(&mut self.poll) {
-              println!("could not deregister socket from epoll: {:?}", err);
-            }
-          }
+        let r = event.readiness();
+        let event_kind = io::EventKind::new()
+          .with_readable(r.is_readable())
+          .with_writable(r.is_writable());
+        let token = io::Token::from_mio_token(event.token());
+        if let Some(conn_idx) = self.handle_event(token, event_kind) {
+          self.close_connection(conn_idx);
         }
       }
     }
   }
 
+  // wake up the connections that received a message or timed out
+  fn dispatch_deliveries(&mut self) {
+    while let Some((token, event_kind)) = self.broker.next_delivery() {
+      let closed_conn_idx = self.handle_event(token, event_kind);
+      self.broker.finish_delivery(token);
+      if let Some(conn_idx) = closed_conn_idx {
+        self.close_connection(conn_idx);
+      }
+    }
+  }
+
+  // wake up the readers of the file reads that completed
+  fn dispatch_file_reads(&mut self) {
+    let read_queue = self.read_queue.clone();
+    read_queue.receive_completions();
+    while let Some(token) = read_queue.next_completion() {
+      let event_kind = io::EventKind::new().with_readable(true);
+      if let Some(conn_idx) = self.handle_event(token, event_kind) {
+        self.close_connection(conn_idx);
+      }
+    }
+  }
+
+  fn close_connection(&mut self, conn_idx: usize) {
+    let conn_opt = self.connections[conn_idx].take();
+    if let Some(mut conn) = conn_opt {
+      println!("closing connection {:?}", conn_idx + 1);
+      self.broker.unsubscribe_connection(io::ConnectionId::from_index(conn_idx));
+      if let Err(err) = conn.deregister(&mut self.poll) {
+        println!("could not deregister socket from epoll: {:?}", err);
+      }
+    }
+  }
+
   fn accept_connections(&mut self) {
     let mut would_block = false;
     while !would_block {
       match self.server_socket.accept() {
-        Ok((socket, _)) => self.register_connection(socket),
+        Ok((socket, addr)) => self.register_connection(socket, addr),
         Err(err) => {
           match err.kind() {
             std::io::ErrorKind::WouldBlock => would_block = true,
@@ -107,23 +199,23 @@ impl<T, F> Server<T, F>
     }
   }
 
-  fn handle_event(&mut self, event: &mio::Event) -> Option<usize> {
-    let token = io::Token::from_mio_token(event.token());
+  fn handle_event(&mut self, token: io::Token, event_kind: io::EventKind) -> Option<usize> {
     let conn_id = token.connection_id();
     let conn_idx = conn_id.as_index();
 
     if let Some(ref mut connection) = self.connections[conn_idx] {
 
+      let tcp = io::TcpSocket::new(connection.socket.as_raw_fd(), &self.tcp_policy);
       let mut ctx = io::Context::new(
         &self.poll,
         conn_id,
         &mut connection.token_source,
-        &mut connection.socket);
+        &mut self.broker,
+        &mut connection.socket,
+        &connection.info,
+        tcp,
+        &self.read_queue);
 
-      let r = event.readiness();
-      let event_kind = io::EventKind::new()
-        .with_readable(r.is_readable())
-        .with_writable(r.is_writable());
       let io_event = io::Event::new(token.async_token(), event_kind);
       if let Some(_) = connection.handler.handle_event(&io_event, &mut ctx) {
         return Some(conn_idx);
@@ -132,16 +224,15 @@ impl<T, F> Server<T, F>
     None
   }
 
-  fn register_connection(&mut self, socket: TcpStream) {
-    let addr = socket.peer_addr();
+  fn register_connection(&mut self, socket: TcpStream, addr: SocketAddr) {
     let free_idx = self.connections
       .iter()
       .position(|conn| conn.is_none());
 
-    if let (Some(conn_idx), Ok(addr)) = (free_idx, addr)
+    if let Some(conn_idx) = free_idx
     {
       let conn_id = io::ConnectionId::from_index(conn_idx);
-      match self.create_and_register_connection(conn_id, socket) {
+      match self.create_and_register_connection(conn_id, socket, addr) {
         Ok(connection) => {
           self.connections[conn_idx] = Some(connection);
           println!("{}/{:?} connected", addr, conn_id);
@@ -152,17 +243,19 @@ impl<T, F> Server<T, F>
       }
     }
     else {
-      println!("too many connections or could not get peer addr, dropping this one");
+      println!("too many connections, dropping {}", addr);
     }
   }
 
-  fn create_and_register_connection(&self, conn_id: io::ConnectionId, socket: TcpStream) -> std::io::Result<Connection<T>> {
+  fn create_and_register_connection(&self, conn_id: io::ConnectionId, socket: TcpStream, peer_addr: SocketAddr) -> std::io::Result<Connection<T>> {
     let socket_async_token = io::AsyncToken::default();
     let token = io::Token::from_parts(conn_id, socket_async_token);
+    io::TcpSocket::new(socket.as_raw_fd(), &self.tcp_policy).configure()?;
     let registered_socket = io::Registered::register(socket, token, &self.poll)?;
     let handler = (self.handler_creator)();
     Ok(Connection {
       socket: registered_socket,
+      info: io::ConnectionInfo::new(peer_addr),
       handler,
       token_source: io::AsyncTokenSource::starting_from(socket_async_token)
     })
@@ -1,7 +1,11 @@
 use io;
+use std::os::raw::c_char;
+use std::rc::Rc;
 use ::buffer::PageBuffer;
 use super::wrapper::*;
 use super::socket::SocketWrapper;
+use super::policy::{Policy, RecordSizer};
+use super::ktls::KernelTls;
 
 /*
 there are 4 buffers in the context:
@@ -15,15 +19,29 @@ so:
   sendapp buffer is for data to be encrypted and sent over the socket 
 */
 
+// the protocols that can be offered with ALPN, NUL terminated for BearSSL
+const ALPN_PROTOCOL_NAMES : [(&'static str, &'static [u8]); 2] = [("h2", b"h2\0"), ("http/1.1", b"http/1.1\0")];
+
 pub struct Context<'a> {
   buffer: PageBuffer,
+  // boxed so the engine can keep pointing to it when the context moves
+  protocol_names: Box<[*const c_char]>,
+  protocols: &'static [&'static str],
+  client_validator: Option<Box<x509::ClientValidator<'a>>>,
+  record_sizer: RecordSizer,
+  kernel_tls: KernelTls,
+  // the engine points to it
+  ocsp_response: Option<Rc<[u8]>>,
   server_context: server::Context<'a>,
 }
 
 impl<'a> Context<'a> {
   pub fn from_certificate(
     certificate_chain: &'a [x509::Certificate<'a>],
-    key: &'a secret::Key)
+    key: &'a secret::Key,
+    client_trust_anchors: Option<&'a x509::TrustAnchors>,
+    session_cache: Option<&'a session_cache::SessionCache>,
+    policy: &Policy)
   -> Result<Context<'a>>
   {
     let mut server_context = match key {
@@ -38,19 +56,217 @@ impl<'a> Context<'a> {
     // probably yes, we plan to drain the recvapp buffer as soon as we can
     let mut buffer = PageBuffer::new(ffi::BR_SSL_BUFSIZE_BIDI as usize);
     server_context.engine_mut().set_buffer(buffer.as_mut_slice(), true);
+    let protocols = policy.alpn_protocols();
+    let protocol_names : Box<[*const c_char]> = protocols.iter()
+      .filter_map(|protocol| ALPN_PROTOCOL_NAMES.iter().find(|&&(name, _)| name == *protocol))
+      .map(|&(_, name)| name.as_ptr() as *const c_char)
+      .collect::<Vec<_>>()
+      .into_boxed_slice();
+    unsafe {
+      server_context.engine_mut().set_protocol_names(&protocol_names[..])
+    };
+    policy.apply(server_context.engine_mut());
+    // a resumed session skips the client certificate, so there would be
+    // no common name to hand to the request handlers. Only cache sessions
+    // when clients aren't asked for a certificate.
+    if let (Some(session_cache), None) = (session_cache, client_trust_anchors) {
+      unsafe {
+        server_context.set_cache(session_cache.as_class_ptr())
+      };
+    }
+    // clients without a (valid) certificate can still connect,
+    // it's up to the request handlers whether they need one
+    let client_validator = client_trust_anchors.map(|trust_anchors| {
+      let validator = x509::ClientValidator::new(trust_anchors);
+      unsafe {
+        server_context.set_trust_anchor_names(trust_anchors.as_slice());
+        server_context.engine_mut().set_x509(validator.as_class_ptr());
+      }
+      server_context.engine_mut().set_default_signature_verifiers();
+      server_context.engine_mut().add_flags(ffi::BR_OPT_TOLERATE_NO_CLIENT_AUTH);
+      validator
+    });
     server_context.reset()?;
     server_context.engine().last_error().map(|_| {
       Context {
         buffer,
+        protocol_names,
+        protocols,
+        client_validator,
+        record_sizer: policy.record_sizer(),
+        kernel_tls: policy.kernel_tls_mode(),
+        ocsp_response: None,
         server_context
       }
     })
   }
 
+  /// Sends the OCSP response in the handshake, if the client asks for it.
+  pub fn staple_ocsp_response(&mut self, response: Rc<[u8]>) {
+    unsafe {
+      self.server_context.set_ocsp_response(&response)
+    };
+    self.ocsp_response = Some(response);
+  }
+
+  /// the application protocol negotiated with ALPN, if any
+  pub fn selected_protocol(&self) -> Option<&'static str> {
+    self.server_context.engine().selected_protocol()
+      .and_then(|index| self.protocols.get(index).map(|p| *p))
+  }
+
+  /// the parameters of the session, once the handshake is done
+  pub fn tls_info(&self) -> io::TlsInfo {
+    let engine = self.server_context.engine();
+    let info = io::TlsInfo::new(engine.version(), engine.cipher_suite(), self.selected_protocol(), engine.server_name());
+    match self.client_validator.as_ref().and_then(|v| v.common_name()) {
+      Some(common_name) => info.with_client_common_name(common_name),
+      None => info
+    }
+  }
+
   pub fn wrap_socket<'b, 's>(&'s mut self, socket: &'b mut io::Socket)
     -> SocketWrapper<'b>
     where 's: 'b
   {
-    SocketWrapper::new(self.server_context.engine_mut(), socket)
+    SocketWrapper::new(self.server_context.engine_mut(), socket, &mut self.record_sizer, &mut self.kernel_tls)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std;
+  use super::Context;
+  use super::super::wrapper::*;
+  use super::super::policy::Policy;
+
+  const SERVER_CERT : &'static [u8] = include_bytes!("../../../test_fixtures/tls/server.der");
+  const SERVER_KEY : &'static [u8] = include_bytes!("../../../test_fixtures/tls/server_key.der");
+  const CLIENT_CA_CERT : &'static [u8] = include_bytes!("../../../test_fixtures/tls/client_ca.der");
+  const CLIENT_CERT : &'static [u8] = include_bytes!("../../../test_fixtures/tls/client.der");
+  const CLIENT_KEY : &'static [u8] = include_bytes!("../../../test_fixtures/tls/client_key.der");
+
+  struct Client {
+    ctx: Box<ffi::br_ssl_client_context>,
+    // the client context points to these
+    _validator: Box<ffi::br_x509_minimal_context>,
+    _buffer: Vec<u8>
+  }
+
+  fn decode_key(key: &[u8]) -> secret::Key {
+    let mut decoder = secret::DecoderContext::new();
+    decoder.push(key);
+    decoder.get_key().unwrap()
+  }
+
+  fn connect(
+    server_anchors: &x509::TrustAnchors,
+    chain: &[x509::Certificate],
+    key: &secret::Key,
+    session: Option<ffi::br_ssl_session_parameters>)
+  -> Client
+  {
+    let key = match key {
+      &secret::Key::Rsa(ref rsa_key) => rsa_key,
+      _ => unreachable!()
+    };
+    let mut client = Client {
+      ctx: Box::new(unsafe { std::mem::zeroed() }),
+      _validator: Box::new(unsafe { std::mem::zeroed() }),
+      _buffer: vec![0u8; ffi::BR_SSL_BUFSIZE_BIDI as usize]
+    };
+    let anchors = server_anchors.as_slice();
+    unsafe {
+      ffi::br_ssl_client_init_full(&mut *client.ctx, &mut *client._validator, anchors.as_ptr(), anchors.len());
+      ffi::br_ssl_client_set_single_rsa(
+        &mut *client.ctx,
+        chain[0].as_ptr(),
+        chain.len(),
+        key.as_ptr(),
+        ffi::br_rsa_pkcs1_sign_get_default());
+    }
+    client.ctx.eng.set_buffer(&mut client._buffer, true);
+    if let Some(session) = session {
+      // like br_ssl_engine_set_session_parameters
+      client.ctx.eng.session = session;
+    }
+    let is_reset = unsafe {
+      ffi::br_ssl_client_reset(&mut *client.ctx, std::ptr::null(), session.is_some() as std::os::raw::c_int)
+    };
+    assert_eq!(is_reset, 1);
+    client
+  }
+
+  // moves records between the engines until both are done with the handshake
+  fn handshake(server: &mut engine::Context, client: &mut engine::Context) {
+    for _ in 0 .. 100 {
+      if server.is_handshake_done() && client.is_handshake_done() {
+        return;
+      }
+      transfer(client, server);
+      transfer(server, client);
+    }
+    panic!("handshake not done, server: {:?}, client: {:?}", server.last_error(), client.last_error());
+  }
+
+  fn transfer(from: &mut engine::Context, to: &mut engine::Context) {
+    loop {
+      let len = match (from.sendrec_buf(), to.recvrec_buf()) {
+        (Some(src), Some(dst)) => {
+          let len = src.len().min(dst.len());
+          dst[.. len].copy_from_slice(&src[.. len]);
+          len
+        },
+        _ => return
+      };
+      from.sendrec_ack(len).unwrap();
+      to.recvrec_ack(len).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_resumed_session() {
+    let server_chain = [x509::Certificate::from_bytes(SERVER_CERT)];
+    let server_key = decode_key(SERVER_KEY);
+    let server_anchors = x509::TrustAnchors::from_certificates(&[SERVER_CERT]).unwrap();
+    let client_chain = [x509::Certificate::from_bytes(CLIENT_CERT)];
+    let client_key = decode_key(CLIENT_KEY);
+    let session_cache = session_cache::SessionCache::new(4 * session_cache::SESSION_ENTRY_SIZE);
+    let policy = Policy::new();
+
+    let mut server = Context::from_certificate(&server_chain, &server_key, None, Some(&session_cache), &policy).unwrap();
+    let mut client = connect(&server_anchors, &client_chain, &client_key, None);
+    handshake(server.server_context.engine_mut(), &mut client.ctx.eng);
+    let session = client.ctx.eng.session;
+
+    let mut server = Context::from_certificate(&server_chain, &server_key, None, Some(&session_cache), &policy).unwrap();
+    let mut client = connect(&server_anchors, &client_chain, &client_key, Some(session));
+    handshake(server.server_context.engine_mut(), &mut client.ctx.eng);
+    // the server accepted the session id, rather than making up a new one
+    assert_eq!(client.ctx.eng.session.session_id, session.session_id);
+  }
+
+  #[test]
+  fn test_client_common_name_after_reconnect() {
+    let server_chain = [x509::Certificate::from_bytes(SERVER_CERT)];
+    let server_key = decode_key(SERVER_KEY);
+    let server_anchors = x509::TrustAnchors::from_certificates(&[SERVER_CERT]).unwrap();
+    let client_anchors = x509::TrustAnchors::from_certificates(&[CLIENT_CA_CERT]).unwrap();
+    let client_chain = [x509::Certificate::from_bytes(CLIENT_CERT)];
+    let client_key = decode_key(CLIENT_KEY);
+    let session_cache = session_cache::SessionCache::new(4 * session_cache::SESSION_ENTRY_SIZE);
+    let policy = Policy::new();
+
+    let mut server = Context::from_certificate(&server_chain, &server_key, Some(&client_anchors), Some(&session_cache), &policy).unwrap();
+    let mut client = connect(&server_anchors, &client_chain, &client_key, None);
+    handshake(server.server_context.engine_mut(), &mut client.ctx.eng);
+    assert_eq!(server.tls_info().client_common_name(), Some("test client"));
+    let session = client.ctx.eng.session;
+
+    // the client tries to resume its session
+    let mut server = Context::from_certificate(&server_chain, &server_key, Some(&client_anchors), Some(&session_cache), &policy).unwrap();
+    let mut client = connect(&server_anchors, &client_chain, &client_key, Some(session));
+    handshake(server.server_context.engine_mut(), &mut client.ctx.eng);
+    assert_eq!(server.tls_info().client_common_name(), Some("test client"));
   }
 }
@@ -1,12 +1,20 @@
 use std;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use io;
 use super::wrapper::*;
 use super::handler::Handler;
 use super::context::Context;
+use super::policy::Policy;
+use super::ocsp::OcspStaple;
 
 pub struct HandlerFactory<'a> {
   private_key: secret::Key,
   trust_chain: [x509::Certificate<'a>; 1],
+  client_trust_anchors: Option<x509::TrustAnchors>,
+  session_cache: Option<session_cache::SessionCache>,
+  ocsp_staple: Option<OcspStaple>,
+  policy: Policy
 }
 
 impl<'a> HandlerFactory<'a> {
@@ -17,14 +25,71 @@ impl<'a> HandlerFactory<'a> {
     let mut private_key_decoder = secret::DecoderContext::new();
     private_key_decoder.push(private_key);
     let private_key = private_key_decoder.get_key()?;
-    Ok(HandlerFactory { trust_chain, private_key })
+    Ok(HandlerFactory {
+      trust_chain,
+      private_key,
+      client_trust_anchors: None,
+      session_cache: None,
+      ocsp_staple: None,
+      policy: Policy::new()
+    })
+  }
+
+  /// Asks clients for a certificate issued by one of the given CAs (DER encoded).
+  /// Clients that don't send one can still connect, see `io::TlsInfo::client_common_name`.
+  pub fn with_client_auth(mut self, ca_certificates: &[&[u8]]) -> std::result::Result<HandlerFactory<'a>, x509::Error> {
+    self.client_trust_anchors = Some(x509::TrustAnchors::from_certificates(ca_certificates)?);
+    Ok(self)
+  }
+
+  /// Lets clients resume their TLS session on a new connection without
+  /// a full handshake, remembering the sessions in an LRU cache shared by all connections.
+  /// Each session takes `session_cache::SESSION_ENTRY_SIZE` bytes of the memory budget.
+  /// BearSSL doesn't implement stateless session tickets (RFC 5077).
+  /// Ignored with `with_client_auth`, as a resumed session has no client certificate.
+  pub fn with_session_cache(mut self, memory_budget: usize) -> HandlerFactory<'a> {
+    self.session_cache = Some(session_cache::SessionCache::new(memory_budget));
+    self
+  }
+
+  /// Staples the DER encoded OCSP response in the file to the handshake,
+  /// so clients don't have to ask the CA whether the certificate was revoked.
+  /// The file is reread when it changes, so something like a cron job can keep it
+  /// up to date, and an expired response isn't sent anymore.
+  /// Needs BearSSL to be built with lib/status_request.patch.
+  pub fn with_ocsp_response_file(mut self, path: &Path) -> std::io::Result<HandlerFactory<'a>> {
+    if !server::HAS_STATUS_REQUEST {
+      return Err(std::io::Error::new(std::io::ErrorKind::Other,
+        "BearSSL was built without status_request support, see src/tls/bearssl/lib/status_request.patch"));
+    }
+    self.ocsp_staple = Some(OcspStaple::load(path, unix_time_now())?);
+    Ok(self)
+  }
+
+  pub fn with_policy(mut self, policy: Policy) -> HandlerFactory<'a> {
+    self.policy = policy;
+    self
   }
 
   pub fn create_handler<'s, T, H: io::Handler<T>>(&'s self, child_handler: H)
     -> Handler<'s, H>
     where 'a : 's
   {
-    let tls_context = Context::from_certificate(&self.trust_chain, &self.private_key).expect("could not create context");
+    let mut tls_context = Context::from_certificate(
+      &self.trust_chain,
+      &self.private_key,
+      self.client_trust_anchors.as_ref(),
+      self.session_cache.as_ref(),
+      &self.policy).expect("could not create context");
+    let ocsp_response = self.ocsp_staple.as_ref()
+      .and_then(|staple| staple.current(unix_time_now()));
+    if let Some(ocsp_response) = ocsp_response {
+      tls_context.staple_ocsp_response(ocsp_response);
+    }
     Handler::new(tls_context, child_handler)
   }
 }
+
+fn unix_time_now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -18,8 +18,9 @@ impl<'a, H> Handler<'a, H> {
     -> std::io::Result<Option<()>>
     where H: io::Handler<()>
   {
-    let (socket, mut child_ctx_factory) = ctx.as_socket_and_factory(); 
-    child_ctx_factory.set_tls(true);
+    let connection = ctx.connection().with_tls(self.tls_context.tls_info());
+    let (socket, mut child_ctx_factory) = ctx.as_socket_and_factory();
+    child_ctx_factory.set_connection(&connection);
     let mut tls_socket = self.tls_context.wrap_socket(socket);
     let event_kind = event.kind();
 
@@ -29,6 +30,7 @@ impl<'a, H> Handler<'a, H> {
     if event_kind.is_writable() {
       while tls_socket.write_records()?.should_retry() {};
     }
+    tls_socket.try_enable_kernel_tls()?;
 
     if self.is_closing {
       tls_socket.discard_incoming_data()?;
@@ -83,8 +85,9 @@ impl<'a, H: io::Handler<()>> io::Handler<()> for Handler<'a, H> {
     // (e.g. file data available from reader) anymore
     // if this is the case.
     else if !self.is_closing {
+      let connection = ctx.connection().with_tls(self.tls_context.tls_info());
       let (socket, mut child_ctx_factory) = ctx.as_socket_and_factory();
-      child_ctx_factory.set_tls(true);
+      child_ctx_factory.set_connection(&connection);
       let mut tls_socket = self.tls_context.wrap_socket(socket);
       let mut child_ctx = child_ctx_factory.into_context(&mut tls_socket);
       let result = self.child_handler.handle_event(event, &mut child_ctx);
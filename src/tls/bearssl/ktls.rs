@@ -0,0 +1,252 @@
+/*
+Kernel TLS (linux 4.13+): once the handshake is done, the keys to encrypt
+outgoing records are installed on the socket, so plaintext written to it
+is encrypted by the kernel. This way file data can be sent with sendfile,
+without copying it through userspace to encrypt it.
+
+Incoming records are still decrypted by the engine, but the engine can't
+send any records anymore, as the kernel now keeps the sequence number.
+*/
+use libc;
+use std::io;
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::os::raw::c_void;
+use std::os::unix::io::{RawFd, AsRawFd};
+use super::wrapper::engine;
+use super::policy::*;
+
+// from linux/tcp.h and linux/tls.h
+const TCP_ULP : libc::c_int = 31;
+const SOL_TLS : libc::c_int = 282;
+const TLS_TX : libc::c_int = 1;
+const TLS_SET_RECORD_TYPE : libc::c_int = 1;
+const TLS_CIPHER_AES_GCM_128 : u16 = 51;
+const TLS_CIPHER_AES_GCM_256 : u16 = 52;
+const TLS_CIPHER_CHACHA20_POLY1305 : u16 = 54;
+
+const ALERT_RECORD_TYPE : u8 = 21;
+// client and server write key and IV for the longest keys and IVs
+const MAX_KEY_BLOCK_LEN : usize = 2 * 32 + 2 * 12;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KernelTls {
+  /// records are encrypted by the engine
+  Off,
+  /// hand encryption to the kernel once the handshake is done
+  Pending,
+  /// records are encrypted by the kernel
+  On
+}
+
+#[repr(C)]
+struct CryptoInfo {
+  version: u16,
+  cipher_type: u16
+}
+
+#[repr(C)]
+struct AesGcm128Info {
+  info: CryptoInfo,
+  iv: [u8; 8],
+  key: [u8; 16],
+  salt: [u8; 4],
+  rec_seq: [u8; 8]
+}
+
+#[repr(C)]
+struct AesGcm256Info {
+  info: CryptoInfo,
+  iv: [u8; 8],
+  key: [u8; 32],
+  salt: [u8; 4],
+  rec_seq: [u8; 8]
+}
+
+#[repr(C)]
+struct Chacha20Poly1305Info {
+  info: CryptoInfo,
+  iv: [u8; 12],
+  key: [u8; 32],
+  rec_seq: [u8; 8]
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Cipher {
+  AesGcm128,
+  AesGcm256,
+  Chacha20Poly1305
+}
+
+impl Cipher {
+  fn from_suite(suite: u16) -> Option<Cipher> {
+    match suite {
+      TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 |
+      TLS_RSA_WITH_AES_128_GCM_SHA256 => Some(Cipher::AesGcm128),
+      TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 |
+      TLS_RSA_WITH_AES_256_GCM_SHA384 => Some(Cipher::AesGcm256),
+      TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => Some(Cipher::Chacha20Poly1305),
+      _ => None
+    }
+  }
+
+  fn key_len(self) -> usize {
+    match self {
+      Cipher::AesGcm128 => 16,
+      Cipher::AesGcm256 | Cipher::Chacha20Poly1305 => 32
+    }
+  }
+
+  /// the implicit part of the nonce
+  fn iv_len(self) -> usize {
+    match self {
+      Cipher::AesGcm128 | Cipher::AesGcm256 => 4,
+      Cipher::Chacha20Poly1305 => 12
+    }
+  }
+}
+
+/// Whether the kernel has the tls module, by installing it on a loopback connection,
+/// as the upper layer protocol can only be set on a connected socket.
+pub fn probe() -> io::Result<()> {
+  let listener = TcpListener::bind("127.0.0.1:0")?;
+  let stream = TcpStream::connect(listener.local_addr()?)?;
+  set_option(stream.as_raw_fd(), libc::IPPROTO_TCP, TCP_ULP, b"tls")
+}
+
+/// Whether the kernel can take over encryption for the negotiated session.
+pub fn is_supported(engine: &engine::Context) -> bool {
+  engine.version() == TLS12 && Cipher::from_suite(engine.cipher_suite()).is_some()
+}
+
+/// Installs the server write key of the session on the socket.
+/// All records of the engine should be sent before calling this.
+pub fn enable_tx(fd: RawFd, engine: &engine::Context) -> io::Result<()> {
+  let cipher = Cipher::from_suite(engine.cipher_suite())
+    .filter(|_| engine.version() == TLS12)
+    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "kernel TLS needs TLS 1.2 with an AEAD suite"))?;
+  let key_len = cipher.key_len();
+  let iv_len = cipher.iv_len();
+  let mut key_block = [0u8; MAX_KEY_BLOCK_LEN];
+  let key_block = &mut key_block[.. 2 * key_len + 2 * iv_len];
+  engine.key_block(key_block, cipher == Cipher::AesGcm256);
+  let key = &key_block[key_len .. 2 * key_len];
+  let iv = &key_block[2 * key_len + iv_len ..];
+  let seq = sequence_bytes(engine.aead_out_sequence());
+
+  set_option(fd, libc::IPPROTO_TCP, TCP_ULP, b"tls")?;
+  match cipher {
+    Cipher::AesGcm128 => {
+      let mut info : AesGcm128Info = unsafe { mem::zeroed() };
+      info.info = CryptoInfo { version: TLS12, cipher_type: TLS_CIPHER_AES_GCM_128 };
+      // the explicit nonce is the sequence number, as sent by the engine
+      info.iv = seq;
+      info.key.copy_from_slice(key);
+      info.salt.copy_from_slice(iv);
+      info.rec_seq = seq;
+      set_option(fd, SOL_TLS, TLS_TX, &info)
+    },
+    Cipher::AesGcm256 => {
+      let mut info : AesGcm256Info = unsafe { mem::zeroed() };
+      info.info = CryptoInfo { version: TLS12, cipher_type: TLS_CIPHER_AES_GCM_256 };
+      info.iv = seq;
+      info.key.copy_from_slice(key);
+      info.salt.copy_from_slice(iv);
+      info.rec_seq = seq;
+      set_option(fd, SOL_TLS, TLS_TX, &info)
+    },
+    Cipher::Chacha20Poly1305 => {
+      let mut info : Chacha20Poly1305Info = unsafe { mem::zeroed() };
+      info.info = CryptoInfo { version: TLS12, cipher_type: TLS_CIPHER_CHACHA20_POLY1305 };
+      info.iv.copy_from_slice(iv);
+      info.key.copy_from_slice(key);
+      info.rec_seq = seq;
+      set_option(fd, SOL_TLS, TLS_TX, &info)
+    }
+  }
+}
+
+/// Sends a close_notify alert through the kernel,
+/// as the engine can't send it anymore.
+pub fn send_close_notify(fd: RawFd) -> io::Result<()> {
+  // warning level, close_notify
+  let alert = [1u8, 0u8];
+  let mut iov = libc::iovec {
+    iov_base: alert.as_ptr() as *mut c_void,
+    iov_len: alert.len()
+  };
+  // aligned room for a control message with a single byte of data
+  let mut control = [0u64; 4];
+  let mut msg : libc::msghdr = unsafe { mem::zeroed() };
+  msg.msg_iov = &mut iov as *mut libc::iovec;
+  msg.msg_iovlen = 1;
+  msg.msg_control = control.as_mut_ptr() as *mut c_void;
+  unsafe {
+    msg.msg_controllen = libc::CMSG_SPACE(1) as _;
+    let cmsg = libc::CMSG_FIRSTHDR(&msg as *const libc::msghdr);
+    (*cmsg).cmsg_level = SOL_TLS;
+    (*cmsg).cmsg_type = TLS_SET_RECORD_TYPE;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(1) as _;
+    *libc::CMSG_DATA(cmsg) = ALERT_RECORD_TYPE;
+  }
+  let sent = unsafe {
+    libc::sendmsg(fd, &msg as *const libc::msghdr, 0)
+  };
+  if sent == -1 {
+    Err(io::Error::last_os_error())
+  }
+  else {
+    Ok( () )
+  }
+}
+
+fn set_option<T: ?Sized>(fd: RawFd, level: libc::c_int, name: libc::c_int, value: &T) -> io::Result<()> {
+  let result = unsafe {
+    libc::setsockopt(
+      fd,
+      level,
+      name,
+      value as *const T as *const c_void,
+      mem::size_of_val(value) as libc::socklen_t)
+  };
+  if result == -1 {
+    Err(io::Error::last_os_error())
+  }
+  else {
+    Ok( () )
+  }
+}
+
+fn sequence_bytes(seq: u64) -> [u8; 8] {
+  [
+    (seq >> 56) as u8, (seq >> 48) as u8, (seq >> 40) as u8, (seq >> 32) as u8,
+    (seq >> 24) as u8, (seq >> 16) as u8, (seq >> 8) as u8, seq as u8
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem;
+  use super::*;
+
+  #[test]
+  fn test_crypto_info_matches_kernel_abi() {
+    assert_eq!(mem::size_of::<AesGcm128Info>(), 40);
+    assert_eq!(mem::size_of::<AesGcm256Info>(), 56);
+    assert_eq!(mem::size_of::<Chacha20Poly1305Info>(), 56);
+  }
+
+  #[test]
+  fn test_cipher_from_suite() {
+    assert_eq!(Cipher::from_suite(TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256), Some(Cipher::AesGcm128));
+    assert_eq!(Cipher::from_suite(TLS_RSA_WITH_AES_256_GCM_SHA384), Some(Cipher::AesGcm256));
+    assert_eq!(Cipher::from_suite(TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256), Some(Cipher::Chacha20Poly1305));
+    assert_eq!(Cipher::from_suite(TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256), None);
+  }
+
+  #[test]
+  fn test_sequence_bytes() {
+    assert_eq!(sequence_bytes(1), [0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(sequence_bytes(0x0102030405060708), [1, 2, 3, 4, 5, 6, 7, 8]);
+  }
+}
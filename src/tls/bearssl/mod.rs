@@ -3,6 +3,13 @@ mod context;
 mod socket;
 mod handler;
 mod factory;
+mod policy;
+mod ktls;
+mod ocsp;
 
 pub use self::handler::*;
 pub use self::factory::*;
+pub use self::policy::*;
+pub use self::wrapper::hash::{Sha1, Sha256, Md5, HmacKey, Hmac, mac_eq, pbkdf2_hmac_sha256, SHA256_SIZE};
+pub use self::wrapper::random::Random;
+pub use self::wrapper::ecdsa::verify_p256_raw;
@@ -0,0 +1,276 @@
+/*
+OCSP stapling: the server sends a recent OCSP response for its certificate
+in the handshake (status_request extension, RFC 6066), so clients don't have
+to ask the CA themselves whether the certificate was revoked.
+
+The response is fetched by something else, e.g. a cron job running
+`openssl ocsp -issuer chain.pem -cert cert.pem -url <responder> -respout ocsp.der`,
+and reread from the file when it changes. BearSSL only sends it
+when built with lib/status_request.patch.
+*/
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+// how often the file is checked for a new response, in seconds
+const CHECK_INTERVAL : u64 = 60;
+
+const SEQUENCE : u8 = 0x30;
+const ENUMERATED : u8 = 0x0A;
+const OCTET_STRING : u8 = 0x04;
+const OID : u8 = 0x06;
+const GENERALIZED_TIME : u8 = 0x18;
+// [0] EXPLICIT
+const CONTEXT_0 : u8 = 0xA0;
+// certStatus good, [0] IMPLICIT NULL
+const CERT_STATUS_GOOD : u8 = 0x80;
+// 1.3.6.1.5.5.7.48.1.1
+const ID_PKIX_OCSP_BASIC : &'static [u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// The OCSP response to staple, shared by the contexts of all connections.
+pub struct OcspStaple {
+  path: PathBuf,
+  state: RefCell<StapleState>
+}
+
+struct StapleState {
+  // the contexts keep the response they were given alive
+  response: Option<Rc<[u8]>>,
+  next_update: Option<u64>,
+  modified: Option<SystemTime>,
+  checked: u64
+}
+
+impl OcspStaple {
+  /// Loads the DER encoded OCSP response from the file,
+  /// which should be for the leaf certificate and say it is good.
+  pub fn load(path: &Path, now: u64) -> io::Result<OcspStaple> {
+    let staple = OcspStaple {
+      path: path.to_owned(),
+      state: RefCell::new(StapleState {
+        response: None,
+        next_update: None,
+        modified: None,
+        checked: now
+      })
+    };
+    staple.reload()?;
+    Ok(staple)
+  }
+
+  /// The response to staple in a handshake starting now, if it didn't expire.
+  /// Rereads the file if it changed, checking at most every `CHECK_INTERVAL` seconds.
+  /// A file that can't be read or parsed keeps the previous response.
+  pub fn current(&self, now: u64) -> Option<Rc<[u8]>> {
+    let is_due = now >= self.state.borrow().checked + CHECK_INTERVAL;
+    if is_due {
+      self.state.borrow_mut().checked = now;
+      if let Err(err) = self.reload() {
+        println!("could not reload OCSP response: {:?}", err);
+      }
+    }
+    let state = self.state.borrow();
+    match state.next_update {
+      // clients reject an expired response, better to send none
+      Some(next_update) if next_update <= now => None,
+      _ => state.response.clone()
+    }
+  }
+
+  fn reload(&self) -> io::Result<()> {
+    let modified = fs::metadata(&self.path)?.modified()?;
+    if self.state.borrow().modified == Some(modified) {
+      return Ok( () );
+    }
+    let response = fs::read(&self.path)?;
+    let next_update = parse_response(&response)?;
+    let mut state = self.state.borrow_mut();
+    state.response = Some(Rc::from(response));
+    state.next_update = next_update;
+    state.modified = Some(modified);
+    Ok( () )
+  }
+}
+
+/// Checks the response is a successful basic response saying the certificate is good,
+/// and returns its nextUpdate in seconds since the unix epoch, if any.
+/// The signature is left to the clients.
+pub fn parse_response(der: &[u8]) -> io::Result<Option<u64>> {
+  let (response, _) = read_element(der, SEQUENCE)?;
+  let (status, rest) = read_element(response, ENUMERATED)?;
+  if status != [0] {
+    return Err(invalid("OCSP response is not successful"));
+  }
+  let (response_bytes, _) = read_element(rest, CONTEXT_0)?;
+  let (response_bytes, _) = read_element(response_bytes, SEQUENCE)?;
+  let (response_type, rest) = read_element(response_bytes, OID)?;
+  if response_type != ID_PKIX_OCSP_BASIC {
+    return Err(invalid("not a basic OCSP response"));
+  }
+  let (basic_response, _) = read_element(rest, OCTET_STRING)?;
+  let (basic_response, _) = read_element(basic_response, SEQUENCE)?;
+  let (response_data, _) = read_element(basic_response, SEQUENCE)?;
+  // version, only there if not v1
+  let response_data = if response_data.first() == Some(&CONTEXT_0) {
+    read_element(response_data, CONTEXT_0)?.1
+  } else {
+    response_data
+  };
+  // responderID, by name or by key hash
+  let (_, rest) = read_any_element(response_data)?;
+  // producedAt
+  let (_, rest) = read_element(rest, GENERALIZED_TIME)?;
+  let (responses, _) = read_element(rest, SEQUENCE)?;
+  let (single_response, _) = read_element(responses, SEQUENCE)?;
+  // certID
+  let (_, rest) = read_element(single_response, SEQUENCE)?;
+  let (_, rest) = read_element(rest, CERT_STATUS_GOOD)
+    .map_err(|_| invalid("OCSP response doesn't say the certificate is good"))?;
+  // thisUpdate
+  let (_, rest) = read_element(rest, GENERALIZED_TIME)?;
+  if rest.first() != Some(&CONTEXT_0) {
+    return Ok(None);
+  }
+  let (next_update, _) = read_element(rest, CONTEXT_0)?;
+  let (next_update, _) = read_element(next_update, GENERALIZED_TIME)?;
+  parse_generalized_time(next_update).map(Some)
+}
+
+// the contents of the element with `tag` at the start of `der`, and what comes after it
+fn read_element(der: &[u8], tag: u8) -> io::Result<(&[u8], &[u8])> {
+  match der.first() {
+    Some(t) if *t == tag => read_any_element(der),
+    _ => Err(invalid("unexpected element in OCSP response"))
+  }
+}
+
+fn read_any_element(der: &[u8]) -> io::Result<(&[u8], &[u8])> {
+  let truncated = || invalid("truncated OCSP response");
+  let first_len_byte = *der.get(1).ok_or_else(truncated)?;
+  let (len, header_len) = if first_len_byte < 0x80 {
+    (first_len_byte as usize, 2)
+  } else {
+    let len_len = (first_len_byte & 0x7F) as usize;
+    if len_len == 0 || len_len > 4 {
+      return Err(invalid("unsupported length in OCSP response"));
+    }
+    let len_bytes = der.get(2 .. 2 + len_len).ok_or_else(truncated)?;
+    let len = len_bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+    (len, 2 + len_len)
+  };
+  let end = header_len.checked_add(len).ok_or_else(truncated)?;
+  let contents = der.get(header_len .. end).ok_or_else(truncated)?;
+  Ok((contents, &der[end ..]))
+}
+
+// YYYYMMDDHHMMSSZ, as RFC 5280 requires for GeneralizedTime
+fn parse_generalized_time(time: &[u8]) -> io::Result<u64> {
+  if time.len() != 15 || time[14] != b'Z' || !time[.. 14].iter().all(|b| b.is_ascii_digit()) {
+    return Err(invalid("unsupported time in OCSP response"));
+  }
+  let number = |range: ::std::ops::Range<usize>| {
+    time[range].iter().fold(0i64, |n, b| n * 10 + (b - b'0') as i64)
+  };
+  let (year, month, day) = (number(0 .. 4), number(4 .. 6), number(6 .. 8));
+  if month < 1 || month > 12 || day < 1 || day > 31 || year < 1970 {
+    return Err(invalid("unsupported time in OCSP response"));
+  }
+  let days = days_from_civil(year, month, day);
+  let secs = days * 86_400 + number(8 .. 10) * 3600 + number(10 .. 12) * 60 + number(12 .. 14);
+  Ok(secs as u64)
+}
+
+// days since 1970-01-01 from year, month (1-12) and day (1-31),
+// see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = (if year >= 0 { year } else { year - 399 }) / 400;
+  let year_of_era = year - era * 400;
+  let mp = if month > 2 { month - 3 } else { month + 9 };
+  let day_of_year = (153 * mp + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146_097 + day_of_era - 719_468
+}
+
+fn invalid(message: &'static str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, process};
+  use super::{OcspStaple, parse_response, parse_generalized_time, ID_PKIX_OCSP_BASIC, CHECK_INTERVAL};
+
+  fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    if contents.len() < 0x80 {
+      element.push(contents.len() as u8);
+    } else {
+      element.extend_from_slice(&[0x82, (contents.len() >> 8) as u8, contents.len() as u8]);
+    }
+    element.extend_from_slice(contents);
+    element
+  }
+
+  // an OCSP response with a single response, without signature
+  fn response(cert_status: &[u8], next_update: Option<&str>) -> Vec<u8> {
+    let mut single = der(0x30, &der(0x04, &[0xAB; 20]));
+    single.extend(cert_status);
+    single.extend(der(0x18, b"20261019000000Z"));
+    if let Some(next_update) = next_update {
+      single.extend(der(0xA0, &der(0x18, next_update.as_bytes())));
+    }
+    let mut response_data = der(0xA2, &der(0x04, &[0xCD; 20]));
+    response_data.extend(der(0x18, b"20261019000000Z"));
+    response_data.extend(der(0x30, &der(0x30, &single)));
+    let mut basic = der(0x30, &response_data);
+    basic.extend(der(0x30, &der(0x06, &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0B])));
+    basic.extend(der(0x03, &[0; 257]));
+    let mut response_bytes = der(0x06, ID_PKIX_OCSP_BASIC);
+    response_bytes.extend(der(0x04, &der(0x30, &basic)));
+    let mut response = der(0x0A, &[0]);
+    response.extend(der(0xA0, &der(0x30, &response_bytes)));
+    der(0x30, &response)
+  }
+
+  #[test]
+  fn test_parse_next_update() {
+    let good = [0x80, 0x00];
+    assert_eq!(parse_response(&response(&good, Some("20261026000000Z"))).unwrap(), Some(1_792_972_800));
+    assert_eq!(parse_response(&response(&good, None)).unwrap(), None);
+  }
+
+  #[test]
+  fn test_parse_invalid() {
+    // revoked, with the revocation time
+    let revoked = der(0xA1, &der(0x18, b"20261001000000Z"));
+    assert!(parse_response(&response(&revoked, Some("20261026000000Z"))).is_err());
+    // tryLater
+    assert!(parse_response(&der(0x30, &der(0x0A, &[3]))).is_err());
+    let good = response(&[0x80, 0x00], None);
+    assert!(parse_response(&good[.. good.len() - 1]).is_err());
+  }
+
+  #[test]
+  fn test_parse_generalized_time() {
+    assert_eq!(parse_generalized_time(b"19941106084937Z").unwrap(), 784_111_777);
+    assert_eq!(parse_generalized_time(b"20000229000000Z").unwrap(), 951_782_400);
+    assert!(parse_generalized_time(b"20000229000000.5Z").is_err());
+    assert!(parse_generalized_time(b"20001329000000Z").is_err());
+  }
+
+  #[test]
+  fn test_expired_not_stapled() {
+    let path = env::temp_dir().join(format!("wwwee-ocsp-{}", process::id()));
+    fs::write(&path, response(&[0x80, 0x00], Some("20261026000000Z"))).unwrap();
+    let now = 1_792_972_800 - CHECK_INTERVAL;
+    let staple = OcspStaple::load(&path, now).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(staple.current(now).is_some());
+    // a file that went away keeps the response until it expires
+    assert!(staple.current(now + CHECK_INTERVAL).is_none());
+  }
+}
@@ -0,0 +1,256 @@
+use std::time::{Duration, Instant};
+use super::wrapper::{ffi, engine};
+use super::ktls::{self, KernelTls};
+
+pub const TLS10 : u16 = ffi::BR_TLS10 as u16;
+pub const TLS11 : u16 = ffi::BR_TLS11 as u16;
+pub const TLS12 : u16 = ffi::BR_TLS12 as u16;
+
+// the suites that work with an RSA key
+pub const TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256 : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256 as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384 : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384 as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA as u16;
+pub const TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA : u16 = ffi::BR_TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA as u16;
+pub const TLS_RSA_WITH_AES_128_GCM_SHA256 : u16 = ffi::BR_TLS_RSA_WITH_AES_128_GCM_SHA256 as u16;
+pub const TLS_RSA_WITH_AES_256_GCM_SHA384 : u16 = ffi::BR_TLS_RSA_WITH_AES_256_GCM_SHA384 as u16;
+pub const TLS_RSA_WITH_AES_128_CBC_SHA256 : u16 = ffi::BR_TLS_RSA_WITH_AES_128_CBC_SHA256 as u16;
+pub const TLS_RSA_WITH_AES_256_CBC_SHA256 : u16 = ffi::BR_TLS_RSA_WITH_AES_256_CBC_SHA256 as u16;
+pub const TLS_RSA_WITH_AES_128_CBC_SHA : u16 = ffi::BR_TLS_RSA_WITH_AES_128_CBC_SHA as u16;
+pub const TLS_RSA_WITH_AES_256_CBC_SHA : u16 = ffi::BR_TLS_RSA_WITH_AES_256_CBC_SHA as u16;
+
+const ALPN_PROTOCOLS : [&'static str; 2] = ["h2", "http/1.1"];
+
+const AES_FIRST_SUITES : [u16; 13] = [
+  TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+  TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+  TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+  TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256,
+  TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384,
+  TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA,
+  TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA,
+  TLS_RSA_WITH_AES_128_GCM_SHA256,
+  TLS_RSA_WITH_AES_256_GCM_SHA384,
+  TLS_RSA_WITH_AES_128_CBC_SHA256,
+  TLS_RSA_WITH_AES_256_CBC_SHA256,
+  TLS_RSA_WITH_AES_128_CBC_SHA,
+  TLS_RSA_WITH_AES_256_CBC_SHA
+];
+
+/// Which TLS versions and cipher suites are negotiated,
+/// and how the data sent is cut up in records.
+#[derive(Clone)]
+pub struct Policy {
+  min_version: u16,
+  max_version: u16,
+  cipher_suites: Vec<u16>,
+  prefer_server_order: bool,
+  record_sizing: RecordSizing,
+  kernel_tls: bool,
+  http2: bool
+}
+
+impl Policy {
+  /// TLS 1.0 to 1.2, with ChaCha20-Poly1305 first
+  /// unless the CPU has AES instructions BearSSL can use.
+  pub fn new() -> Policy {
+    let mut cipher_suites = AES_FIRST_SUITES.to_vec();
+    if !has_aes_instructions() {
+      // without AES instructions, ChaCha20 is a lot faster than constant-time AES
+      cipher_suites.retain(|s| *s != TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256);
+      cipher_suites.insert(0, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256);
+    }
+    Policy {
+      min_version: TLS10,
+      max_version: TLS12,
+      cipher_suites,
+      prefer_server_order: true,
+      record_sizing: RecordSizing::dynamic(),
+      kernel_tls: false,
+      http2: false
+    }
+  }
+
+  pub fn versions(mut self, min_version: u16, max_version: u16) -> Policy {
+    self.min_version = min_version;
+    self.max_version = max_version;
+    self
+  }
+
+  /// The suites to offer, in order of preference. Suites that
+  /// don't work with the key of the certificate are never picked.
+  pub fn cipher_suites(mut self, cipher_suites: &[u16]) -> Policy {
+    self.cipher_suites = cipher_suites.to_vec();
+    self
+  }
+
+  /// Picks the first suite in our order that the client supports,
+  /// rather than the first in the order of the client.
+  pub fn prefer_server_order(mut self, prefer_server_order: bool) -> Policy {
+    self.prefer_server_order = prefer_server_order;
+    self
+  }
+
+  pub fn record_sizing(mut self, record_sizing: RecordSizing) -> Policy {
+    self.record_sizing = record_sizing;
+    self
+  }
+
+  /// Lets the kernel encrypt outgoing records after the handshake (linux only),
+  /// so files can be sent with sendfile. Only works for TLS 1.2
+  /// with an AES-GCM or ChaCha20-Poly1305 suite, other sessions
+  /// and kernels without the tls module keep encrypting in userspace.
+  /// Records are always filled up when the kernel encrypts them.
+  /// Whether the kernel has the tls module is checked once, here.
+  pub fn kernel_tls(mut self, kernel_tls: bool) -> Policy {
+    self.kernel_tls = kernel_tls && match ktls::probe() {
+      Ok(_) => true,
+      Err(err) => {
+        println!("kernel tls not available, encrypting in userspace: {:?}", err);
+        false
+      }
+    };
+    self
+  }
+
+  /// Offers HTTP/2 ("h2") before HTTP/1.1 with ALPN. Off by default,
+  /// as HTTP/2 answers long-polling, event streams, websockets and
+  /// other streamed bodies with `501 Not Implemented`.
+  pub fn http2(mut self, http2: bool) -> Policy {
+    self.http2 = http2;
+    self
+  }
+
+  /// the protocols to offer with ALPN, in order of preference
+  pub fn alpn_protocols(&self) -> &'static [&'static str] {
+    if self.http2 { &ALPN_PROTOCOLS } else { &ALPN_PROTOCOLS[1 ..] }
+  }
+
+  /// configures an engine initialized with the full profile
+  pub fn apply(&self, engine: &mut engine::Context) {
+    engine.set_versions(self.min_version, self.max_version);
+    engine.set_suites(&self.cipher_suites);
+    if self.prefer_server_order {
+      engine.add_flags(ffi::BR_OPT_ENFORCE_SERVER_PREFERENCES);
+    }
+    if self.kernel_tls {
+      // a new handshake would need to send records with the engine
+      engine.add_flags(ffi::BR_OPT_NO_RENEGOTIATION);
+    }
+  }
+
+  pub fn record_sizer(&self) -> RecordSizer {
+    RecordSizer::new(self.record_sizing)
+  }
+
+  pub fn kernel_tls_mode(&self) -> KernelTls {
+    if self.kernel_tls { KernelTls::Pending } else { KernelTls::Off }
+  }
+}
+
+fn has_aes_instructions() -> bool {
+  unsafe {
+    !ffi::br_aes_x86ni_ctr_get_vtable().is_null() ||
+    !ffi::br_aes_pwr8_ctr_get_vtable().is_null()
+  }
+}
+
+/// How big the records are that the data we send is put in.
+#[derive(Clone, Copy)]
+pub enum RecordSizing {
+  /// records are filled up to the maximum of 16KB
+  Full,
+  /// Records that fit in a single TCP segment at the start of the connection,
+  /// so the browser can start parsing as soon as the first segment arrives,
+  /// rather than having to wait for all the segments of a 16KB record.
+  /// After sending `boost_after` bytes, records are filled up,
+  /// until the connection was idle for `idle_reset`.
+  Dynamic {
+    small_record_size: usize,
+    boost_after: usize,
+    idle_reset: Duration
+  }
+}
+
+impl RecordSizing {
+  /// the values recommended by https://istlsfastyet.com
+  pub fn dynamic() -> RecordSizing {
+    RecordSizing::Dynamic {
+      // a 1460 byte segment, minus the TLS header, IV and MAC
+      small_record_size: 1400,
+      boost_after: 1024 * 1024,
+      idle_reset: Duration::from_secs(1)
+    }
+  }
+}
+
+/// Keeps track of how much was sent on a connection, to size the records.
+pub struct RecordSizer {
+  sizing: RecordSizing,
+  bytes_sent: usize,
+  last_sent: Option<Instant>
+}
+
+impl RecordSizer {
+  pub fn new(sizing: RecordSizing) -> RecordSizer {
+    RecordSizer { sizing, bytes_sent: 0, last_sent: None }
+  }
+
+  /// how many bytes to put in the next record, if it shouldn't be filled up
+  pub fn next_record_size(&mut self, now: Instant) -> Option<usize> {
+    match self.sizing {
+      RecordSizing::Full => None,
+      RecordSizing::Dynamic { small_record_size, boost_after, idle_reset } => {
+        let is_idle = self.last_sent
+          .map(|last_sent| now > last_sent && now - last_sent >= idle_reset)
+          .unwrap_or(false);
+        if is_idle {
+          self.bytes_sent = 0;
+        }
+        if self.bytes_sent < boost_after {
+          Some(small_record_size)
+        } else {
+          None
+        }
+      }
+    }
+  }
+
+  pub fn sent(&mut self, len: usize, now: Instant) {
+    self.bytes_sent = self.bytes_sent.saturating_add(len);
+    self.last_sent = Some(now);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use super::{RecordSizer, RecordSizing};
+
+  #[test]
+  fn test_dynamic_record_size() {
+    let sizing = RecordSizing::Dynamic {
+      small_record_size: 100,
+      boost_after: 200,
+      idle_reset: Duration::from_secs(1)
+    };
+    let mut sizer = RecordSizer::new(sizing);
+    let now = Instant::now();
+    assert_eq!(sizer.next_record_size(now), Some(100));
+    sizer.sent(100, now);
+    assert_eq!(sizer.next_record_size(now), Some(100));
+    sizer.sent(100, now);
+    assert_eq!(sizer.next_record_size(now), None);
+    sizer.sent(16384, now);
+    assert_eq!(sizer.next_record_size(now + Duration::from_millis(999)), None);
+    assert_eq!(sizer.next_record_size(now + Duration::from_secs(1)), Some(100));
+  }
+
+  #[test]
+  fn test_full_record_size() {
+    let mut sizer = RecordSizer::new(RecordSizing::Full);
+    assert_eq!(sizer.next_record_size(Instant::now()), None);
+  }
+}
@@ -1,8 +1,12 @@
-use io::{Socket, ReadSizeHint, EventSource, AsyncToken};
+use io::{Socket, ReadSizeHint, SocketFd, EventSource, AsyncToken};
 use io::handlers::{send_buffer, receive_buffer, IoReport};
 use std::io::{Result, Read, Write};
 use super::wrapper::engine;
+use super::policy::RecordSizer;
+use super::ktls::{self, KernelTls};
 use std::cmp;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
 
 /**
 one the one hand we have a tcp socket, which when reading will give x amount of bytes
@@ -25,22 +29,54 @@ we might have just received the end of a tls record.
 
 on every write we also call into bearssl but on Write::flush we tell
 bearssl to force a tls record. 
+
+with kernel tls, the kernel encrypts what is written to the socket,
+so writes go straight to the socket, and whatever records the engine
+still wants to send (e.g. alerts) are dropped.
 */
 
 pub struct SocketWrapper<'a> {
   engine: &'a mut engine::Context,
-  socket: &'a mut Socket
+  socket: &'a mut Socket,
+  record_sizer: &'a mut RecordSizer,
+  kernel_tls: &'a mut KernelTls
 }
 
 impl<'a> SocketWrapper<'a> {
-  pub fn new(engine: &'a mut engine::Context, socket: &'a mut Socket) -> SocketWrapper<'a> {
-    SocketWrapper {engine, socket}
+  pub fn new(engine: &'a mut engine::Context, socket: &'a mut Socket, record_sizer: &'a mut RecordSizer, kernel_tls: &'a mut KernelTls) -> SocketWrapper<'a> {
+    SocketWrapper {engine, socket, record_sizer, kernel_tls}
   }
 
   pub fn is_writable(&self) -> bool {
+    if *self.kernel_tls == KernelTls::On {
+      // can't tell, the socket will return WouldBlock if not
+      return true;
+    }
     self.engine.sendapp_buf().is_some()
   }
 
+  /// Hands encryption to the kernel if pending and the handshake just finished.
+  /// Only tried once, before any application data is sent: if the last handshake
+  /// records couldn't be sent yet, or the session or kernel doesn't support it,
+  /// the engine keeps encrypting.
+  pub fn try_enable_kernel_tls(&mut self) -> Result<()> {
+    if *self.kernel_tls != KernelTls::Pending || !self.engine.is_handshake_done() {
+      return Ok( () );
+    }
+    *self.kernel_tls = KernelTls::Off;
+    while self.write_records()?.should_retry() {};
+    let is_flushed = self.engine.sendrec_buf().is_none();
+    if let (true, Some(fd)) = (is_flushed, self.socket.socket_fd()) {
+      if ktls::is_supported(self.engine) {
+        match ktls::enable_tx(fd, self.engine) {
+          Ok(_) => *self.kernel_tls = KernelTls::On,
+          Err(err) => println!("could not enable kernel tls: {:?}", err)
+        }
+      }
+    }
+    Ok( () )
+  }
+
   pub fn is_readable(&self) -> bool {
     self.engine.recvapp_buf().is_some()
   }
@@ -48,6 +84,9 @@ impl<'a> SocketWrapper<'a> {
   /// tries to send tls records over the socket
   //returns IoReport because could interact with real socket that returns would_block
   pub fn write_records(&mut self) -> Result<IoReport> {
+    if *self.kernel_tls == KernelTls::On {
+      return self.drop_records();
+    }
     let engine = &mut self.engine;
     let socket : &mut Write = &mut self.socket;
     engine.sendrec_buf()
@@ -65,6 +104,16 @@ impl<'a> SocketWrapper<'a> {
       .unwrap_or(Ok(IoReport::with_buffer_size(0).ok(0)))
   }
   
+  // records the engine produces once the kernel encrypts can't be sent,
+  // as the sequence number of the engine is out of date
+  fn drop_records(&mut self) -> Result<IoReport> {
+    while let Some(len) = self.engine.sendrec_buf().map(|buf| buf.len()) {
+      self.engine.sendrec_ack(len)
+        .map_err(|err| err.as_io_error("engine error while dropping records"))?;
+    }
+    Ok(IoReport::with_buffer_size(0).ok(0))
+  }
+
   //returns IoReport because could interact with real socket that returns would_block
   pub fn read_records(&mut self) -> Result<IoReport> {
     let socket : &mut Read = &mut self.socket;
@@ -103,10 +152,14 @@ impl<'a> SocketWrapper<'a> {
   }
 
   fn write_plaintext(&mut self, src_buffer: &[u8]) -> Result<usize> {
+    let now = Instant::now();
+    let record_size = self.record_sizer.next_record_size(now);
+    let record_sizer = &mut self.record_sizer;
     let engine = &mut self.engine;
     //space available to send plaintext data?
     engine.sendapp_buf().map(|dst_buffer| {
       let len = cmp::min(src_buffer.len(), dst_buffer.len());
+      let len = record_size.map(|size| cmp::min(len, size)).unwrap_or(len);
       let op_src_buffer = &src_buffer[.. len];
       let op_dst_buffer = &mut dst_buffer[.. len];
       op_dst_buffer.copy_from_slice(op_src_buffer);
@@ -115,12 +168,22 @@ impl<'a> SocketWrapper<'a> {
     .map(|len| {
       engine.sendapp_ack(len)
         .map_err(|err| err.as_io_error("engine error after sendapp ack"))?;
+      //close the record before it grows beyond the size we want
+      if record_size.is_some() {
+        engine.flush(false);
+      }
+      record_sizer.sent(len, now);
       Ok(len)
     })
     .unwrap_or(Ok(0))
   }
 
   pub fn close(&mut self) -> Result<()> {
+    if *self.kernel_tls == KernelTls::On {
+      if let Some(fd) = self.socket.socket_fd() {
+        ktls::send_close_notify(fd)?;
+      }
+    }
     self.engine.close();
     self.discard_incoming_data()?;
     //write outstanding records
@@ -172,6 +235,9 @@ impl<'a> ReadSizeHint for SocketWrapper<'a> {
 
 impl<'a> Write for SocketWrapper<'a> {
   fn write(&mut self, src_buffer: &[u8]) -> Result<usize> {
+    if *self.kernel_tls == KernelTls::On {
+      return self.socket.write(src_buffer);
+    }
     let buffer_len = src_buffer.len();
     let mut should_retry = true;
     let mut app_bytes_written = 0;
@@ -190,6 +256,9 @@ impl<'a> Write for SocketWrapper<'a> {
   }
 
   fn flush(&mut self) -> Result<()> {
+    if *self.kernel_tls == KernelTls::On {
+      return self.socket.flush();
+    }
     self.engine.flush(true); //force emit non-full record
     self.write_records().map(|_| () )
   }
@@ -200,3 +269,14 @@ impl<'a> EventSource for SocketWrapper<'a> {
     self.socket.token()
   }
 }
+
+impl<'a> SocketFd for SocketWrapper<'a> {
+  fn socket_fd(&self) -> Option<RawFd> {
+    if *self.kernel_tls == KernelTls::On {
+      self.socket.socket_fd()
+    }
+    else {
+      None
+    }
+  }
+}
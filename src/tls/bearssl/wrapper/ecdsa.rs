@@ -0,0 +1,27 @@
+use super::ffi::*;
+use std::os::raw::{c_int, c_uchar, c_void};
+
+/// Verifies a raw (`r || s`) ECDSA signature over the hash of the signed data,
+/// with a P-256 public key as uncompressed curve point.
+pub fn verify_p256_raw(public_key: &[u8], hash: &[u8], signature: &[u8]) -> bool {
+  let key = br_ec_public_key {
+    curve: BR_EC_secp256r1 as c_int,
+    // not written to by BearSSL
+    q: public_key.as_ptr() as *mut c_uchar,
+    qlen: public_key.len()
+  };
+  let verify = match unsafe { br_ecdsa_vrfy_raw_get_default() } {
+    Some(verify) => verify,
+    None => return false
+  };
+  let is_valid = unsafe {
+    verify(
+      br_ec_get_default(),
+      hash.as_ptr() as *const c_void,
+      hash.len(),
+      &key as *const br_ec_public_key,
+      signature.as_ptr() as *const c_void,
+      signature.len())
+  };
+  is_valid == 1
+}
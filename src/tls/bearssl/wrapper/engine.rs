@@ -1,6 +1,6 @@
 use super::ffi::*;
 use std;
-use std::os::raw::{c_int, c_void};
+use std::os::raw::{c_char, c_int, c_void};
 use super::Result;
 
 pub type Context = br_ssl_engine_context;
@@ -111,6 +111,114 @@ impl Context {
     }
   }
 
+  /// sets the protocols to negotiate with ALPN, in order of preference.
+  /// The names need to be nul-terminated and outlive the engine.
+  pub unsafe fn set_protocol_names(&mut self, names: &[*const c_char]) {
+    self.protocol_names = names.as_ptr() as *mut *const c_char;
+    self.protocol_names_num = names.len() as u16;
+  }
+
+  /// index in the protocol names of the protocol selected with ALPN
+  pub fn selected_protocol(&self) -> Option<usize> {
+    match self.selected_protocol {
+      0 | 0xFFFF => None,
+      index => Some(index as usize - 1)
+    }
+  }
+
+  /// validates the certificates of the peer with the given X.509 engine,
+  /// like br_ssl_engine_set_x509. The engine needs to outlive this context.
+  pub unsafe fn set_x509(&mut self, x509ctx: *const *const br_x509_class) {
+    self.x509ctx = x509ctx as *mut *const br_x509_class;
+  }
+
+  /// like br_ssl_engine_set_versions
+  pub fn set_versions(&mut self, min_version: u16, max_version: u16) {
+    self.version_min = min_version;
+    self.version_max = max_version;
+  }
+
+  /// the cipher suites to support, in order of preference
+  pub fn set_suites(&mut self, suites: &[u16]) {
+    unsafe {
+      br_ssl_engine_set_suites(self as *mut Context, suites.as_ptr(), suites.len())
+    }
+  }
+
+  /// like br_ssl_engine_add_flags
+  pub fn add_flags(&mut self, flags: u32) {
+    self.flags |= flags;
+  }
+
+  /// the RSA and ECDSA implementations to verify signatures from the peer,
+  /// needed for the CertificateVerify message of client authentication.
+  pub fn set_default_signature_verifiers(&mut self) {
+    unsafe {
+      br_ssl_engine_set_default_rsavrfy(self as *mut Context);
+      br_ssl_engine_set_default_ecdsa(self as *mut Context);
+    }
+  }
+
+  /// the negotiated protocol version, like br_ssl_engine_get_version
+  pub fn version(&self) -> u16 {
+    self.session.version
+  }
+
+  pub fn cipher_suite(&self) -> u16 {
+    self.session.cipher_suite
+  }
+
+  /// the name the client sent with SNI, like br_ssl_engine_get_server_name
+  pub fn server_name(&self) -> &[u8] {
+    let name = unsafe {
+      std::slice::from_raw_parts(self.server_name.as_ptr() as *const u8, self.server_name.len())
+    };
+    let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+    &name[.. len]
+  }
+
+  /// whether the handshake is done and application data can be exchanged
+  pub fn is_handshake_done(&self) -> bool {
+    self.application_data == 1
+  }
+
+  /// Derives the key block from the master secret, like the engine does when
+  /// switching to the negotiated cipher suite (RFC 5246, section 6.3).
+  /// The suites with SHA-384 use it for the PRF as well.
+  pub fn key_block(&self, dst: &mut [u8], use_sha384: bool) {
+    let seed = [
+      br_tls_prf_seed_chunk {
+        data: self.server_random.as_ptr() as *const c_void,
+        len: self.server_random.len()
+      },
+      br_tls_prf_seed_chunk {
+        data: self.client_random.as_ptr() as *const c_void,
+        len: self.client_random.len()
+      }
+    ];
+    let label = b"key expansion\0";
+    unsafe {
+      let prf = if use_sha384 { br_tls12_sha384_prf } else { br_tls12_sha256_prf };
+      prf(
+        dst.as_mut_ptr() as *mut c_void,
+        dst.len(),
+        self.session.master_secret.as_ptr() as *const c_void,
+        self.session.master_secret.len(),
+        label.as_ptr() as *const c_char,
+        seed.len(),
+        seed.as_ptr())
+    }
+  }
+
+  /// the sequence number of the next record sent,
+  /// only valid with an AES-GCM or ChaCha20+Poly1305 suite.
+  pub fn aead_out_sequence(&self) -> u64 {
+    // both record contexts start with the vtable and the sequence number
+    unsafe {
+      self.out.gcm.seq
+    }
+  }
+
   pub fn last_error(&self) -> Result<()> {
     if self.err == BR_ERR_OK as i32 {
       Ok(())
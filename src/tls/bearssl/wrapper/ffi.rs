@@ -87,6 +87,10 @@ pub const BR_SSL30: ::std::os::raw::c_uint = 768;
 pub const BR_TLS10: ::std::os::raw::c_uint = 769;
 pub const BR_TLS11: ::std::os::raw::c_uint = 770;
 pub const BR_TLS12: ::std::os::raw::c_uint = 771;
+pub const BR_OPT_ENFORCE_SERVER_PREFERENCES: ::std::os::raw::c_uint = 1;
+pub const BR_OPT_NO_RENEGOTIATION: ::std::os::raw::c_uint = 2;
+pub const BR_OPT_TOLERATE_NO_CLIENT_AUTH: ::std::os::raw::c_uint = 4;
+pub const BR_OPT_FAIL_ON_ALPN_MISMATCH: ::std::os::raw::c_uint = 8;
 pub const BR_ERR_OK: ::std::os::raw::c_uint = 0;
 pub const BR_ERR_BAD_PARAM: ::std::os::raw::c_uint = 1;
 pub const BR_ERR_BAD_STATE: ::std::os::raw::c_uint = 2;
@@ -634,6 +638,10 @@ extern "C" {
     );
 }
 pub type br_sha256_context = br_sha224_context;
+extern "C" {
+    /// \brief SHA-256 implementation vtable.
+    pub static br_sha256_vtable: br_hash_class;
+}
 extern "C" {
 
     /// \brief SHA-256 context initialisation.
@@ -8112,6 +8120,10 @@ pub struct br_ssl_server_context_ {
     pub hash_CV: [::std::os::raw::c_uchar; 64usize],
     pub hash_CV_len: usize,
     pub hash_CV_id: ::std::os::raw::c_int,
+    // from lib/status_request.patch, only read by a patched BearSSL
+    pub ocsp_response: *const ::std::os::raw::c_uchar,
+    pub ocsp_response_len: usize,
+    pub status_requested: ::std::os::raw::c_uchar,
 }
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -0,0 +1,217 @@
+use super::ffi::*;
+use std;
+use std::os::raw::c_void;
+
+pub const SHA1_SIZE : usize = 20;
+
+pub type Sha1 = br_sha1_context;
+
+impl Sha1 {
+  pub fn new() -> Sha1 {
+    let mut ctx : br_sha1_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_sha1_init(&mut ctx as *mut br_sha1_context)
+    };
+    ctx
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    unsafe {
+      br_sha1_update(
+        self as *mut br_sha1_context,
+        data.as_ptr() as *const c_void,
+        data.len())
+    }
+  }
+
+  pub fn finish(&self) -> [u8; SHA1_SIZE] {
+    let mut hash = [0u8; SHA1_SIZE];
+    unsafe {
+      br_sha1_out(
+        self as *const br_sha1_context,
+        hash.as_mut_ptr() as *mut c_void)
+    };
+    hash
+  }
+}
+
+pub const SHA256_SIZE : usize = 32;
+
+pub type Sha256 = br_sha256_context;
+
+impl Sha256 {
+  pub fn new() -> Sha256 {
+    let mut ctx : br_sha256_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_sha256_init(&mut ctx as *mut br_sha256_context)
+    };
+    ctx
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    // br_sha256_update is a macro for br_sha224_update
+    unsafe {
+      br_sha224_update(
+        self as *mut br_sha256_context,
+        data.as_ptr() as *const c_void,
+        data.len())
+    }
+  }
+
+  pub fn finish(&self) -> [u8; SHA256_SIZE] {
+    let mut hash = [0u8; SHA256_SIZE];
+    unsafe {
+      br_sha256_out(
+        self as *const br_sha256_context,
+        hash.as_mut_ptr() as *mut c_void)
+    };
+    hash
+  }
+}
+
+pub const MD5_SIZE : usize = 16;
+
+/// Only for protocols that still require it, like HTTP Digest auth.
+pub type Md5 = br_md5_context;
+
+impl Md5 {
+  pub fn new() -> Md5 {
+    let mut ctx : br_md5_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_md5_init(&mut ctx as *mut br_md5_context)
+    };
+    ctx
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    unsafe {
+      br_md5_update(
+        self as *mut br_md5_context,
+        data.as_ptr() as *const c_void,
+        data.len())
+    }
+  }
+
+  pub fn finish(&self) -> [u8; MD5_SIZE] {
+    let mut hash = [0u8; MD5_SIZE];
+    unsafe {
+      br_md5_out(
+        self as *const br_md5_context,
+        hash.as_mut_ptr() as *mut c_void)
+    };
+    hash
+  }
+}
+
+pub type HmacKey = br_hmac_key_context;
+
+impl HmacKey {
+  pub fn sha256(key: &[u8]) -> HmacKey {
+    let mut ctx : br_hmac_key_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_hmac_key_init(
+        &mut ctx as *mut br_hmac_key_context,
+        &br_sha256_vtable as *const br_hash_class,
+        key.as_ptr() as *const c_void,
+        key.len())
+    };
+    ctx
+  }
+}
+
+pub type Hmac = br_hmac_context;
+
+impl Hmac {
+  pub fn new(key: &HmacKey) -> Hmac {
+    let mut ctx : br_hmac_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_hmac_init(
+        &mut ctx as *mut br_hmac_context,
+        key as *const br_hmac_key_context,
+        0)
+    };
+    ctx
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    unsafe {
+      br_hmac_update(
+        self as *mut br_hmac_context,
+        data.as_ptr() as *const c_void,
+        data.len())
+    }
+  }
+
+  pub fn finish(&self) -> [u8; SHA256_SIZE] {
+    let mut mac = [0u8; SHA256_SIZE];
+    unsafe {
+      br_hmac_out(
+        self as *const br_hmac_context,
+        mac.as_mut_ptr() as *mut c_void)
+    };
+    mac
+  }
+}
+
+/// Compares two MACs without leaking through timing
+/// how many leading bytes match.
+pub fn mac_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256 as pseudorandom function,
+/// filling `out` with the derived key.
+pub fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+  let key = HmacKey::sha256(password);
+  for (block_idx, block) in out.chunks_mut(SHA256_SIZE).enumerate() {
+    let block_number = block_idx as u32 + 1;
+    let mut hmac = Hmac::new(&key);
+    hmac.update(salt);
+    hmac.update(&[
+      (block_number >> 24) as u8,
+      (block_number >> 16) as u8,
+      (block_number >> 8) as u8,
+      block_number as u8]);
+    let mut u = hmac.finish();
+    let mut t = u;
+    for _ in 1 .. iterations {
+      let mut hmac = Hmac::new(&key);
+      hmac.update(&u);
+      u = hmac.finish();
+      for (t, u) in t.iter_mut().zip(u.iter()) {
+        *t ^= u;
+      }
+    }
+    block.copy_from_slice(&t[.. block.len()]);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::pbkdf2_hmac_sha256;
+
+  #[test]
+  fn test_pbkdf2_hmac_sha256() {
+    // test vectors from RFC 7914, section 11
+    let mut out = [0u8; 64];
+    pbkdf2_hmac_sha256(b"passwd", b"salt", 1, &mut out);
+    assert_eq!(&out[.. 8], &[0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f]);
+    assert_eq!(&out[56 ..], &[0x09, 0x11, 0x20, 0x41, 0xd3, 0xa1, 0x97, 0x83]);
+    pbkdf2_hmac_sha256(b"Password", b"NaCl", 80000, &mut out);
+    assert_eq!(&out[.. 8], &[0x4d, 0xdc, 0xd8, 0xf6, 0x0b, 0x98, 0xbe, 0x21]);
+    assert_eq!(&out[56 ..], &[0x47, 0x8f, 0x62, 0xb3, 0x97, 0xf3, 0x3c, 0x8d]);
+  }
+}
@@ -3,6 +3,10 @@ pub mod x509;
 pub mod secret;
 pub mod engine;
 pub mod server;
+pub mod hash;
+pub mod random;
+pub mod ecdsa;
+pub mod session_cache;
 mod error;
 mod alert;
 
@@ -0,0 +1,40 @@
+use super::ffi::*;
+use std;
+use std::os::raw::c_void;
+
+/// A cryptographically secure PRNG (HMAC_DRBG with SHA-256),
+/// seeded from the operating system.
+pub type Random = br_hmac_drbg_context;
+
+impl Random {
+  pub fn from_system() -> Option<Random> {
+    let seeder = unsafe {
+      br_prng_seeder_system(std::ptr::null_mut())
+    }?;
+    let mut ctx : br_hmac_drbg_context = unsafe {
+      std::mem::zeroed()
+    };
+    let is_seeded = unsafe {
+      br_hmac_drbg_init(
+        &mut ctx as *mut br_hmac_drbg_context,
+        &br_sha256_vtable as *const br_hash_class,
+        std::ptr::null(),
+        0);
+      seeder(&mut ctx.vtable as *mut *const br_prng_class)
+    };
+    if is_seeded != 0 {
+      Some(ctx)
+    } else {
+      None
+    }
+  }
+
+  pub fn fill(&mut self, dst: &mut [u8]) {
+    unsafe {
+      br_hmac_drbg_generate(
+        self as *mut br_hmac_drbg_context,
+        dst.as_mut_ptr() as *mut c_void,
+        dst.len())
+    }
+  }
+}
@@ -3,6 +3,9 @@ use super::{secret, x509, engine, Result, Error};
 use std::marker::PhantomData;
 use std;
 
+/// whether BearSSL was built with lib/status_request.patch, for OCSP stapling
+pub const HAS_STATUS_REQUEST : bool = cfg!(bearssl_status_request);
+
 pub struct Context<'a> {
   ctx: Box<br_ssl_server_context>,
   lt: PhantomData<&'a u8>
@@ -34,6 +37,30 @@ impl<'a> Context<'a> {
     self.engine().last_error()
   }
 
+  /// Remembers sessions in the cache, so clients can resume them,
+  /// like br_ssl_server_set_cache. The cache needs to outlive this context.
+  pub unsafe fn set_cache(&mut self, cache: *mut *const br_ssl_session_cache_class) {
+    self.ctx.cache_vtable = cache;
+  }
+
+  /// Asks the client for a certificate issued by one of the trust anchors,
+  /// like br_ssl_server_set_trust_anchor_names_alt.
+  /// The trust anchors need to outlive this context.
+  pub unsafe fn set_trust_anchor_names(&mut self, trust_anchors: &[br_x509_trust_anchor]) {
+    self.ctx.ta_names = std::ptr::null();
+    self.ctx.tas = trust_anchors.as_ptr();
+    self.ctx.num_tas = trust_anchors.len();
+  }
+
+  /// Sends the OCSP response to clients asking for it with the status_request extension,
+  /// like br_ssl_server_set_ocsp_response from lib/status_request.patch.
+  /// Ignored by a BearSSL built without it, see `HAS_STATUS_REQUEST`.
+  /// The response needs to outlive this context.
+  pub unsafe fn set_ocsp_response(&mut self, response: &[u8]) {
+    self.ctx.ocsp_response = response.as_ptr();
+    self.ctx.ocsp_response_len = response.len();
+  }
+
   pub fn engine(&self) -> &engine::Context {
     &self.ctx.eng
   }
@@ -0,0 +1,40 @@
+use super::ffi::*;
+use std;
+use std::cell::UnsafeCell;
+
+/// bytes of the memory budget each cached session takes in the BearSSL LRU cache
+pub const SESSION_ENTRY_SIZE : usize = 100;
+
+/// BearSSL's LRU cache of session parameters, so returning clients
+/// can resume their session with an abbreviated handshake.
+///
+/// One cache is shared by the contexts of all connections,
+/// which write to it through the pointer they are given.
+pub struct SessionCache {
+  // boxed so the server contexts can keep pointing to it
+  cache: Box<UnsafeCell<br_ssl_session_cache_lru>>,
+  store: Vec<u8>
+}
+
+impl SessionCache {
+  pub fn new(memory_budget: usize) -> SessionCache {
+    let mut store = vec![0u8; memory_budget];
+    let cache : Box<UnsafeCell<br_ssl_session_cache_lru>> = Box::new(UnsafeCell::new(unsafe {
+      std::mem::zeroed()
+    }));
+    unsafe {
+      br_ssl_session_cache_lru_init(cache.get(), store.as_mut_ptr(), store.len())
+    };
+    SessionCache { cache, store }
+  }
+
+  /// how many sessions fit in the cache
+  pub fn capacity(&self) -> usize {
+    self.store.len() / SESSION_ENTRY_SIZE
+  }
+
+  pub fn as_class_ptr(&self) -> *mut *const br_ssl_session_cache_class {
+    // the vtable is the first field
+    self.cache.get() as *mut *const br_ssl_session_cache_class
+  }
+}
@@ -1,5 +1,7 @@
 use super::ffi::*;
+use std;
 use std::marker::PhantomData;
+use std::os::raw::{c_char, c_void};
 
 pub struct Certificate<'a> {
   cert: br_x509_certificate,
@@ -23,6 +25,148 @@ impl<'a> Certificate<'a> {
   }
 }
 
+/// CA certificates decoded into trust anchors,
+/// to validate the certificates they issued.
+pub struct TrustAnchors {
+  anchors: Vec<br_x509_trust_anchor>,
+  // the DN and public key of the anchors point into these
+  anchor_data: Vec<Vec<u8>>
+}
+
+impl TrustAnchors {
+  pub fn from_certificates(certificates: &[&[u8]]) -> std::result::Result<TrustAnchors, Error> {
+    let mut trust_anchors = TrustAnchors {
+      anchors: Vec::with_capacity(certificates.len()),
+      anchor_data: Vec::with_capacity(certificates.len() * 2)
+    };
+    for certificate in certificates {
+      trust_anchors.push(certificate)?;
+    }
+    Ok(trust_anchors)
+  }
+
+  fn push(&mut self, certificate: &[u8]) -> std::result::Result<(), Error> {
+    let mut dn : Vec<u8> = Vec::new();
+    let mut ctx : br_x509_decoder_context = unsafe {
+      std::mem::zeroed()
+    };
+    unsafe {
+      br_x509_decoder_init(
+        &mut ctx as *mut br_x509_decoder_context,
+        Some(append_dn),
+        &mut dn as *mut Vec<u8> as *mut c_void);
+      br_x509_decoder_push(
+        &mut ctx as *mut br_x509_decoder_context,
+        certificate.as_ptr() as *const c_void,
+        certificate.len());
+    }
+    if ctx.err != 0 {
+      return Err(unsafe { std::mem::transmute(ctx.err as i8) });
+    }
+    if ctx.decoded == 0 {
+      return Err(Error::Truncated);
+    }
+    // copy the key out of the decoder, and point the key to the copy
+    let mut pkey = ctx.pkey;
+    let mut key_data : Vec<u8> = Vec::new();
+    match pkey.key_type as u32 {
+      BR_KEYTYPE_RSA => unsafe {
+        let rsa = &mut pkey.key.rsa;
+        key_data.extend_from_slice(std::slice::from_raw_parts(rsa.n, rsa.nlen));
+        key_data.extend_from_slice(std::slice::from_raw_parts(rsa.e, rsa.elen));
+        rsa.n = key_data.as_mut_ptr();
+        rsa.e = key_data.as_mut_ptr().offset(rsa.nlen as isize);
+      },
+      BR_KEYTYPE_EC => unsafe {
+        let ec = &mut pkey.key.ec;
+        key_data.extend_from_slice(std::slice::from_raw_parts(ec.q, ec.qlen));
+        ec.q = key_data.as_mut_ptr();
+      },
+      _ => return Err(Error::WrongKeyType)
+    };
+    let anchor = br_x509_trust_anchor {
+      dn: br_x500_name {
+        data: dn.as_mut_ptr(),
+        len: dn.len()
+      },
+      flags: if ctx.isCA != 0 { BR_X509_TA_CA } else { 0 },
+      pkey
+    };
+    // moving the vecs doesn't move the data they point to
+    self.anchor_data.push(dn);
+    self.anchor_data.push(key_data);
+    self.anchors.push(anchor);
+    Ok( () )
+  }
+
+  pub fn as_slice(&self) -> &[br_x509_trust_anchor] {
+    &self.anchors
+  }
+}
+
+unsafe extern "C" fn append_dn(ctx: *mut c_void, buf: *const c_void, len: usize) {
+  let dn = &mut *(ctx as *mut Vec<u8>);
+  dn.extend_from_slice(std::slice::from_raw_parts(buf as *const u8, len));
+}
+
+// id-at-commonName (2.5.4.3), prefixed with its length
+static COMMON_NAME_OID : [u8; 4] = [3, 0x55, 0x04, 0x03];
+const COMMON_NAME_BUFFER_SIZE : usize = 256;
+
+/// Validates the certificate chain of a client with the "minimal" X.509 engine,
+/// and extracts the common name of the subject while doing so.
+pub struct ClientValidator<'a> {
+  ctx: br_x509_minimal_context,
+  common_name_element: br_name_element,
+  common_name: [u8; COMMON_NAME_BUFFER_SIZE],
+  lt: PhantomData<&'a TrustAnchors>
+}
+
+impl<'a> ClientValidator<'a> {
+  /// boxed because the engine and the name element point into it
+  pub fn new(trust_anchors: &'a TrustAnchors) -> Box<ClientValidator<'a>> {
+    let mut validator : Box<ClientValidator<'a>> = Box::new(unsafe {
+      std::mem::zeroed()
+    });
+    let anchors = trust_anchors.as_slice();
+    unsafe {
+      br_x509_minimal_init_full(
+        &mut validator.ctx as *mut br_x509_minimal_context,
+        anchors.as_ptr(),
+        anchors.len());
+    }
+    let buffer = validator.common_name.as_mut_ptr() as *mut c_char;
+    validator.common_name_element = br_name_element {
+      oid: COMMON_NAME_OID.as_ptr(),
+      buf: buffer,
+      len: COMMON_NAME_BUFFER_SIZE,
+      status: 0
+    };
+    // like br_x509_minimal_set_name_elements
+    let name_element = &mut validator.common_name_element as *mut br_name_element;
+    validator.ctx.name_elts = name_element;
+    validator.ctx.num_name_elts = 1;
+    validator
+  }
+
+  pub fn as_class_ptr(&self) -> *const *const br_x509_class {
+    &self.ctx.vtable as *const *const br_x509_class
+  }
+
+  /// The common name in the subject DN of the end-entity certificate,
+  /// if the last chain that was validated is trusted.
+  pub fn common_name(&self) -> Option<&[u8]> {
+    if self.ctx.err != BR_ERR_X509_OK as i32 || self.common_name_element.status != 1 {
+      return None;
+    }
+    // the decoder terminates the string with a zero
+    let len = self.common_name.iter()
+      .position(|b| *b == 0)
+      .unwrap_or(COMMON_NAME_BUFFER_SIZE);
+    Some(&self.common_name[.. len])
+  }
+}
+
 #[derive(Debug)]
 pub enum Error {
   InvalidValue = BR_ERR_X509_INVALID_VALUE as isize,